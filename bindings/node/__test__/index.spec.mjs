@@ -0,0 +1,28 @@
+import assert from 'node:assert/strict'
+import { createHash } from 'node:crypto'
+import { test } from 'node:test'
+
+import { MerkleTree } from '../index.js'
+
+const sha256 = (...buffers) => createHash('sha256').update(Buffer.concat(buffers)).digest()
+const leaves = ['a', 'b', 'c'].map((leaf) => Buffer.from(leaf))
+
+test('the root is hashed as in the Rust crate', () => {
+  const tree = new MerkleTree(leaves)
+  // the odd leaf is promoted to the level above without being hashed again
+  const expected = sha256(sha256(sha256(leaves[0]), sha256(leaves[1])), sha256(leaves[2]))
+  assert.deepEqual(tree.root, expected)
+  assert.equal(tree.leafCount, 3)
+  assert.throws(() => new MerkleTree([]))
+})
+
+test('proofs verify only the leaf at their index', () => {
+  const tree = new MerkleTree(leaves)
+  const proof = tree.prove(1)
+  assert.ok(MerkleTree.verify(leaves[1], proof, tree.root))
+  assert.ok(!MerkleTree.verify(leaves[2], proof, tree.root))
+  assert.ok(MerkleTree.verifyAt(leaves[1], 1, 3, proof, tree.root))
+  assert.ok(!MerkleTree.verifyAt(leaves[1], 0, 3, proof, tree.root))
+  assert.throws(() => tree.prove(3))
+  assert.throws(() => MerkleTree.verify(leaves[1], Buffer.from('not a proof'), tree.root))
+})
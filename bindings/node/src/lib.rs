@@ -0,0 +1,66 @@
+use merkle_tree::merkletree::{Data, MerkleTree as Tree, Proof};
+use merkle_tree::root::Root;
+use napi::bindgen_prelude::Buffer;
+use napi::{Error, Result, Status};
+use napi_derive::napi;
+
+/// SHA-256 Merkle tree over the buffers it is constructed from, hashed and padded exactly as the Rust crate does
+///
+/// Proofs are handed out and taken back in the bytes of `Proof::to_bytes`, so a proof made on either side
+/// verifies on the other.
+#[napi]
+pub struct MerkleTree {
+    tree: Tree,
+}
+
+#[napi]
+impl MerkleTree {
+    /// Builds the tree over `leaves`, throwing when there are none
+    #[napi(constructor)]
+    pub fn new(leaves: Vec<Buffer>) -> Result<MerkleTree> {
+        if leaves.is_empty() {
+            return Err(Error::new(Status::InvalidArg, "trees have at least one leaf"));
+        }
+        let leaves = leaves.iter().map(|leaf| leaf.to_vec()).collect::<Vec<Data>>();
+        Ok(MerkleTree { tree: Tree::construct(&leaves) })
+    }
+
+    /// Gets the root hash of the tree
+    #[napi(getter)]
+    pub fn root(&self) -> Buffer {
+        self.tree.root().into_hash().into()
+    }
+
+    /// Gets number of leaves of the tree
+    // a tree built from a JavaScript array has fewer leaves than fit in a u32
+    #[napi(getter)]
+    pub fn leaf_count(&self) -> u32 {
+        self.tree.leaf_count() as u32
+    }
+
+    /// Proves the leaf at `index`, throwing when the tree has no leaf there
+    #[napi]
+    pub fn prove(&self, index: u32) -> Result<Buffer> {
+        let proof = self.tree.prove_by_index(index.into()).ok_or_else(|| Error::new(Status::InvalidArg, "leaf index out of range"))?;
+        Ok(proof.to_bytes().into())
+    }
+
+    /// Verifies that `leaf` is in the tree with the given root, throwing when the proof can't be read
+    #[napi]
+    pub fn verify(leaf: Buffer, proof: Buffer, root: Buffer) -> Result<bool> {
+        let proof = read_proof(&proof)?;
+        Ok(Tree::verify_proof(&leaf.to_vec(), &proof, &Root::new(root.to_vec())))
+    }
+
+    /// Verifies that `leaf` is the leaf at `index` of a tree of `leaf_count` leaves with the given root,
+    /// throwing when the proof can't be read
+    #[napi]
+    pub fn verify_at(leaf: Buffer, index: u32, leaf_count: u32, proof: Buffer, root: Buffer) -> Result<bool> {
+        let proof = read_proof(&proof)?;
+        Ok(Tree::verify_proof_at(&leaf.to_vec(), index.into(), leaf_count.into(), &proof, &Root::new(root.to_vec())))
+    }
+}
+
+fn read_proof(bytes: &[u8]) -> Result<Proof> {
+    Proof::from_bytes(bytes).map_err(|error| Error::new(Status::InvalidArg, error.to_string()))
+}
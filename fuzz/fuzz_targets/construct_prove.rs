@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merkle_tree::merkletree::{Data, MerkleTree, Proof};
+
+// arbitrary leaf sets are built into a tree and every leaf has to be proven against its root
+fuzz_target!(|leaves: Vec<Data>| {
+    if leaves.is_empty() {
+        return;
+    }
+    let tree = MerkleTree::construct(&leaves);
+    let root = tree.root();
+    assert!(MerkleTree::verify(&leaves, &root));
+
+    for leaf in &leaves {
+        let proof = tree.prove(leaf).expect("every leaf of the tree should be provable");
+        let proof = Proof::from_bytes(&proof.to_bytes()).expect("proof should survive a round trip");
+        assert!(MerkleTree::verify_proof(leaf, &proof, &root));
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merkle_tree::merkletree::{MerkleTree, Proof};
+use merkle_tree::root::Root;
+
+// arbitrary bytes are fed into proof deserialization and every decoded proof into verification,
+// neither of which may panic on untrusted input
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(proof) = Proof::from_bytes(bytes) {
+        // the encoding is canonical, so any accepted input has to survive a round trip unchanged
+        assert_eq!(proof.to_bytes(), bytes);
+
+        let root = Root::new(vec![0; 32]);
+        MerkleTree::verify_proof(&bytes.to_vec(), &proof, &root);
+    }
+});
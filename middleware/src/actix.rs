@@ -0,0 +1,103 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+use ::actix_web::dev::Payload;
+use ::actix_web::error::ErrorInternalServerError;
+use ::actix_web::http::StatusCode;
+use ::actix_web::web::{Bytes, Data};
+use ::actix_web::{Error, FromRequest, HttpRequest, ResponseError};
+use merkle_tree::hasher::{Hasher, Sha256Hasher};
+use merkle_tree::merkletree::Proof;
+use merkle_tree::request_proof::{RequestProofError, RequestVerifier};
+
+/// Body of a request that was proven to be a leaf of the configured tree, with the proof it came with
+///
+/// The extractor takes the body and the headers `RequestVerifier::verify` reads, and the verifier from
+/// the app data as a `Data<RequestVerifier<H>>`. A request that is refused fails with a `ProofRejection`,
+/// answered before the handler runs.
+pub struct VerifiedBody<H: Hasher = Sha256Hasher> {
+    pub body: Bytes,
+    pub proof: Proof<H>,
+}
+
+/// Refusal of a request as an actix-web error, answered with the `status` of the error and the error as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofRejection(pub RequestProofError);
+
+impl fmt::Display for ProofRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ResponseError for ProofRejection {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.0.status()).expect("400 and 403 are statuses")
+    }
+}
+
+impl<H: Hasher + 'static> FromRequest for VerifiedBody<H> {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<VerifiedBody<H>, Error>>>>;
+
+    fn from_request(request: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let request = request.clone();
+        let body = Bytes::from_request(&request, payload);
+        Box::pin(async move {
+            let body = body.await?;
+            let verifier = request
+                .app_data::<Data<RequestVerifier<H>>>()
+                .ok_or_else(|| ErrorInternalServerError("no RequestVerifier in the app data"))?;
+            let headers = request.headers().iter().map(|(name, value)| (name.as_str(), value.as_bytes()));
+            let proof = verifier.verify(headers, &body).map_err(ProofRejection)?;
+            Ok(VerifiedBody { body, proof })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::actix_web::test::TestRequest;
+    use merkle_tree::merkletree::{Data as Leaf, MerkleTree};
+    use merkle_tree::request_proof::PROOF_HEADER;
+
+    fn example_data(n: usize) -> Vec<Leaf> {
+        (0..n).map(|i| format!("request {i}").into_bytes()).collect()
+    }
+
+    #[actix_web::test]
+    async fn test_requests_are_verified_before_the_handler_runs() {
+        let data = example_data(5);
+        let tree = MerkleTree::construct(&data);
+        let verifier = Data::new(RequestVerifier::new(tree.root()));
+        let proof = hex::encode(tree.prove_by_index(1).expect("index is in range").to_bytes());
+        let request = |body: &Leaf| {
+            TestRequest::default()
+                .app_data(verifier.clone())
+                .insert_header((PROOF_HEADER, proof.as_str()))
+                .set_payload(body.clone())
+                .to_http_parts()
+        };
+
+        let (http_request, mut payload) = request(&data[1]);
+        let Ok(verified) = <VerifiedBody>::from_request(&http_request, &mut payload).await else {
+            panic!("body is the leaf");
+        };
+        assert_eq!(verified.body, data[1]);
+        assert_eq!(Some(verified.proof), tree.prove_by_index(1));
+
+        let (http_request, mut payload) = request(&data[2]);
+        let Err(error) = <VerifiedBody>::from_request(&http_request, &mut payload).await else {
+            panic!("body is another leaf");
+        };
+        assert_eq!(error.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+        let (http_request, mut payload) = TestRequest::default().set_payload(data[1].clone()).to_http_parts();
+        let Err(error) = <VerifiedBody>::from_request(&http_request, &mut payload).await else {
+            panic!("no verifier is configured");
+        };
+        assert_eq!(error.as_response_error().status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
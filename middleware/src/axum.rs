@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use ::axum::body::Bytes;
+use ::axum::extract::{FromRef, FromRequest, Request};
+use ::axum::http::StatusCode;
+use ::axum::response::{IntoResponse, Response};
+use merkle_tree::hasher::{Hasher, Sha256Hasher};
+use merkle_tree::merkletree::Proof;
+use merkle_tree::request_proof::{RequestProofError, RequestVerifier};
+
+/// Body of a request that was proven to be a leaf of the configured tree, with the proof it came with
+///
+/// The extractor takes the body and the headers `RequestVerifier::verify` reads, and the verifier from
+/// the router's state as an `Arc<RequestVerifier<H>>`. A request that is refused is answered with the
+/// `status` of its `RequestProofError` and the error as text, before the handler runs.
+pub struct VerifiedBody<H: Hasher = Sha256Hasher> {
+    pub body: Bytes,
+    pub proof: Proof<H>,
+}
+
+impl<S, H> FromRequest<S> for VerifiedBody<H>
+where
+    S: Send + Sync,
+    H: Hasher,
+    Arc<RequestVerifier<H>>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = request.headers().clone();
+        let body = Bytes::from_request(request, state).await.map_err(IntoResponse::into_response)?;
+        let verifier = Arc::<RequestVerifier<H>>::from_ref(state);
+        let headers = headers.iter().map(|(name, value)| (name.as_str(), value.as_bytes()));
+        match verifier.verify(headers, &body) {
+            Ok(proof) => Ok(VerifiedBody { body, proof }),
+            Err(error) => Err(rejection(error)),
+        }
+    }
+}
+
+fn rejection(error: RequestProofError) -> Response {
+    let status = StatusCode::from_u16(error.status()).expect("400 and 403 are statuses");
+    (status, error.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::axum::body::Body;
+    use merkle_tree::merkletree::{Data, MerkleTree};
+    use merkle_tree::request_proof::PROOF_HEADER;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| format!("request {i}").into_bytes()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_verified_before_the_handler_runs() {
+        let data = example_data(5);
+        let tree = MerkleTree::construct(&data);
+        let state = Arc::new(RequestVerifier::new(tree.root()));
+        let proof = hex::encode(tree.prove_by_index(1).expect("index is in range").to_bytes());
+        let request = |body: &Data| {
+            ::axum::http::Request::builder().header(PROOF_HEADER, &proof).body(Body::from(body.clone())).expect("request is valid")
+        };
+
+        let Ok(verified) = <VerifiedBody>::from_request(request(&data[1]), &state).await else {
+            panic!("body is the leaf");
+        };
+        assert_eq!(verified.body, data[1]);
+        assert_eq!(Some(verified.proof), tree.prove_by_index(1));
+
+        let Err(response) = <VerifiedBody>::from_request(request(&data[2]), &state).await else {
+            panic!("body is another leaf");
+        };
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let Err(response) = <VerifiedBody>::from_request(Request::new(Body::from(data[1].clone())), &state).await else {
+            panic!("request has no proof");
+        };
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
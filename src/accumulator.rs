@@ -0,0 +1,116 @@
+use crate::frontier::Frontier;
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+
+/// Computes the root over leaves consumed one at a time without ever holding the tree
+///
+/// Only the peaks of the complete subtrees are kept, one per set bit of the leaf count,
+/// so a root over billions of leaves takes a few dozen hashes of memory.
+/// The root is the one `MerkleTree::construct` computes over the same leaves.
+#[derive(Debug, Clone, Default)]
+pub struct RootAccumulator<H: Hasher = Sha256Hasher> {
+    frontier: Frontier<H>,
+}
+
+impl RootAccumulator {
+    /// Starts an accumulator without any leaves
+    pub fn new() -> RootAccumulator {
+        RootAccumulator::default()
+    }
+
+    /// Computes the root over all given leaves, `None` when there are none
+    pub fn root_of<I>(leaves: I) -> Option<Root>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut accumulator = RootAccumulator::new();
+        accumulator.extend(leaves);
+        accumulator.finish()
+    }
+}
+
+impl<H: Hasher> RootAccumulator<H> {
+    /// Starts an accumulator without any leaves, hashing with the given hash function
+    pub fn with_hasher(hasher: H) -> RootAccumulator<H> {
+        RootAccumulator {
+            frontier: Frontier::with_hasher(hasher),
+        }
+    }
+
+    /// Gets the hash function the accumulator hashes with
+    pub fn hasher(&self) -> &H {
+        self.frontier.hasher()
+    }
+
+    /// Gets number of leaves consumed so far
+    pub fn leaf_count(&self) -> u64 {
+        self.frontier.leaf_count()
+    }
+
+    /// Hashes and consumes the next leaf
+    pub fn push(&mut self, data: &[u8]) {
+        let leaf_hash = LeafHash::of(data, self.frontier.hasher());
+        self.frontier.push_hash(leaf_hash);
+    }
+
+    /// Consumes the next leaf that was already hashed
+    pub fn push_hash(&mut self, leaf_hash: LeafHash) {
+        self.frontier.push_hash(leaf_hash);
+    }
+
+    /// Root over the leaves consumed so far, `None` before the first leaf
+    pub fn root(&self) -> Option<Root> {
+        self.frontier.root()
+    }
+
+    /// Yields the final root, `None` when no leaf was consumed
+    pub fn finish(self) -> Option<Root> {
+        self.root()
+    }
+}
+
+impl<H: Hasher, T: AsRef<[u8]>> Extend<T> for RootAccumulator<H> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, leaves: I) {
+        for leaf in leaves {
+            self.push(leaf.as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::{Data, MerkleTree};
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_root_matches_constructed_tree() {
+        for n in 1..=33 {
+            let data = example_data(n);
+            assert_eq!(RootAccumulator::root_of(&data), Some(MerkleTree::construct(&data).root()));
+        }
+        assert_eq!(RootAccumulator::root_of(Vec::<Data>::new()), None);
+    }
+
+    #[test]
+    fn test_accumulator_with_hasher_and_leaf_hashes() {
+        let data = example_data(11);
+        let hasher = Blake2bHasher::new(24).expect("valid length");
+        let mut accumulator = RootAccumulator::with_hasher(hasher);
+        for (i, leaf) in data.iter().enumerate() {
+            if i % 2 == 0 {
+                accumulator.push(leaf);
+            } else {
+                accumulator.push_hash(LeafHash::of(leaf, &hasher));
+            }
+        }
+        assert_eq!(accumulator.leaf_count(), 11);
+        assert_eq!(accumulator.finish(), Some(MerkleTree::construct_with_hasher(&data, hasher).root()));
+    }
+}
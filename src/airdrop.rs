@@ -0,0 +1,236 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde_json::json;
+
+use crate::hasher::{DigestHasher, Hasher};
+use crate::root::Root;
+
+/// hash function of OpenZeppelin's `StandardMerkleTree` and of the EVM
+type Keccak256 = DigestHasher<sha3::Keccak256>;
+
+/// An address entitled to claim `amount` tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Claim {
+    pub address: [u8; 20],
+    pub amount: u128,
+}
+
+/// Reasons an allowlist can not be built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AirdropError {
+    /// an address is not 20 hex encoded bytes
+    InvalidAddress(String),
+    /// an address appears more than once, which would make its claim ambiguous
+    DuplicateAddress(String),
+    /// there is nothing to build a tree from
+    Empty,
+}
+
+impl fmt::Display for AirdropError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AirdropError::InvalidAddress(address) => write!(f, "invalid address {address}"),
+            AirdropError::DuplicateAddress(address) => write!(f, "duplicate address {address}"),
+            AirdropError::Empty => write!(f, "allowlist has no claims"),
+        }
+    }
+}
+
+impl std::error::Error for AirdropError {}
+
+impl Claim {
+    /// Parses a claim from a `0x` prefixed (or bare) hex address
+    pub fn parse(address: &str, amount: u128) -> Result<Claim, AirdropError> {
+        let hex_address = address.strip_prefix("0x").unwrap_or(address);
+        let mut parsed = [0; 20];
+        hex::decode_to_slice(hex_address, &mut parsed).map_err(|_| AirdropError::InvalidAddress(address.to_string()))?;
+        Ok(Claim { address: parsed, amount })
+    }
+
+    /// Address in lowercase `0x` prefixed hex
+    pub fn address_hex(&self) -> String {
+        format!("0x{}", hex::encode(self.address))
+    }
+
+    /// Leaf hash committing to this claim
+    ///
+    /// Follows the de-facto standard of OpenZeppelin's `StandardMerkleTree`: the claim is ABI encoded
+    /// as `abi.encode(address, uint256)` and hashed twice with Keccak-256.
+    /// The double hash keeps a 64 byte leaf from ever being mistaken for a pair of child hashes.
+    pub fn leaf(&self) -> [u8; 32] {
+        let mut encoded = vec![0; 64];
+        encoded[12..32].copy_from_slice(&self.address);
+        encoded[48..64].copy_from_slice(&self.amount.to_be_bytes());
+        let hasher = Keccak256::new();
+        to_bytes32(&hasher.hash(&hasher.hash(&encoded)))
+    }
+}
+
+/// Merkle tree over the claims of an airdrop, from which every address can get its proof
+///
+/// The tree is the one `StandardMerkleTree.of(claims, ["address", "uint256"])` builds, so the root and
+/// proofs are those of OpenZeppelin's library and verify with `MerkleProof.verify` on chain: the leaf
+/// hashes are sorted, laid out as the last nodes of a complete binary tree in an array, and every node
+/// is the Keccak-256 hash of its two children in ascending order. As pairs are sorted, a proof is just
+/// the sibling hashes from the leaf up.
+pub struct Allowlist {
+    claims: Vec<Claim>,
+    /// position of each claim's leaf in `tree`
+    tree_indices: Vec<usize>,
+    /// nodes of the tree, the root first and the children of node `i` at `2i + 1` and `2i + 2`
+    tree: Vec<[u8; 32]>,
+}
+
+impl Allowlist {
+    /// Builds the tree over the claims, which keep the given order
+    pub fn build(claims: Vec<Claim>) -> Result<Allowlist, AirdropError> {
+        if claims.is_empty() {
+            return Err(AirdropError::Empty);
+        }
+        let mut seen = BTreeSet::new();
+        for claim in &claims {
+            if !seen.insert(claim.address) {
+                return Err(AirdropError::DuplicateAddress(claim.address_hex()));
+            }
+        }
+        let mut leaves: Vec<(usize, [u8; 32])> = claims.iter().map(Claim::leaf).enumerate().collect();
+        leaves.sort_by_key(|(_, leaf)| *leaf);
+
+        // the sorted leaves fill the array from its end backwards
+        let mut tree = vec![[0; 32]; 2 * leaves.len() - 1];
+        let mut tree_indices = vec![0; claims.len()];
+        for (position, (claim_index, leaf)) in leaves.iter().enumerate() {
+            let tree_index = tree.len() - 1 - position;
+            tree[tree_index] = *leaf;
+            tree_indices[*claim_index] = tree_index;
+        }
+        for index in (0..tree.len() - leaves.len()).rev() {
+            tree[index] = hash_pair(&tree[2 * index + 1], &tree[2 * index + 2]);
+        }
+        Ok(Allowlist { claims, tree_indices, tree })
+    }
+
+    /// Root to publish, e.g. to the distributor contract
+    pub fn root(&self) -> Root {
+        Root::new(self.tree[0].to_vec())
+    }
+
+    /// Claim and proof for the given address, `None` when it is not on the allowlist
+    /// the proof is the `bytes32[]` that `MerkleProof.verify` takes
+    pub fn proof(&self, address: &[u8; 20]) -> Option<(Claim, Vec<[u8; 32]>)> {
+        let position = self.claims.iter().position(|claim| claim.address == *address)?;
+        Some((self.claims[position], self.proof_at(self.tree_indices[position])))
+    }
+
+    /// Exports the claims file: the root and, per address, its amount and proof
+    ///
+    /// Hashes are `0x` prefixed hex and amounts decimal strings, so that no JSON consumer loses precision.
+    /// Proofs are arrays of sibling hashes from the leaf up, as a contract call takes them.
+    pub fn to_claims_json(&self) -> String {
+        let claims = self
+            .claims
+            .iter()
+            .zip(&self.tree_indices)
+            .map(|(claim, tree_index)| {
+                let proof = self.proof_at(*tree_index).iter().map(|hash| format!("0x{}", hex::encode(hash))).collect::<Vec<_>>();
+                (claim.address_hex(), json!({ "amount": claim.amount.to_string(), "proof": proof }))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let file = json!({ "root": format!("0x{}", hex::encode(self.root())), "claims": claims });
+        serde_json::to_string_pretty(&file).expect("claims file is valid JSON")
+    }
+
+    /// siblings of the node at `tree_index` up to the root
+    fn proof_at(&self, mut tree_index: usize) -> Vec<[u8; 32]> {
+        let mut proof = vec![];
+        while tree_index > 0 {
+            let sibling = if tree_index % 2 == 1 { tree_index + 1 } else { tree_index - 1 };
+            proof.push(self.tree[sibling]);
+            tree_index = (tree_index - 1) / 2;
+        }
+        proof
+    }
+}
+
+/// Verifies that `claim` is part of the airdrop committed to by `root`, as `MerkleProof.verify` does
+pub fn verify_claim(claim: &Claim, proof: &[[u8; 32]], root: &Root) -> bool {
+    let computed = proof.iter().fold(claim.leaf(), |node, sibling| hash_pair(&node, sibling));
+    computed == root.as_bytes()
+}
+
+/// Keccak-256 hash of two nodes in ascending order, so that proofs need no sides
+fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    to_bytes32(&Keccak256::new().hash_concat(left, right))
+}
+
+fn to_bytes32(hash: &[u8]) -> [u8; 32] {
+    hash.try_into().expect("Keccak-256 hashes are 32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_claims(n: u8) -> Vec<Claim> {
+        (0..n).map(|i| Claim { address: [i; 20], amount: 1000 * u128::from(i) }).collect()
+    }
+
+    #[test]
+    fn test_leaf_is_double_hash_of_abi_encoded_claim() {
+        let claim = Claim::parse("0x1111111111111111111111111111111111111111", 5_000_000_000_000_000_000).expect("this should parse");
+        let expected = "0000000000000000000000001111111111111111111111111111111111111111\
+                        0000000000000000000000000000000000000000000000004563918244f40000";
+        let hasher = Keccak256::new();
+        assert_eq!(claim.leaf().to_vec(), hasher.hash(&hasher.hash(&hex::decode(expected).expect("valid hex"))));
+    }
+
+    #[test]
+    fn test_root_matches_standard_merkle_tree() {
+        // the example of the README of @openzeppelin/merkle-tree
+        let claims = vec![
+            Claim::parse("0x1111111111111111111111111111111111111111", 5_000_000_000_000_000_000).expect("this should parse"),
+            Claim::parse("0x2222222222222222222222222222222222222222", 2_500_000_000_000_000_000).expect("this should parse"),
+        ];
+        let allowlist = Allowlist::build(claims).expect("this should build");
+        assert_eq!(hex::encode(allowlist.root()), "d4dee0beab2d53f2cc83e567171bd2820e49898130a22622b10ead383e90bd77");
+    }
+
+    #[test]
+    fn test_every_claim_verifies_against_root() {
+        for n in [1, 2, 5, 8] {
+            let allowlist = Allowlist::build(example_claims(n)).expect("this should build");
+            for claim in example_claims(n) {
+                let (found, proof) = allowlist.proof(&claim.address).expect("this should return Proof");
+                assert_eq!(found, claim);
+                assert!(verify_claim(&claim, &proof, &allowlist.root()));
+
+                let inflated = Claim { amount: claim.amount + 1, ..claim };
+                assert!(!verify_claim(&inflated, &proof, &allowlist.root()));
+            }
+            assert!(allowlist.proof(&[9; 20]).is_none());
+        }
+    }
+
+    #[test]
+    fn test_build_rejects_duplicates_and_empty_lists() {
+        let mut claims = example_claims(3);
+        claims.push(claims[1]);
+        assert_eq!(Allowlist::build(claims).err(), Some(AirdropError::DuplicateAddress(format!("0x{}", "01".repeat(20)))));
+        assert_eq!(Allowlist::build(vec![]).err(), Some(AirdropError::Empty));
+        assert!(matches!(Claim::parse("0x1234", 1), Err(AirdropError::InvalidAddress(_))));
+    }
+
+    #[test]
+    fn test_claims_json_contains_root_and_proofs() {
+        let claims = example_claims(2);
+        let allowlist = Allowlist::build(claims.clone()).expect("this should build");
+        let file: serde_json::Value = serde_json::from_str(&allowlist.to_claims_json()).expect("this should be JSON");
+
+        assert_eq!(file["root"], format!("0x{}", hex::encode(allowlist.root())));
+        let claim = &file["claims"][format!("0x{}", "01".repeat(20))];
+        assert_eq!(claim["amount"], "1000");
+        assert_eq!(claim["proof"], json!([format!("0x{}", hex::encode(claims[0].leaf()))]));
+    }
+}
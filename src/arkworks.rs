@@ -0,0 +1,137 @@
+use std::borrow::Borrow;
+
+use ark_crypto_primitives::crh::sha256::constraints::{DigestVar, Sha256Gadget};
+use ark_ff::PrimeField;
+use ark_r1cs_std::alloc::{AllocVar, AllocationMode};
+use ark_r1cs_std::boolean::Boolean;
+use ark_r1cs_std::eq::EqGadget;
+use ark_r1cs_std::select::CondSelectGadget;
+use ark_r1cs_std::uint8::UInt8;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+
+use crate::merkletree::{HashDirection, Proof};
+
+/// Hashes leaf data inside a circuit the way `MerkleTree` hashes it natively: SHA-256 of the data itself
+pub fn hash_leaf_gadget<F: PrimeField>(data: &[UInt8<F>]) -> Result<DigestVar<F>, SynthesisError> {
+    Sha256Gadget::digest(data)
+}
+
+/// Hashes two child hashes inside a circuit the way `MerkleTree` hashes them natively:
+/// SHA-256 of the left hash followed by the right one, without any prefix telling nodes from leaves
+pub fn hash_node_gadget<F: PrimeField>(left: &DigestVar<F>, right: &DigestVar<F>) -> Result<DigestVar<F>, SynthesisError> {
+    let mut sha256 = Sha256Gadget::default();
+    sha256.update(&left.0)?;
+    sha256.update(&right.0)?;
+    sha256.finalize()
+}
+
+/// An inclusion proof of a SHA-256 tree allocated in a constraint system
+///
+/// The sibling hashes and the side each of them goes on are variables, so one circuit verifies
+/// proofs of any leaf. The number of siblings is not: it fixes the shape of the circuit,
+/// and proofs have to be allocated from a `Proof` of the depth the circuit was set up with.
+pub struct ProofVar<F: PrimeField> {
+    /// per level from the leaf up, whether the sibling goes on the left, and the sibling hash
+    hashes: Vec<(Boolean<F>, DigestVar<F>)>,
+}
+
+impl<F: PrimeField> AllocVar<Proof, F> for ProofVar<F> {
+    fn new_variable<T: Borrow<Proof>>(
+        cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into().cs();
+        let proof = f()?;
+        let mut hashes = Vec::with_capacity(proof.borrow().hashes.len());
+        for (hash_direction, hash) in &proof.borrow().hashes {
+            // a sibling of any other length can't be part of a SHA-256 proof
+            if hash.len() != 32 {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+            let is_left = Boolean::new_variable(cs.clone(), || Ok(*hash_direction == HashDirection::Left), mode)?;
+            let sibling = DigestVar::new_variable(cs.clone(), || Ok(hash.clone()), mode)?;
+            hashes.push((is_left, sibling));
+        }
+        Ok(ProofVar { hashes })
+    }
+}
+
+impl<F: PrimeField> ProofVar<F> {
+    /// Gets number of sibling hashes, the depth the circuit verifies proofs of
+    pub fn depth(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Computes the root the proof leads to from the given leaf data, like `MerkleTree::verify_proof` does natively
+    pub fn root(&self, data: &[UInt8<F>]) -> Result<DigestVar<F>, SynthesisError> {
+        let mut hashed_data = hash_leaf_gadget(data)?;
+        for (is_left, sibling) in &self.hashes {
+            let left = DigestVar::conditionally_select(is_left, sibling, &hashed_data)?;
+            let right = DigestVar::conditionally_select(is_left, &hashed_data, sibling)?;
+            hashed_data = hash_node_gadget(&left, &right)?;
+        }
+        Ok(hashed_data)
+    }
+
+    /// Whether the given leaf data and this proof produce the given root
+    /// enforce the result to be true to make the circuit verify the proof
+    pub fn verify(&self, data: &[UInt8<F>], root: &DigestVar<F>) -> Result<Boolean<F>, SynthesisError> {
+        self.root(data)?.is_eq(root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::{hash_concat, hash_data, Data, MerkleTree};
+    use ark_bls12_381::Fr;
+    use ark_r1cs_std::R1CSVar;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_gadget_hashes_like_native_tree() {
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        let left = DigestVar::new_witness(cs.clone(), || Ok(hash_data(&vec![1]))).expect("allocates");
+        let right = DigestVar::new_witness(cs.clone(), || Ok(hash_data(&vec![2]))).expect("allocates");
+        let node = hash_node_gadget(&left, &right).expect("hashes");
+        assert_eq!(node.value().expect("has a value").to_vec(), hash_concat(&hash_data(&vec![1]), &hash_data(&vec![2])));
+
+        let data = UInt8::new_witness_vec(cs.clone(), &[7, 8, 9]).expect("allocates");
+        let leaf = hash_leaf_gadget(&data).expect("hashes");
+        assert_eq!(leaf.value().expect("has a value").to_vec(), hash_data(&vec![7, 8, 9]));
+    }
+
+    #[test]
+    fn test_gadget_verifies_native_proofs() {
+        let data = example_data(3);
+        let tree = MerkleTree::construct(&data);
+        for leaf in &data {
+            let proof = tree.prove(leaf).expect("this should return Proof");
+
+            let cs = ConstraintSystem::<Fr>::new_ref();
+            let root = DigestVar::new_input(cs.clone(), || Ok(tree.root().into_hash())).expect("allocates");
+            let leaf = UInt8::new_witness_vec(cs.clone(), leaf).expect("allocates");
+            let proof = ProofVar::new_witness(cs.clone(), || Ok(&proof)).expect("allocates");
+            proof.verify(&leaf, &root).expect("verifies").enforce_equal(&Boolean::TRUE).expect("enforces");
+            assert!(cs.is_satisfied().expect("is checked"));
+        }
+    }
+
+    #[test]
+    fn test_gadget_rejects_proof_of_other_leaf() {
+        let data = example_data(2);
+        let tree = MerkleTree::construct(&data);
+        let proof = tree.prove(&data[0]).expect("this should return Proof");
+
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        let root = DigestVar::new_input(cs.clone(), || Ok(tree.root().into_hash())).expect("allocates");
+        let leaf = UInt8::new_witness_vec(cs.clone(), &data[1]).expect("allocates");
+        let proof = ProofVar::new_witness(cs.clone(), || Ok(&proof)).expect("allocates");
+        assert!(!proof.verify(&leaf, &root).expect("verifies").value().expect("has a value"));
+    }
+}
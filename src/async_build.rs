@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::io;
+use std::pin::pin;
+
+use futures_util::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::task::JoinHandle;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{scrub, Data, MerkleTree};
+use crate::node_hash::LeafHash;
+
+/// number of leaves hashed together by one task on the blocking pool
+const BATCH_SIZE: usize = 256;
+/// number of batches hashed at the same time before waiting for the oldest one
+const BATCHES_IN_FLIGHT: usize = 8;
+
+impl MerkleTree {
+    /// Constructs a Merkle tree from leaves arriving on a stream, `None` when the stream has no items
+    /// leaves are hashed on tokio's blocking pool while further items arrive, so the runtime never stalls
+    pub async fn construct_from_stream(stream: impl Stream<Item = Data>) -> Option<MerkleTree> {
+        MerkleTree::construct_from_stream_with_hasher(stream, Sha256Hasher::new()).await
+    }
+
+    /// Constructs a Merkle tree over `chunk_size` byte chunks of everything read from `reader`,
+    /// the last chunk holding whatever is left over; `Ok(None)` when the reader is empty
+    pub async fn construct_from_async_reader(reader: impl AsyncRead, chunk_size: usize) -> io::Result<Option<MerkleTree>> {
+        MerkleTree::construct_from_async_reader_with_hasher(reader, chunk_size, Sha256Hasher::new()).await
+    }
+}
+
+impl<H: Hasher + Send + 'static> MerkleTree<H> {
+    /// Constructs a Merkle tree from leaves arriving on a stream with the given hash function
+    pub async fn construct_from_stream_with_hasher(stream: impl Stream<Item = Data>, hasher: H) -> Option<MerkleTree<H>> {
+        let mut stream = pin!(stream);
+        let mut leaves = ConcurrentLeaves::new(hasher);
+        while let Some(data) = stream.next().await {
+            leaves.push(data).await;
+        }
+        leaves.finish().await
+    }
+
+    /// Constructs a Merkle tree over chunks of everything read from `reader` with the given hash function
+    pub async fn construct_from_async_reader_with_hasher(
+        reader: impl AsyncRead,
+        chunk_size: usize,
+        hasher: H,
+    ) -> io::Result<Option<MerkleTree<H>>> {
+        assert!(chunk_size > 0, "chunks must not be empty");
+        let mut reader = pin!(reader);
+        let mut leaves = ConcurrentLeaves::new(hasher);
+        loop {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            // reads on until the chunk is full or the reader is exhausted
+            reader.as_mut().take(chunk_size as u64).read_to_end(&mut chunk).await?;
+            if chunk.is_empty() {
+                break;
+            }
+            let last = chunk.len() < chunk_size;
+            leaves.push(chunk).await;
+            if last {
+                break;
+            }
+        }
+        Ok(leaves.finish().await)
+    }
+}
+
+/// leaves hashed in batches on the blocking pool, in order, with a bounded number of batches in flight
+struct ConcurrentLeaves<H> {
+    hasher: H,
+    batch: Vec<Data>,
+    in_flight: VecDeque<JoinHandle<Vec<LeafHash>>>,
+    leaf_hashes: Vec<LeafHash>,
+}
+
+impl<H: Hasher + Send + 'static> ConcurrentLeaves<H> {
+    fn new(hasher: H) -> ConcurrentLeaves<H> {
+        ConcurrentLeaves {
+            hasher,
+            batch: Vec::with_capacity(BATCH_SIZE),
+            in_flight: VecDeque::with_capacity(BATCHES_IN_FLIGHT),
+            leaf_hashes: vec![],
+        }
+    }
+
+    async fn push(&mut self, data: Data) {
+        self.batch.push(data);
+        if self.batch.len() == BATCH_SIZE {
+            self.spawn_batch();
+            if self.in_flight.len() == BATCHES_IN_FLIGHT {
+                self.join_oldest().await;
+            }
+        }
+    }
+
+    async fn finish(mut self) -> Option<MerkleTree<H>> {
+        self.spawn_batch();
+        while !self.in_flight.is_empty() {
+            self.join_oldest().await;
+        }
+        if self.leaf_hashes.is_empty() {
+            return None;
+        }
+        let (hasher, leaf_hashes) = (self.hasher, self.leaf_hashes);
+        let tree = tokio::task::spawn_blocking(move || MerkleTree::from_leaf_hashes_with_hasher(leaf_hashes, hasher));
+        Some(join(tree).await)
+    }
+
+    fn spawn_batch(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+        let hasher = self.hasher.clone();
+        self.in_flight.push_back(tokio::task::spawn_blocking(move || {
+            batch
+                .into_iter()
+                .map(|mut data| {
+                    let hash = LeafHash::of(&data, &hasher);
+                    scrub(&mut data);
+                    hash
+                })
+                .collect()
+        }));
+    }
+
+    async fn join_oldest(&mut self) {
+        if let Some(batch) = self.in_flight.pop_front() {
+            self.leaf_hashes.extend(join(batch).await);
+        }
+    }
+}
+
+/// waits for a task on the blocking pool, passing a panic inside it on to the caller
+async fn join<T>(task: JoinHandle<T>) -> T {
+    match task.await {
+        Ok(value) => value,
+        Err(error) => std::panic::resume_unwind(error.into_panic()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Shake128Hasher;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().expect("runtime starts").block_on(future)
+    }
+
+    #[test]
+    fn test_construct_from_stream_matches_construct() {
+        for n in [1, 5, BATCH_SIZE, BATCH_SIZE * BATCHES_IN_FLIGHT * 2 + 3] {
+            let data = example_data(n);
+            let tree = block_on(MerkleTree::construct_from_stream(futures_util::stream::iter(data.clone()))).expect("stream has items");
+            assert_eq!(tree.root(), MerkleTree::construct(&data).root());
+            assert_eq!(tree.leaf_count(), n as u64);
+        }
+        assert!(block_on(MerkleTree::construct_from_stream(futures_util::stream::empty())).is_none());
+    }
+
+    #[test]
+    fn test_construct_from_async_reader_chunks_its_input() {
+        let content: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let chunks: Vec<Data> = content.chunks(64).map(<[u8]>::to_vec).collect();
+        let hasher = Shake128Hasher::new(20).expect("valid length");
+
+        let tree = block_on(MerkleTree::construct_from_async_reader_with_hasher(content.as_slice(), 64, hasher))
+            .expect("reading from a slice")
+            .expect("reader is not empty");
+        assert_eq!(tree.root(), MerkleTree::construct_with_hasher(&chunks, hasher).root());
+
+        let empty = block_on(MerkleTree::construct_from_async_reader(&[][..], 64)).expect("reading from a slice");
+        assert!(empty.is_none());
+    }
+}
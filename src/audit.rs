@@ -0,0 +1,106 @@
+use std::fmt;
+
+use crate::hasher::Hasher;
+use crate::merkletree::MerkleTree;
+
+/// First position at which a tree is not the tree over its own leaves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditError {
+    /// the node at `index` on `level` is not the hash of its children, or not the copy of the node it was promoted from
+    NodeMismatch { level: usize, index: u64 },
+    /// the Bloom filter of the tree rules out the leaf at `index`, so proving it would be refused
+    BloomFilterMismatch { index: u64 },
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::NodeMismatch { level, index } => write!(f, "node {index} on level {level} doesn't match its children"),
+            AuditError::BloomFilterMismatch { index } => write!(f, "Bloom filter rules out leaf {index}"),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Recomputes every node above the leaves from the level below and compares it to the stored one,
+    /// returning the first mismatch from the leaves up, left to right
+    /// a tree that passes serves exactly the proofs of a tree built from its leaves; the leaves themselves
+    /// can only be checked against a root from elsewhere
+    pub fn audit(&self) -> Result<(), AuditError> {
+        let digest_len = self.hasher.digest_len();
+        let mut expected = vec![0; digest_len];
+        for level in 1..self.levels.count() {
+            let below = self.levels.len(level - 1);
+            for index in 0..self.levels.len(level) {
+                let (left, right) = (2 * index, 2 * index + 1);
+                let stored = self.levels.hash(level, index);
+                let matches = if right < below {
+                    self.hasher.hash_concat_into(self.levels.hash(level - 1, left), self.levels.hash(level - 1, right), &mut expected);
+                    stored == expected.as_slice()
+                } else {
+                    // the odd node out of the level below is promoted unchanged
+                    stored == self.levels.hash(level - 1, left)
+                };
+                if !matches {
+                    return Err(AuditError::NodeMismatch { level, index: index as u64 });
+                }
+            }
+        }
+        if let Some(bloom_filter) = &self.bloom_filter {
+            if let Some(index) = self.leaf_hashes().position(|leaf_hash| !bloom_filter.maybe_contains(leaf_hash)) {
+                return Err(AuditError::BloomFilterMismatch { index: index as u64 });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_sound_trees_pass_the_audit() {
+        for n in [1, 2, 3, 7, 8, 100] {
+            assert_eq!(MerkleTree::construct(&example_data(n)).with_bloom_filter(0.01).audit(), Ok(()));
+        }
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        assert_eq!(MerkleTree::construct_with_hasher(&example_data(13), hasher).audit(), Ok(()));
+    }
+
+    #[test]
+    fn test_audit_reports_the_first_corrupted_node() {
+        let mut tree = MerkleTree::construct(&example_data(7));
+        // the parent of the flipped node on level 2 doesn't match it either, but comes later
+        Arc::make_mut(&mut tree.levels).hash_mut(2, 1)[0] ^= 1;
+        assert_eq!(tree.audit(), Err(AuditError::NodeMismatch { level: 2, index: 1 }));
+
+        // a flipped leaf shows up at its parent, the leaves have nothing to be checked against
+        let mut tree = MerkleTree::construct(&example_data(7));
+        Arc::make_mut(&mut tree.levels).hash_mut(0, 6)[31] ^= 0x80;
+        assert_eq!(tree.audit(), Err(AuditError::NodeMismatch { level: 1, index: 3 }));
+
+        let mut tree = MerkleTree::construct(&example_data(7));
+        let root_level = tree.levels.count() - 1;
+        Arc::make_mut(&mut tree.levels).hash_mut(root_level, 0).fill(0);
+        assert_eq!(tree.audit(), Err(AuditError::NodeMismatch { level: root_level, index: 0 }));
+    }
+
+    #[test]
+    fn test_audit_checks_the_bloom_filter() {
+        let data = example_data(20);
+        let mut tree = MerkleTree::construct(&data).with_bloom_filter(0.01);
+        tree.bloom_filter = Some(Arc::new(crate::bloom::BloomFilter::new(20, 0.01)));
+        assert!(matches!(tree.audit(), Err(AuditError::BloomFilterMismatch { .. })));
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use crate::hasher::Hasher;
+use crate::merkletree::{Data, MerkleTree};
+
+/// Bloom filter over leaf hashes, answering "definitely not a leaf" without touching the tree
+///
+/// Leaf hashes are already uniformly distributed, so the bit positions are derived from the hash
+/// bytes themselves instead of hashing them again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    /// Creates a filter sized for `capacity` hashes at the given false positive rate, e.g. `0.01`
+    pub fn new(capacity: usize, false_positive_rate: f64) -> BloomFilter {
+        assert!(false_positive_rate > 0.0 && false_positive_rate < 1.0, "false positive rate must be within (0, 1)");
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-(capacity.max(1) as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let hash_count = (bit_count / capacity.max(1) as f64 * ln2).round().clamp(1.0, 32.0) as u32;
+        BloomFilter {
+            bits: vec![0; (bit_count as usize).div_ceil(64)],
+            hash_count,
+        }
+    }
+
+    /// Adds a hash to the filter
+    pub fn insert(&mut self, hash: &[u8]) {
+        for bit in self.bit_positions(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether the hash may have been inserted, `false` means it definitely was not
+    pub fn maybe_contains(&self, hash: &[u8]) -> bool {
+        self.bit_positions(hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// positions of the bits of a hash by double hashing, seeded with the first 16 bytes of the hash
+    fn bit_positions(&self, hash: &[u8]) -> impl Iterator<Item = usize> {
+        let mut seed = [0; 16];
+        let len = hash.len().min(16);
+        seed[..len].copy_from_slice(&hash[..len]);
+        let first = u64::from_le_bytes(seed[..8].try_into().expect("8 bytes"));
+        // odd, so that every step visits a different bit
+        let second = u64::from_le_bytes(seed[8..].try_into().expect("8 bytes")) | 1;
+        let bit_count = (self.bits.len() * 64) as u64;
+        (0..u64::from(self.hash_count)).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bit_count) as usize)
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Builds a Bloom filter over the leaves of this tree, enabling `maybe_contains`
+    pub fn with_bloom_filter(mut self, false_positive_rate: f64) -> MerkleTree<H> {
+        let mut bloom_filter = BloomFilter::new(self.leaf_count, false_positive_rate);
+        for leaf_hash in self.leaf_hashes() {
+            bloom_filter.insert(leaf_hash);
+        }
+        self.bloom_filter = Some(Arc::new(bloom_filter));
+        self
+    }
+
+    /// Whether the data may be a leaf of this tree, `false` means that proving it would fail
+    /// always `true` for trees built without `with_bloom_filter`
+    pub fn maybe_contains(&self, data: &Data) -> bool {
+        match &self.bloom_filter {
+            Some(bloom_filter) => bloom_filter.maybe_contains(&self.hasher.hash(data)),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_filter_has_no_false_negatives() {
+        let data = example_data(1000);
+        let tree = MerkleTree::construct(&data).with_bloom_filter(0.01);
+        assert!(data.iter().all(|leaf| tree.maybe_contains(leaf)));
+    }
+
+    #[test]
+    fn test_filter_rejects_most_non_members() {
+        let data = example_data(1000);
+        let tree = MerkleTree::construct(&data).with_bloom_filter(0.01);
+        let false_positives = (1000..11000_u32).filter(|i| tree.maybe_contains(&i.to_le_bytes().to_vec())).count();
+        // 1% of 10000 expected, with plenty of slack for variance
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn test_tree_without_filter_maybe_contains_everything() {
+        let tree = MerkleTree::construct(&example_data(4));
+        assert!(tree.maybe_contains(&vec![42]));
+    }
+}
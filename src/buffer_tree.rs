@@ -0,0 +1,114 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{node_count, Levels, Proof};
+
+/// Merkle tree whose hashes live in a buffer the caller hands in, e.g. a slab of a pool or a frame arena
+///
+/// `MerkleTree` keeps every hash in a single `Vec` from the global allocator. A `BufferTree` builds
+/// the very same levels into a slice the caller already owns, and borrows it for as long as the tree
+/// lives, so the memory of the tree comes from wherever the caller took the slice from. Every hash is
+/// written in place, see `Hasher::hash_into`, and only the small table of level starts, one entry per
+/// level, is allocated by the crate. The root and every proof are the ones `MerkleTree::construct` gives.
+pub struct BufferTree<'a, H: Hasher = Sha256Hasher> {
+    hasher: H,
+    levels: Levels<&'a mut [u8]>,
+}
+
+impl<'a> BufferTree<'a> {
+    /// Builds a SHA-256 tree inside `buffer`, see `build_with_hasher`
+    pub fn build<T: AsRef<[u8]>>(input: &[T], buffer: &'a mut [u8]) -> Option<BufferTree<'a>> {
+        BufferTree::build_with_hasher(input, buffer, Sha256Hasher::new())
+    }
+
+    /// Gets number of bytes a buffer needs to hold a tree over `leaf_count` leaves with `hash_size` byte hashes
+    pub fn buffer_len(leaf_count: usize, hash_size: usize) -> usize {
+        node_count(leaf_count).saturating_mul(hash_size)
+    }
+}
+
+impl<'a, H: Hasher> BufferTree<'a, H> {
+    /// Builds a tree over the given input data inside `buffer`, hashing with the given hash function
+    /// only the first `buffer_len` bytes of the buffer are written;
+    /// returns `None` when there is no data or the buffer is shorter than that
+    pub fn build_with_hasher<T: AsRef<[u8]>>(input: &[T], buffer: &'a mut [u8], hasher: H) -> Option<BufferTree<'a, H>> {
+        let digest_len = hasher.digest_len();
+        let needed = BufferTree::buffer_len(input.len(), digest_len);
+        if input.is_empty() || buffer.len() < needed {
+            return None;
+        }
+        let mut levels = Levels::in_buffer(&mut buffer[..needed], digest_len);
+        for (data, slot) in input.iter().zip(levels.leaves_mut(input.len()).chunks_exact_mut(digest_len)) {
+            hasher.hash_into(data.as_ref(), slot);
+        }
+        levels.build(input.len(), &hasher);
+        Some(BufferTree { hasher, levels })
+    }
+
+    /// Gets root hash for this tree
+    pub fn root(&self) -> &[u8] {
+        self.levels.hash(self.levels.count() - 1, 0)
+    }
+
+    /// Gets number of leaves in this tree
+    pub fn leaf_count(&self) -> u64 {
+        self.levels.len(0) as u64
+    }
+
+    /// Returns the proof for the first leaf holding the given data
+    pub fn prove(&self, data: &[u8]) -> Option<Proof<H>> {
+        let leaf = self.hasher.hash(data);
+        let index = self.levels.level(0).chunks_exact(leaf.len()).position(|leaf_hash| leaf_hash == leaf.as_slice())?;
+        Some(Proof::new(self.levels.path(index)))
+    }
+
+    /// Returns the proof for the leaf at `index`
+    pub fn prove_by_index(&self, index: u64) -> Option<Proof<H>> {
+        let index = usize::try_from(index).ok().filter(|index| *index < self.levels.len(0))?;
+        Some(Proof::new(self.levels.path(index)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::{Data, MerkleTree};
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_buffer_tree_matches_merkle_tree() {
+        // one slab shared by trees of every size, as a pool would hand it out
+        let mut slab = vec![0; BufferTree::buffer_len(100, 32)];
+        for n in [1, 2, 3, 7, 8, 100] {
+            let data = example_data(n);
+            let tree = MerkleTree::construct(&data);
+            let buffer_tree = BufferTree::build(&data, &mut slab).expect("buffer is large enough");
+            assert_eq!(buffer_tree.root(), tree.root().as_bytes());
+            assert_eq!(buffer_tree.leaf_count(), n as u64);
+            for (index, leaf) in data.iter().enumerate() {
+                let proof = buffer_tree.prove(leaf).expect("leaf is in the tree");
+                assert_eq!(proof.hashes, tree.prove_by_index(index as u64).expect("index is in range").hashes);
+                assert!(MerkleTree::verify_proof(leaf, &proof, &tree.root()));
+            }
+            assert!(buffer_tree.prove_by_index(n as u64).is_none());
+        }
+    }
+
+    #[test]
+    fn test_short_buffers_and_empty_input_will_return_none() {
+        let data = example_data(5);
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        let needed = BufferTree::buffer_len(5, 20);
+        assert_eq!(needed, (5 + 3 + 2 + 1) * 20);
+
+        let mut buffer = vec![0xff; needed + 7];
+        assert!(BufferTree::build_with_hasher(&data, &mut buffer[..needed - 1], hasher).is_none());
+        let root = BufferTree::build_with_hasher(&data, &mut buffer, hasher).expect("buffer is large enough").root().to_vec();
+        assert_eq!(root, MerkleTree::construct_with_hasher(&data, hasher).root());
+        // bytes past the tree are left alone
+        assert_eq!(buffer[needed..], [0xff; 7]);
+        assert!(BufferTree::build(&Vec::<Data>::new(), &mut buffer).is_none());
+    }
+}
@@ -0,0 +1,256 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{Data, MerkleTree, Proof};
+use crate::params::{TreeParams, PARAMS_LEN};
+use crate::root::Root;
+use crate::snapshot::header_params;
+
+/// bytes every proof bundle starts with
+pub const BUNDLE_MAGIC: [u8; 4] = *b"MRKB";
+/// version of the bundle format written by this crate
+pub const BUNDLE_VERSION: u8 = 2;
+/// version of the bundle format before it recorded every parameter of the tree, still read
+const LEGACY_BUNDLE_VERSION: u8 = 1;
+
+/// One proof in a bundle: data proven against the root of one tree
+#[derive(Debug)]
+pub struct BundleEntry<H: Hasher = Sha256Hasher> {
+    pub root: Root,
+    pub data: Data,
+    pub proof: Proof<H>,
+}
+
+/// Proofs against several independent trees, e.g. one per epoch, verified and shipped together
+///
+/// Every tree has to be built with the same hash function, which the bundle records once
+/// instead of once per proof. Entries keep the order they were pushed in.
+#[derive(Debug)]
+pub struct ProofBundle<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    entries: Vec<BundleEntry<H>>,
+}
+
+impl ProofBundle {
+    /// Starts an empty bundle of proofs against SHA-256 trees
+    pub fn new() -> ProofBundle {
+        ProofBundle::with_hasher(Sha256Hasher::new())
+    }
+
+    /// Decodes a bundle of proofs against SHA-256 trees produced by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Option<ProofBundle> {
+        ProofBundle::from_bytes_with_hasher(bytes, Sha256Hasher::new())
+    }
+}
+
+impl Default for ProofBundle {
+    fn default() -> Self {
+        ProofBundle::new()
+    }
+}
+
+impl<H: Hasher> ProofBundle<H> {
+    /// Starts an empty bundle of proofs against trees built with the given hash function
+    pub fn with_hasher(hasher: H) -> ProofBundle<H> {
+        ProofBundle { hasher, entries: vec![] }
+    }
+
+    /// Adds the proof that `data` is in the tree with the given root
+    pub fn push(&mut self, root: Root, data: Data, proof: Proof<H>) {
+        self.entries.push(BundleEntry { root, data, proof });
+    }
+
+    /// Gets the proofs in the order they were pushed
+    pub fn entries(&self) -> &[BundleEntry<H>] {
+        &self.entries
+    }
+
+    /// Gets number of proofs in the bundle
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the bundle holds no proofs
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Verifies every proof against its root, true only if all of them hold
+    pub fn verify(&self) -> bool {
+        self.invalid_entries().is_empty()
+    }
+
+    /// Gets the positions of the proofs that do not hold, to report which epochs failed
+    pub fn invalid_entries(&self) -> Vec<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !MerkleTree::verify_proof_with_hasher(&entry.data, &entry.proof, &entry.root, &self.hasher))
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    /// Serializes the bundle behind a header shared by all of its proofs
+    ///
+    /// The header holds the magic bytes, the format version, the parameters of the trees as `TreeParams`
+    /// writes them and the number of entries (`u32` little-endian).
+    /// Every entry follows as its root, then its data and its proof (as written by `Proof::to_untagged_bytes`),
+    /// each of the two prefixed by its length (`u32` little-endian).
+    /// returns `None` when the hash function has no multicodec code to record
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let mut bytes = BUNDLE_MAGIC.to_vec();
+        bytes.push(BUNDLE_VERSION);
+        bytes.extend_from_slice(&header_params(&self.hasher).ok()?.to_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for entry in &self.entries {
+            let proof = entry.proof.to_untagged_bytes();
+            bytes.extend_from_slice(entry.root.as_bytes());
+            bytes.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&entry.data);
+            bytes.extend_from_slice(&(proof.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&proof);
+        }
+        Some(bytes)
+    }
+
+    /// Decodes a bundle produced by `to_bytes` of proofs against trees built with the given hash function
+    /// returns `None` for bundles of any other tree parameters or version,
+    /// and when the bytes are truncated, hold a malformed proof or have trailing data
+    /// version 1 bundles, which record only the algorithm and hash length, are read as bundles of binary trees
+    pub fn from_bytes_with_hasher(bytes: &[u8], hasher: H) -> Option<ProofBundle<H>> {
+        let expected = TreeParams::binary(&hasher)?;
+        let (header, rest) = bytes.split_first_chunk::<5>()?;
+        if header[..4] != BUNDLE_MAGIC {
+            return None;
+        }
+        let rest = match header[4] {
+            BUNDLE_VERSION => {
+                let (params, rest) = rest.split_first_chunk::<PARAMS_LEN>()?;
+                expected.check(params).ok()?;
+                rest
+            }
+            LEGACY_BUNDLE_VERSION => {
+                let (legacy, rest) = rest.split_first_chunk::<9>()?;
+                let code = u64::from_le_bytes(legacy[..8].try_into().expect("8 bytes"));
+                (code == expected.algorithm && legacy[8] == expected.digest_len).then_some(rest)?
+            }
+            _ => return None,
+        };
+        let (count, rest) = rest.split_first_chunk::<4>()?;
+        let count = u32::from_le_bytes(*count) as usize;
+
+        let digest_len = hasher.digest_len();
+        // every entry takes at least its root and two lengths, which bounds the allocation for hostile counts
+        let mut entries = Vec::with_capacity(count.min(rest.len() / (digest_len + 8)));
+        let mut rest = rest;
+        for _ in 0..count {
+            if rest.len() < digest_len {
+                return None;
+            }
+            let (root, tail) = rest.split_at(digest_len);
+            let (data, tail) = split_length_prefixed(tail)?;
+            let (proof, tail) = split_length_prefixed(tail)?;
+            entries.push(BundleEntry {
+                root: Root::new(root.to_vec()),
+                data: data.to_vec(),
+                proof: Proof::from_untagged_bytes(proof)?,
+            });
+            rest = tail;
+        }
+        rest.is_empty().then_some(ProofBundle { hasher, entries })
+    }
+}
+
+/// splits off a field prefixed by its `u32` little-endian length
+fn split_length_prefixed(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = bytes.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Shake256Hasher;
+
+    /// one tree per epoch, each over different data
+    fn epochs(n: usize) -> Vec<(Vec<Data>, MerkleTree)> {
+        (0..n)
+            .map(|epoch| {
+                let data: Vec<Data> = (0..5).map(|i| vec![epoch as u8, i]).collect();
+                let tree = MerkleTree::construct(&data);
+                (data, tree)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bundle_verifies_proofs_against_every_root() {
+        let mut bundle = ProofBundle::new();
+        for (data, tree) in epochs(4) {
+            let proof = tree.prove(&data[3]).expect("this should return Proof");
+            bundle.push(tree.root(), data[3].clone(), proof);
+        }
+        assert_eq!(bundle.len(), 4);
+        assert!(bundle.verify());
+
+        // a proof against the wrong epoch's root is reported by its position
+        let epochs = epochs(2);
+        let proof = epochs[0].1.prove(&epochs[0].0[1]).expect("this should return Proof");
+        bundle.push(epochs[1].1.root(), epochs[0].0[1].clone(), proof);
+        assert!(!bundle.verify());
+        assert_eq!(bundle.invalid_entries(), vec![4]);
+    }
+
+    #[test]
+    fn test_bundle_bytes_round_trip() {
+        let mut bundle = ProofBundle::new();
+        for (data, tree) in epochs(3) {
+            let proof = tree.prove(&data[0]).expect("this should return Proof");
+            bundle.push(tree.root(), data[0].clone(), proof);
+        }
+        let bytes = bundle.to_bytes().expect("SHA-256 has a multicodec code");
+
+        let decoded = ProofBundle::from_bytes(&bytes).expect("this should decode ProofBundle");
+        assert_eq!(decoded.len(), 3);
+        assert!(decoded.verify());
+        for (entry, decoded) in bundle.entries().iter().zip(decoded.entries()) {
+            assert_eq!(entry.root, decoded.root);
+            assert_eq!(entry.data, decoded.data);
+            assert_eq!(entry.proof.to_bytes(), decoded.proof.to_bytes());
+        }
+        assert!(ProofBundle::new().to_bytes().and_then(|bytes| ProofBundle::from_bytes(&bytes)).is_some_and(|bundle| bundle.is_empty()));
+    }
+
+    #[test]
+    fn test_bundle_from_malformed_bytes_will_return_none() {
+        let (data, tree) = epochs(1).pop().expect("one epoch");
+        let mut bundle = ProofBundle::new();
+        bundle.push(tree.root(), data[2].clone(), tree.prove(&data[2]).expect("this should return Proof"));
+        let bytes = bundle.to_bytes().expect("SHA-256 has a multicodec code");
+
+        assert!(ProofBundle::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(ProofBundle::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_none());
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(ProofBundle::from_bytes(&bad_magic).is_none());
+        // a bundle of another hash function is refused rather than failing every proof
+        let shake = Shake256Hasher::new(32).expect("valid length");
+        assert!(ProofBundle::from_bytes_with_hasher(&bytes, shake).is_none());
+        let mut wider = bytes.clone();
+        wider[16] = 4;
+        assert!(ProofBundle::from_bytes(&wider).is_none());
+    }
+
+    #[test]
+    fn test_bundle_reads_version_1() {
+        let (data, tree) = epochs(1).pop().expect("one epoch");
+        let mut bundle = ProofBundle::new();
+        bundle.push(tree.root(), data[4].clone(), tree.prove(&data[4]).expect("this should return Proof"));
+        let bytes = bundle.to_bytes().expect("SHA-256 has a multicodec code");
+        // version 1 wrote the algorithm code and the hash length only
+        let legacy = [&BUNDLE_MAGIC[..], &[1], &bytes[5..13], &bytes[14..15], &bytes[20..]].concat();
+
+        let decoded = ProofBundle::from_bytes(&legacy).expect("this should decode ProofBundle");
+        assert_eq!(decoded.len(), 1);
+        assert!(decoded.verify());
+    }
+}
@@ -0,0 +1,214 @@
+use std::io::{self, Read};
+
+use crate::merkletree::{scrub, Data};
+
+/// Content-defined chunker in the style of FastCDC
+///
+/// Boundaries are placed where a rolling gear hash over the last bytes matches a mask, so they move
+/// together with the content: inserting or removing bytes only changes the chunks around the edit,
+/// instead of shifting every chunk, and therefore leaf, after it as fixed offsets would.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunker {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    /// stricter mask used below the average size, making small chunks less likely
+    mask_small: u64,
+    /// looser mask used above the average size, making large chunks less likely
+    mask_large: u64,
+}
+
+impl Default for Chunker {
+    /// 2 KiB minimum, 8 KiB average and 64 KiB maximum chunk size
+    fn default() -> Chunker {
+        Chunker::new(2 * 1024, 8 * 1024, 64 * 1024)
+    }
+}
+
+impl Chunker {
+    /// Creates a chunker producing chunks of `min_size..=max_size` bytes, `avg_size` on average
+    /// `avg_size` has to be a power of two and `min_size <= avg_size <= max_size`
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Chunker {
+        assert!(avg_size.is_power_of_two(), "average chunk size has to be a power of two");
+        assert!(0 < min_size && min_size <= avg_size && avg_size <= max_size, "chunk sizes have to be ordered");
+        let bits = avg_size.trailing_zeros();
+        Chunker {
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: high_bits_mask(bits + 1),
+            mask_large: high_bits_mask(bits.saturating_sub(1)),
+        }
+    }
+
+    /// Length of the chunk starting at the beginning of `data`
+    /// all of `data` is one chunk when it is not longer than `max_size` and has no boundary
+    pub fn cut_point(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let normal = end.min(self.avg_size);
+        let mut fingerprint = 0u64;
+        for (i, byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            fingerprint = (fingerprint << 1).wrapping_add(GEAR[usize::from(*byte)]);
+            let mask = if i < normal { self.mask_small } else { self.mask_large };
+            if fingerprint & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Iterates over the content-defined chunks of `data`
+    pub fn chunks<'a>(&self, data: &'a [u8]) -> Chunks<'a> {
+        Chunks { chunker: *self, data }
+    }
+
+    /// Splits `data` into content-defined chunks ready to be used as leaves
+    pub fn split(&self, data: &[u8]) -> Vec<Data> {
+        self.chunks(data).map(<[u8]>::to_vec).collect()
+    }
+
+    /// Splits everything read from `reader` into content-defined chunks
+    /// only up to `max_size` bytes beyond the chunks already cut are buffered at a time
+    pub fn split_reader(&self, mut reader: impl Read) -> io::Result<Vec<Data>> {
+        let mut chunks = vec![];
+        let mut buffer = Vec::with_capacity(self.max_size);
+        let mut eof = false;
+        loop {
+            while !eof && buffer.len() < self.max_size {
+                let filled = buffer.len();
+                buffer.resize(self.max_size, 0);
+                match reader.read(&mut buffer[filled..]) {
+                    Ok(read) => {
+                        buffer.truncate(filled + read);
+                        eof = read == 0;
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => buffer.truncate(filled),
+                    Err(error) => {
+                        scrub(&mut buffer);
+                        return Err(error);
+                    }
+                }
+            }
+            if buffer.is_empty() {
+                // drained chunks leave copies of their bytes in the spare capacity
+                scrub(&mut buffer);
+                return Ok(chunks);
+            }
+            let cut = self.cut_point(&buffer);
+            chunks.push(buffer.drain(..cut).collect());
+        }
+    }
+}
+
+/// Iterator over the content-defined chunks of a byte slice, created by `Chunker::chunks`
+pub struct Chunks<'a> {
+    chunker: Chunker,
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let (chunk, rest) = self.data.split_at(self.chunker.cut_point(self.data));
+        self.data = rest;
+        Some(chunk)
+    }
+}
+
+/// mask of the `bits` most significant bits, the ones that depend on the widest window of input
+const fn high_bits_mask(bits: u32) -> u64 {
+    if bits == 0 {
+        0
+    } else {
+        u64::MAX << (64 - bits)
+    }
+}
+
+/// random value per byte mixed into the gear hash, generated with splitmix64 from a fixed seed
+/// so that every build of the crate places boundaries identically
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x6d65_726b_6c65_7472u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::{hash_data, MerkleTree};
+
+    /// deterministic pseudo random content, chunking zeros would only ever hit the maximum size
+    fn example_content(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_respect_size_bounds_and_cover_content() {
+        let chunker = Chunker::new(256, 1024, 4096);
+        let content = example_content(100_000);
+        let chunks = chunker.split(&content);
+
+        assert_eq!(chunks.concat(), content);
+        let (last, rest) = chunks.split_last().expect("this should return chunks");
+        assert!(last.len() <= 4096);
+        for chunk in rest {
+            assert!((256..=4096).contains(&chunk.len()));
+        }
+    }
+
+    #[test]
+    fn test_insertion_only_changes_leaves_around_the_edit() {
+        let chunker = Chunker::new(256, 1024, 4096);
+        let content = example_content(100_000);
+        let mut edited = content.clone();
+        edited.splice(50_000..50_000, *b"inserted");
+
+        let leaves = chunker.split(&content).iter().map(hash_data).collect::<Vec<_>>();
+        let edited_leaves = chunker.split(&edited).iter().map(hash_data).collect::<Vec<_>>();
+        let changed = edited_leaves.iter().filter(|leaf| !leaves.contains(leaf)).count();
+        assert!(changed <= 2, "{changed} leaves changed");
+    }
+
+    #[test]
+    fn test_split_reader_matches_split() {
+        let chunker = Chunker::new(256, 1024, 4096);
+        let content = example_content(20_000);
+        // a reader that never fills the buffer in one go
+        let reader = content.chunks(100).fold(Box::new(io::empty()) as Box<dyn Read>, |reader, part| Box::new(reader.chain(part)));
+
+        let chunks = chunker.split_reader(reader).expect("this should read chunks");
+        assert_eq!(chunks, chunker.split(&content));
+        assert_eq!(MerkleTree::construct(&chunks).root(), MerkleTree::construct(&chunker.split(&content)).root());
+    }
+
+    #[test]
+    fn test_short_content_is_a_single_chunk() {
+        let chunker = Chunker::default();
+        assert_eq!(chunker.split(b"short"), vec![b"short".to_vec()]);
+        assert!(chunker.split(&[]).is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+use serde_json::{json, Value};
+
+use crate::hasher::Hasher;
+use crate::merkletree::{HashDirection, Proof};
+
+/// order of the scalar field of BN254, big-endian, the field circom compiles circuits over by default
+pub const BN254_MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d, 0x28, 0x33, 0xe8, 0x48, 0x79, 0xb9, 0x70, 0x91,
+    0x43, 0xe1, 0xf5, 0x93, 0xf0, 0x00, 0x00, 0x01,
+];
+
+/// Inputs of a fixed depth circom Merkle verifier for one inclusion proof
+///
+/// Follows the layout of templates like zk-kit's `BinaryMerkleRoot`: a sibling per level,
+/// a path index per level that is 1 where the proven node is the right child, and the actual depth.
+/// Levels above the depth are padded with zero siblings and zero indices, which such templates ignore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircomWitness {
+    /// number of levels the proof actually has
+    pub depth: usize,
+    /// sibling hashes from the leaf up as decimal integers (big-endian), padded to the circuit depth
+    pub siblings: Vec<String>,
+    /// 1 where the sibling goes on the left, padded to the circuit depth
+    pub indices: Vec<u8>,
+}
+
+impl CircomWitness {
+    /// Gets the witness as snarkjs input JSON, with every number as a decimal string
+    pub fn to_json(&self) -> Value {
+        json!({
+            "depth": self.depth.to_string(),
+            "siblings": self.siblings,
+            "indices": self.indices.iter().map(|index| index.to_string()).collect::<Vec<String>>(),
+        })
+    }
+}
+
+impl<H: Hasher> Proof<H> {
+    /// Converts the proof into the inputs of a circom verifier compiled for `circuit_depth` levels
+    /// returns `None` when the proof has more levels than the circuit, or a sibling is not below
+    /// `BN254_MODULUS`, as the circuit would silently reduce it to another field element
+    /// siblings are written at the full width of the hash, so trees have to be built with a hash function
+    /// whose output is an element of the field, e.g. SHA-256 truncated to 31 bytes
+    pub fn to_circom_witness(&self, circuit_depth: usize) -> Option<CircomWitness> {
+        if self.hashes.len() > circuit_depth || !self.hashes.iter().all(|(_, hash)| is_bn254_element(hash)) {
+            return None;
+        }
+        let mut siblings = Vec::with_capacity(circuit_depth);
+        let mut indices = Vec::with_capacity(circuit_depth);
+        for (hash_direction, hash) in &self.hashes {
+            siblings.push(to_decimal(hash));
+            indices.push(match hash_direction {
+                HashDirection::Left => 1,
+                HashDirection::Right => 0,
+            });
+        }
+        siblings.resize(circuit_depth, "0".to_string());
+        indices.resize(circuit_depth, 0);
+        Some(CircomWitness {
+            depth: self.hashes.len(),
+            siblings,
+            indices,
+        })
+    }
+}
+
+/// whether a big-endian unsigned integer of any length is below `BN254_MODULUS`
+fn is_bn254_element(bytes: &[u8]) -> bool {
+    let significant = &bytes[bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len())..];
+    let modulus = &BN254_MODULUS[BN254_MODULUS.iter().position(|byte| *byte != 0).expect("modulus is not zero")..];
+    significant.len() < modulus.len() || (significant.len() == modulus.len() && significant < modulus)
+}
+
+/// decimal digits of a big-endian unsigned integer of any length
+fn to_decimal(bytes: &[u8]) -> String {
+    let mut number = bytes.to_vec();
+    let mut digits = vec![];
+    // long division by ten until nothing is left, collecting the remainders
+    while number.iter().any(|byte| *byte != 0) {
+        let mut remainder = 0_u32;
+        for byte in number.iter_mut() {
+            let value = (remainder << 8) | u32::from(*byte);
+            *byte = (value / 10) as u8;
+            remainder = value % 10;
+        }
+        digits.push(b'0' + remainder as u8);
+    }
+    if digits.is_empty() {
+        digits.push(b'0');
+    }
+    digits.reverse();
+    String::from_utf8(digits).expect("ascii digits")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Sha256Hasher, Truncated};
+    use crate::merkletree::{Data, MerkleTree};
+
+    #[test]
+    fn test_to_decimal() {
+        assert_eq!(to_decimal(&[]), "0");
+        assert_eq!(to_decimal(&[0, 0]), "0");
+        assert_eq!(to_decimal(&[1, 0]), "256");
+        assert_eq!(to_decimal(&[0xff; 8]), u64::MAX.to_string());
+        assert_eq!(to_decimal(&[0xff; 16]), u128::MAX.to_string());
+    }
+
+    #[test]
+    fn test_witness_is_padded_to_circuit_depth() {
+        let data: Vec<Data> = (0..3).map(|i| vec![i]).collect();
+        let hasher = Truncated::new(Sha256Hasher::new(), 31).expect("valid length");
+        let tree = MerkleTree::construct_with_hasher(&data, hasher);
+        // the third leaf was promoted, so its only sibling is on the left
+        let proof = tree.prove(&data[2]).expect("this should return Proof");
+        let witness = proof.to_circom_witness(4).expect("proof fits the circuit");
+
+        let sibling = MerkleTree::construct_with_hasher(&data[..2], hasher).root().into_hash();
+        assert_eq!(witness.depth, 1);
+        assert_eq!(witness.siblings, vec![to_decimal(&sibling), "0".into(), "0".into(), "0".into()]);
+        assert_eq!(witness.indices, vec![1, 0, 0, 0]);
+        assert_eq!(witness.to_json()["indices"], json!(["1", "0", "0", "0"]));
+        assert_eq!(witness.to_json()["depth"], json!("1"));
+
+        let proof = tree.prove(&data[0]).expect("this should return Proof");
+        let witness = proof.to_circom_witness(2).expect("proof fits the circuit");
+        assert_eq!(witness.siblings[0], to_decimal(&hasher.hash(&data[1])));
+        assert_eq!(witness.indices, vec![0, 0]);
+        assert!(proof.to_circom_witness(1).is_none());
+    }
+
+    #[test]
+    fn test_witness_refuses_siblings_outside_the_field() {
+        let below: Vec<u8> = [&BN254_MODULUS[..31], &[0x00]].concat();
+        let proof: Proof = Proof::new(vec![(HashDirection::Right, below), (HashDirection::Left, vec![0xff; 16])]);
+        assert!(proof.to_circom_witness(2).is_some());
+
+        for outside in [BN254_MODULUS.to_vec(), vec![0xff; 32], [&[1][..], &[0; 32]].concat()] {
+            let proof: Proof = Proof::new(vec![(HashDirection::Right, outside)]);
+            assert!(proof.to_circom_witness(2).is_none());
+        }
+        // leading zero bytes don't count
+        let proof: Proof = Proof::new(vec![(HashDirection::Right, [&[0; 8][..], &[0xff; 16]].concat())]);
+        assert!(proof.to_circom_witness(1).is_some());
+    }
+}
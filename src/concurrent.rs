@@ -0,0 +1,172 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::frontier::Frontier;
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::MerkleTree;
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+
+/// Append-only tree many threads push leaves into at once, split into shards that each have their own lock
+///
+/// Every shard is a `Frontier` of the leaves pushed into it, in the order they were pushed. Leaves are
+/// hashed before any lock is taken, and a writer that keeps to its own shard with `push_to` never waits
+/// for another writer. The root is the tree over the commitments of the shards in shard order, each the hash
+/// of the shard's leaf count (`u64` little-endian) followed by its root, which an empty shard has none of, so
+/// no shard can pass for another of a different size. Reading it locks every shard for as long as it takes
+/// to copy its peaks.
+pub struct ShardedFrontier<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    shards: Vec<Mutex<Frontier<H>>>,
+    /// shard the next `push` goes to, modulo the number of shards
+    next_shard: AtomicUsize,
+}
+
+/// Root over all shards of a `ShardedFrontier` at one point in time, with the number of leaves in each shard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardedHead {
+    pub root: Root,
+    pub shard_sizes: Vec<u64>,
+}
+
+impl ShardedFrontier {
+    /// Starts a SHA-256 tree of `shards` empty shards, see `with_hasher`
+    pub fn new(shards: usize) -> ShardedFrontier {
+        ShardedFrontier::with_hasher(shards, Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> ShardedFrontier<H> {
+    /// Starts a tree of `shards` empty shards, hashing with the given hash function
+    ///
+    /// # Panics
+    ///
+    /// When there are no shards.
+    pub fn with_hasher(shards: usize, hasher: H) -> ShardedFrontier<H> {
+        assert!(shards > 0, "at least one shard has to take leaves");
+        ShardedFrontier {
+            shards: (0..shards).map(|_| Mutex::new(Frontier::with_hasher(hasher.clone()))).collect(),
+            hasher,
+            next_shard: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Gets number of leaves pushed so far across all shards
+    pub fn leaf_count(&self) -> u64 {
+        (0..self.shards.len()).map(|shard| self.lock(shard).leaf_count()).sum()
+    }
+
+    /// Hashes and pushes a leaf into the next shard in turn, returning the shard it went to
+    pub fn push(&self, data: &[u8]) -> usize {
+        let shard = self.next_shard.fetch_add(1, Ordering::Relaxed) % self.shards.len();
+        self.push_to(shard, data);
+        shard
+    }
+
+    /// Hashes and pushes a leaf into the given shard, e.g. the one a writer thread owns
+    ///
+    /// # Panics
+    ///
+    /// When the shard is not below the shard count.
+    pub fn push_to(&self, shard: usize, data: &[u8]) {
+        let leaf_hash = LeafHash::of(data, &self.hasher);
+        self.lock(shard).push_hash(leaf_hash);
+    }
+
+    /// Gets the root over every leaf pushed into the shards before the call
+    /// every shard is locked at once, so no push is counted in one shard's size but missing from the root
+    pub fn head(&self) -> ShardedHead {
+        let shards: Vec<MutexGuard<Frontier<H>>> = (0..self.shards.len()).map(|shard| self.lock(shard)).collect();
+        let shard_sizes = shards.iter().map(|frontier| frontier.leaf_count()).collect();
+        let frontiers: Vec<Frontier<H>> = shards.iter().map(|frontier| (**frontier).clone()).collect();
+        drop(shards);
+
+        // the roots are hashed with the locks released, and then committed to as the leaves of the tree over the shards
+        let commitments = frontiers.iter().map(|frontier| shard_commitment(frontier.leaf_count(), frontier.root(), &self.hasher)).collect();
+        let root = MerkleTree::from_leaf_hashes_with_hasher(commitments, self.hasher.clone()).root();
+        ShardedHead { root, shard_sizes }
+    }
+
+    fn lock(&self, shard: usize) -> MutexGuard<'_, Frontier<H>> {
+        // a writer panicking while pushing leaves its shard as it was before or after the push
+        self.shards[shard].lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// leaf of the tree over the shards committing to a shard of `size` leaves with the given root
+fn shard_commitment(size: u64, root: Option<Root>, hasher: &impl Hasher) -> LeafHash {
+    let root = root.map(Root::into_hash).unwrap_or_default();
+    LeafHash::new(hasher.hash_concat(&size.to_le_bytes(), &root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn shard_data(shard: usize, n: usize) -> Vec<Data> {
+        (0..n).map(|i| format!("shard {shard} leaf {i}").into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_writers_on_their_own_shards() {
+        let tree = ShardedFrontier::new(4);
+        std::thread::scope(|scope| {
+            // the last shard gets no writer and stays empty
+            for shard in 0..3 {
+                let tree = &tree;
+                scope.spawn(move || {
+                    for leaf in shard_data(shard, 100 + shard) {
+                        tree.push_to(shard, &leaf);
+                    }
+                });
+            }
+        });
+
+        let head = tree.head();
+        assert_eq!(head.shard_sizes, vec![100, 101, 102, 0]);
+        assert_eq!(tree.leaf_count(), 303);
+        let hasher = Sha256Hasher::new();
+        let mut commitments: Vec<LeafHash> = (0..3)
+            .map(|shard| {
+                let root = MerkleTree::construct(&shard_data(shard, 100 + shard)).root().into_hash();
+                LeafHash::new(hasher.hash(&[&(100 + shard as u64).to_le_bytes()[..], &root].concat()))
+            })
+            .collect();
+        commitments.push(LeafHash::new(hasher.hash(&0u64.to_le_bytes())));
+        assert_eq!(head.root, MerkleTree::from_leaf_hashes(commitments).root());
+    }
+
+    #[test]
+    fn test_empty_shards_differ_from_shards_of_an_empty_leaf() {
+        let (empty, holding_empty_leaf) = (ShardedFrontier::new(2), ShardedFrontier::new(2));
+        empty.push_to(0, b"leaf");
+        holding_empty_leaf.push_to(0, b"leaf");
+        holding_empty_leaf.push_to(1, b"");
+        assert_ne!(empty.head().root, holding_empty_leaf.head().root);
+    }
+
+    #[test]
+    fn test_pushes_are_spread_over_shards() {
+        let tree = ShardedFrontier::new(3);
+        std::thread::scope(|scope| {
+            for writer in 0..8 {
+                let tree = &tree;
+                scope.spawn(move || {
+                    for leaf in shard_data(writer, 50) {
+                        tree.push(&leaf);
+                    }
+                });
+            }
+        });
+        let head = tree.head();
+        assert_eq!(head.shard_sizes.iter().sum::<u64>(), 400);
+        assert!(head.shard_sizes.iter().all(|size| (133..=134).contains(size)));
+        assert_eq!(tree.head(), head);
+    }
+}
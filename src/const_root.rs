@@ -0,0 +1,162 @@
+/// SHA-256 round constants
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 initial hash value
+const H0: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+/// byte `index` of the padded SHA-256 message of `left` followed by `right`
+/// the message is never materialized, every block is read out of it on the fly
+const fn padded_byte(left: &[u8], right: &[u8], index: usize) -> u8 {
+    let len = left.len() + right.len();
+    let padded_len = (len + 9).div_ceil(64) * 64;
+    if index < left.len() {
+        left[index]
+    } else if index < len {
+        right[index - left.len()]
+    } else if index == len {
+        0x80
+    } else if index >= padded_len - 8 {
+        let bit_len = (len as u64) * 8;
+        (bit_len >> (8 * (padded_len - 1 - index))) as u8
+    } else {
+        0
+    }
+}
+
+/// SHA-256 of `left` followed by `right`, usable in constant expressions
+const fn sha256_concat(left: &[u8], right: &[u8]) -> [u8; 32] {
+    let len = left.len() + right.len();
+    let blocks = (len + 9).div_ceil(64);
+    let mut state = H0;
+    let mut block = 0;
+    while block < blocks {
+        let mut w = [0u32; 64];
+        let mut t = 0;
+        while t < 16 {
+            let offset = block * 64 + t * 4;
+            w[t] = u32::from_be_bytes([
+                padded_byte(left, right, offset),
+                padded_byte(left, right, offset + 1),
+                padded_byte(left, right, offset + 2),
+                padded_byte(left, right, offset + 3),
+            ]);
+            t += 1;
+        }
+        while t < 64 {
+            let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+            let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+            w[t] = w[t - 16].wrapping_add(s0).wrapping_add(w[t - 7]).wrapping_add(s1);
+            t += 1;
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        t = 0;
+        while t < 64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[t]).wrapping_add(w[t]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+            t += 1;
+        }
+        let round = [a, b, c, d, e, f, g, h];
+        let mut i = 0;
+        while i < 8 {
+            state[i] = state[i].wrapping_add(round[i]);
+            i += 1;
+        }
+        block += 1;
+    }
+
+    let mut digest = [0u8; 32];
+    let mut i = 0;
+    while i < 32 {
+        digest[i] = state[i / 4].to_be_bytes()[i % 4];
+        i += 1;
+    }
+    digest
+}
+
+/// SHA-256 of `data`, usable in constant expressions
+pub const fn sha256(data: &[u8]) -> [u8; 32] {
+    sha256_concat(data, &[])
+}
+
+/// Root of the tree `MerkleTree::construct` builds over `leaves`, usable in constant expressions
+///
+/// Splitting the leaves the way construction pairs them up needs no storage for the levels,
+/// which is what makes it possible to evaluate at compile time. Panics, or fails compilation, without leaves.
+pub const fn merkle_root(leaves: &[&[u8]]) -> [u8; 32] {
+    assert!(!leaves.is_empty(), "a tree needs at least one leaf");
+    if leaves.len() == 1 {
+        return sha256(leaves[0]);
+    }
+    // largest power of two strictly smaller than the leaf count goes to the left
+    let (left, right) = leaves.split_at(1 << (usize::BITS - 1 - (leaves.len() - 1).leading_zeros()));
+    sha256_concat(&merkle_root(left), &merkle_root(right))
+}
+
+/// Computes the Merkle root of the given leaves at compile time, as a `[u8; 32]` constant
+/// leaves are byte arrays or byte string literals, slices can be passed to `merkle_root` directly
+///
+/// ```
+/// const EXPECTED_ROOT: [u8; 32] = merkle_tree::merkle_root!(b"firmware", b"config", [0x01, 0x02]);
+/// ```
+#[macro_export]
+macro_rules! merkle_root {
+    ($($leaf:expr),+ $(,)?) => {{
+        const ROOT: [u8; 32] = $crate::const_root::merkle_root(&[$($leaf.as_slice()),+]);
+        ROOT
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::{hash_data, Data, MerkleTree};
+
+    #[test]
+    fn test_sha256_matches_runtime_digest() {
+        for len in [0, 1, 55, 56, 63, 64, 65, 119, 120, 200] {
+            let data = (0..len).map(|i| i as u8).collect::<Data>();
+            assert_eq!(sha256(&data).to_vec(), hash_data(&data));
+        }
+    }
+
+    #[test]
+    fn test_merkle_root_matches_constructed_tree() {
+        let data = (0..9).map(|i| vec![i as u8]).collect::<Vec<Data>>();
+        for n in 1..=data.len() {
+            let leaves = data[..n].iter().map(Vec::as_slice).collect::<Vec<_>>();
+            assert_eq!(merkle_root(&leaves).to_vec(), MerkleTree::construct(&data[..n]).root());
+        }
+    }
+
+    #[test]
+    fn test_merkle_root_macro_is_a_constant() {
+        const ROOT: [u8; 32] = merkle_root!([0], [1], [2]);
+        assert_eq!(
+            hex::encode(ROOT),
+            "773a93ac37ea78b3f14ac31872c83886b0a0f1fec562c4e848e023c889c2ce9f"
+        );
+        assert_eq!(merkle_root!(b"firmware", b"config"), merkle_root(&[&b"firmware"[..], &b"config"[..]]));
+    }
+}
@@ -0,0 +1,166 @@
+use std::io;
+
+use crate::disk_tree::DiskTree;
+use crate::hasher::Hasher;
+use crate::merkletree::{Hash, MerkleTree};
+use crate::root::Root;
+
+/// Position at which a stored tree departs from the tree a trusted root stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    /// the node at `index` on `level` holds the wrong hash, while its children are the trusted ones
+    Node { level: usize, index: u64 },
+    /// the children of the node at `index` on `level` don't hash to the trusted hash of the node,
+    /// so at least one of them, or something below them, is corrupted; nothing below is searched
+    Children { level: usize, index: u64 },
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Finds the nodes that disagree with a root known to be good, e.g. one published when the tree was built
+    /// see `locate_corruption` of `DiskTree`
+    pub fn locate_corruption(&self, trusted_root: &Root) -> Vec<Corruption> {
+        let level_sizes: Vec<u64> = (0..self.levels.count()).map(|level| self.levels.len(level) as u64).collect();
+        let found: io::Result<_> = locate(&level_sizes, trusted_root, &self.hasher, |level, index| Ok(self.levels.hash(level, index as usize).to_vec()));
+        found.expect("reading from memory can't fail")
+    }
+}
+
+impl<H: Hasher> DiskTree<H> {
+    /// Finds the nodes of the level files that disagree with a root known to be good
+    ///
+    /// Starting at the root, the children of a node whose hash is trusted are trusted too when they hash
+    /// to it, and are searched in turn. When they don't, the child whose own children give the hash that
+    /// makes the pair match is reported as the corrupted `Node`, and otherwise the parent as `Children`,
+    /// which ends the search below it. Only hashes are read, never the leaf data, so a corrupted leaf,
+    /// which has no children to be checked against, shows up as the `Children` of its parent. Every hash the
+    /// root vouches for is read, as a flipped leaf under sound nodes can't be seen from above, but no hash
+    /// is hashed twice, and nothing below a mismatch that can't be pinned down is read at all.
+    /// Corruptions are sorted from the root down, and left to right on each level.
+    pub fn locate_corruption(&self, trusted_root: &Root) -> io::Result<Vec<Corruption>> {
+        locate(self.level_sizes(), trusted_root, &self.hasher, |level, index| self.read_hash(level, index))
+    }
+}
+
+/// searches a tree of the given level sizes top down, reading stored hashes with `read`
+fn locate<H: Hasher>(level_sizes: &[u64], trusted_root: &Root, hasher: &H, mut read: impl FnMut(usize, u64) -> io::Result<Hash>) -> io::Result<Vec<Corruption>> {
+    let mut found = vec![];
+    // nodes whose hash is trusted, with that hash and the stored one when it was already read
+    let mut pending = vec![(level_sizes.len() - 1, 0u64, trusted_root.as_bytes().to_vec(), None)];
+    while let Some((level, index, trusted, stored)) = pending.pop() {
+        let stored = match stored {
+            Some(stored) => stored,
+            None => read(level, index)?,
+        };
+        if level == 0 {
+            if stored != trusted {
+                found.push(Corruption::Node { level, index });
+            }
+            continue;
+        }
+        let (left, right) = (2 * index, 2 * index + 1);
+        if right >= level_sizes[level - 1] {
+            // the odd node out is promoted, and has to be the very hash of its parent
+            if stored != trusted {
+                found.push(Corruption::Node { level, index });
+            }
+            pending.push((level - 1, left, trusted, None));
+            continue;
+        }
+        let (mut left_hash, mut right_hash) = (read(level - 1, left)?, read(level - 1, right)?);
+        let (mut left_stored, mut right_stored) = (Some(left_hash.clone()), Some(right_hash.clone()));
+        if hasher.hash_concat(&left_hash, &right_hash) != trusted {
+            // a single corrupted child is the one whose own children hash to what makes the pair match,
+            // and is reported once it is searched with that as its trusted hash
+            let left_recomputed = recompute(level_sizes, level - 1, left, hasher, &mut read)?;
+            let right_recomputed = recompute(level_sizes, level - 1, right, hasher, &mut read)?;
+            if let Some(recomputed) = left_recomputed.filter(|recomputed| hasher.hash_concat(recomputed, &right_hash) == trusted) {
+                (left_hash, left_stored) = (recomputed, None);
+            } else if let Some(recomputed) = right_recomputed.filter(|recomputed| hasher.hash_concat(&left_hash, recomputed) == trusted) {
+                (right_hash, right_stored) = (recomputed, None);
+            } else {
+                found.push(Corruption::Children { level, index });
+                continue;
+            }
+        }
+        if stored != trusted {
+            found.push(Corruption::Node { level, index });
+        }
+        // right child first, so that the left one is searched first
+        pending.push((level - 1, right, right_hash, right_stored));
+        pending.push((level - 1, left, left_hash, left_stored));
+    }
+    found.sort_by_key(|corruption| match *corruption {
+        Corruption::Node { level, index } | Corruption::Children { level, index } => (std::cmp::Reverse(level), index),
+    });
+    Ok(found)
+}
+
+/// hash of the node at `index` on `level` as its stored children give it, `None` for leaves
+fn recompute<H: Hasher>(level_sizes: &[u64], level: usize, index: u64, hasher: &H, read: &mut impl FnMut(usize, u64) -> io::Result<Hash>) -> io::Result<Option<Hash>> {
+    if level == 0 {
+        return Ok(None);
+    }
+    let (left, right) = (2 * index, 2 * index + 1);
+    if right >= level_sizes[level - 1] {
+        return read(level - 1, left).map(Some);
+    }
+    Ok(Some(hasher.hash_concat(&read(level - 1, left)?, &read(level - 1, right)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_corrupted_nodes_and_leaves_are_located() {
+        let data = example_data(13);
+        let trusted = MerkleTree::construct(&data).root();
+        assert!(MerkleTree::construct(&data).locate_corruption(&trusted).is_empty());
+
+        let mut tree = MerkleTree::construct(&data);
+        Arc::make_mut(&mut tree.levels).hash_mut(2, 1)[0] ^= 1;
+        Arc::make_mut(&mut tree.levels).hash_mut(0, 12)[5] ^= 1;
+        Arc::make_mut(&mut tree.levels).hash_mut(0, 4)[5] ^= 1;
+        assert_eq!(
+            tree.locate_corruption(&trusted),
+            vec![
+                Corruption::Node { level: 2, index: 1 },
+                Corruption::Children { level: 1, index: 2 },
+                // the last leaf is promoted up to level 2, so it is compared against the trusted hash itself
+                Corruption::Node { level: 0, index: 12 },
+            ]
+        );
+
+        // a tree of other leaves is wrong from the root down
+        let other = MerkleTree::construct(&example_data(12));
+        assert_eq!(other.locate_corruption(&trusted), vec![Corruption::Children { level: 4, index: 0 }]);
+    }
+
+    #[test]
+    fn test_corruption_in_level_files_is_located() {
+        use std::fs::OpenOptions;
+        use std::io::{Seek, SeekFrom, Write};
+
+        let dir = std::env::temp_dir().join(format!("merkle-corruption-{}", std::process::id()));
+        let data = example_data(100);
+        let disk_tree = DiskTree::build(&data, &dir, 0).expect("writes levels").expect("has leaves");
+        let trusted = disk_tree.root().clone();
+        assert!(disk_tree.locate_corruption(&trusted).expect("reads levels").is_empty());
+
+        // zero the node at index 3 of level 3
+        let mut file = OpenOptions::new().write(true).open(dir.join("level-3")).expect("opens level");
+        file.seek(SeekFrom::Start(3 * 32)).expect("seeks");
+        file.write_all(&[0; 32]).expect("writes");
+        drop(file);
+        assert_eq!(disk_tree.locate_corruption(&trusted).expect("reads levels"), vec![Corruption::Node { level: 3, index: 3 }]);
+        drop(disk_tree);
+        std::fs::remove_dir_all(dir).expect("removes directory");
+    }
+}
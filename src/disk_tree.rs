@@ -0,0 +1,331 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{Hash, HashDirection, Proof};
+use crate::metrics::{self, MetricsEvent};
+use crate::root::Root;
+
+/// smallest buffer each open level file gets, whatever the budget
+const MIN_BUFFER: usize = 4096;
+
+/// Merkle tree whose levels live in files instead of memory, for more leaves than memory holds
+///
+/// Leaf hashes are written to the first level file as they come in, and each level is then
+/// streamed back, paired up and written out as the next, until only the root remains. At most two
+/// level files are open at a time, each through a buffer of half the memory budget, so building
+/// takes the same memory for three billion leaves as for three thousand. The root and every proof
+/// are the ones `MerkleTree::construct` gives over the same leaves.
+///
+/// Once built, the tree keeps every level file open, one per level, and reads hashes at their offset
+/// without moving a shared cursor, so any number of threads can prove from the same tree at once.
+/// The level files are removed once the tree is dropped.
+pub struct DiskTree<H: Hasher = Sha256Hasher> {
+    pub(crate) hasher: H,
+    dir: PathBuf,
+    /// number of hashes in each level, the leaves first
+    level_sizes: Vec<u64>,
+    /// the level files opened for reading, the leaves first
+    files: Vec<LevelFile>,
+    root: Root,
+}
+
+impl DiskTree {
+    /// Builds a SHA-256 tree, see `build_with_hasher`
+    pub fn build<I>(leaves: I, dir: impl AsRef<Path>, memory_budget: usize) -> io::Result<Option<DiskTree>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        DiskTree::build_with_hasher(leaves, dir, memory_budget, Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> DiskTree<H> {
+    /// Builds the tree over the given leaves with its level files in `dir`, which is created if missing
+    /// buffers take about `memory_budget` bytes in total; `Ok(None)` when there are no leaves
+    pub fn build_with_hasher<I>(leaves: I, dir: impl AsRef<Path>, memory_budget: usize, hasher: H) -> io::Result<Option<DiskTree<H>>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        fs::create_dir_all(dir.as_ref())?;
+        let buffer = (memory_budget / 2).max(MIN_BUFFER);
+        let mut tree = DiskTree {
+            hasher,
+            dir: dir.as_ref().to_path_buf(),
+            level_sizes: vec![],
+            files: vec![],
+            root: Root::new(vec![]),
+        };
+
+        let mut writer = BufWriter::with_capacity(buffer, File::create(tree.level_path(0))?);
+        let mut leaf_count = 0;
+        for leaf in leaves {
+            writer.write_all(&tree.hasher.hash(leaf.as_ref()))?;
+            leaf_count += 1;
+        }
+        writer.flush()?;
+        tree.level_sizes.push(leaf_count);
+        if leaf_count == 0 {
+            return Ok(None);
+        }
+
+        while let Some(&size) = tree.level_sizes.last().filter(|size| **size > 1) {
+            let level = tree.level_sizes.len() - 1;
+            let mut reader = BufReader::with_capacity(buffer, File::open(tree.level_path(level))?);
+            let mut writer = BufWriter::with_capacity(buffer, File::create(tree.level_path(level + 1))?);
+            let (mut left, mut right) = (vec![0; tree.hasher.digest_len()], vec![0; tree.hasher.digest_len()]);
+            for _ in 0..size / 2 {
+                reader.read_exact(&mut left)?;
+                reader.read_exact(&mut right)?;
+                writer.write_all(&tree.hasher.hash_concat(&left, &right))?;
+            }
+            // odd node out is promoted to the next level, as `MerkleTree` does
+            if size % 2 == 1 {
+                reader.read_exact(&mut left)?;
+                writer.write_all(&left)?;
+            }
+            writer.flush()?;
+            tree.level_sizes.push(size.div_ceil(2));
+        }
+        tree.files = (0..tree.level_sizes.len()).map(|level| open_level(&tree.level_path(level))).collect::<io::Result<_>>()?;
+        tree.root = Root::new(tree.read_hash(tree.level_sizes.len() - 1, 0)?);
+        Ok(Some(tree))
+    }
+
+    /// Gets root hash for this tree
+    pub fn root(&self) -> &Root {
+        &self.root
+    }
+
+    /// Gets number of leaves the tree was built from
+    pub fn leaf_count(&self) -> u64 {
+        self.level_sizes[0]
+    }
+
+    /// Returns the proof for the leaf at `index`, reading one sibling from each level file
+    /// `Ok(None)` when the index is not below the leaf count
+    ///
+    /// The position of every sibling follows from the index alone, so the reads are all issued at once,
+    /// one thread per level, and a proof takes about as long as the slowest read instead of the sum of them.
+    /// Targets without positional reads read the siblings one after another.
+    pub fn prove_by_index(&self, index: u64) -> io::Result<Option<Proof<H>>> {
+        if index >= self.leaf_count() {
+            return Ok(None);
+        }
+        let siblings = self.sibling_positions(index);
+        // only the open files are shared with the readers, the hasher need not be `Sync`
+        let (files, digest_len) = (&self.files, self.hasher.digest_len());
+        let read = move |&(direction, level, sibling): &(HashDirection, usize, u64)| read_hash_at(&files[level], sibling, digest_len).map(|hash| (direction, hash));
+        let hashes = if cfg!(any(unix, windows)) {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = siblings.iter().map(|sibling| scope.spawn(move || read(sibling))).collect();
+                handles.into_iter().map(|handle| handle.join().expect("reading doesn't panic")).collect::<io::Result<Vec<_>>>()
+            })?
+        } else {
+            // without positional reads every read takes the same lock, and some of these targets have no threads
+            siblings.iter().map(read).collect::<io::Result<Vec<_>>>()?
+        };
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Ok(Some(Proof::new(hashes)))
+    }
+
+    /// side, level and position of every sibling of the leaf at `index`, from the leaf up
+    fn sibling_positions(&self, index: u64) -> Vec<(HashDirection, usize, u64)> {
+        let mut siblings = vec![];
+        let mut position = index;
+        for (level, size) in self.level_sizes.iter().enumerate() {
+            let sibling = position ^ 1;
+            // the odd node out has no sibling on this level
+            if sibling < *size {
+                let direction = if position.is_multiple_of(2) { HashDirection::Right } else { HashDirection::Left };
+                siblings.push((direction, level, sibling));
+            }
+            position /= 2;
+        }
+        siblings
+    }
+
+    fn level_path(&self, level: usize) -> PathBuf {
+        self.dir.join(format!("level-{level}"))
+    }
+
+    /// Gets number of hashes in each level, the leaves first
+    pub(crate) fn level_sizes(&self) -> &[u64] {
+        &self.level_sizes
+    }
+
+    pub(crate) fn read_hash(&self, level: usize, position: u64) -> io::Result<Hash> {
+        read_hash_at(&self.files[level], position, self.hasher.digest_len())
+    }
+}
+
+/// level file open for reading at any offset, from any number of threads at once
+/// targets without positional reads share one cursor behind a lock instead
+#[cfg(any(unix, windows))]
+type LevelFile = File;
+#[cfg(not(any(unix, windows)))]
+type LevelFile = std::sync::Mutex<File>;
+
+#[cfg(any(unix, windows))]
+fn open_level(path: &Path) -> io::Result<LevelFile> {
+    File::open(path)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn open_level(path: &Path) -> io::Result<LevelFile> {
+    File::open(path).map(std::sync::Mutex::new)
+}
+
+/// reads the hash at `position` of a level file
+fn read_hash_at(file: &LevelFile, position: u64, digest_len: usize) -> io::Result<Hash> {
+    let mut hash = vec![0; digest_len];
+    read_exact_at(file, &mut hash, position * digest_len as u64)?;
+    Ok(hash)
+}
+
+/// fills `buf` from the file at `offset`, leaving the cursor of the file where it is
+#[cfg(unix)]
+fn read_exact_at(file: &LevelFile, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// fills `buf` from the file at `offset`, moving the cursor of the file, which no read depends on
+#[cfg(windows)]
+fn read_exact_at(file: &LevelFile, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buf, offset)? {
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            read => {
+                buf = &mut buf[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// fills `buf` from the file at `offset`, seeking the shared cursor while holding the lock
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(file: &LevelFile, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+
+    // a reader panicking while holding the lock leaves nothing behind but the cursor, which is set anew
+    let mut file = file.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+impl<H: Hasher> Drop for DiskTree<H> {
+    fn drop(&mut self) {
+        // the files are closed first, as some systems don't remove files that are still open
+        self.files.clear();
+        for level in 0..self.level_sizes.len() {
+            let _ = fs::remove_file(self.level_path(level));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::{Data, MerkleTree};
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    fn example_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("merkle-disk-tree-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_disk_tree_matches_merkle_tree() {
+        let dir = example_dir("matches");
+        for n in [1, 2, 7, 64, 1000] {
+            let data = example_data(n);
+            let tree = MerkleTree::construct(&data);
+            let disk_tree = DiskTree::build(&data, &dir, 0).expect("writes levels").expect("has leaves");
+            assert_eq!(disk_tree.root(), &tree.root());
+            assert_eq!(disk_tree.leaf_count(), n as u64);
+            for index in [0, n / 3, n - 1] {
+                let proof = disk_tree.prove_by_index(index as u64).expect("reads levels").expect("index is in range");
+                assert_eq!(proof.hashes, tree.prove_by_index(index as u64).expect("index is in range").hashes);
+                assert!(MerkleTree::verify_proof(&data[index], &proof, &tree.root()));
+            }
+            assert!(disk_tree.prove_by_index(n as u64).expect("reads nothing").is_none());
+        }
+        assert!(DiskTree::build(Vec::<Data>::new(), &dir, 0).expect("writes levels").is_none());
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_disk_tree_with_hasher_removes_its_levels() {
+        let dir = example_dir("hasher");
+        let data = example_data(33);
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        let disk_tree = DiskTree::build_with_hasher(&data, &dir, 1 << 16, hasher).expect("writes levels").expect("has leaves");
+        assert_eq!(disk_tree.root(), &MerkleTree::construct_with_hasher(&data, hasher).root());
+        assert_eq!(fs::metadata(dir.join("level-0")).expect("level exists").len(), 33 * 20);
+
+        drop(disk_tree);
+        assert_eq!(fs::read_dir(&dir).expect("lists directory").count(), 0);
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_threads_prove_from_the_same_tree_at_once() {
+        let dir = example_dir("threads");
+        let data = example_data(300);
+        let tree = MerkleTree::construct(&data);
+        let disk_tree = DiskTree::build(&data, &dir, 0).expect("writes levels").expect("has leaves");
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let (data, tree, disk_tree) = (&data, &tree, &disk_tree);
+                scope.spawn(move || {
+                    // every thread walks the leaves in a different order, so the reads interleave
+                    for index in (0..300).map(|i| (i * 7 + thread * 37) % 300) {
+                        let proof = disk_tree.prove_by_index(index).expect("reads levels").expect("index is in range");
+                        assert_eq!(proof.hashes, tree.prove_by_index(index).expect("index is in range").hashes);
+                        assert!(MerkleTree::verify_proof(&data[index as usize], &proof, &tree.root()));
+                    }
+                });
+            }
+        });
+        drop(disk_tree);
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_siblings_are_read_at_their_positions() {
+        let dir = example_dir("positions");
+        let data = example_data(11);
+        let disk_tree = DiskTree::build(&data, &dir, 0).expect("writes levels").expect("has leaves");
+        // levels of 11, 6, 3, 2 and 1 hashes; leaf 9 has no sibling on level 2, where it is the odd node out
+        let expected = vec![(HashDirection::Left, 0, 8), (HashDirection::Right, 1, 5), (HashDirection::Left, 3, 0)];
+        assert_eq!(disk_tree.sibling_positions(9), expected);
+        assert_eq!(disk_tree.sibling_positions(0), vec![(HashDirection::Right, 0, 1), (HashDirection::Right, 1, 1), (HashDirection::Right, 2, 1), (HashDirection::Right, 3, 1)]);
+
+        let proof = disk_tree.prove_by_index(9).expect("reads levels").expect("index is in range");
+        let read: Vec<(HashDirection, Hash)> = expected.iter().map(|&(direction, level, sibling)| (direction, disk_tree.read_hash(level, sibling).expect("reads level"))).collect();
+        assert_eq!(proof.hashes, read);
+        assert_eq!(proof.hashes, MerkleTree::construct(&data).prove_by_index(9).expect("index is in range").hashes);
+        drop(disk_tree);
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_failed_sibling_reads_fail_the_proof() {
+        let dir = example_dir("failed");
+        let data = example_data(20);
+        let disk_tree = DiskTree::build(&data, &dir, 0).expect("writes levels").expect("has leaves");
+        fs::write(dir.join("level-2"), b"short").expect("truncates level");
+        assert!(disk_tree.prove_by_index(5).is_err());
+        assert!(disk_tree.prove_by_index(19).expect("level 2 has no sibling of the last leaf").is_some());
+        drop(disk_tree);
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+}
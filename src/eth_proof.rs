@@ -0,0 +1,248 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::mpt::{keccak256, verify_proof, MptError, Rlp, EMPTY_TRIE_ROOT};
+
+/// An account and storage slots whose values were proven against a state root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedAccount {
+    pub address: [u8; 20],
+    /// whether the state holds the account, one it does not hold reads as empty
+    pub exists: bool,
+    /// big-endian without leading zeros, empty for zero
+    pub nonce: Vec<u8>,
+    /// big-endian without leading zeros, empty for zero
+    pub balance: Vec<u8>,
+    pub storage_root: [u8; 32],
+    pub code_hash: [u8; 32],
+    pub storage: Vec<StorageSlot>,
+}
+
+/// A storage slot and its proven value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageSlot {
+    pub key: [u8; 32],
+    /// big-endian without leading zeros, empty for zero
+    pub value: Vec<u8>,
+}
+
+/// Reasons an `eth_getProof` response is refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EthProofError {
+    /// the response is not JSON, or the named field is missing or not of the expected form
+    MalformedResponse(&'static str),
+    AccountProof(MptError),
+    StorageProof { key: [u8; 32], error: MptError },
+    /// the named field of the response disagrees with the account the proof holds
+    AccountMismatch(&'static str),
+    /// the response's value for the slot disagrees with the one the proof holds
+    StorageMismatch([u8; 32]),
+}
+
+impl fmt::Display for EthProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EthProofError::MalformedResponse(field) => write!(f, "malformed response field: {field}"),
+            EthProofError::AccountProof(error) => write!(f, "invalid account proof: {error}"),
+            EthProofError::StorageProof { key, error } => write!(f, "invalid storage proof for 0x{}: {error}", hex::encode(key)),
+            EthProofError::AccountMismatch(field) => write!(f, "{field} differs from the proven account"),
+            EthProofError::StorageMismatch(key) => write!(f, "value of 0x{} differs from the proven one", hex::encode(key)),
+        }
+    }
+}
+
+impl std::error::Error for EthProofError {}
+
+/// Verifies the account and storage proofs of an `eth_getProof` response against a state root
+///
+/// Takes either the whole JSON-RPC response or just its `result`. The account proof is checked
+/// against `state_root` and the response's nonce, balance, storage hash and code hash against the
+/// proven account. Each storage proof is then checked against the proven storage root, and its
+/// value against the proven one. Accounts and slots the proofs show to be absent read as zero.
+pub fn verify_eth_get_proof(response: &str, state_root: &[u8; 32]) -> Result<VerifiedAccount, EthProofError> {
+    let response: Value = serde_json::from_str(response).map_err(|_| EthProofError::MalformedResponse("response"))?;
+    let result = response.get("result").unwrap_or(&response);
+
+    let address: [u8; 20] = fixed_hex(result, "address")?;
+    let account_proof = hex_list(result, "accountProof")?;
+    let proven = verify_proof(state_root, &keccak256(&address), &account_proof).map_err(EthProofError::AccountProof)?;
+    let (exists, nonce, balance, storage_root, code_hash) = match &proven {
+        Some(account) => {
+            let account = Rlp::decode(account).ok_or(EthProofError::AccountProof(MptError::MalformedNode(account_proof.len() - 1)))?;
+            match account.as_list() {
+                Some([Rlp::Bytes(nonce), Rlp::Bytes(balance), Rlp::Bytes(storage_root), Rlp::Bytes(code_hash)]) => (
+                    true,
+                    nonce.to_vec(),
+                    balance.to_vec(),
+                    (*storage_root).try_into().map_err(|_| EthProofError::AccountMismatch("storageHash"))?,
+                    (*code_hash).try_into().map_err(|_| EthProofError::AccountMismatch("codeHash"))?,
+                ),
+                _ => return Err(EthProofError::AccountProof(MptError::MalformedNode(account_proof.len() - 1))),
+            }
+        }
+        None => (false, vec![], vec![], EMPTY_TRIE_ROOT, keccak256(&[])),
+    };
+    if quantity(result, "nonce")? != nonce {
+        return Err(EthProofError::AccountMismatch("nonce"));
+    }
+    if quantity(result, "balance")? != balance {
+        return Err(EthProofError::AccountMismatch("balance"));
+    }
+    if fixed_hex::<32>(result, "storageHash")? != storage_root {
+        return Err(EthProofError::AccountMismatch("storageHash"));
+    }
+    if fixed_hex::<32>(result, "codeHash")? != code_hash {
+        return Err(EthProofError::AccountMismatch("codeHash"));
+    }
+
+    let mut storage = vec![];
+    let slots = result.get("storageProof").and_then(Value::as_array).ok_or(EthProofError::MalformedResponse("storageProof"))?;
+    for slot in slots {
+        // slot keys are quantities as often as they are full words
+        let key_bytes = quantity(slot, "key")?;
+        if key_bytes.len() > 32 {
+            return Err(EthProofError::MalformedResponse("key"));
+        }
+        let mut key = [0; 32];
+        key[32 - key_bytes.len()..].copy_from_slice(&key_bytes);
+
+        let proof = hex_list(slot, "proof")?;
+        let proven = verify_proof(&storage_root, &keccak256(&key), &proof).map_err(|error| EthProofError::StorageProof { key, error })?;
+        let value = match proven {
+            Some(encoded) => Rlp::decode(&encoded)
+                .and_then(|value| value.as_bytes().map(<[u8]>::to_vec))
+                .ok_or(EthProofError::StorageProof { key, error: MptError::MalformedNode(proof.len() - 1) })?,
+            None => vec![],
+        };
+        if quantity(slot, "value")? != value {
+            return Err(EthProofError::StorageMismatch(key));
+        }
+        storage.push(StorageSlot { key, value });
+    }
+
+    Ok(VerifiedAccount {
+        address,
+        exists,
+        nonce,
+        balance,
+        storage_root,
+        code_hash,
+        storage,
+    })
+}
+
+/// bytes of a `0x` prefixed hex string, odd lengths taking a leading zero
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    let digits = value.strip_prefix("0x")?;
+    if digits.len() % 2 == 1 {
+        return hex::decode(format!("0{digits}")).ok();
+    }
+    hex::decode(digits).ok()
+}
+
+fn field<'a>(object: &'a Value, name: &'static str) -> Result<&'a str, EthProofError> {
+    object.get(name).and_then(Value::as_str).ok_or(EthProofError::MalformedResponse(name))
+}
+
+fn fixed_hex<const N: usize>(object: &Value, name: &'static str) -> Result<[u8; N], EthProofError> {
+    decode_hex(field(object, name)?).and_then(|bytes| bytes.try_into().ok()).ok_or(EthProofError::MalformedResponse(name))
+}
+
+/// a hex quantity as big-endian bytes without leading zeros
+fn quantity(object: &Value, name: &'static str) -> Result<Vec<u8>, EthProofError> {
+    let bytes = decode_hex(field(object, name)?).ok_or(EthProofError::MalformedResponse(name))?;
+    Ok(bytes.into_iter().skip_while(|byte| *byte == 0).collect())
+}
+
+fn hex_list(object: &Value, name: &'static str) -> Result<Vec<Vec<u8>>, EthProofError> {
+    let list = object.get(name).and_then(Value::as_array).ok_or(EthProofError::MalformedResponse(name))?;
+    list.iter().map(|item| item.as_str().and_then(decode_hex).ok_or(EthProofError::MalformedResponse(name))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpt::tests::{encode_bytes, encode_list, encode_path, nibbles};
+
+    const ADDRESS: [u8; 20] = [0xab; 20];
+
+    /// root of a trie holding only `value` at `key`, and the proof of it
+    fn single_key_trie(key: &[u8], value: &[u8]) -> ([u8; 32], Vec<u8>) {
+        let leaf = encode_list(&[encode_bytes(&encode_path(&nibbles(key), true)), encode_bytes(value)]);
+        (keccak256(&leaf), leaf)
+    }
+
+    fn example_response(balance: &str, slot_value: &str) -> (String, [u8; 32]) {
+        let mut slot = [0; 32];
+        slot[31] = 2;
+        let (storage_root, storage_leaf) = single_key_trie(&keccak256(&slot), &encode_bytes(&[0x01, 0x00]));
+        let account = encode_list(&[
+            encode_bytes(&[5]),
+            encode_bytes(&[0x0d, 0xe0, 0xb6]),
+            encode_bytes(&storage_root),
+            encode_bytes(&keccak256(b"code")),
+        ]);
+        let (state_root, account_leaf) = single_key_trie(&keccak256(&ADDRESS), &account);
+        let response = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {
+                "address": format!("0x{}", hex::encode(ADDRESS)),
+                "accountProof": [format!("0x{}", hex::encode(account_leaf))],
+                "nonce": "0x5",
+                "balance": balance,
+                "storageHash": format!("0x{}", hex::encode(storage_root)),
+                "codeHash": format!("0x{}", hex::encode(keccak256(b"code"))),
+                "storageProof": [{
+                    "key": "0x2",
+                    "value": slot_value,
+                    "proof": [format!("0x{}", hex::encode(storage_leaf))],
+                }],
+            },
+        });
+        (response.to_string(), state_root)
+    }
+
+    #[test]
+    fn test_account_and_storage_are_verified() {
+        let (response, state_root) = example_response("0xde0b6", "0x100");
+        let account = verify_eth_get_proof(&response, &state_root).expect("valid response");
+        assert!(account.exists);
+        assert_eq!(account.address, ADDRESS);
+        assert_eq!(account.nonce, vec![5]);
+        assert_eq!(account.balance, vec![0x0d, 0xe0, 0xb6]);
+        assert_eq!(account.code_hash, keccak256(b"code"));
+        assert_eq!(account.storage.len(), 1);
+        assert_eq!(account.storage[0].key[31], 2);
+        assert_eq!(account.storage[0].value, vec![0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_responses_disagreeing_with_proofs_are_refused() {
+        let (response, state_root) = example_response("0xde0b7", "0x100");
+        assert_eq!(verify_eth_get_proof(&response, &state_root), Err(EthProofError::AccountMismatch("balance")));
+        let (response, state_root) = example_response("0xde0b6", "0x101");
+        assert!(matches!(verify_eth_get_proof(&response, &state_root), Err(EthProofError::StorageMismatch(_))));
+        let (response, _) = example_response("0xde0b6", "0x100");
+        assert_eq!(verify_eth_get_proof(&response, &[0; 32]), Err(EthProofError::AccountProof(MptError::HashMismatch(0))));
+        assert_eq!(verify_eth_get_proof("{}", &[0; 32]), Err(EthProofError::MalformedResponse("address")));
+    }
+
+    #[test]
+    fn test_absent_account_reads_as_empty() {
+        let response = serde_json::json!({
+            "address": format!("0x{}", hex::encode(ADDRESS)),
+            "accountProof": [],
+            "nonce": "0x0",
+            "balance": "0x0",
+            "storageHash": format!("0x{}", hex::encode(EMPTY_TRIE_ROOT)),
+            "codeHash": format!("0x{}", hex::encode(keccak256(&[]))),
+            "storageProof": [{ "key": "0x0", "value": "0x0", "proof": [] }],
+        });
+        let account = verify_eth_get_proof(&response.to_string(), &EMPTY_TRIE_ROOT).expect("valid response");
+        assert!(!account.exists);
+        assert!(account.balance.is_empty());
+        assert_eq!(account.storage[0].value, Vec::<u8>::new());
+    }
+}
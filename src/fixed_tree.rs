@@ -0,0 +1,217 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{HashDirection, Proof};
+use crate::metrics::{self, MetricsEvent};
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+use crate::zero_hashes::ZeroHashes;
+
+/// length in bytes of the hashes of a `FixedMerkleTree`, which its proofs hold in arrays
+pub const FIXED_HASH_LEN: usize = 32;
+
+/// Tree of exactly `2^DEPTH` positions, filled with leaves from the left, for protocols that mandate a depth
+///
+/// The depth is part of the type, so a tree or proof of another depth doesn't type check where one of
+/// depth 32 is expected, and proofs are arrays of exactly `DEPTH` siblings that live on the stack.
+/// Positions no leaf was appended to yet hold an all zero default leaf, and the subtrees made of them
+/// hash to the roots in `ZeroHashes`, as in the deposit contract of Ethereum. The root is the one an
+/// `IndexedTree` of the same depth gives with the same leaves in its first slots, and once every position
+/// is filled the one `MerkleTree::construct` gives. Hashes are `FIXED_HASH_LEN` bytes long.
+#[derive(Debug, Clone)]
+pub struct FixedMerkleTree<const DEPTH: usize, H: Hasher = Sha256Hasher> {
+    hasher: H,
+    /// hashes of the nodes above at least one appended leaf, by level, the leaves first
+    levels: Vec<Vec<[u8; FIXED_HASH_LEN]>>,
+    /// hash of an empty subtree by height, the default leaf first
+    empty: ZeroHashes,
+}
+
+/// Proof of the leaf at `index` of a `FixedMerkleTree`, one sibling per level from the leaf up
+/// the sides the siblings go on follow from the index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedProof<const DEPTH: usize> {
+    pub index: u64,
+    pub siblings: [[u8; FIXED_HASH_LEN]; DEPTH],
+}
+
+impl<const DEPTH: usize> FixedMerkleTree<DEPTH> {
+    /// Starts an empty SHA-256 tree, see `with_hasher`
+    pub fn new() -> FixedMerkleTree<DEPTH> {
+        FixedMerkleTree::with_hasher(Sha256Hasher::new())
+    }
+
+    /// Verifies a proof of a SHA-256 tree, see `verify_proof_with_hasher`
+    pub fn verify_proof(data: &[u8], proof: &FixedProof<DEPTH>, root_hash: &Root) -> bool {
+        FixedMerkleTree::verify_proof_with_hasher(data, proof, root_hash, &Sha256Hasher::new())
+    }
+}
+
+impl<const DEPTH: usize> Default for FixedMerkleTree<DEPTH> {
+    fn default() -> Self {
+        FixedMerkleTree::new()
+    }
+}
+
+impl<const DEPTH: usize, H: Hasher> FixedMerkleTree<DEPTH, H> {
+    /// Starts a tree with every position empty, hashing with the given hash function
+    /// a depth above 63 fails to compile, as positions are indexed by `u64`
+    ///
+    /// # Panics
+    ///
+    /// When the hash function doesn't produce `FIXED_HASH_LEN` byte hashes.
+    pub fn with_hasher(hasher: H) -> FixedMerkleTree<DEPTH, H> {
+        const { assert!(DEPTH < 64, "positions are indexed by u64") };
+        assert_eq!(hasher.digest_len(), FIXED_HASH_LEN, "fixed trees hold {FIXED_HASH_LEN} byte hashes");
+        FixedMerkleTree {
+            empty: ZeroHashes::with_hasher(DEPTH as u32, LeafHash::new(vec![0; FIXED_HASH_LEN]), &hasher),
+            hasher,
+            levels: vec![vec![]; DEPTH + 1],
+        }
+    }
+
+    /// Gets number of positions, filled or not
+    pub fn capacity(&self) -> u64 {
+        1 << DEPTH
+    }
+
+    /// Gets number of leaves appended
+    pub fn leaf_count(&self) -> u64 {
+        self.levels[0].len() as u64
+    }
+
+    /// Gets root hash for this tree
+    pub fn root(&self) -> Root {
+        Root::new(self.node(DEPTH, 0).to_vec())
+    }
+
+    /// Hashes and appends a leaf at the first empty position, returning its index
+    /// `None` when every position is filled
+    pub fn push(&mut self, data: &[u8]) -> Option<u64> {
+        let leaf_hash = LeafHash::of(data, &self.hasher);
+        self.push_hash(leaf_hash)
+    }
+
+    /// Appends a leaf that was already hashed at the first empty position, returning its index
+    /// `None` when every position is filled
+    ///
+    /// # Panics
+    ///
+    /// When the hash is not `FIXED_HASH_LEN` bytes long.
+    pub fn push_hash(&mut self, leaf_hash: LeafHash) -> Option<u64> {
+        let index = self.leaf_count();
+        if index == self.capacity() {
+            return None;
+        }
+        let leaf: [u8; FIXED_HASH_LEN] = leaf_hash.as_bytes().try_into().expect("leaf hashes are as long as the hashes of the tree");
+        self.levels[0].push(leaf);
+        // rehashes the ancestors of the new leaf, the rightmost node of every level
+        let mut position = index as usize;
+        for level in 0..DEPTH {
+            let mut parent = [0; FIXED_HASH_LEN];
+            let (left, right) = (self.node(level, position & !1), self.node(level, position | 1));
+            self.hasher.hash_concat_into(&left, &right, &mut parent);
+            position /= 2;
+            match self.levels[level + 1].get_mut(position) {
+                Some(node) => *node = parent,
+                None => self.levels[level + 1].push(parent),
+            }
+        }
+        Some(index)
+    }
+
+    /// Returns the proof for the position at `index`, the default leaf's for empty positions
+    /// `None` when the index is not below the capacity
+    pub fn prove(&self, index: u64) -> Option<FixedProof<DEPTH>> {
+        if index >= self.capacity() {
+            return None;
+        }
+        let siblings = std::array::from_fn(|level| self.node(level, (index >> level) as usize ^ 1));
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Some(FixedProof { index, siblings })
+    }
+
+    /// Verifies that the proof leads from the given data at its index to the given root_hash with the given
+    /// hash function, without allocating
+    pub fn verify_proof_with_hasher(data: &[u8], proof: &FixedProof<DEPTH>, root_hash: &Root, hasher: &H) -> bool {
+        if hasher.digest_len() != FIXED_HASH_LEN || DEPTH >= 64 || proof.index >> DEPTH != 0 {
+            return metrics::verified(hasher, false);
+        }
+        let (mut current, mut next) = ([0; FIXED_HASH_LEN], [0; FIXED_HASH_LEN]);
+        hasher.hash_into(data, &mut current);
+        for (level, sibling) in proof.siblings.iter().enumerate() {
+            match direction(proof.index, level) {
+                HashDirection::Left => hasher.hash_concat_into(sibling, &current, &mut next),
+                HashDirection::Right => hasher.hash_concat_into(&current, sibling, &mut next),
+            }
+            current = next;
+        }
+        metrics::verified(hasher, current == root_hash.as_bytes())
+    }
+
+    /// hash of the node at `index` on `level`, the empty subtree's when no leaf was appended below it
+    fn node(&self, level: usize, index: usize) -> [u8; FIXED_HASH_LEN] {
+        match self.levels[level].get(index) {
+            Some(node) => *node,
+            None => self.empty.get(level as u32).expect("the zero hashes reach the root").try_into().expect("hashes are FIXED_HASH_LEN bytes"),
+        }
+    }
+}
+
+impl<const DEPTH: usize> FixedProof<DEPTH> {
+    /// Converts the proof into a `Proof` of `DEPTH` siblings, e.g. to serialize it with `to_bytes`
+    pub fn to_proof<H: Hasher>(&self) -> Proof<H> {
+        let hashes = self.siblings.iter().enumerate().map(|(level, sibling)| (direction(self.index, level), sibling.to_vec())).collect();
+        Proof::new(hashes)
+    }
+}
+
+/// side the sibling of the position at `index` goes on at `level`
+fn direction(index: u64, level: usize) -> HashDirection {
+    if (index >> level) & 1 == 0 { HashDirection::Right } else { HashDirection::Left }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexed::IndexedTree;
+    use crate::merkletree::{Data, MerkleTree};
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| format!("deposit {i}").into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_fixed_trees_match_indexed_and_full_trees() {
+        let data = example_data(8);
+        let mut tree = FixedMerkleTree::<3>::new();
+        let mut indexed = IndexedTree::new(3);
+        assert_eq!(tree.root(), indexed.root());
+        for (index, leaf) in (0..).zip(&data) {
+            assert_eq!(tree.push(leaf), Some(index));
+            indexed.insert(index, leaf);
+            assert_eq!(tree.root(), indexed.root());
+        }
+        assert_eq!(tree.root(), MerkleTree::construct(&data).root());
+        assert_eq!(tree.push(b"one too many"), None);
+        assert_eq!(tree.leaf_count(), tree.capacity());
+    }
+
+    #[test]
+    fn test_fixed_proofs_verify_on_the_stack() {
+        let data = example_data(5);
+        let mut tree = FixedMerkleTree::<32>::new();
+        for leaf in &data {
+            tree.push(leaf);
+        }
+        let root = tree.root();
+        let proof = tree.prove(3).expect("index is in range");
+        assert!(FixedMerkleTree::verify_proof(&data[3], &proof, &root));
+        assert!(!FixedMerkleTree::verify_proof(&data[2], &proof, &root));
+        assert!(!FixedMerkleTree::verify_proof(&data[3], &FixedProof { index: 2, ..proof }, &root));
+        assert!(MerkleTree::verify_proof(&data[3], &proof.to_proof(), &root));
+
+        // an empty position proves the default leaf, as in an indexed tree
+        let vacant = tree.prove(1 << 31).expect("index is in range").to_proof();
+        assert!(IndexedTree::verify_vacant(1 << 31, 32, &vacant, &root));
+        assert!(tree.prove(1 << 32).is_none());
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{scrub, Data, Hash};
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+use crate::snapshot::{header_params, read_legacy_params, read_params, SnapshotError, LEGACY_VERSION};
+use crate::wal::sync_dir;
+
+/// bytes every checkpoint starts with
+pub const CHECKPOINT_MAGIC: [u8; 4] = *b"MRKF";
+/// version of the checkpoint format written by this crate
+pub const CHECKPOINT_VERSION: u8 = 2;
+
+/// Partial state of a streaming build: the roots of the complete subtrees built so far
+///
+/// Leaves are pushed one at a time and every pair of equally sized subtrees is merged as soon as
+/// it is complete, so the frontier only ever holds one peak per set bit of the leaf count.
+/// Its root is the root `MerkleTree::construct` computes over the same leaves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frontier<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    /// roots of the complete subtrees, largest and leftmost first
+    peaks: Vec<Hash>,
+    leaf_count: u64,
+}
+
+impl Frontier {
+    /// Starts a build without any leaves
+    pub fn new() -> Frontier {
+        Frontier::default()
+    }
+
+    /// Reads a checkpoint written by `checkpoint` of a SHA-256 build
+    /// the build continues with leaf number `leaf_count()` of the input
+    pub fn resume(reader: impl Read) -> Result<Frontier, SnapshotError> {
+        Frontier::resume_with_hasher(reader, Sha256Hasher::new())
+    }
+
+    /// Resumes from the checkpoint at `path`, or starts from scratch when there is none yet
+    pub fn load_checkpoint(path: impl AsRef<Path>) -> Result<Frontier, SnapshotError> {
+        Frontier::load_checkpoint_with_hasher(path, Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> Frontier<H> {
+    /// Starts a build without any leaves, hashing with the given hash function
+    pub fn with_hasher(hasher: H) -> Frontier<H> {
+        Frontier {
+            hasher,
+            peaks: vec![],
+            leaf_count: 0,
+        }
+    }
+
+    /// Gets number of leaves pushed so far
+    pub fn leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
+
+    /// Gets the hash function the frontier hashes with
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Hashes and pushes the next leaf
+    pub fn push(&mut self, data: &Data) {
+        self.push_hash(LeafHash::of(data, &self.hasher))
+    }
+
+    /// Pushes the next leaf that was already hashed
+    ///
+    /// # Panics
+    ///
+    /// When the frontier already holds `u64::MAX` leaves.
+    pub fn push_hash(&mut self, leaf_hash: LeafHash) {
+        let leaf_count = self.leaf_count.checked_add(1).expect("a frontier holds at most u64::MAX leaves");
+        let mut node = leaf_hash.into_hash();
+        // every trailing one bit of the leaf count is a complete subtree of the new node's size
+        let mut complete = self.leaf_count;
+        while complete & 1 == 1 {
+            let mut left = self.peaks.pop().expect("a peak per set bit");
+            node = self.hasher.hash_concat(&left, &node);
+            scrub(&mut left);
+            complete >>= 1;
+        }
+        self.peaks.push(node);
+        self.leaf_count = leaf_count;
+    }
+
+    /// Root over all leaves pushed so far, `None` before the first leaf
+    pub fn root(&self) -> Option<Root> {
+        let (last, rest) = self.peaks.split_last()?;
+        // the odd subtrees on the right are promoted until they meet a peak of their size
+        Some(Root::new(rest.iter().rev().fold(last.clone(), |right, left| self.hasher.hash_concat(left, &right))))
+    }
+
+    /// Writes the frontier as a checkpoint to resume the build from
+    ///
+    /// The header holds the magic bytes, the format version, the parameters of the tree as `TreeParams`
+    /// writes them and the leaf count (`u64` little-endian).
+    /// One peak per set bit of the leaf count follows, largest first.
+    pub fn checkpoint(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&CHECKPOINT_MAGIC)?;
+        writer.write_all(&[CHECKPOINT_VERSION])?;
+        writer.write_all(&header_params(&self.hasher)?.to_bytes())?;
+        writer.write_all(&self.leaf_count.to_le_bytes())?;
+        for peak in &self.peaks {
+            writer.write_all(peak)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a checkpoint written by `checkpoint` of a build with the given hash function
+    /// refuses checkpoints of a version or of tree parameters other than those of a tree of the hash function
+    /// version 1 checkpoints, which record only the algorithm and hash length, are read as binary trees promoting odd nodes
+    /// the rest of the input has to be exactly one peak per set bit of the leaf count
+    pub fn resume_with_hasher(mut reader: impl Read, hasher: H) -> Result<Frontier<H>, SnapshotError> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if header[..4] != CHECKPOINT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        match header[4] {
+            CHECKPOINT_VERSION => read_params(&mut reader, &hasher)?,
+            LEGACY_VERSION => read_legacy_params(&mut reader, &hasher, false)?,
+            version => return Err(SnapshotError::UnsupportedVersion(version)),
+        }
+        let mut leaf_count = [0; 8];
+        reader.read_exact(&mut leaf_count)?;
+        let leaf_count = u64::from_le_bytes(leaf_count);
+
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest)?;
+        let digest_len = hasher.digest_len();
+        let peaks: Vec<Hash> = rest.chunks_exact(digest_len).map(<[u8]>::to_vec).collect();
+        let torn = rest.len() % digest_len != 0;
+        scrub(&mut rest);
+        if torn {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        if peaks.len() != leaf_count.count_ones() as usize {
+            return Err(SnapshotError::PeakCountMismatch { leaf_count, peaks: peaks.len() });
+        }
+        Ok(Frontier { hasher, peaks, leaf_count })
+    }
+
+    /// Atomically replaces the checkpoint at `path`
+    /// the checkpoint is written next to it and renamed over it, so a crash never leaves a torn file behind,
+    /// and the directory is synced afterwards, so the rename itself survives a power loss
+    pub fn save_checkpoint(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        self.checkpoint(&mut writer)?;
+        writer.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        fs::rename(&partial, path)?;
+        // a bare file name has an empty parent, which is the working directory
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        sync_dir(dir)
+    }
+
+    /// Resumes a build with the given hash function from the checkpoint at `path`,
+    /// or starts from scratch when there is none yet
+    pub fn load_checkpoint_with_hasher(path: impl AsRef<Path>, hasher: H) -> Result<Frontier<H>, SnapshotError> {
+        match File::open(path) {
+            Ok(file) => Frontier::resume_with_hasher(BufReader::new(file), hasher),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Frontier::with_hasher(hasher)),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<H: Hasher> Drop for Frontier<H> {
+    fn drop(&mut self) {
+        for peak in &mut self.peaks {
+            scrub(peak);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::MerkleTree;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_root_matches_constructed_tree() {
+        let mut frontier = Frontier::new();
+        assert_eq!(frontier.root(), None);
+        for (i, leaf) in example_data(17).iter().enumerate() {
+            frontier.push(leaf);
+            assert_eq!(frontier.root(), Some(MerkleTree::construct(&example_data(i + 1)).root()));
+        }
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint_continues_build() {
+        let data = example_data(11);
+        let mut frontier = Frontier::new();
+        for leaf in &data[..6] {
+            frontier.push(leaf);
+        }
+        let mut checkpoint = vec![];
+        frontier.checkpoint(&mut checkpoint).expect("this should checkpoint");
+        // 6 leaves are a subtree of 4 and one of 2
+        assert_eq!(checkpoint.len(), 28 + 2 * 32);
+
+        let mut resumed = Frontier::resume(checkpoint.as_slice()).expect("this should resume");
+        assert_eq!(resumed, frontier);
+        for leaf in &data[resumed.leaf_count() as usize..] {
+            resumed.push(leaf);
+        }
+        assert_eq!(resumed.root(), Some(MerkleTree::construct(&data).root()));
+    }
+
+    #[test]
+    fn test_resume_rejects_foreign_and_truncated_checkpoints() {
+        let mut frontier = Frontier::new();
+        frontier.push(&vec![1]);
+        let mut checkpoint = vec![];
+        frontier.checkpoint(&mut checkpoint).expect("this should checkpoint");
+
+        assert!(matches!(Frontier::resume(&checkpoint[..checkpoint.len() - 1]), Err(SnapshotError::Io(_))));
+        let mut extra_peak = checkpoint.clone();
+        extra_peak.extend_from_slice(&[0; 32]);
+        assert!(matches!(Frontier::resume(extra_peak.as_slice()), Err(SnapshotError::PeakCountMismatch { leaf_count: 1, peaks: 2 })));
+        let missing_peak = &checkpoint[..checkpoint.len() - 32];
+        assert!(matches!(Frontier::resume(missing_peak), Err(SnapshotError::PeakCountMismatch { leaf_count: 1, peaks: 0 })));
+        checkpoint[0] = b'X';
+        assert!(matches!(Frontier::resume(checkpoint.as_slice()), Err(SnapshotError::BadMagic)));
+    }
+
+    #[test]
+    #[should_panic(expected = "at most u64::MAX leaves")]
+    fn test_push_panics_once_the_leaf_count_is_exhausted() {
+        let mut frontier = Frontier { hasher: Sha256Hasher::new(), peaks: vec![vec![0; 32]; 64], leaf_count: u64::MAX };
+        frontier.push(&vec![1]);
+    }
+
+    #[test]
+    fn test_resume_reads_version_1_checkpoints() {
+        let mut frontier = Frontier::new();
+        for i in 0..6 {
+            frontier.push(&vec![i]);
+        }
+        let mut checkpoint = vec![];
+        frontier.checkpoint(&mut checkpoint).expect("this should checkpoint");
+        // version 1 wrote the algorithm code and the hash length only
+        let mut legacy = [&CHECKPOINT_MAGIC[..], &[1], &checkpoint[5..13], &checkpoint[14..15]].concat();
+        legacy.extend_from_slice(&checkpoint[20..]);
+
+        assert_eq!(Frontier::resume(legacy.as_slice()).expect("this should resume"), frontier);
+        legacy[4] = 3;
+        assert!(matches!(Frontier::resume(legacy.as_slice()), Err(SnapshotError::UnsupportedVersion(3))));
+    }
+
+    #[test]
+    fn test_frontier_with_hasher_matches_tree_and_checkpoints_its_length() {
+        use crate::hasher::Blake2bHasher;
+
+        let data = example_data(7);
+        let hasher = Blake2bHasher::new(24).expect("valid length");
+        let mut frontier = Frontier::with_hasher(hasher);
+        for leaf in &data {
+            frontier.push(leaf);
+        }
+        assert_eq!(frontier.root(), Some(MerkleTree::construct_with_hasher(&data, hasher).root()));
+
+        let mut checkpoint = vec![];
+        frontier.checkpoint(&mut checkpoint).expect("this should checkpoint");
+        assert_eq!(Frontier::resume_with_hasher(checkpoint.as_slice(), hasher).expect("this should resume"), frontier);
+        assert!(matches!(Frontier::resume(checkpoint.as_slice()), Err(SnapshotError::UnsupportedHashAlgorithm(0xb218))));
+    }
+
+    #[test]
+    fn test_save_and_load_checkpoint_file() {
+        let path = std::env::temp_dir().join(format!("merkle-frontier-{}", std::process::id()));
+        assert_eq!(Frontier::load_checkpoint(&path).expect("this should start over"), Frontier::new());
+
+        let mut frontier = Frontier::new();
+        for leaf in &example_data(3) {
+            frontier.push(leaf);
+        }
+        frontier.save_checkpoint(&path).expect("this should save");
+        let loaded = Frontier::load_checkpoint(&path).expect("this should load");
+        fs::remove_file(&path).expect("this should clean up");
+        assert_eq!(loaded, frontier);
+    }
+}
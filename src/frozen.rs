@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{Data, MerkleTree, Proof, ProofIter};
+use crate::root::Root;
+
+/// Immutable tree shared between readers, each clone costing a reference count rather than a copy
+///
+/// Freezing shares the levels and the bloom filter of the tree instead of copying them, so proofs can be
+/// served from any number of threads at once while the tree itself keeps taking changes. The first write
+/// to the tree while a snapshot still holds its hashes copies them, so the snapshot keeps answering for
+/// the tree as it was frozen, and a tree no snapshot shares is written in place as before.
+pub struct TreeSnapshot<H: Hasher = Sha256Hasher> {
+    tree: Arc<MerkleTree<H>>,
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Takes a snapshot of the tree that can be cloned and sent across threads without copying its hashes
+    pub fn freeze(&self) -> TreeSnapshot<H> {
+        let tree = MerkleTree {
+            hasher: self.hasher.clone(),
+            levels: Arc::clone(&self.levels),
+            leaf_count: self.leaf_count,
+            bloom_filter: self.bloom_filter.clone(),
+        };
+        TreeSnapshot { tree: Arc::new(tree) }
+    }
+}
+
+impl<H: Hasher> TreeSnapshot<H> {
+    /// Gets the tree the snapshot was frozen from
+    pub fn tree(&self) -> &MerkleTree<H> {
+        &self.tree
+    }
+
+    /// Gets root hash of the frozen tree
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Gets number of leaves of the frozen tree
+    pub fn leaf_count(&self) -> u64 {
+        self.tree.leaf_count()
+    }
+
+    /// Returns the proof of the first leaf holding the given data, see `MerkleTree::prove`
+    pub fn prove(&self, data: &Data) -> Option<Proof<H>> {
+        self.tree.prove(data)
+    }
+
+    /// Returns the proof for the leaf at `index`
+    pub fn prove_by_index(&self, index: u64) -> Option<Proof<H>> {
+        self.tree.prove_by_index(index)
+    }
+
+    /// Returns the siblings of the leaf at `index` one at a time, see `MerkleTree::proof_iter`
+    pub fn proof_iter(&self, index: u64) -> Option<ProofIter<'_>> {
+        self.tree.proof_iter(index)
+    }
+
+    /// Tells whether both share the same hashes, as clones of a snapshot and snapshots of a tree that didn't
+    /// change in between do, the cheap way to see that nothing changed
+    pub fn ptr_eq(&self, other: &TreeSnapshot<H>) -> bool {
+        Arc::ptr_eq(&self.tree.levels, &other.tree.levels)
+    }
+}
+
+impl<H: Hasher> Clone for TreeSnapshot<H> {
+    fn clone(&self) -> Self {
+        TreeSnapshot { tree: Arc::clone(&self.tree) }
+    }
+}
+
+impl<H: Hasher> std::fmt::Debug for TreeSnapshot<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TreeSnapshot").field(&*self.tree).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshots_serve_proofs_from_many_threads() {
+        let data: Vec<Data> = (0..100).map(|i| vec![i as u8]).collect();
+        let snapshot = MerkleTree::construct(&data).freeze();
+        let root = snapshot.root();
+        std::thread::scope(|scope| {
+            for reader in 0..4 {
+                let (snapshot, data, root) = (snapshot.clone(), &data, &root);
+                scope.spawn(move || {
+                    for index in (reader..100).step_by(4) {
+                        let proof = snapshot.prove_by_index(index).expect("index is in range");
+                        assert!(MerkleTree::verify_proof(&data[index as usize], &proof, root));
+                    }
+                });
+            }
+        });
+
+        // the builder moves on to the next tree while the frozen one is still served
+        let next = MerkleTree::construct(&data[..50]).freeze();
+        assert!(!next.ptr_eq(&snapshot));
+        assert!(snapshot.clone().ptr_eq(&snapshot));
+        assert_eq!(snapshot.leaf_count(), 100);
+        assert_eq!(snapshot.prove(&data[7]), snapshot.prove_by_index(7));
+        assert_eq!(snapshot.proof_iter(7).map(|siblings| siblings.len()), Some(7));
+        assert_eq!(snapshot.tree(), &MerkleTree::construct(&data));
+    }
+
+    #[test]
+    fn test_changes_after_freezing_copy_the_shared_levels() {
+        let data: Vec<Data> = (0..10).map(|i| vec![i as u8]).collect();
+        let mut tree = MerkleTree::construct(&data);
+        let snapshot = tree.freeze();
+        assert!(tree.freeze().ptr_eq(&snapshot));
+        let frozen_root = snapshot.root();
+
+        let mut transaction = tree.begin();
+        transaction.update(3, b"three").expect("index is in range");
+        let root = transaction.commit();
+        assert_ne!(root, frozen_root);
+        assert_eq!(snapshot.root(), frozen_root);
+        assert_eq!(snapshot.tree(), &MerkleTree::construct(&data));
+        assert!(!tree.freeze().ptr_eq(&snapshot));
+
+        let mut transaction = tree.begin();
+        transaction.append(b"ten");
+        transaction.commit();
+        assert_eq!((snapshot.leaf_count(), tree.leaf_count()), (10, 11));
+    }
+}
@@ -0,0 +1,458 @@
+use std::any::TypeId;
+use std::fmt;
+use std::marker::PhantomData;
+
+use sha2::Digest;
+
+use crate::merkletree::{scrub, scrub_slice, Hash};
+use crate::metrics::MetricsSink;
+use crate::multihash::HashAlgorithm;
+
+/// Hash function a tree is built with, hashing leaves as well as the concatenation of two child hashes
+///
+/// Every hash a hasher produces is `digest_len()` bytes long. Trees, proofs and every verification
+/// carry that length along instead of assuming 32 bytes, and reject hashes of any other length.
+pub trait Hasher: Clone {
+    /// algorithm and parameters identifying this hasher in serialized formats
+    /// `None` for hash functions without a multicodec code, which can't be told apart once serialized
+    fn algorithm(&self) -> Option<HashAlgorithm>;
+
+    /// length in bytes of every hash this hasher produces
+    /// at most 255, as serialized proofs store hash lengths in a single byte
+    fn digest_len(&self) -> usize;
+
+    /// hashes leaf data
+    fn hash(&self, data: &[u8]) -> Hash;
+
+    /// hashes the concatenation of a left and a right child hash into their parent
+    fn hash_concat(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut concatenated = [left, right].concat();
+        let hash = self.hash(&concatenated);
+        scrub(&mut concatenated);
+        hash
+    }
+
+    /// hashes leaf data into `out`, which is `digest_len()` bytes long
+    /// the hashers of this crate write the hash in place, others fall back to copying from `hash`
+    fn hash_into(&self, data: &[u8], out: &mut [u8]) {
+        let mut hash = self.hash(data);
+        out.copy_from_slice(&hash);
+        scrub(&mut hash);
+    }
+
+    /// hashes the concatenation of two child hashes into `out`, which is `digest_len()` bytes long,
+    /// see `hash_into`
+    fn hash_concat_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        let mut hash = self.hash_concat(left, right);
+        out.copy_from_slice(&hash);
+        scrub(&mut hash);
+    }
+
+    /// sink proofs generated and verification failures with this hasher are reported to, see `MeteredHasher`
+    /// `None` for hashers that aren't metered
+    fn metrics(&self) -> Option<&dyn MetricsSink> {
+        None
+    }
+}
+
+/// Any RustCrypto `Digest` as the hash function of a tree, e.g. `DigestHasher<sha3::Sha3_256>`
+///
+/// The digest is part of the type, so trees and proofs built with different digests can't be mixed up.
+pub struct DigestHasher<D> {
+    digest: PhantomData<fn() -> D>,
+}
+
+/// SHA-256, the hasher trees are built with unless another one is given
+pub type Sha256Hasher = DigestHasher<sha2::Sha256>;
+
+impl<D> DigestHasher<D> {
+    /// Creates the hasher, digests carry no configuration of their own
+    pub const fn new() -> DigestHasher<D> {
+        DigestHasher { digest: PhantomData }
+    }
+}
+
+// implemented by hand, deriving would require the digest itself to implement them
+
+impl<D> Clone for DigestHasher<D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D> Copy for DigestHasher<D> {}
+
+impl<D> Default for DigestHasher<D> {
+    fn default() -> Self {
+        DigestHasher::new()
+    }
+}
+
+impl<D> PartialEq for DigestHasher<D> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<D> Eq for DigestHasher<D> {}
+
+impl<D> fmt::Debug for DigestHasher<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DigestHasher<{}>", std::any::type_name::<D>())
+    }
+}
+
+impl<D: Digest + 'static> Hasher for DigestHasher<D> {
+    fn algorithm(&self) -> Option<HashAlgorithm> {
+        digest_algorithm::<D>()
+    }
+
+    fn digest_len(&self) -> usize {
+        <D as Digest>::output_size()
+    }
+
+    fn hash(&self, data: &[u8]) -> Hash {
+        D::digest(data).to_vec()
+    }
+
+    fn hash_concat(&self, left: &[u8], right: &[u8]) -> Hash {
+        D::new().chain_update(left).chain_update(right).finalize().to_vec()
+    }
+
+    fn hash_into(&self, data: &[u8], out: &mut [u8]) {
+        out.copy_from_slice(&D::digest(data));
+    }
+
+    fn hash_concat_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        out.copy_from_slice(&D::new().chain_update(left).chain_update(right).finalize());
+    }
+}
+
+/// multicodec identity of the digests this crate depends on anyway, `None` for any other digest
+fn digest_algorithm<D: 'static>() -> Option<HashAlgorithm> {
+    let known = [
+        (TypeId::of::<sha2::Sha256>(), HashAlgorithm::Sha2_256),
+        (TypeId::of::<sha2::Sha512>(), HashAlgorithm::Sha2_512),
+        (TypeId::of::<sha3::Sha3_256>(), HashAlgorithm::Sha3_256),
+        (TypeId::of::<sha3::Sha3_512>(), HashAlgorithm::Sha3_512),
+        (TypeId::of::<sha3::Keccak256>(), HashAlgorithm::Keccak256),
+        (TypeId::of::<blake2::Blake2b512>(), HashAlgorithm::Blake2b(64)),
+    ];
+    known.into_iter().find(|(id, _)| *id == TypeId::of::<D>()).map(|(_, algorithm)| algorithm)
+}
+
+/// SHAKE128 extendable output function, producing hashes of a configurable length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shake128Hasher {
+    digest_len: usize,
+}
+
+/// SHAKE256 extendable output function, producing hashes of a configurable length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shake256Hasher {
+    digest_len: usize,
+}
+
+/// BLAKE2b with a configurable output length of up to 64 bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blake2bHasher {
+    digest_len: usize,
+}
+
+impl Shake128Hasher {
+    /// Creates a hasher producing `digest_len` byte hashes, `None` unless `1..=255` bytes
+    pub fn new(digest_len: usize) -> Option<Shake128Hasher> {
+        (1..=255).contains(&digest_len).then_some(Shake128Hasher { digest_len })
+    }
+}
+
+impl Shake256Hasher {
+    /// Creates a hasher producing `digest_len` byte hashes, `None` unless `1..=255` bytes
+    pub fn new(digest_len: usize) -> Option<Shake256Hasher> {
+        (1..=255).contains(&digest_len).then_some(Shake256Hasher { digest_len })
+    }
+}
+
+impl Blake2bHasher {
+    /// Creates a hasher producing `digest_len` byte hashes, `None` unless `1..=64` bytes
+    pub fn new(digest_len: usize) -> Option<Blake2bHasher> {
+        (1..=64).contains(&digest_len).then_some(Blake2bHasher { digest_len })
+    }
+}
+
+/// Any hasher with its hashes cut down to their first `digest_len` bytes, at every node
+///
+/// Leaves, inner nodes and proof siblings all carry the shorter hashes, trading collision resistance
+/// for size: a 16 byte hash leaves about 64 bits of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncated<H> {
+    inner: H,
+    digest_len: usize,
+}
+
+impl<H: Hasher> Truncated<H> {
+    /// Creates a hasher keeping `digest_len` bytes of each hash, `None` unless `1..=inner.digest_len()` bytes
+    pub fn new(inner: H, digest_len: usize) -> Option<Truncated<H>> {
+        (1..=inner.digest_len()).contains(&digest_len).then_some(Truncated { inner, digest_len })
+    }
+
+    /// Gets the hasher whose hashes are truncated
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    fn truncate(&self, mut hash: Hash) -> Hash {
+        scrub_tail(&mut hash, self.digest_len);
+        hash.truncate(self.digest_len);
+        hash
+    }
+}
+
+/// overwrites the bytes past `len` that truncating drops, so no part of a full hash is left behind
+fn scrub_tail(hash: &mut Hash, len: usize) {
+    let mut tail = hash.split_off(len);
+    scrub(&mut tail);
+}
+
+/// reads `digest_len` bytes of output from an extendable output function fed with `parts`
+fn xof<X: sha3::digest::Update + sha3::digest::ExtendableOutput + Default>(parts: &[&[u8]], digest_len: usize) -> Hash {
+    let mut hash = vec![0; digest_len];
+    xof_into::<X>(parts, &mut hash);
+    hash
+}
+
+/// fills `out` with output of an extendable output function fed with `parts`
+fn xof_into<X: sha3::digest::Update + sha3::digest::ExtendableOutput + Default>(parts: &[&[u8]], out: &mut [u8]) {
+    let mut xof = X::default();
+    for part in parts {
+        xof.update(part);
+    }
+    xof.finalize_xof_into(out);
+}
+
+/// BLAKE2b of `parts` with a `digest_len` byte output
+fn blake2b(parts: &[&[u8]], digest_len: usize) -> Hash {
+    let mut hash = vec![0; digest_len];
+    blake2b_into(parts, &mut hash);
+    hash
+}
+
+/// BLAKE2b of `parts` with an output as long as `out`
+fn blake2b_into(parts: &[&[u8]], out: &mut [u8]) {
+    use blake2::digest::{Update, VariableOutput};
+
+    let mut blake2b = blake2::Blake2bVar::new(out.len()).expect("length checked on construction");
+    for part in parts {
+        blake2b.update(part);
+    }
+    blake2b.finalize_variable(out).expect("buffer of the configured length");
+}
+
+impl Hasher for Shake128Hasher {
+    fn algorithm(&self) -> Option<HashAlgorithm> {
+        Some(HashAlgorithm::Shake128)
+    }
+
+    fn digest_len(&self) -> usize {
+        self.digest_len
+    }
+
+    fn hash(&self, data: &[u8]) -> Hash {
+        xof::<sha3::Shake128>(&[data], self.digest_len)
+    }
+
+    fn hash_concat(&self, left: &[u8], right: &[u8]) -> Hash {
+        xof::<sha3::Shake128>(&[left, right], self.digest_len)
+    }
+
+    fn hash_into(&self, data: &[u8], out: &mut [u8]) {
+        xof_into::<sha3::Shake128>(&[data], out);
+    }
+
+    fn hash_concat_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        xof_into::<sha3::Shake128>(&[left, right], out);
+    }
+}
+
+impl Hasher for Shake256Hasher {
+    fn algorithm(&self) -> Option<HashAlgorithm> {
+        Some(HashAlgorithm::Shake256)
+    }
+
+    fn digest_len(&self) -> usize {
+        self.digest_len
+    }
+
+    fn hash(&self, data: &[u8]) -> Hash {
+        xof::<sha3::Shake256>(&[data], self.digest_len)
+    }
+
+    fn hash_concat(&self, left: &[u8], right: &[u8]) -> Hash {
+        xof::<sha3::Shake256>(&[left, right], self.digest_len)
+    }
+
+    fn hash_into(&self, data: &[u8], out: &mut [u8]) {
+        xof_into::<sha3::Shake256>(&[data], out);
+    }
+
+    fn hash_concat_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        xof_into::<sha3::Shake256>(&[left, right], out);
+    }
+}
+
+impl Hasher for Blake2bHasher {
+    fn algorithm(&self) -> Option<HashAlgorithm> {
+        Some(HashAlgorithm::Blake2b(self.digest_len as u8))
+    }
+
+    fn digest_len(&self) -> usize {
+        self.digest_len
+    }
+
+    fn hash(&self, data: &[u8]) -> Hash {
+        blake2b(&[data], self.digest_len)
+    }
+
+    fn hash_concat(&self, left: &[u8], right: &[u8]) -> Hash {
+        blake2b(&[left, right], self.digest_len)
+    }
+
+    fn hash_into(&self, data: &[u8], out: &mut [u8]) {
+        blake2b_into(&[data], out);
+    }
+
+    fn hash_concat_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        blake2b_into(&[left, right], out);
+    }
+}
+
+impl<H: Hasher> Hasher for Truncated<H> {
+    /// multihashes identify truncated digests by the code of the full digest and a shorter length,
+    /// except for BLAKE2b, whose codes stand for its own output lengths rather than truncations
+    fn algorithm(&self) -> Option<HashAlgorithm> {
+        match self.inner.algorithm() {
+            Some(HashAlgorithm::Blake2b(_)) => None,
+            algorithm => algorithm,
+        }
+    }
+
+    fn digest_len(&self) -> usize {
+        self.digest_len
+    }
+
+    fn hash(&self, data: &[u8]) -> Hash {
+        self.truncate(self.inner.hash(data))
+    }
+
+    fn hash_concat(&self, left: &[u8], right: &[u8]) -> Hash {
+        self.truncate(self.inner.hash_concat(left, right))
+    }
+
+    fn hash_into(&self, data: &[u8], out: &mut [u8]) {
+        let mut full = [0; 255];
+        let full = &mut full[..self.inner.digest_len()];
+        self.inner.hash_into(data, full);
+        out.copy_from_slice(&full[..self.digest_len]);
+        scrub_slice(full);
+    }
+
+    fn hash_concat_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        let mut full = [0; 255];
+        let full = &mut full[..self.inner.digest_len()];
+        self.inner.hash_concat_into(left, right, full);
+        out.copy_from_slice(&full[..self.digest_len]);
+        scrub_slice(full);
+    }
+
+    fn metrics(&self) -> Option<&dyn MetricsSink> {
+        self.inner.metrics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashers_produce_configured_lengths() {
+        let shake128 = Shake128Hasher::new(20).expect("valid length");
+        let shake256 = Shake256Hasher::new(100).expect("valid length");
+        let blake2b = Blake2bHasher::new(48).expect("valid length");
+
+        assert_eq!(shake128.hash(b"abc").len(), 20);
+        assert_eq!(shake256.hash(b"abc").len(), 100);
+        assert_eq!(blake2b.hash(b"abc").len(), 48);
+        assert_eq!(Sha256Hasher::new().hash(b"abc").len(), 32);
+        assert!(Shake128Hasher::new(0).is_none());
+        assert!(Shake256Hasher::new(256).is_none());
+        assert!(Blake2bHasher::new(65).is_none());
+    }
+
+    #[test]
+    fn test_hashing_in_place_matches_hashing() {
+        fn check<H: Hasher>(hasher: H) {
+            let mut out = vec![0; hasher.digest_len()];
+            hasher.hash_into(b"abc", &mut out);
+            assert_eq!(out, hasher.hash(b"abc"));
+            hasher.hash_concat_into(b"left", b"right", &mut out);
+            assert_eq!(out, hasher.hash_concat(b"left", b"right"));
+        }
+        check(Sha256Hasher::new());
+        check(DigestHasher::<sha3::Keccak256>::new());
+        check(Shake128Hasher::new(20).expect("valid length"));
+        check(Shake256Hasher::new(100).expect("valid length"));
+        check(Blake2bHasher::new(48).expect("valid length"));
+        check(Truncated::new(Sha256Hasher::new(), 16).expect("valid length"));
+    }
+
+    #[test]
+    fn test_hash_concat_hashes_concatenation() {
+        let left = vec![1; 32];
+        let right = vec![2; 32];
+        let concatenated = [left.as_slice(), right.as_slice()].concat();
+
+        let shake128 = Shake128Hasher::new(16).expect("valid length");
+        let blake2b = Blake2bHasher::new(32).expect("valid length");
+        assert_eq!(Sha256Hasher::new().hash_concat(&left, &right), Sha256Hasher::new().hash(&concatenated));
+        assert_eq!(shake128.hash_concat(&left, &right), shake128.hash(&concatenated));
+        assert_eq!(blake2b.hash_concat(&left, &right), blake2b.hash(&concatenated));
+    }
+
+    #[test]
+    fn test_xof_output_is_prefix_of_longer_output() {
+        // SHAKE hashes of the same data only differ in how much output is read
+        let short = Shake256Hasher::new(16).expect("valid length").hash(b"abc");
+        let long = Shake256Hasher::new(64).expect("valid length").hash(b"abc");
+        assert_eq!(short, long[..16]);
+        assert_eq!(
+            hex::encode(Shake128Hasher::new(32).expect("valid length").hash(b"")),
+            "7f9c2ba4e88f827d616045507605853ed73b8093f6efbc88eb1a6eacfa66ef26"
+        );
+    }
+
+    #[test]
+    fn test_digest_hashers_match_their_digest() {
+        let sha3 = DigestHasher::<sha3::Sha3_256>::new();
+        assert_eq!(sha3.hash(b"abc"), sha3::Sha3_256::digest(b"abc").to_vec());
+        assert_eq!(sha3.digest_len(), 32);
+        assert_eq!(sha3.algorithm(), Some(HashAlgorithm::Sha3_256));
+        assert_eq!(DigestHasher::<sha2::Sha512>::new().digest_len(), 64);
+        assert_eq!(Sha256Hasher::new().algorithm(), Some(HashAlgorithm::Sha2_256));
+        // a digest this crate has no multicodec code for still hashes, it just can't be identified
+        assert_eq!(DigestHasher::<sha2::Sha224>::new().algorithm(), None);
+        assert_eq!(DigestHasher::<sha2::Sha224>::new().hash(b"").len(), 28);
+    }
+
+    #[test]
+    fn test_truncated_hashers_keep_prefix_of_hashes() {
+        let truncated = Truncated::new(Sha256Hasher::new(), 16).expect("valid length");
+        assert_eq!(truncated.hash(b"abc"), Sha256Hasher::new().hash(b"abc")[..16]);
+        assert_eq!(truncated.hash_concat(b"l", b"r"), Sha256Hasher::new().hash_concat(b"l", b"r")[..16]);
+        assert_eq!(truncated.digest_len(), 16);
+        assert_eq!(truncated.algorithm(), Some(HashAlgorithm::Sha2_256));
+        let blake2b = Blake2bHasher::new(64).expect("valid length");
+        assert_eq!(Truncated::new(blake2b, 20).expect("valid length").algorithm(), None);
+        assert!(Truncated::new(Sha256Hasher::new(), 0).is_none());
+        assert!(Truncated::new(Sha256Hasher::new(), 33).is_none());
+    }
+}
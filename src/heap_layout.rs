@@ -0,0 +1,103 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::MerkleTree;
+use crate::node_hash::LeafHash;
+
+// The heap layout is the array of a complete binary tree of `2^depth` leaves, 1-indexed: slot 1 holds
+// the root and the children of slot `i` are slots `2i` and `2i + 1`, so slot `2^(depth - level) + index`
+// holds the node at `index` on `level`. Slot 0 and the slots of nodes the tree doesn't have, to the right
+// of the last node of each level, are all zero bytes. A promoted node appears on every level it is
+// promoted across, with its left child holding the very same hash and an empty right child.
+
+impl MerkleTree {
+    /// Reads a SHA-256 tree of `leaf_count` leaves from its heap layout, see `from_heap_bytes_with_hasher`
+    pub fn from_heap_bytes(bytes: &[u8], leaf_count: u64) -> Option<MerkleTree> {
+        MerkleTree::from_heap_bytes_with_hasher(bytes, leaf_count, Sha256Hasher::new())
+    }
+
+    /// Gets number of bytes the heap layout of a tree over `leaf_count` leaves with `hash_size` byte hashes takes
+    /// that is two hashes per leaf of the tree padded to a power of two leaves, or `None` when that overflows
+    pub fn heap_len(leaf_count: u64, hash_size: usize) -> Option<usize> {
+        let slots = usize::try_from(leaf_count.max(1).checked_next_power_of_two()?.checked_mul(2)?).ok()?;
+        slots.checked_mul(hash_size)
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Writes every node of the tree into the 1-indexed heap layout, as one contiguous buffer
+    /// a consumer can map and index directly, slot `i` taking bytes `i * hash size..(i + 1) * hash size`
+    pub fn to_heap_bytes(&self) -> Vec<u8> {
+        let digest_len = self.hasher.digest_len();
+        let depth = self.levels.count() - 1;
+        let mut bytes = vec![0; MerkleTree::heap_len(self.leaf_count as u64, digest_len).expect("the tree fits in memory")];
+        for level in 0..=depth {
+            let first = 1 << (depth - level);
+            bytes[first * digest_len..][..self.levels.len(level) * digest_len].copy_from_slice(self.levels.level(level));
+        }
+        bytes
+    }
+
+    /// Reads a tree of `leaf_count` leaves built with the given hash function from its heap layout
+    /// the tree is rebuilt from the leaf slots, and `None` is returned unless every other slot holds
+    /// the hash it should, or the bytes are not exactly `heap_len` long
+    pub fn from_heap_bytes_with_hasher(bytes: &[u8], leaf_count: u64, hasher: H) -> Option<MerkleTree<H>> {
+        let digest_len = hasher.digest_len();
+        if leaf_count == 0 || digest_len == 0 || Some(bytes.len()) != MerkleTree::heap_len(leaf_count, digest_len) {
+            return None;
+        }
+        let first_leaf = bytes.len() / digest_len / 2;
+        let leaf_hashes = bytes[first_leaf * digest_len..].chunks_exact(digest_len).take(leaf_count as usize).map(|leaf| LeafHash::new(leaf.to_vec())).collect();
+        let tree = MerkleTree::from_leaf_hashes_with_hasher(leaf_hashes, hasher);
+        (tree.to_heap_bytes() == bytes).then_some(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_heap_layout_indexes_children_at_twice_the_parent() {
+        let data = example_data(6);
+        let tree = MerkleTree::construct(&data);
+        let bytes = tree.to_heap_bytes();
+        assert_eq!(bytes.len(), MerkleTree::heap_len(6, 32).expect("fits"));
+        assert_eq!(bytes.len(), 16 * 32);
+        let slot = |i: usize| &bytes[i * 32..(i + 1) * 32];
+        assert_eq!(slot(0), [0; 32]);
+        assert_eq!(slot(1), tree.root().as_bytes());
+        for i in [1, 2, 4, 5] {
+            assert_eq!(Sha256Hasher::new().hash_concat(slot(2 * i), slot(2 * i + 1)), slot(i));
+        }
+        // leaves 4 and 5 are paired, and their parent is promoted to the level below the root
+        assert_eq!(slot(3), slot(6));
+        assert_eq!(slot(7), [0; 32]);
+        assert_eq!(slot(8 + 5), tree.leaf_hash(5).expect("index is in range").as_bytes());
+        assert_eq!(slot(8 + 6), [0; 32]);
+    }
+
+    #[test]
+    fn test_heap_layout_round_trips() {
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        for n in [1, 2, 3, 7, 8, 9, 100] {
+            let tree = MerkleTree::construct_with_hasher(&example_data(n), hasher);
+            let bytes = tree.to_heap_bytes();
+            assert_eq!(MerkleTree::from_heap_bytes_with_hasher(&bytes, n as u64, hasher), Some(tree));
+        }
+
+        let tree = MerkleTree::construct(&example_data(9));
+        let mut bytes = tree.to_heap_bytes();
+        // a tenth leaf would have a parent where the layout holds zeros
+        assert!(MerkleTree::from_heap_bytes(&bytes, 10).is_none());
+        assert!(MerkleTree::from_heap_bytes(&bytes, 17).is_none());
+        assert!(MerkleTree::from_heap_bytes(&bytes[1..], 9).is_none());
+        // a slot past the last leaf
+        bytes[31 * 32] ^= 1;
+        assert!(MerkleTree::from_heap_bytes(&bytes, 9).is_none());
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{Hash, HashDirection, MerkleTree, Proof};
+use crate::metrics::{self, MetricsEvent};
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+use crate::zero_hashes::ZeroHashes;
+
+/// Tree of a fixed number of slots, `2^depth`, every slot holding a leaf or the default leaf
+///
+/// A vacant slot holds the default leaf, unless told otherwise a hash of all zero bytes, which no data
+/// hashes to. Only the nodes above occupied slots are stored, every other node being the hash of an
+/// empty subtree of its height, see `ZeroHashes`, so a tree of 2^40 slots costs no more than the slots
+/// in use. Besides proving what a slot holds, the tree proves that a slot holds nothing at all, see
+/// `prove_vacant`. While every slot is occupied, the root and proofs are the ones `MerkleTree::construct`
+/// gives over the slots in order.
+#[derive(Debug, Clone)]
+pub struct IndexedTree<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    depth: u32,
+    /// hashes of non-empty nodes by (level, index), level 0 the slots
+    nodes: HashMap<(u32, u64), Hash>,
+    /// hash of an empty subtree by height, the default leaf first
+    empty: ZeroHashes,
+}
+
+impl IndexedTree {
+    /// Starts a SHA-256 tree of `2^depth` vacant slots, see `with_hasher`
+    pub fn new(depth: u32) -> IndexedTree {
+        IndexedTree::with_hasher(depth, Sha256Hasher::new())
+    }
+
+    /// Verifies a SHA-256 proof that the slot at `index` holds the given data, see `verify_slot_with_hasher`
+    pub fn verify_slot(data: &[u8], index: u64, depth: u32, proof: &Proof, root_hash: &Root) -> bool {
+        IndexedTree::verify_slot_with_hasher(data, index, depth, proof, root_hash, &Sha256Hasher::new())
+    }
+
+    /// Verifies a SHA-256 proof that the slot at `index` holds the all zero default leaf, see `verify_vacant_with_hasher`
+    pub fn verify_vacant(index: u64, depth: u32, proof: &Proof, root_hash: &Root) -> bool {
+        IndexedTree::verify_vacant_with_hasher(index, depth, proof, root_hash, &LeafHash::new(vec![0; 32]), &Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> IndexedTree<H> {
+    /// Starts a tree of `2^depth` vacant slots, hashing with the given hash function
+    ///
+    /// # Panics
+    ///
+    /// When `depth` is above 63, as slots are indexed by `u64`.
+    pub fn with_hasher(depth: u32, hasher: H) -> IndexedTree<H> {
+        let default_leaf = LeafHash::new(vec![0; hasher.digest_len()]);
+        IndexedTree::with_default_leaf(depth, default_leaf, hasher)
+    }
+
+    /// Starts a tree of `2^depth` slots that all hold the given default leaf, hashing with the given hash function
+    /// the default leaf should be one no data hashes to, or storing that data can't be told from vacating the slot
+    ///
+    /// # Panics
+    ///
+    /// When `depth` is above 63.
+    pub fn with_default_leaf(depth: u32, default_leaf: LeafHash, hasher: H) -> IndexedTree<H> {
+        assert!(depth < 64, "slots are indexed by u64");
+        IndexedTree {
+            empty: ZeroHashes::with_hasher(depth, default_leaf, &hasher),
+            hasher,
+            depth,
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Gets the leaf a vacant slot holds
+    pub fn default_leaf(&self) -> LeafHash {
+        LeafHash::new(self.empty.default_leaf().to_vec())
+    }
+
+    /// Gets number of levels below the root
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Gets number of slots, occupied or not
+    pub fn capacity(&self) -> u64 {
+        1 << self.depth
+    }
+
+    /// Gets root hash for this tree
+    pub fn root(&self) -> Root {
+        Root::new(self.node(self.depth, 0).to_vec())
+    }
+
+    /// Tells whether the slot at `index` holds the default leaf
+    pub fn is_vacant(&self, index: u64) -> bool {
+        !self.nodes.contains_key(&(0, index))
+    }
+
+    /// Hashes the data into the slot at `index`, replacing what the slot held
+    ///
+    /// # Panics
+    ///
+    /// When the index is not below the capacity.
+    pub fn insert(&mut self, index: u64, data: &[u8]) {
+        self.set(index, Some(self.hasher.hash(data)));
+    }
+
+    /// Empties the slot at `index`, so that it holds the default leaf again
+    ///
+    /// # Panics
+    ///
+    /// When the index is not below the capacity.
+    pub fn remove(&mut self, index: u64) {
+        self.set(index, None);
+    }
+
+    /// Returns the proof for the data in the slot at `index`, `None` when the slot is vacant
+    pub fn prove(&self, index: u64) -> Option<Proof<H>> {
+        (index < self.capacity() && !self.is_vacant(index)).then(|| self.path(index))
+    }
+
+    /// Returns the proof that the slot at `index` is vacant, `None` when it is occupied or out of range
+    pub fn prove_vacant(&self, index: u64) -> Option<Proof<H>> {
+        (index < self.capacity() && self.is_vacant(index)).then(|| self.path(index))
+    }
+
+    /// Verifies that the proof leads from the given data in the slot at `index` of a tree of `2^depth`
+    /// slots to the given root_hash with the given hash function
+    /// the proof has to take the sibling sides the index gives, so it can't be passed off as the proof of another slot
+    pub fn verify_slot_with_hasher(data: &[u8], index: u64, depth: u32, proof: &Proof<H>, root_hash: &Root, hasher: &H) -> bool {
+        metrics::verified(hasher, is_path_of(index, depth, proof)) && MerkleTree::verify_proof_hashed_with_hasher(&LeafHash::of(data, hasher), proof, root_hash, hasher)
+    }
+
+    /// Verifies that the proof leads from the default leaf in the slot at `index` of a tree of `2^depth`
+    /// slots to the given root_hash with the given hash function, see `default_leaf`
+    pub fn verify_vacant_with_hasher(index: u64, depth: u32, proof: &Proof<H>, root_hash: &Root, default_leaf: &LeafHash, hasher: &H) -> bool {
+        metrics::verified(hasher, is_path_of(index, depth, proof)) && MerkleTree::verify_proof_hashed_with_hasher(default_leaf, proof, root_hash, hasher)
+    }
+
+    /// writes a slot and rehashes its ancestors, dropping every node that turns out empty
+    fn set(&mut self, index: u64, leaf_hash: Option<Hash>) {
+        assert!(index < self.capacity(), "slot {index} is out of range");
+        let mut node = leaf_hash;
+        let mut position = index;
+        for level in 0..=self.depth {
+            match node {
+                Some(ref hash) if hash.as_slice() != self.empty_hash(level) => self.nodes.insert((level, position), hash.clone()),
+                _ => self.nodes.remove(&(level, position)),
+            };
+            if level == self.depth {
+                break;
+            }
+            let (left, right) = (self.node(level, position & !1), self.node(level, position | 1));
+            node = Some(self.hasher.hash_concat(left, right));
+            position /= 2;
+        }
+    }
+
+    /// hash of the node at `index` on `level`, the empty subtree's when nothing is stored there
+    fn node(&self, level: u32, index: u64) -> &[u8] {
+        self.nodes.get(&(level, index)).map_or_else(|| self.empty_hash(level), Vec::as_slice)
+    }
+
+    /// hash of an empty subtree of the given height, there being one for every level
+    fn empty_hash(&self, level: u32) -> &[u8] {
+        self.empty.get(level).expect("the zero hashes reach the root")
+    }
+
+    /// siblings of the slot at `index` from the slot up, one per level
+    fn path(&self, index: u64) -> Proof<H> {
+        let hashes = (0..self.depth)
+            .zip(directions(index, self.depth))
+            .map(|(level, hash_direction)| (hash_direction, self.node(level, (index >> level) ^ 1).to_vec()))
+            .collect();
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Proof::new(hashes)
+    }
+}
+
+/// sides the siblings of the slot at `index` go on, from the slot up
+fn directions(index: u64, depth: u32) -> impl Iterator<Item = HashDirection> {
+    (0..depth).map(move |level| if (index >> level) & 1 == 0 { HashDirection::Right } else { HashDirection::Left })
+}
+
+/// whether the proof has a sibling on every level, on the sides that lead to the slot at `index`
+fn is_path_of<H: Hasher>(index: u64, depth: u32, proof: &Proof<H>) -> bool {
+    depth < 64
+        && index < 1 << depth
+        && proof.hashes.len() == depth as usize
+        && directions(index, depth).zip(&proof.hashes).all(|(expected, (hash_direction, _))| expected == *hash_direction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::Data;
+
+    #[test]
+    fn test_full_trees_match_merkle_tree() {
+        let data: Vec<Data> = (0..8).map(|i| vec![i as u8]).collect();
+        let mut tree = IndexedTree::new(3);
+        for (index, leaf) in data.iter().enumerate() {
+            tree.insert(index as u64, leaf);
+        }
+        let full = MerkleTree::construct(&data);
+        assert_eq!(tree.root(), full.root());
+        let proof = tree.prove(5).expect("slot is occupied");
+        assert_eq!(proof, full.prove_by_index(5).expect("index is in range"));
+        assert!(IndexedTree::verify_slot(&data[5], 5, 3, &proof, &tree.root()));
+        assert!(!IndexedTree::verify_slot(&data[5], 4, 3, &proof, &tree.root()));
+        assert!(tree.prove_vacant(5).is_none());
+    }
+
+    #[test]
+    fn test_vacant_slots_are_proven_empty() {
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        let mut tree = IndexedTree::with_hasher(40, hasher);
+        let default_leaf = tree.default_leaf();
+        let empty_root = tree.root();
+        tree.insert(3, b"claimed");
+        tree.insert(1 << 39, b"claimed too");
+        assert_eq!(tree.capacity(), 1 << 40);
+        assert!(tree.is_vacant(2) && !tree.is_vacant(3));
+
+        let proof = tree.prove_vacant(2).expect("slot is vacant");
+        assert!(IndexedTree::verify_vacant_with_hasher(2, 40, &proof, &tree.root(), &default_leaf, &hasher));
+        // the proof of one vacant slot says nothing about its neighbour
+        assert!(!IndexedTree::verify_vacant_with_hasher(3, 40, &proof, &tree.root(), &default_leaf, &hasher));
+        assert!(!IndexedTree::verify_vacant_with_hasher(2, 40, &proof, &empty_root, &default_leaf, &hasher));
+        assert!(!IndexedTree::verify_vacant_with_hasher(2, 39, &proof, &tree.root(), &default_leaf, &hasher));
+
+        // an occupied slot can't be passed off as vacant
+        let proof = tree.prove(3).expect("slot is occupied");
+        assert!(tree.prove_vacant(3).is_none());
+        assert!(!IndexedTree::verify_vacant_with_hasher(3, 40, &proof, &tree.root(), &default_leaf, &hasher));
+        assert!(tree.prove_vacant(1 << 40).is_none());
+
+        tree.remove(3);
+        tree.remove(1 << 39);
+        assert_eq!(tree.root(), empty_root);
+        assert!(tree.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_custom_default_leaves() {
+        let default_leaf = LeafHash::of(b"unclaimed", &Sha256Hasher::new());
+        let mut tree = IndexedTree::with_default_leaf(2, default_leaf.clone(), Sha256Hasher::new());
+        tree.insert(1, b"claimed");
+        let data: Vec<Data> = [b"unclaimed".as_slice(), b"claimed", b"unclaimed", b"unclaimed"].iter().map(|leaf| leaf.to_vec()).collect();
+        assert_eq!(tree.root(), MerkleTree::construct(&data).root());
+        let proof = tree.prove_vacant(2).expect("slot is vacant");
+        assert!(IndexedTree::verify_vacant_with_hasher(2, 2, &proof, &tree.root(), &default_leaf, &Sha256Hasher::new()));
+        assert!(!IndexedTree::verify_vacant(2, 2, &proof, &tree.root()));
+    }
+}
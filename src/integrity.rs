@@ -0,0 +1,389 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+
+use crate::manifest::file_entry;
+use crate::merkletree::{hash_data, scrub, Data, Hash, MerkleTree};
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+
+/// first line of every integrity manifest
+const HEADER: &str = "#merkle-manifest v1";
+
+/// A file as recorded in an integrity manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    /// path relative to the directory, with `/` between components
+    pub path: String,
+    pub size: u64,
+    /// hashes of the file's chunks in order, an empty file has the hash of one empty chunk
+    pub leaf_hashes: Vec<LeafHash>,
+    pub root: Hash,
+}
+
+/// mtree-style record of every file below a directory, committed to by a single root
+///
+/// The root is the `Manifest` root over the files' chunk trees, so it can be signed and published
+/// on its own while the manifest tells exactly which files and chunks changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityManifest {
+    chunk_size: usize,
+    /// files ordered by path
+    files: Vec<FileRecord>,
+}
+
+/// A difference between a directory and its manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// the file is in the directory but not in the manifest
+    Added(String),
+    /// the file is in the manifest but not in the directory
+    Removed(String),
+    /// the file's content differs in the chunks at these positions, counting chunks only one side has
+    Modified { path: String, chunks: Vec<usize> },
+}
+
+/// Reasons an integrity manifest can not be read
+#[derive(Debug)]
+pub enum IntegrityError {
+    Io(io::Error),
+    /// the line is malformed or its hashes do not add up to the roots it records
+    Parse { line: usize, reason: String },
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::Io(error) => write!(f, "{error}"),
+            IntegrityError::Parse { line, reason } => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+impl From<io::Error> for IntegrityError {
+    fn from(error: io::Error) -> IntegrityError {
+        IntegrityError::Io(error)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(path) => write!(f, "added {path}"),
+            Change::Removed(path) => write!(f, "removed {path}"),
+            Change::Modified { path, chunks } => {
+                let chunks: Vec<String> = chunks.iter().map(usize::to_string).collect();
+                write!(f, "modified {path} chunks {}", chunks.join(","))
+            }
+        }
+    }
+}
+
+impl Change {
+    /// Gets the path of the changed file
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added(path) | Change::Removed(path) | Change::Modified { path, .. } => path,
+        }
+    }
+}
+
+impl FileRecord {
+    /// Hashes the content read from `reader` in `chunk_size` byte chunks
+    pub fn hash(path: String, mut reader: impl Read, chunk_size: usize) -> io::Result<FileRecord> {
+        assert!(chunk_size > 0, "chunks must not be empty");
+        let mut leaf_hashes = vec![];
+        let mut size = 0;
+        loop {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            (&mut reader).take(chunk_size as u64).read_to_end(&mut chunk)?;
+            size += chunk.len() as u64;
+            let last = chunk.len() < chunk_size;
+            if !chunk.is_empty() || leaf_hashes.is_empty() {
+                leaf_hashes.push(LeafHash::new(hash_data(&chunk)));
+            }
+            scrub(&mut chunk);
+            if last {
+                break;
+            }
+        }
+        let root = MerkleTree::from_leaf_hashes(leaf_hashes.clone()).root().into_hash();
+        Ok(FileRecord { path, size, leaf_hashes, root })
+    }
+}
+
+impl IntegrityManifest {
+    /// Records every regular file below `dir`, hashed in `chunk_size` byte chunks
+    /// symbolic links are not followed
+    pub fn scan(dir: impl AsRef<Path>, chunk_size: usize) -> io::Result<IntegrityManifest> {
+        let mut paths = vec![];
+        list_files(dir.as_ref(), "", &mut paths)?;
+        paths.sort();
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let file = File::open(dir.as_ref().join(&path))?;
+            files.push(FileRecord::hash(path, file, chunk_size)?);
+        }
+        Ok(IntegrityManifest { chunk_size, files })
+    }
+
+    /// Creates a manifest from files hashed in `chunk_size` byte chunks, which have to be ordered by path
+    pub(crate) fn from_records(chunk_size: usize, files: Vec<FileRecord>) -> IntegrityManifest {
+        debug_assert!(files.windows(2).all(|pair| pair[0].path < pair[1].path), "files are ordered by path");
+        IntegrityManifest { chunk_size, files }
+    }
+
+    /// Gets the size of the chunks files were hashed in
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Gets the recorded files ordered by path
+    pub fn files(&self) -> &[FileRecord] {
+        &self.files
+    }
+
+    /// Gets the root to sign, `None` for a manifest of an empty directory
+    pub fn root(&self) -> Option<Root> {
+        let entries: Vec<Data> = self.files.iter().map(|file| file_entry(&file.path, &file.root)).collect();
+        (!entries.is_empty()).then(|| MerkleTree::construct(&entries).root())
+    }
+
+    /// Compares the directory with the manifest, returning every difference in path order
+    pub fn verify_directory(&self, dir: impl AsRef<Path>) -> io::Result<Vec<Change>> {
+        let current = IntegrityManifest::scan(dir, self.chunk_size)?;
+        let mut changes = vec![];
+        let (mut recorded, mut found) = (self.files.iter().peekable(), current.files.iter().peekable());
+        loop {
+            match (recorded.peek(), found.peek()) {
+                (None, None) => break,
+                (Some(file), None) => {
+                    changes.push(Change::Removed(file.path.clone()));
+                    recorded.next();
+                }
+                (None, Some(file)) => {
+                    changes.push(Change::Added(file.path.clone()));
+                    found.next();
+                }
+                (Some(old), Some(new)) if old.path < new.path => {
+                    changes.push(Change::Removed(old.path.clone()));
+                    recorded.next();
+                }
+                (Some(old), Some(new)) if old.path > new.path => {
+                    changes.push(Change::Added(new.path.clone()));
+                    found.next();
+                }
+                (Some(old), Some(new)) => {
+                    if old.root != new.root || old.size != new.size {
+                        changes.push(Change::Modified { path: old.path.clone(), chunks: changed_chunks(old, new) });
+                    }
+                    recorded.next();
+                    found.next();
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Writes the manifest as text: a header with the chunk size, the root, then a line per file
+    /// with its escaped path, size, root and leaf hashes, all hashes hex encoded
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{HEADER} chunk={}", self.chunk_size)?;
+        writeln!(writer, "root={}", self.root().map(hex::encode).unwrap_or_default())?;
+        for file in &self.files {
+            let leaves: Vec<String> = file.leaf_hashes.iter().map(hex::encode).collect();
+            writeln!(writer, "{} size={} root={} leaves={}", escape(&file.path), file.size, hex::encode(&file.root), leaves.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// Reads a manifest written by `write`, checking that every recorded root matches the hashes below it
+    pub fn read(reader: impl BufRead) -> Result<IntegrityManifest, IntegrityError> {
+        let mut lines = reader.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let parse_error = |line: usize, reason: &str| IntegrityError::Parse { line, reason: reason.to_string() };
+
+        let (_, header) = lines.next().ok_or_else(|| parse_error(1, "missing header"))?;
+        let chunk_size = header?
+            .strip_prefix(HEADER)
+            .and_then(|rest| rest.strip_prefix(" chunk="))
+            .and_then(|chunk_size| chunk_size.parse().ok())
+            .filter(|chunk_size| *chunk_size > 0)
+            .ok_or_else(|| parse_error(1, "missing header"))?;
+        let (_, root) = lines.next().ok_or_else(|| parse_error(2, "missing root"))?;
+        let root = root?.strip_prefix("root=").map(str::to_string).ok_or_else(|| parse_error(2, "missing root"))?;
+
+        let mut files: Vec<FileRecord> = vec![];
+        for (number, line) in lines {
+            let line = line?;
+            let file = parse_file(&line).ok_or_else(|| parse_error(number, "malformed file record"))?;
+            if MerkleTree::from_leaf_hashes(file.leaf_hashes.clone()).root().as_bytes() != file.root {
+                return Err(parse_error(number, "leaf hashes don't match file root"));
+            }
+            if files.last().is_some_and(|previous| previous.path >= file.path) {
+                return Err(parse_error(number, "files out of order"));
+            }
+            files.push(file);
+        }
+        let manifest = IntegrityManifest { chunk_size, files };
+        if manifest.root().map(hex::encode).unwrap_or_default() != root {
+            return Err(parse_error(2, "file roots don't match root"));
+        }
+        Ok(manifest)
+    }
+}
+
+/// positions of the chunks that differ between two versions of a file, counting chunks only one has
+pub(crate) fn changed_chunks(old: &FileRecord, new: &FileRecord) -> Vec<usize> {
+    let len = old.leaf_hashes.len().max(new.leaf_hashes.len());
+    (0..len).filter(|i| old.leaf_hashes.get(*i) != new.leaf_hashes.get(*i)).collect()
+}
+
+fn parse_file(line: &str) -> Option<FileRecord> {
+    let mut fields = line.split(' ');
+    let path = unescape(fields.next()?)?;
+    let size = fields.next()?.strip_prefix("size=")?.parse().ok()?;
+    let root = hex::decode(fields.next()?.strip_prefix("root=")?).ok()?;
+    let leaves = fields.next()?.strip_prefix("leaves=")?;
+    let leaf_hashes = leaves.split(',').map(|leaf| hex::decode(leaf).ok().filter(|hash| hash.len() == 32).map(LeafHash::new)).collect::<Option<Vec<_>>>()?;
+    if fields.next().is_some() || root.len() != 32 {
+        return None;
+    }
+    Some(FileRecord { path, size, leaf_hashes, root })
+}
+
+/// relative paths of the regular files below `dir`, which is `prefix` below the scanned directory
+pub(crate) fn list_files(dir: &Path, prefix: &str, paths: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().map_err(|name| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("path is not UTF-8: {}", name.to_string_lossy()))
+        })?;
+        let path = format!("{prefix}{name}");
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            list_files(&entry.path(), &format!("{path}/"), paths)?;
+        } else if file_type.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// escapes spaces, backslashes and anything but printable ASCII as `\ooo` octal, like mtree does
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_graphic() && byte != b'\\' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("\\{byte:03o}"));
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = escaped.as_bytes();
+    while let Some((byte, tail)) = rest.split_first() {
+        if *byte == b'\\' {
+            let digits = std::str::from_utf8(tail.get(..3)?).ok()?;
+            bytes.push(u8::from_str_radix(digits, 8).ok()?);
+            rest = &tail[3..];
+        } else {
+            bytes.push(*byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest;
+
+    /// a fresh directory below the system's temporary directory
+    fn example_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("merkle-integrity-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub dir")).expect("creates directory");
+        fs::write(dir.join("a.txt"), vec![1; 100]).expect("writes file");
+        fs::write(dir.join("empty"), []).expect("writes file");
+        fs::write(dir.join("sub dir").join("b.bin"), vec![2; 64]).expect("writes file");
+        dir
+    }
+
+    #[test]
+    fn test_manifest_round_trips_and_matches_manifest_root() {
+        let dir = example_dir("round-trip");
+        let manifest = IntegrityManifest::scan(&dir, 32).expect("scans directory");
+        let paths: Vec<&str> = manifest.files().iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.txt", "empty", "sub dir/b.bin"]);
+        assert_eq!(manifest.files()[0].leaf_hashes.len(), 4);
+        assert_eq!(manifest.files()[1].leaf_hashes, vec![LeafHash::new(hash_data(&vec![]))]);
+
+        let mut text = vec![];
+        manifest.write(&mut text).expect("writes to memory");
+        assert!(String::from_utf8_lossy(&text).contains("sub\\040dir/b.bin size=64"));
+        assert_eq!(IntegrityManifest::read(text.as_slice()).expect("reads manifest"), manifest);
+
+        // the same root as a manifest over the same chunk trees
+        let chunk_trees = manifest.files().iter().map(|file| (file.path.clone(), MerkleTree::from_leaf_hashes(file.leaf_hashes.clone())));
+        assert_eq!(manifest.root(), Manifest::from_files(chunk_trees).map(|manifest| manifest.root()));
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_verify_directory_reports_changed_files_and_chunks() {
+        let dir = example_dir("verify");
+        let manifest = IntegrityManifest::scan(&dir, 32).expect("scans directory");
+        assert_eq!(manifest.verify_directory(&dir).expect("scans directory"), vec![]);
+
+        let mut content = vec![1; 100];
+        content[40] = 9;
+        content.extend_from_slice(&[1; 10]);
+        fs::write(dir.join("a.txt"), content).expect("writes file");
+        fs::remove_file(dir.join("empty")).expect("removes file");
+        fs::write(dir.join("new"), b"new").expect("writes file");
+
+        let changes = manifest.verify_directory(&dir).expect("scans directory");
+        assert_eq!(
+            changes,
+            vec![
+                Change::Modified { path: "a.txt".into(), chunks: vec![1, 3] },
+                Change::Removed("empty".into()),
+                Change::Added("new".into()),
+            ]
+        );
+        assert_eq!(changes[0].to_string(), "modified a.txt chunks 1,3");
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_read_rejects_tampered_manifests() {
+        let dir = example_dir("tampered");
+        let manifest = IntegrityManifest::scan(&dir, 32).expect("scans directory");
+        fs::remove_dir_all(dir).expect("removes directory");
+        let mut text = vec![];
+        manifest.write(&mut text).expect("writes to memory");
+        let text = String::from_utf8(text).expect("manifests are text");
+
+        let root = hex::encode(&manifest.files()[0].root);
+        let root_changed = text.replace(&root, &hex::encode([0; 32]));
+        assert!(matches!(IntegrityManifest::read(root_changed.as_bytes()), Err(IntegrityError::Parse { line: 3, .. })));
+        let dropped_file: String = text.lines().filter(|line| !line.starts_with("empty")).map(|line| format!("{line}\n")).collect();
+        assert!(matches!(IntegrityManifest::read(dropped_file.as_bytes()), Err(IntegrityError::Parse { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        for path in ["plain", "with space", "back\\slash", "ünïcode/ä b"] {
+            assert_eq!(unescape(&escape(path)).as_deref(), Some(path));
+        }
+        assert!(unescape("bad\\9").is_none());
+    }
+}
@@ -0,0 +1,234 @@
+use std::fmt;
+
+use crate::merkletree::{hash_data, split_point, Data, Hash, Levels, MerkleTree};
+use crate::multihash::{read_varint, write_varint, HashAlgorithm, Multihash};
+
+/// multicodec code of raw binary blocks
+pub const RAW_CODEC: u64 = 0x55;
+
+/// A version 1 content identifier of an IPLD block
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cid {
+    codec: u64,
+    multihash: Multihash,
+}
+
+/// An IPLD block ready to be put into a blockstore, addressed by its `cid`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub cid: Cid,
+    pub data: Vec<u8>,
+}
+
+impl Cid {
+    /// CID of a raw block whose SHA-256 digest is `hash`
+    pub fn raw(hash: Hash) -> Cid {
+        Cid {
+            codec: RAW_CODEC,
+            multihash: Multihash::new(HashAlgorithm::Sha2_256, hash),
+        }
+    }
+
+    /// multicodec code describing how the block content is encoded
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+
+    /// digest of the block content
+    pub fn multihash(&self) -> &Multihash {
+        &self.multihash
+    }
+
+    /// Binary form of the CID: `<varint version><varint codec><multihash>`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_varint(&mut bytes, 1);
+        write_varint(&mut bytes, self.codec);
+        bytes.extend(self.multihash.to_bytes());
+        bytes
+    }
+
+    /// Decodes the binary form of a version 1 CID, `None` for anything malformed
+    pub fn from_bytes(bytes: &[u8]) -> Option<Cid> {
+        let (version, rest) = read_varint(bytes)?;
+        if version != 1 {
+            return None;
+        }
+        let (codec, rest) = read_varint(rest)?;
+        let multihash = Multihash::from_bytes(rest)?;
+        Some(Cid { codec, multihash })
+    }
+
+    /// checks that the block content hashes to this CID
+    fn addresses(&self, data: &[u8]) -> bool {
+        self.multihash.algorithm() == HashAlgorithm::Sha2_256 && hash_data(&data.to_vec()).eq(self.multihash.digest())
+    }
+}
+
+/// Formats the CID as multibase lowercase base32, the default string form of version 1 CIDs
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+        let mut encoded = String::from("b");
+        let (mut buffer, mut bits) = (0u16, 0);
+        for byte in self.to_bytes() {
+            buffer = (buffer << 8) | u16::from(byte);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                encoded.push(ALPHABET[usize::from((buffer >> bits) & 0x1f)] as char);
+            }
+        }
+        if bits > 0 {
+            encoded.push(ALPHABET[usize::from((buffer << (5 - bits)) & 0x1f)] as char);
+        }
+        f.write_str(&encoded)
+    }
+}
+
+/// Maps every node of the tree to a raw IPLD block, root first
+///
+/// Leaves become blocks of their input data and inner nodes blocks of their concatenated child hashes.
+/// Both are exactly what the tree hashes, so every node hash already is the digest of its block
+/// and no second hashing pass is needed. Returns `None` when `input` is not the data the tree was built from.
+pub fn export_blocks(tree: &MerkleTree, input: &[Data]) -> Option<Vec<Block>> {
+    let mut blocks = vec![];
+    let mut leaves = input.iter();
+    collect_blocks(&tree.levels, (tree.levels.count() - 1, 0), &mut leaves, &mut blocks)?;
+    leaves.next().is_none().then_some(blocks)
+}
+
+/// recursive pre-order walk emitting a block per Node
+fn collect_blocks<'a>(levels: &Levels, (level, index): (usize, usize), leaves: &mut impl Iterator<Item = &'a Data>, blocks: &mut Vec<Block>) -> Option<()> {
+    let hash = levels.hash(level, index);
+    match levels.children(level, index) {
+        Some([left, right]) => {
+            blocks.push(Block {
+                cid: Cid::raw(hash.to_vec()),
+                data: [levels.hash(left.0, left.1), levels.hash(right.0, right.1)].concat(),
+            });
+            collect_blocks(levels, left, leaves, blocks)?;
+            collect_blocks(levels, right, leaves, blocks)
+        }
+        None => {
+            let leaf = leaves.next()?;
+            if hash_data(leaf) != hash {
+                return None;
+            }
+            blocks.push(Block {
+                cid: Cid::raw(hash.to_vec()),
+                data: leaf.clone(),
+            });
+            Some(())
+        }
+    }
+}
+
+/// Fetches the leaves of a tree with `leaf_count` leaves from a blockstore, starting at the root CID
+///
+/// `get_block` is asked for every block of the tree. Each block is checked against the CID it was
+/// requested by, so the leaves returned are guaranteed to construct a tree with the given root.
+/// Returns `None` when a block is missing or does not match its CID.
+pub fn import_leaves(root: &Cid, leaf_count: u64, mut get_block: impl FnMut(&Cid) -> Option<Vec<u8>>) -> Option<Vec<Data>> {
+    if leaf_count == 0 {
+        return None;
+    }
+    let mut leaves = Vec::with_capacity(usize::try_from(leaf_count).ok()?);
+    fetch_leaves(root, leaf_count, &mut get_block, &mut leaves)?;
+    Some(leaves)
+}
+
+/// recursive descent splitting the leaf range the same way construction pairs it
+fn fetch_leaves(cid: &Cid, leaf_count: u64, get_block: &mut impl FnMut(&Cid) -> Option<Vec<u8>>, leaves: &mut Vec<Data>) -> Option<()> {
+    if cid.codec() != RAW_CODEC {
+        return None;
+    }
+    let data = get_block(cid)?;
+    if !cid.addresses(&data) {
+        return None;
+    }
+    if leaf_count == 1 {
+        leaves.push(data);
+        return Some(());
+    }
+    if data.len() % 2 != 0 {
+        return None;
+    }
+    let (left, right) = data.split_at(data.len() / 2);
+    let left_count = split_point(leaf_count);
+    fetch_leaves(&Cid::raw(left.to_vec()), left_count, get_block, leaves)?;
+    fetch_leaves(&Cid::raw(right.to_vec()), leaf_count - left_count, get_block, leaves)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_export_blocks_are_addressed_by_tree_hashes() {
+        let data = example_data(5);
+        let tree = MerkleTree::construct(&data);
+        let blocks = export_blocks(&tree, &data).expect("this should export blocks");
+
+        // 5 leaves and 4 inner nodes
+        assert_eq!(blocks.len(), 9);
+        assert_eq!(blocks[0].cid, Cid::raw(tree.root().into_hash()));
+        for block in &blocks {
+            assert!(block.cid.addresses(&block.data));
+        }
+    }
+
+    #[test]
+    fn test_export_blocks_with_foreign_input_will_return_none() {
+        let data = example_data(4);
+        let tree = MerkleTree::construct(&data);
+
+        assert!(export_blocks(&tree, &example_data(3)).is_none());
+        assert!(export_blocks(&tree, &example_data(5)).is_none());
+    }
+
+    #[test]
+    fn test_import_leaves_round_trip() {
+        let data = example_data(7);
+        let tree = MerkleTree::construct(&data);
+        let blockstore = export_blocks(&tree, &data)
+            .expect("this should export blocks")
+            .into_iter()
+            .map(|block| (block.cid, block.data))
+            .collect::<HashMap<_, _>>();
+
+        let root = Cid::raw(tree.root().into_hash());
+        let leaves = import_leaves(&root, data.len() as u64, |cid| blockstore.get(cid).cloned());
+        assert_eq!(leaves, Some(data));
+    }
+
+    #[test]
+    fn test_import_leaves_with_tampered_block_will_return_none() {
+        let data = example_data(4);
+        let tree = MerkleTree::construct(&data);
+        let mut blockstore = export_blocks(&tree, &data)
+            .expect("this should export blocks")
+            .into_iter()
+            .map(|block| (block.cid, block.data))
+            .collect::<HashMap<_, _>>();
+        let leaf = Cid::raw(hash_data(&data[3]));
+        blockstore.insert(leaf, vec![42]);
+
+        let root = Cid::raw(tree.root().into_hash());
+        assert!(import_leaves(&root, data.len() as u64, |cid| blockstore.get(cid).cloned()).is_none());
+    }
+
+    #[test]
+    fn test_cid_string_and_bytes() {
+        // CID of the empty raw block, as printed by `ipfs block put --cid-codec raw`
+        let cid = Cid::raw(hash_data(&vec![]));
+        assert_eq!(cid.to_string(), "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku");
+        assert_eq!(Cid::from_bytes(&cid.to_bytes()), Some(cid));
+    }
+}
@@ -0,0 +1,161 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::accumulator::RootAccumulator;
+use crate::frontier::Frontier;
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{scrub, Data, MerkleTree};
+use crate::node_hash::LeafHash;
+
+/// Turns a value into the bytes of the leaf that commits to it
+///
+/// Two parties only compute the same root over the same records when they encode every record into
+/// the very same bytes. An encoder has to be deterministic: equal values give equal bytes, whatever
+/// the order a map was filled in or the platform the encoding runs on.
+pub trait LeafEncoder {
+    type Error: std::error::Error;
+
+    /// Encodes a value into leaf data
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Data, Self::Error>;
+}
+
+/// Canonical JSON: no whitespace, object keys sorted by their bytes, integers without a fraction
+///
+/// Structs are written as objects of their field names, so reordering the fields of a struct or the
+/// entries of a map doesn't change the leaf, while renaming a field does. Values JSON can't hold,
+/// such as 128-bit integers or maps with keys that aren't strings or integers, are refused.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CanonicalJson;
+
+impl LeafEncoder for CanonicalJson {
+    type Error = serde_json::Error;
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Data, serde_json::Error> {
+        serde_json::to_vec(&sort_keys(serde_json::to_value(value)?))
+    }
+}
+
+/// the value with the keys of every object in byte order
+/// the map of a `Value` only sorts its keys itself as long as no crate enables `serde_json/preserve_order`
+pub(crate) fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<(String, Value)> = object.into_iter().collect();
+            entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+            Value::Object(entries.into_iter().map(|(key, value)| (key, sort_keys(value))).collect())
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
+
+impl MerkleTree {
+    /// Constructs a SHA-256 tree over the canonical JSON of the given values, see `construct_values_with_hasher`
+    pub fn construct_values<T: Serialize>(values: &[T]) -> Result<MerkleTree, serde_json::Error> {
+        MerkleTree::construct_values_with_hasher(values, &CanonicalJson, Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Constructs a tree whose leaves are the given values in the given encoding, hashing with the given hash function
+    /// proofs of a value are the proofs of its encoding, see `LeafEncoder::encode`
+    pub fn construct_values_with_hasher<T: Serialize, E: LeafEncoder>(values: &[T], encoder: &E, hasher: H) -> Result<MerkleTree<H>, E::Error> {
+        let leaf_hashes = values.iter().map(|value| encode_leaf(value, encoder, &hasher)).collect::<Result<_, _>>()?;
+        Ok(MerkleTree::from_leaf_hashes_with_hasher(leaf_hashes, hasher))
+    }
+}
+
+impl<H: Hasher> Frontier<H> {
+    /// Pushes the canonical JSON of a value as the next leaf
+    pub fn push_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), serde_json::Error> {
+        self.push_value_with(value, &CanonicalJson)
+    }
+
+    /// Pushes a value in the given encoding as the next leaf
+    /// nothing is pushed when the value can't be encoded
+    pub fn push_value_with<T: Serialize + ?Sized, E: LeafEncoder>(&mut self, value: &T, encoder: &E) -> Result<(), E::Error> {
+        let leaf_hash = encode_leaf(value, encoder, self.hasher())?;
+        self.push_hash(leaf_hash);
+        Ok(())
+    }
+}
+
+impl<H: Hasher> RootAccumulator<H> {
+    /// Consumes the canonical JSON of a value as the next leaf
+    pub fn push_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), serde_json::Error> {
+        self.push_value_with(value, &CanonicalJson)
+    }
+
+    /// Consumes a value in the given encoding as the next leaf
+    /// nothing is consumed when the value can't be encoded
+    pub fn push_value_with<T: Serialize + ?Sized, E: LeafEncoder>(&mut self, value: &T, encoder: &E) -> Result<(), E::Error> {
+        let leaf_hash = encode_leaf(value, encoder, self.hasher())?;
+        self.push_hash(leaf_hash);
+        Ok(())
+    }
+}
+
+/// hash of the leaf a value is encoded into, the encoding scrubbed once hashed
+fn encode_leaf<T: Serialize + ?Sized, E: LeafEncoder, H: Hasher>(value: &T, encoder: &E, hasher: &H) -> Result<LeafHash, E::Error> {
+    let mut data = encoder.encode(value)?;
+    let leaf_hash = LeafHash::of(&data, hasher);
+    scrub(&mut data);
+    Ok(leaf_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Record {
+        id: u64,
+        owner: String,
+        tags: HashMap<String, i32>,
+    }
+
+    #[derive(Serialize)]
+    struct Reordered {
+        tags: BTreeMap<String, i32>,
+        owner: String,
+        id: u64,
+    }
+
+    #[test]
+    fn test_canonical_json_ignores_field_and_entry_order() {
+        let tags: Vec<(String, i32)> = (0..20).map(|i| (format!("tag {i}"), i)).collect();
+        let record = Record { id: 7, owner: "alice".to_string(), tags: tags.iter().cloned().collect() };
+        let reordered = Reordered { tags: tags.iter().rev().cloned().collect(), owner: "alice".to_string(), id: 7 };
+        let encoded = CanonicalJson.encode(&record).expect("records are JSON");
+        assert_eq!(encoded, CanonicalJson.encode(&reordered).expect("records are JSON"));
+        assert!(encoded.starts_with(br#"{"id":7,"owner":"alice","tags":{"tag 0":0,"tag 1":1,"tag 10":10"#));
+        assert!(CanonicalJson.encode(&u128::MAX).is_err());
+        let nested = serde_json::json!({ "b": [{ "é": 1, "z": 2 }], "a": null });
+        assert_eq!(CanonicalJson.encode(&nested).expect("values are JSON"), r#"{"a":null,"b":[{"z":2,"é":1}]}"#.as_bytes());
+    }
+
+    #[test]
+    fn test_values_are_committed_to_by_their_encoding() {
+        let values: Vec<Record> = (0..5).map(|id| Record { id, owner: format!("owner {id}"), tags: HashMap::new() }).collect();
+        let tree = MerkleTree::construct_values(&values).expect("records are JSON");
+        let encoded: Vec<Data> = values.iter().map(|value| CanonicalJson.encode(value).expect("records are JSON")).collect();
+        assert_eq!(tree, MerkleTree::construct(&encoded));
+        let proof = tree.prove(&encoded[3]).expect("this should return Proof");
+        assert!(MerkleTree::verify_proof(&encoded[3], &proof, &tree.root()));
+
+        let mut frontier = Frontier::new();
+        let mut accumulator = RootAccumulator::new();
+        for value in &values {
+            frontier.push_value(value).expect("records are JSON");
+            accumulator.push_value(value).expect("records are JSON");
+        }
+        assert!(accumulator.push_value(&u128::MAX).is_err());
+        assert_eq!(accumulator.leaf_count(), 5);
+        assert_eq!(frontier.root(), Some(tree.root()));
+        assert_eq!(accumulator.finish(), Some(tree.root()));
+    }
+}
@@ -0,0 +1,58 @@
+pub mod accumulator;
+pub mod airdrop;
+#[cfg(feature = "tokio")]
+pub mod async_build;
+#[cfg(feature = "arkworks")]
+pub mod arkworks;
+pub mod audit;
+pub mod buffer_tree;
+pub mod bundle;
+pub mod bloom;
+pub mod chunking;
+pub mod circom;
+pub mod concurrent;
+pub mod const_root;
+pub mod corruption;
+pub mod disk_tree;
+pub mod eth_proof;
+pub mod fixed_tree;
+pub mod frontier;
+pub mod frozen;
+pub mod hasher;
+pub mod heap_layout;
+pub mod indexed;
+pub mod integrity;
+pub mod ipld;
+pub mod leaf_encoder;
+pub mod loaders;
+pub mod manifest;
+pub mod merkle_clock;
+pub mod merkle_log;
+pub mod merkle_vec;
+pub mod merkletree;
+pub mod metrics;
+pub mod minimal_proof;
+pub mod mpt;
+pub mod multihash;
+pub mod node_cache;
+pub mod node_hash;
+pub mod node_store;
+pub mod nary;
+pub mod params;
+pub mod pipeline;
+pub mod proof_array;
+pub mod proof_ref;
+pub mod request_proof;
+pub mod root;
+pub mod root_chain;
+pub mod rs_merkle;
+pub mod search;
+pub mod shard;
+pub mod snapshot;
+pub mod streaming;
+pub mod table;
+pub mod transaction;
+pub mod tree_head;
+pub mod wal;
+pub mod watch;
+pub mod zero_hashes;
@@ -0,0 +1,291 @@
+use std::fmt;
+use std::io::{self, BufRead};
+
+use serde_json::Value;
+
+use crate::leaf_encoder::sort_keys;
+use crate::merkletree::Data;
+
+/// How a loaded value is normalized before it becomes leaf data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Canonicalization {
+    /// use the value exactly as found
+    #[default]
+    None,
+    /// strip leading and trailing whitespace
+    Trim,
+    /// strip leading and trailing whitespace and lowercase, e.g. for hex addresses or emails
+    TrimLowercase,
+}
+
+impl Canonicalization {
+    fn apply(&self, value: &str) -> Data {
+        match self {
+            Canonicalization::None => value.as_bytes().to_vec(),
+            Canonicalization::Trim => value.trim().as_bytes().to_vec(),
+            Canonicalization::TrimLowercase => value.trim().to_lowercase().into_bytes(),
+        }
+    }
+}
+
+/// Which column of a CSV file the leaves are read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// column with this name in the header row
+    Name(String),
+    /// zero based column index
+    Index(usize),
+}
+
+/// Options for `load_csv`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub column: Column,
+    /// whether the first record is a header row, required to select a column by name
+    pub has_header: bool,
+    pub delimiter: u8,
+    pub canonicalization: Canonicalization,
+}
+
+impl CsvOptions {
+    /// comma separated file with a header row, reading the named column as is
+    pub fn column(name: &str) -> CsvOptions {
+        CsvOptions {
+            column: Column::Name(name.to_string()),
+            has_header: true,
+            delimiter: b',',
+            canonicalization: Canonicalization::None,
+        }
+    }
+}
+
+/// Reasons leaves can not be loaded, lines are 1-based
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// a CSV record is malformed, e.g. an unterminated quote
+    Csv { line: usize, reason: String },
+    /// a line of newline-delimited JSON does not parse
+    Json { line: usize, reason: String },
+    /// the selected column does not exist in the header or in a record
+    MissingColumn { line: usize },
+    /// the selected field does not exist in a JSON object
+    MissingField { line: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{error}"),
+            LoadError::Csv { line, reason } => write!(f, "invalid CSV on line {line}: {reason}"),
+            LoadError::Json { line, reason } => write!(f, "invalid JSON on line {line}: {reason}"),
+            LoadError::MissingColumn { line } => write!(f, "missing column on line {line}"),
+            LoadError::MissingField { line } => write!(f, "missing field on line {line}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> LoadError {
+        LoadError::Io(error)
+    }
+}
+
+/// Reads one column of a CSV file as leaf data, in file order
+///
+/// Fields follow RFC 4180: they may be quoted, contain the delimiter, doubled quotes and line breaks,
+/// which are kept as they are in the file, `\r\n` or `\n`. A closing quote has to be followed by the
+/// delimiter or the end of the record. Empty lines are skipped.
+pub fn load_csv(reader: impl BufRead, options: &CsvOptions) -> Result<Vec<Data>, LoadError> {
+    let mut records = CsvRecords { reader, line: 0, delimiter: options.delimiter };
+    let column = match (&options.column, options.has_header) {
+        (Column::Index(index), has_header) => {
+            if has_header {
+                records.next_record()?;
+            }
+            *index
+        }
+        (Column::Name(name), true) => {
+            let header = records.next_record()?.ok_or(LoadError::MissingColumn { line: 1 })?;
+            header.1.iter().position(|field| field == name).ok_or(LoadError::MissingColumn { line: header.0 })?
+        }
+        (Column::Name(_), false) => return Err(LoadError::MissingColumn { line: 1 }),
+    };
+
+    let mut leaves = vec![];
+    while let Some((line, fields)) = records.next_record()? {
+        let field = fields.get(column).ok_or(LoadError::MissingColumn { line })?;
+        leaves.push(options.canonicalization.apply(field));
+    }
+    Ok(leaves)
+}
+
+/// Reads one field of every object in a newline-delimited JSON file as leaf data, in file order
+///
+/// `field` is either a top level key or a JSON pointer such as `/user/id`. String values become
+/// leaves as their text, after `canonicalization`. Any other value becomes its compact JSON with
+/// object keys sorted, so that the same value always makes the same leaf no matter how it was
+/// formatted in the file. Empty lines are skipped.
+pub fn load_ndjson(reader: impl BufRead, field: &str, canonicalization: Canonicalization) -> Result<Vec<Data>, LoadError> {
+    let mut leaves = vec![];
+    for (i, text) in reader.lines().enumerate() {
+        let (line, text) = (i + 1, text?);
+        if text.trim().is_empty() {
+            continue;
+        }
+        let object: Value = serde_json::from_str(&text).map_err(|error| LoadError::Json { line, reason: error.to_string() })?;
+        let value = if field.starts_with('/') { object.pointer(field) } else { object.get(field) };
+        leaves.push(match value.ok_or(LoadError::MissingField { line })? {
+            Value::String(text) => canonicalization.apply(text),
+            value => sort_keys(value.clone()).to_string().into_bytes(),
+        });
+    }
+    Ok(leaves)
+}
+
+/// Splits CSV records out of lines, joining lines inside quoted fields
+struct CsvRecords<R> {
+    reader: R,
+    line: usize,
+    delimiter: u8,
+}
+
+impl<R: BufRead> CsvRecords<R> {
+    /// next non-empty record with the line it starts on, `None` at the end of input
+    fn next_record(&mut self) -> Result<Option<(usize, Vec<String>)>, LoadError> {
+        let delimiter = char::from(self.delimiter);
+        loop {
+            let Some(mut text) = self.next_line()? else {
+                return Ok(None);
+            };
+            let start = self.line;
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let mut fields = vec![];
+            let mut field = String::new();
+            let (mut quoted, mut closed) = (false, false);
+            loop {
+                let content = text.trim_end_matches('\n').trim_end_matches('\r');
+                let mut chars = content.chars().peekable();
+                while let Some(c) = chars.next() {
+                    match (quoted, c) {
+                        (true, '"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        (true, '"') => (quoted, closed) = (false, true),
+                        (true, c) => field.push(c),
+                        (false, c) if c == delimiter => {
+                            fields.push(std::mem::take(&mut field));
+                            closed = false;
+                        }
+                        (false, _) if closed => {
+                            return Err(LoadError::Csv { line: self.line, reason: format!("unexpected {c:?} after closing quote") });
+                        }
+                        (false, '"') if field.is_empty() => quoted = true,
+                        (false, c) => field.push(c),
+                    }
+                }
+                if !quoted {
+                    break;
+                }
+                // a quoted field spans the line break, which is kept as the file has it
+                field.push_str(&text[content.len()..]);
+                text = match self.next_line()? {
+                    Some(text) => text,
+                    None => return Err(LoadError::Csv { line: start, reason: "unterminated quoted field".to_string() }),
+                };
+            }
+            fields.push(field);
+            return Ok(Some((start, fields)));
+        }
+    }
+
+    /// next line together with its line break, `None` at the end of input
+    fn next_line(&mut self) -> io::Result<Option<String>> {
+        let mut text = String::new();
+        if self.reader.read_line(&mut text)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        Ok(Some(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_csv_by_column_name_with_quotes() {
+        let csv = "id,address,amount\n1,\" 0xAB \",10\n\n2,\"0x\"\"cd\",\"20,5\"\n3,\"multi\nline\",30\n";
+        let options = CsvOptions { canonicalization: Canonicalization::TrimLowercase, ..CsvOptions::column("address") };
+
+        let leaves = load_csv(csv.as_bytes(), &options).expect("this should load");
+        assert_eq!(leaves, vec![b"0xab".to_vec(), b"0x\"cd".to_vec(), b"multi\nline".to_vec()]);
+
+        let options = CsvOptions::column("amount");
+        let leaves = load_csv(csv.as_bytes(), &options).expect("this should load");
+        assert_eq!(leaves, vec![b"10".to_vec(), b"20,5".to_vec(), b"30".to_vec()]);
+    }
+
+    #[test]
+    fn test_load_csv_keeps_line_breaks_of_quoted_fields() {
+        let csv = "id,note\r\n1,\"two\r\nlines\"\r\n2,\"unix\nline\"\n";
+        let leaves = load_csv(csv.as_bytes(), &CsvOptions::column("note")).expect("this should load");
+        assert_eq!(leaves, vec![b"two\r\nlines".to_vec(), b"unix\nline".to_vec()]);
+    }
+
+    #[test]
+    fn test_load_csv_refuses_text_after_closing_quote() {
+        let options = CsvOptions::column("a");
+        assert!(load_csv("a,b\n\"x\" ,1\n".as_bytes(), &options).is_err());
+        let error = load_csv("a,b\n1,2\n\"x\"y,1\n".as_bytes(), &options).expect_err("junk after the quote");
+        assert!(matches!(error, LoadError::Csv { line: 3, .. }));
+        // an empty quoted field is closed like any other
+        assert_eq!(load_csv("a,b\n\"\",1\n".as_bytes(), &options).expect("this should load"), vec![b"".to_vec()]);
+    }
+
+    #[test]
+    fn test_load_csv_by_index_with_delimiter() {
+        let csv = "a;1\nb;2\n";
+        let options = CsvOptions { column: Column::Index(1), has_header: false, delimiter: b';', canonicalization: Canonicalization::None };
+
+        let leaves = load_csv(csv.as_bytes(), &options).expect("this should load");
+        assert_eq!(leaves, vec![b"1".to_vec(), b"2".to_vec()]);
+    }
+
+    #[test]
+    fn test_load_csv_errors_report_lines() {
+        let options = CsvOptions::column("amount");
+        assert!(matches!(load_csv("id,address\n1,2\n".as_bytes(), &options), Err(LoadError::MissingColumn { line: 1 })));
+        assert!(matches!(load_csv("amount,id\n1\n2\n3,\"4\n".as_bytes(), &options), Err(LoadError::Csv { line: 4, .. })));
+
+        let options = CsvOptions { column: Column::Index(1), ..options };
+        assert!(matches!(load_csv("a,b\n1,2\n3\n".as_bytes(), &options), Err(LoadError::MissingColumn { line: 3 })));
+    }
+
+    #[test]
+    fn test_load_ndjson_canonicalizes_values() {
+        let ndjson = "{\"id\": \" A \", \"meta\": {\"b\": 1, \"a\": [true, null]}}\n\n{\"meta\":{\"a\":[true,null],\"b\":1},\"id\":\"b\"}\n";
+
+        let leaves = load_ndjson(ndjson.as_bytes(), "id", Canonicalization::TrimLowercase).expect("this should load");
+        assert_eq!(leaves, vec![b"a".to_vec(), b"b".to_vec()]);
+
+        let leaves = load_ndjson(ndjson.as_bytes(), "meta", Canonicalization::None).expect("this should load");
+        assert_eq!(leaves, vec![b"{\"a\":[true,null],\"b\":1}".to_vec(); 2]);
+
+        let leaves = load_ndjson(ndjson.as_bytes(), "/meta/b", Canonicalization::None).expect("this should load");
+        assert_eq!(leaves, vec![b"1".to_vec(); 2]);
+    }
+
+    #[test]
+    fn test_load_ndjson_errors_report_lines() {
+        assert!(matches!(load_ndjson("{\"id\":1}\n{\"other\":2}\n".as_bytes(), "id", Canonicalization::None), Err(LoadError::MissingField { line: 2 })));
+        assert!(matches!(load_ndjson("{\"id\":1}\nnot json\n".as_bytes(), "id", Canonicalization::None), Err(LoadError::Json { line: 2, .. })));
+    }
+}
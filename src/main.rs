@@ -1,12 +1,91 @@
-use crate::merkletree::{Data, MerkleTree};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
 
-pub mod merkletree;
+use merkle_tree::integrity::IntegrityManifest;
+use merkle_tree::root::Root;
+use merkle_tree::watch::DirectoryWatcher;
 
-fn main() {
-    let mut data: Vec<Data> = vec![];
-    for i in 0..4 {
-        data.push(vec![i as u8]);
+/// chunk size of manifests when none is given
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// time between two looks at a watched directory
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "usage:
+    merkle-tree manifest <dir> [chunk-size]    writes the integrity manifest of <dir> to stdout
+    merkle-tree verify <dir> <manifest>        lists how <dir> differs from <manifest>
+    merkle-tree watch <dir> [chunk-size]       prints changes to <dir> and its new root as they happen";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["manifest", dir] => manifest(dir, DEFAULT_CHUNK_SIZE),
+        ["manifest", dir, chunk_size] => parse_chunk_size(chunk_size).and_then(|chunk_size| manifest(dir, chunk_size)),
+        ["verify", dir, manifest] => verify(dir, manifest),
+        ["watch", dir] => watch(dir, DEFAULT_CHUNK_SIZE),
+        ["watch", dir, chunk_size] => parse_chunk_size(chunk_size).and_then(|chunk_size| watch(dir, chunk_size)),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn parse_chunk_size(chunk_size: &str) -> Result<usize, String> {
+    match chunk_size.parse() {
+        Ok(chunk_size) if chunk_size > 0 => Ok(chunk_size),
+        _ => Err(format!("invalid chunk size: {chunk_size}")),
+    }
+}
+
+fn manifest(dir: &str, chunk_size: usize) -> Result<bool, String> {
+    let manifest = IntegrityManifest::scan(dir, chunk_size).map_err(|error| format!("{dir}: {error}"))?;
+    manifest.write(io::stdout().lock()).map_err(|error| error.to_string())?;
+    Ok(true)
+}
+
+/// prints every change, succeeding only when there are none
+fn verify(dir: &str, manifest: &str) -> Result<bool, String> {
+    let file = File::open(manifest).map_err(|error| format!("{manifest}: {error}"))?;
+    let manifest = IntegrityManifest::read(BufReader::new(file)).map_err(|error| format!("{manifest}: {error}"))?;
+    let changes = manifest.verify_directory(dir).map_err(|error| format!("{dir}: {error}"))?;
+    for change in &changes {
+        println!("{change}");
+    }
+    Ok(changes.is_empty())
+}
+
+/// polls until interrupted, a failed poll is reported and retried on the next one
+fn watch(dir: &str, chunk_size: usize) -> Result<bool, String> {
+    let mut watcher = DirectoryWatcher::new(dir, chunk_size).map_err(|error| format!("{dir}: {error}"))?;
+    println!("root {}", format_root(watcher.root()));
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        match watcher.poll() {
+            Ok(changes) if changes.is_empty() => {}
+            Ok(changes) => {
+                for change in &changes {
+                    println!("{change}");
+                }
+                println!("root {}", format_root(watcher.root()));
+            }
+            Err(error) => eprintln!("error: {dir}: {error}"),
+        }
     }
-    let _mt = MerkleTree::construct(&data);
-    print!("Hello World!")
+}
+
+fn format_root(root: Option<Root>) -> String {
+    root.map(|root| root.to_string()).unwrap_or_else(|| "none".to_string())
 }
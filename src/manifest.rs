@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use crate::merkletree::{Data, MerkleTree, Proof};
+use crate::root::Root;
+
+/// Files by path, each committed to by the root of the tree over its chunks, all under one top root
+///
+/// The top tree has a leaf per file, in path order, so the same files always give the same root.
+pub struct Manifest {
+    files: BTreeMap<String, MerkleTree>,
+    tree: MerkleTree,
+}
+
+/// Proof that a chunk belongs to a file of a manifest: the chunk's proof up to the file's root,
+/// followed by the file's proof up to the manifest's root
+#[derive(Debug)]
+pub struct ChunkProof {
+    pub path: String,
+    pub file_root: Root,
+    pub chunk_proof: Proof,
+    pub file_proof: Proof,
+}
+
+impl Manifest {
+    /// Builds the manifest over the chunk trees of the given files
+    /// returns `None` when there are no files or a path is given twice
+    pub fn from_files(files: impl IntoIterator<Item = (String, MerkleTree)>) -> Option<Manifest> {
+        let mut by_path = BTreeMap::new();
+        for (path, tree) in files {
+            if by_path.insert(path, tree).is_some() {
+                return None;
+            }
+        }
+        if by_path.is_empty() {
+            return None;
+        }
+        let entries: Vec<Data> = by_path.iter().map(|(path, tree)| file_entry(path, tree.root().as_bytes())).collect();
+        Some(Manifest {
+            tree: MerkleTree::construct(&entries),
+            files: by_path,
+        })
+    }
+
+    /// Gets the root committing to every file
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Gets the paths of all files in order
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// Gets the chunk tree of the file at `path`
+    pub fn file(&self, path: &str) -> Option<&MerkleTree> {
+        self.files.get(path)
+    }
+
+    /// Proves that the chunk is part of the file at `path` in this manifest
+    pub fn prove_chunk(&self, path: &str, chunk: &Data) -> Option<ChunkProof> {
+        let file = self.files.get(path)?;
+        let file_root = file.root();
+        let chunk_proof = file.prove(chunk)?;
+        let file_proof = self.tree.prove(&file_entry(path, file_root.as_bytes()))?;
+        Some(ChunkProof {
+            path: path.to_string(),
+            file_root,
+            chunk_proof,
+            file_proof,
+        })
+    }
+
+    /// Verifies that the chunk is part of the proof's file in the manifest with the given root
+    pub fn verify_chunk(chunk: &Data, proof: &ChunkProof, manifest_root: &Root) -> bool {
+        MerkleTree::verify_proof(chunk, &proof.chunk_proof, &proof.file_root)
+            && MerkleTree::verify_proof(&file_entry(&proof.path, proof.file_root.as_bytes()), &proof.file_proof, manifest_root)
+    }
+}
+
+/// leaf of a file in the top tree: its path, prefixed by its length (`u32` little-endian), and its root
+pub(crate) fn file_entry(path: &str, root: &[u8]) -> Data {
+    let mut entry = (path.len() as u32).to_le_bytes().to_vec();
+    entry.extend_from_slice(path.as_bytes());
+    entry.extend_from_slice(root);
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(file: u8, n: u8) -> Vec<Data> {
+        (0..n).map(|i| vec![file, i]).collect()
+    }
+
+    fn example_manifest() -> Manifest {
+        let files = [("bin/app", chunks(0, 5)), ("lib/core.so", chunks(1, 3)), ("README", chunks(2, 1))];
+        Manifest::from_files(files.into_iter().map(|(path, chunks)| (path.to_string(), MerkleTree::construct(&chunks))))
+            .expect("valid files")
+    }
+
+    #[test]
+    fn test_chunks_are_proven_under_their_file() {
+        let manifest = example_manifest();
+        assert_eq!(manifest.paths().collect::<Vec<_>>(), vec!["README", "bin/app", "lib/core.so"]);
+
+        for chunk in chunks(0, 5) {
+            let proof = manifest.prove_chunk("bin/app", &chunk).expect("this should return ChunkProof");
+            assert!(Manifest::verify_chunk(&chunk, &proof, &manifest.root()));
+        }
+        let proof = manifest.prove_chunk("README", &chunks(2, 1)[0]).expect("this should return ChunkProof");
+        assert!(Manifest::verify_chunk(&chunks(2, 1)[0], &proof, &manifest.root()));
+    }
+
+    #[test]
+    fn test_chunk_proof_for_other_file_will_not_verify() {
+        let manifest = example_manifest();
+        let chunk = &chunks(1, 3)[2];
+        assert!(manifest.prove_chunk("bin/app", chunk).is_none());
+
+        let mut proof = manifest.prove_chunk("lib/core.so", chunk).expect("this should return ChunkProof");
+        proof.path = "bin/app".to_string();
+        assert!(!Manifest::verify_chunk(chunk, &proof, &manifest.root()));
+    }
+
+    #[test]
+    fn test_manifest_root_does_not_depend_on_file_order() {
+        let manifest = example_manifest();
+        let files = [("README", chunks(2, 1)), ("lib/core.so", chunks(1, 3)), ("bin/app", chunks(0, 5))];
+        let reordered = Manifest::from_files(files.into_iter().map(|(path, chunks)| (path.to_string(), MerkleTree::construct(&chunks))))
+            .expect("valid files");
+        assert_eq!(manifest.root(), reordered.root());
+
+        assert!(Manifest::from_files(vec![]).is_none());
+        let duplicate = vec![("a".to_string(), MerkleTree::construct(&chunks(0, 1))), ("a".to_string(), MerkleTree::construct(&chunks(1, 1)))];
+        assert!(Manifest::from_files(duplicate).is_none());
+    }
+}
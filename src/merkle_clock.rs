@@ -0,0 +1,286 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::Hash;
+
+/// Set of events keyed by their hashes that two peers can compare to find the events the other lacks
+///
+/// Events are kept in a trie over the nibbles of their hashes. Every branch commits to its children,
+/// so peers first compare roots and then only descend into the branches whose hashes differ:
+/// finding a handful of missing events among millions takes a few round trips of small summaries.
+#[derive(Debug, Clone, Default)]
+pub struct MerkleClock<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    root: Option<ClockNode>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+enum ClockNode {
+    /// the only event below this prefix
+    Event(Hash),
+    /// two or more events, by the next nibble of their hashes
+    Branch { hash: Hash, children: Box<[Option<ClockNode>; 16]> },
+}
+
+impl ClockNode {
+    fn hash(&self) -> &Hash {
+        match self {
+            ClockNode::Event(hash) | ClockNode::Branch { hash, .. } => hash,
+        }
+    }
+}
+
+impl MerkleClock {
+    /// Starts a clock without any events
+    pub fn new() -> MerkleClock {
+        MerkleClock::default()
+    }
+}
+
+impl<H: Hasher> MerkleClock<H> {
+    /// Starts a clock without any events, hashing with the given hash function
+    pub fn with_hasher(hasher: H) -> MerkleClock<H> {
+        MerkleClock { hasher, root: None, len: 0 }
+    }
+
+    /// Gets number of events in the clock
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the clock holds no events
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the hash committing to every event, `None` while there are none
+    pub fn root(&self) -> Option<&Hash> {
+        self.root.as_ref().map(ClockNode::hash)
+    }
+
+    /// Hashes and adds an event, returning its hash
+    pub fn insert_event(&mut self, event: &[u8]) -> Hash {
+        let event_hash = self.hasher.hash(event);
+        self.insert(event_hash.clone());
+        event_hash
+    }
+
+    /// Adds an event by its hash, returning whether it was new
+    pub fn insert(&mut self, event_hash: Hash) -> bool {
+        assert_eq!(event_hash.len(), self.hasher.digest_len(), "event hashes have the length of the hash function");
+        let inserted = insert(&self.hasher, &mut self.root, event_hash, 0);
+        self.len += usize::from(inserted);
+        inserted
+    }
+
+    /// Whether the clock holds the event with the given hash
+    pub fn contains(&self, event_hash: &[u8]) -> bool {
+        let mut node = self.root.as_ref();
+        let mut depth = 0;
+        while let Some(current) = node {
+            match current {
+                ClockNode::Event(hash) => return hash == event_hash,
+                ClockNode::Branch { children, .. } => {
+                    node = children[nibble(event_hash, depth)].as_ref();
+                    depth += 1;
+                }
+            }
+        }
+        false
+    }
+
+    /// Gets the hashes of the children of the branch at the given nibble path, as sent to a peer
+    /// `None` when no branch is at that path, in which case `events_under` lists its events outright
+    pub fn children(&self, path: &[u8]) -> Option<Vec<(u8, Hash)>> {
+        match self.node_at(path)? {
+            ClockNode::Event(_) => None,
+            ClockNode::Branch { children, .. } => Some(
+                children
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(nibble, child)| Some((nibble as u8, child.as_ref()?.hash().clone())))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Gets the hashes of all events whose hash starts with the given nibble path
+    pub fn events_under(&self, path: &[u8]) -> Vec<Hash> {
+        let mut events = vec![];
+        if let Some(node) = self.node_at(path) {
+            collect_events(node, &mut events);
+        }
+        events.into_iter().filter(|event| starts_with_nibbles(event, path)).collect()
+    }
+
+    /// Gets the hashes of the events this clock holds and `other` lacks,
+    /// descending only into branches whose hashes differ
+    pub fn missing_from(&self, other: &MerkleClock<H>) -> Vec<Hash> {
+        let mut missing = vec![];
+        if let Some(root) = &self.root {
+            diff(root, other.root.as_ref(), other, &mut missing);
+        }
+        missing
+    }
+
+    /// node at the end of a nibble path, an event node also stands for every longer path leading to it
+    fn node_at(&self, path: &[u8]) -> Option<&ClockNode> {
+        let mut node = self.root.as_ref()?;
+        for nibble in path {
+            match node {
+                ClockNode::Event(_) => return Some(node),
+                ClockNode::Branch { children, .. } => node = children.get(usize::from(*nibble))?.as_ref()?,
+            }
+        }
+        Some(node)
+    }
+}
+
+/// inserts below `node`, which sits `depth` nibbles deep, rehashing branches on the way back up
+fn insert<H: Hasher>(hasher: &H, node: &mut Option<ClockNode>, event_hash: Hash, depth: usize) -> bool {
+    let inserted = match node {
+        None => {
+            *node = Some(ClockNode::Event(event_hash));
+            return true;
+        }
+        Some(ClockNode::Event(existing)) if *existing == event_hash => return false,
+        Some(ClockNode::Event(_)) => {
+            // two events below the same prefix, so they get a branch of their own
+            let Some(ClockNode::Event(existing)) = node.take() else {
+                unreachable!("matched an event node");
+            };
+            let mut children: Box<[Option<ClockNode>; 16]> = Box::default();
+            let position = nibble(&existing, depth);
+            children[position] = Some(ClockNode::Event(existing));
+            // inserting the new event into the branch hashes the branch as well
+            let mut branch = Some(ClockNode::Branch { hash: vec![], children });
+            insert(hasher, &mut branch, event_hash, depth);
+            *node = branch;
+            return true;
+        }
+        Some(ClockNode::Branch { children, .. }) => insert(hasher, &mut children[nibble(&event_hash, depth)], event_hash, depth + 1),
+    };
+    if let Some(ClockNode::Branch { hash, children }) = node {
+        *hash = branch_hash(hasher, children);
+    }
+    inserted
+}
+
+/// hash of the present children, each tagged with its nibble
+fn branch_hash<H: Hasher>(hasher: &H, children: &[Option<ClockNode>; 16]) -> Hash {
+    let mut summary = vec![];
+    for (nibble, child) in children.iter().enumerate() {
+        if let Some(child) = child {
+            summary.push(nibble as u8);
+            summary.extend_from_slice(child.hash());
+        }
+    }
+    hasher.hash(&summary)
+}
+
+fn diff<H: Hasher>(node: &ClockNode, theirs: Option<&ClockNode>, other: &MerkleClock<H>, missing: &mut Vec<Hash>) {
+    match (node, theirs) {
+        (node, Some(theirs)) if node.hash() == theirs.hash() => {}
+        (ClockNode::Branch { children, .. }, Some(ClockNode::Branch { children: their_children, .. })) => {
+            for (child, their_child) in children.iter().zip(their_children.iter()) {
+                if let Some(child) = child {
+                    diff(child, their_child.as_ref(), other, missing);
+                }
+            }
+        }
+        // the shapes differ below here, so each event is looked up on its own
+        _ => {
+            let mut events = vec![];
+            collect_events(node, &mut events);
+            missing.extend(events.into_iter().filter(|event| !other.contains(event)));
+        }
+    }
+}
+
+fn collect_events(node: &ClockNode, events: &mut Vec<Hash>) {
+    match node {
+        ClockNode::Event(hash) => events.push(hash.clone()),
+        ClockNode::Branch { children, .. } => {
+            for child in children.iter().flatten() {
+                collect_events(child, events);
+            }
+        }
+    }
+}
+
+/// nibble number `depth` of a hash, high nibble of each byte first
+fn nibble(hash: &[u8], depth: usize) -> usize {
+    let byte = hash[depth / 2];
+    usize::from(if depth.is_multiple_of(2) { byte >> 4 } else { byte & 0x0f })
+}
+
+fn starts_with_nibbles(hash: &[u8], path: &[u8]) -> bool {
+    path.len() <= hash.len() * 2 && path.iter().enumerate().all(|(depth, nibble_value)| nibble(hash, depth) == usize::from(*nibble_value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_clock(events: std::ops::Range<u32>) -> MerkleClock {
+        let mut clock = MerkleClock::new();
+        for event in events {
+            clock.insert_event(&event.to_le_bytes());
+        }
+        clock
+    }
+
+    #[test]
+    fn test_root_does_not_depend_on_insertion_order() {
+        let forward = example_clock(0..200);
+        let mut backward = MerkleClock::new();
+        for event in (0..200_u32).rev() {
+            backward.insert_event(&event.to_le_bytes());
+        }
+        assert_eq!(forward.root(), backward.root());
+        assert_eq!(forward.len(), 200);
+        assert!(MerkleClock::new().root().is_none());
+    }
+
+    #[test]
+    fn test_peers_find_events_the_other_lacks() {
+        let mut ours = example_clock(0..500);
+        let theirs = example_clock(3..503);
+        assert_ne!(ours.root(), theirs.root());
+
+        let mut missing = ours.missing_from(&theirs);
+        let mut expected: Vec<Hash> = (0..3_u32).map(|event| Sha256Hasher::new().hash(&event.to_le_bytes())).collect();
+        missing.sort();
+        expected.sort();
+        assert_eq!(missing, expected);
+        assert_eq!(theirs.missing_from(&ours).len(), 3);
+
+        // once both hold every event they agree again
+        for event in theirs.missing_from(&ours) {
+            assert!(ours.insert(event));
+        }
+        assert!(example_clock(0..503).missing_from(&ours).is_empty());
+        assert_eq!(ours.root(), example_clock(0..503).root());
+    }
+
+    #[test]
+    fn test_duplicate_events_are_not_inserted_twice() {
+        let mut clock = example_clock(0..10);
+        let root = clock.root().cloned();
+        assert!(!clock.insert(Sha256Hasher::new().hash(&3_u32.to_le_bytes())));
+        assert_eq!(clock.len(), 10);
+        assert_eq!(clock.root().cloned(), root);
+    }
+
+    #[test]
+    fn test_children_and_events_under_paths() {
+        let clock = example_clock(0..100);
+        let children = clock.children(&[]).expect("root is a branch");
+        assert_eq!(children.len(), 16);
+        let events: usize = (0..16).map(|nibble| clock.events_under(&[nibble]).len()).sum();
+        assert_eq!(events, 100);
+        for event in clock.events_under(&[7]) {
+            assert_eq!(event[0] >> 4, 7);
+            assert!(clock.contains(&event));
+        }
+    }
+}
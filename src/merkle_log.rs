@@ -0,0 +1,351 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{sibling_directions, split_point, Hash, HashDirection, MerkleTree, Proof};
+use crate::metrics::{self, MetricsEvent};
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+use crate::tree_head::TreeHead;
+
+/// Proof that the entry at `leaf_index` is in the log of `tree_size` entries
+#[derive(Debug)]
+pub struct InclusionProof<H: Hasher = Sha256Hasher> {
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    pub proof: Proof<H>,
+}
+
+/// Proof that the log of `old_size` entries is a prefix of the log of `new_size` entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyProof {
+    pub old_size: u64,
+    pub new_size: u64,
+    /// subtree hashes in the order of RFC 6962, section 2.1.2
+    pub hashes: Vec<Hash>,
+}
+
+/// Append-only log of entries, e.g. for a transparency log
+///
+/// The log at any size has the root `MerkleTree::construct` computes over its first entries,
+/// which is the split of RFC 6962 with this crate's hash function instead of its prefixed SHA-256.
+/// Every complete subtree is kept, so proofs for any past size take no more than a few lookups per level.
+#[derive(Debug, Clone, Default)]
+pub struct MerkleLog<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    /// `levels[k][i]` is the root of the complete subtree over entries `i << k` up to `(i + 1) << k`
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleLog {
+    /// Starts an empty log
+    pub fn new() -> MerkleLog {
+        MerkleLog::default()
+    }
+
+    /// Verifies an inclusion proof of a SHA-256 log against a head of the same size
+    pub fn verify_inclusion(data: &[u8], proof: &InclusionProof, head: &TreeHead) -> bool {
+        MerkleLog::verify_inclusion_with_hasher(data, proof, head, &Sha256Hasher::new())
+    }
+
+    /// Verifies that a SHA-256 log with head `old` grew into the log with head `new` by appending only
+    pub fn verify_consistency(old: &TreeHead, new: &TreeHead, proof: &ConsistencyProof) -> bool {
+        MerkleLog::verify_consistency_with_hasher(old, new, proof, &Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> MerkleLog<H> {
+    /// Starts an empty log hashing with the given hash function
+    pub fn with_hasher(hasher: H) -> MerkleLog<H> {
+        MerkleLog { hasher, levels: vec![] }
+    }
+
+    /// Gets number of entries appended so far
+    pub fn size(&self) -> u64 {
+        self.levels.first().map_or(0, |leaves| leaves.len() as u64)
+    }
+
+    /// Hashes and appends an entry, returning its index
+    pub fn append(&mut self, data: &[u8]) -> u64 {
+        let leaf_hash = LeafHash::of(data, &self.hasher);
+        self.append_hash(leaf_hash)
+    }
+
+    /// Appends an entry that was already hashed, returning its index
+    pub fn append_hash(&mut self, leaf_hash: LeafHash) -> u64 {
+        let index = self.size();
+        let mut node = leaf_hash.into_hash();
+        let mut level = 0;
+        loop {
+            if self.levels.len() == level {
+                self.levels.push(vec![]);
+            }
+            self.levels[level].push(node);
+            let nodes = &self.levels[level];
+            // every completed pair is a complete subtree one level up
+            if nodes.len() % 2 == 1 {
+                break;
+            }
+            node = self.hasher.hash_concat(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+            level += 1;
+        }
+        index
+    }
+
+    /// Gets the head over all entries, `None` while the log is empty
+    pub fn head(&self) -> Option<TreeHead> {
+        self.head_at(self.size())
+    }
+
+    /// Gets the head the log had when it held `tree_size` entries, `None` for sizes it never had
+    pub fn head_at(&self, tree_size: u64) -> Option<TreeHead> {
+        if tree_size == 0 || tree_size > self.size() {
+            return None;
+        }
+        Some(TreeHead::new(Root::new(self.subtree_root(0, tree_size)), tree_size))
+    }
+
+    /// Whether the log had the head's root when it had the head's size
+    pub fn has_head(&self, head: &TreeHead) -> bool {
+        self.head_at(head.tree_size).is_some_and(|own| own.describes_same_tree(head))
+    }
+
+    /// Proves that the entry at `leaf_index` is in the log as the given head commits to it
+    /// returns `None` unless the index is below the head's size and the head is one this log had
+    pub fn prove_inclusion(&self, leaf_index: u64, head: &TreeHead) -> Option<InclusionProof<H>> {
+        let tree_size = head.tree_size;
+        if leaf_index >= tree_size || !self.has_head(head) {
+            return None;
+        }
+        let mut hashes = vec![];
+        let (mut start, mut end) = (0, tree_size);
+        // descending from the root, so the siblings are collected top down and reversed afterwards
+        while end - start > 1 {
+            let middle = start + split_point(end - start);
+            if leaf_index < middle {
+                hashes.push((HashDirection::Right, self.subtree_root(middle, end)));
+                end = middle;
+            } else {
+                hashes.push((HashDirection::Left, self.subtree_root(start, middle)));
+                start = middle;
+            }
+        }
+        hashes.reverse();
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Some(InclusionProof {
+            leaf_index,
+            tree_size,
+            proof: Proof::new(hashes),
+        })
+    }
+
+    /// Proves that the log as the `old` head commits to it is a prefix of the log as the `new` head does
+    /// returns `None` unless the old head is no larger than the new one and both are heads this log had,
+    /// a client holding a head of a forked log gets no proof at all
+    pub fn prove_consistency(&self, old: &TreeHead, new: &TreeHead) -> Option<ConsistencyProof> {
+        let (old_size, new_size) = (old.tree_size, new.tree_size);
+        if old_size > new_size || !self.has_head(old) || !self.has_head(new) {
+            return None;
+        }
+        let mut hashes = vec![];
+        self.subproof(old_size, 0, new_size, true, &mut hashes);
+        Some(ConsistencyProof { old_size, new_size, hashes })
+    }
+
+    /// Verifies an inclusion proof against a head of the same size with the given hash function
+    /// the path has to be the one the index and size determine, a proof for any other position never verifies
+    pub fn verify_inclusion_with_hasher(data: &[u8], proof: &InclusionProof<H>, root: &TreeHead, hasher: &H) -> bool {
+        let positioned = proof.tree_size == root.tree_size
+            && sibling_directions(proof.leaf_index, proof.tree_size)
+                .is_some_and(|directions| directions.iter().eq(proof.proof.hashes.iter().map(|(direction, _)| direction)));
+        metrics::verified(hasher, positioned) && MerkleTree::verify_proof_with_hasher(&data.to_vec(), &proof.proof, &root.root, hasher)
+    }
+
+    /// Verifies that the log with head `old` grew into the log with head `new` by appending only,
+    /// following RFC 9162, section 2.1.4.2
+    /// heads that both carry a timestamp also have to be issued in order
+    pub fn verify_consistency_with_hasher(old: &TreeHead, new: &TreeHead, proof: &ConsistencyProof, hasher: &H) -> bool {
+        metrics::verified(hasher, MerkleLog::is_consistent(old, new, proof, hasher))
+    }
+
+    fn is_consistent(old: &TreeHead, new: &TreeHead, proof: &ConsistencyProof, hasher: &H) -> bool {
+        if proof.old_size != old.tree_size || proof.new_size != new.tree_size || old.tree_size == 0 || old.tree_size > new.tree_size {
+            return false;
+        }
+        if let (Some(old_timestamp), Some(new_timestamp)) = (old.timestamp, new.timestamp) {
+            if old_timestamp > new_timestamp {
+                return false;
+            }
+        }
+        if old.tree_size == new.tree_size {
+            return proof.hashes.is_empty() && old.root == new.root;
+        }
+        let mut path = proof.hashes.iter();
+        // when the old log is a complete subtree, the proof leaves out its root
+        let first = if old.tree_size.is_power_of_two() { Some(old.root.as_bytes()) } else { path.next().map(Vec::as_slice) };
+        let Some(first) = first else {
+            return false;
+        };
+        let (mut old_node, mut new_node) = (old.tree_size - 1, new.tree_size - 1);
+        while old_node & 1 == 1 {
+            old_node >>= 1;
+            new_node >>= 1;
+        }
+        let (mut old_root, mut new_root) = (first.to_vec(), first.to_vec());
+        for hash in path {
+            if new_node == 0 {
+                return false;
+            }
+            if old_node & 1 == 1 || old_node == new_node {
+                old_root = hasher.hash_concat(hash, &old_root);
+                new_root = hasher.hash_concat(hash, &new_root);
+                while old_node & 1 == 0 && old_node != 0 {
+                    old_node >>= 1;
+                    new_node >>= 1;
+                }
+            } else {
+                new_root = hasher.hash_concat(&new_root, hash);
+            }
+            old_node >>= 1;
+            new_node >>= 1;
+        }
+        new_node == 0 && old_root == old.root.as_bytes() && new_root == new.root.as_bytes()
+    }
+
+    /// root over the entries `start..end`, looked up whenever that is a complete subtree
+    fn subtree_root(&self, start: u64, end: u64) -> Hash {
+        let size = end - start;
+        if size.is_power_of_two() && start.is_multiple_of(size) {
+            let level = size.trailing_zeros() as usize;
+            return self.levels[level][(start >> level) as usize].clone();
+        }
+        let middle = start + split_point(size);
+        self.hasher.hash_concat(&self.subtree_root(start, middle), &self.subtree_root(middle, end))
+    }
+
+    /// SUBPROOF of RFC 6962, section 2.1.2, for the first `old_size` entries of `start..end`
+    fn subproof(&self, old_size: u64, start: u64, end: u64, complete: bool, hashes: &mut Vec<Hash>) {
+        if old_size == end - start {
+            if !complete {
+                hashes.push(self.subtree_root(start, end));
+            }
+            return;
+        }
+        let split = split_point(end - start);
+        if old_size <= split {
+            self.subproof(old_size, start, start + split, complete, hashes);
+            hashes.push(self.subtree_root(start + split, end));
+        } else {
+            self.subproof(old_size - split, start + split, end, false, hashes);
+            hashes.push(self.subtree_root(start, start + split));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    fn example_log(n: usize) -> MerkleLog {
+        let mut log = MerkleLog::new();
+        for (i, data) in example_data(n).iter().enumerate() {
+            assert_eq!(log.append(data), i as u64);
+        }
+        log
+    }
+
+    #[test]
+    fn test_roots_at_every_size_match_constructed_tree() {
+        let data = example_data(13);
+        let log = example_log(13);
+        for size in 1..=13 {
+            let root = log.head_at(size).expect("log had this size");
+            assert_eq!(root.tree_size, size);
+            assert_eq!(root.root, MerkleTree::construct(&data[..size as usize]).root());
+        }
+        assert!(log.head_at(0).is_none());
+        assert!(log.head_at(14).is_none());
+        assert!(MerkleLog::new().head().is_none());
+    }
+
+    #[test]
+    fn test_inclusion_proofs_verify_at_every_size() {
+        let data = example_data(11);
+        let log = example_log(11);
+        for size in 1..=11 {
+            let root = log.head_at(size).expect("log had this size");
+            for index in 0..size {
+                let proof = log.prove_inclusion(index, &root).expect("this should return InclusionProof");
+                assert!(MerkleLog::verify_inclusion(&data[index as usize], &proof, &root));
+                // the same path is no proof for a root of another size
+                if size > 1 {
+                    assert!(!MerkleLog::verify_inclusion(&data[index as usize], &proof, &log.head_at(size - 1).expect("log had this size")));
+                }
+            }
+        }
+        let head = log.head_at(3).expect("log had this size");
+        assert!(log.prove_inclusion(3, &head).is_none());
+        assert!(log.prove_inclusion(0, &TreeHead::new(head.root.clone(), 12)).is_none());
+        // a head with a root this log never had at that size gets no proof
+        assert!(log.prove_inclusion(0, &TreeHead::new(head.root, 4)).is_none());
+    }
+
+    #[test]
+    fn test_inclusion_proof_for_other_index_will_not_verify() {
+        let data = example_data(6);
+        let log = example_log(6);
+        let root = log.head().expect("log is not empty");
+        let mut proof = log.prove_inclusion(4, &root).expect("this should return InclusionProof");
+        assert!(MerkleLog::verify_inclusion(&data[4], &proof, &root));
+        proof.leaf_index = 5;
+        assert!(!MerkleLog::verify_inclusion(&data[4], &proof, &root));
+    }
+
+    #[test]
+    fn test_consistency_proofs_verify_between_every_pair_of_sizes() {
+        let log = example_log(17);
+        for old_size in 1..=17 {
+            for new_size in old_size..=17 {
+                let old = log.head_at(old_size).expect("log had this size");
+                let new = log.head_at(new_size).expect("log had this size");
+                let proof = log.prove_consistency(&old, &new).expect("this should return ConsistencyProof");
+                assert!(MerkleLog::verify_consistency(&old, &new, &proof), "{old_size} -> {new_size}");
+            }
+        }
+        let (four, five) = (log.head_at(4).expect("log had this size"), log.head_at(5).expect("log had this size"));
+        assert!(log.prove_consistency(&five, &four).is_none());
+        assert!(log.prove_consistency(&four, &TreeHead::new(five.root, 18)).is_none());
+    }
+
+    #[test]
+    fn test_consistency_with_rewritten_history_will_not_verify() {
+        let log = example_log(7);
+        let mut forked = example_log(2);
+        for data in [vec![42], vec![3], vec![4], vec![5], vec![6]] {
+            forked.append(&data);
+        }
+        let old = log.head_at(3).expect("log had this size");
+        let new = forked.head().expect("log is not empty");
+        // the forked log never had the old head, so it can't prove anything about it
+        assert!(forked.prove_consistency(&old, &new).is_none());
+        let forked_old = forked.head_at(3).expect("log had this size");
+        let proof = forked.prove_consistency(&forked_old, &new).expect("this should return ConsistencyProof");
+        assert!(!MerkleLog::verify_consistency(&old, &new, &proof));
+
+        let proof = log.prove_consistency(&old, &log.head().expect("log is not empty")).expect("this should return ConsistencyProof");
+        assert!(!MerkleLog::verify_consistency(&old, &new, &proof));
+        assert!(MerkleLog::verify_consistency(&old, &log.head().expect("log is not empty"), &proof));
+    }
+
+    #[test]
+    fn test_consistency_of_heads_issued_out_of_order_will_not_verify() {
+        let log = example_log(5);
+        let old = log.head_at(2).expect("log had this size").with_timestamp(2000);
+        let new = log.head().expect("log is not empty").with_timestamp(1000);
+        let proof = log.prove_consistency(&old, &new).expect("this should return ConsistencyProof");
+        assert!(!MerkleLog::verify_consistency(&old, &new, &proof));
+        assert!(MerkleLog::verify_consistency(&old, &new.with_timestamp(3000), &proof));
+    }
+}
@@ -0,0 +1,239 @@
+use std::borrow::Cow;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{Hash, HashDirection, MerkleTree, Proof};
+use crate::metrics::{self, MetricsEvent};
+use crate::root::Root;
+
+/// Value that can be an element of a `MerkleVec`, committed to by the bytes of its leaf
+/// equal values have to give equal bytes, see `LeafEncoder` for values that don't come as bytes
+pub trait Hashable {
+    /// Gets the leaf data of the value
+    fn leaf_data(&self) -> Cow<'_, [u8]>;
+}
+
+impl Hashable for Vec<u8> {
+    fn leaf_data(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Hashable for Box<[u8]> {
+    fn leaf_data(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl<const N: usize> Hashable for [u8; N] {
+    fn leaf_data(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Hashable for String {
+    fn leaf_data(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+/// Vector of values that keeps the root over its elements up to date as they are pushed and replaced
+///
+/// Each element is the leaf at its index, so there are no positions to keep in step with a tree built
+/// on the side. Pushing or replacing an element rehashes only the nodes above it, one per level, and
+/// proofs are read off the stored levels. The root and every proof are the ones `MerkleTree::construct`
+/// gives over the leaf data of the elements in order.
+#[derive(Debug, Clone)]
+pub struct MerkleVec<T: Hashable, H: Hasher = Sha256Hasher> {
+    hasher: H,
+    items: Vec<T>,
+    /// hashes by level, the leaves first, up to the root once there is an element
+    levels: Vec<Vec<Hash>>,
+}
+
+impl<T: Hashable> MerkleVec<T> {
+    /// Starts an empty vector hashed with SHA-256
+    pub fn new() -> MerkleVec<T> {
+        MerkleVec::with_hasher(Sha256Hasher::new())
+    }
+
+    /// Verifies a SHA-256 proof of the element at `index` of a vector of `len` elements, see `verify_with_hasher`
+    pub fn verify(value: &T, index: u64, len: u64, proof: &Proof, root_hash: &Root) -> bool {
+        MerkleVec::verify_with_hasher(value, index, len, proof, root_hash, &Sha256Hasher::new())
+    }
+}
+
+impl<T: Hashable> Default for MerkleVec<T> {
+    fn default() -> Self {
+        MerkleVec::new()
+    }
+}
+
+impl<T: Hashable> FromIterator<T> for MerkleVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> Self {
+        let mut vec = MerkleVec::new();
+        vec.extend(values);
+        vec
+    }
+}
+
+impl<T: Hashable, H: Hasher> Extend<T> for MerkleVec<T, H> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, values: I) {
+        for value in values {
+            self.push(value);
+        }
+    }
+}
+
+impl<T: Hashable, H: Hasher> MerkleVec<T, H> {
+    /// Starts an empty vector hashed with the given hash function
+    pub fn with_hasher(hasher: H) -> MerkleVec<T, H> {
+        MerkleVec { hasher, items: vec![], levels: vec![vec![]] }
+    }
+
+    /// Gets number of elements
+    pub fn len(&self) -> u64 {
+        self.items.len() as u64
+    }
+
+    /// Whether there is no element
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Gets the element at `index`
+    pub fn get(&self, index: u64) -> Option<&T> {
+        self.items.get(usize::try_from(index).ok()?)
+    }
+
+    /// Gets the elements in order
+    pub fn as_slice(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Gets root hash over the elements, `None` while there is none
+    pub fn root(&self) -> Option<Root> {
+        self.levels.last().and_then(|top| top.first()).map(|root| Root::new(root.clone()))
+    }
+
+    /// Appends an element, returning its index
+    pub fn push(&mut self, value: T) -> u64 {
+        let leaf_hash = self.hasher.hash(&value.leaf_data());
+        self.items.push(value);
+        self.levels[0].push(leaf_hash);
+        let index = self.items.len() - 1;
+        self.rehash_above(index);
+        index as u64
+    }
+
+    /// Replaces the element at `index`, returning the one it held
+    ///
+    /// # Panics
+    ///
+    /// When the index is not below the length.
+    pub fn set(&mut self, index: u64, value: T) -> T {
+        let position = usize::try_from(index).ok().filter(|position| *position < self.items.len());
+        let Some(position) = position else {
+            panic!("index {index} out of range for {} elements", self.items.len());
+        };
+        self.levels[0][position] = self.hasher.hash(&value.leaf_data());
+        self.rehash_above(position);
+        std::mem::replace(&mut self.items[position], value)
+    }
+
+    /// Returns the proof for the element at `index`, `None` when the index is not below the length
+    pub fn prove(&self, index: u64) -> Option<Proof<H>> {
+        let mut position = usize::try_from(index).ok().filter(|position| *position < self.items.len())?;
+        let mut hashes = vec![];
+        for level in &self.levels[..self.levels.len() - 1] {
+            // the odd node out has no sibling on this level
+            if let Some(sibling) = level.get(position ^ 1) {
+                let direction = if position.is_multiple_of(2) { HashDirection::Right } else { HashDirection::Left };
+                hashes.push((direction, sibling.clone()));
+            }
+            position /= 2;
+        }
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Some(Proof::new(hashes))
+    }
+
+    /// Verifies that the proof proves the value is the element at `index` of a vector of `len` elements
+    /// with the given root_hash, hashing with the given hash function
+    /// the proof of another index never verifies, see `MerkleTree::verify_proof_at_with_hasher`
+    pub fn verify_with_hasher(value: &T, index: u64, len: u64, proof: &Proof<H>, root_hash: &Root, hasher: &H) -> bool {
+        MerkleTree::verify_proof_at_with_hasher(&value.leaf_data().into_owned(), index, len, proof, root_hash, hasher)
+    }
+
+    /// recomputes the ancestors of the leaf at `position`, adding a level once the top one has two nodes
+    fn rehash_above(&mut self, mut position: usize) {
+        let mut level = 0;
+        while self.levels[level].len() > 1 {
+            if level + 1 == self.levels.len() {
+                self.levels.push(vec![]);
+            }
+            let nodes = &self.levels[level];
+            // odd node out is promoted to the next level, as `MerkleTree` does
+            let parent = match nodes.get(position | 1) {
+                Some(right) => self.hasher.hash_concat(&nodes[position & !1], right),
+                None => nodes[position].clone(),
+            };
+            position /= 2;
+            let parents = &mut self.levels[level + 1];
+            match parents.get_mut(position) {
+                Some(node) => *node = parent,
+                None => parents.push(parent),
+            }
+            level += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| format!("element {i}").into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_pushed_elements_match_merkle_tree() {
+        let data = example_data(13);
+        let mut vec = MerkleVec::new();
+        assert_eq!(vec.root(), None);
+        for (n, leaf) in (1..).zip(&data) {
+            assert_eq!(vec.push(leaf.clone()), n - 1);
+            let tree = MerkleTree::construct(&data[..n as usize]);
+            assert_eq!(vec.root(), Some(tree.root()));
+            for index in 0..n {
+                assert_eq!(vec.prove(index), tree.prove_by_index(index));
+            }
+        }
+        assert_eq!(vec.get(12), Some(&data[12]));
+        assert!(vec.get(13).is_none() && vec.prove(13).is_none());
+
+        let collected: MerkleVec<Data> = data.iter().cloned().collect();
+        assert_eq!(collected.root(), vec.root());
+        assert_eq!(collected.as_slice(), data.as_slice());
+    }
+
+    #[test]
+    fn test_replaced_elements_prove_at_their_index() {
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        let mut data: Vec<String> = (0..6).map(|i| format!("entry {i}")).collect();
+        let mut vec = MerkleVec::with_hasher(hasher);
+        vec.extend(data.iter().cloned());
+        assert_eq!(vec.set(5, "five".to_string()), "entry 5");
+        assert_eq!(vec.set(0, "zero".to_string()), "entry 0");
+        (data[0], data[5]) = ("zero".to_string(), "five".to_string());
+
+        let leaves: Vec<Data> = data.iter().map(|entry| entry.clone().into_bytes()).collect();
+        let root = vec.root().expect("has elements");
+        assert_eq!(root, MerkleTree::construct_with_hasher(&leaves, hasher).root());
+        let proof = vec.prove(5).expect("index is in range");
+        assert!(MerkleVec::verify_with_hasher(&data[5], 5, 6, &proof, &root, &hasher));
+        assert!(!MerkleVec::verify_with_hasher(&data[5], 4, 6, &proof, &root, &hasher));
+        assert!(!MerkleVec::verify_with_hasher(&"entry 5".to_string(), 5, 6, &proof, &root, &hasher));
+    }
+}
@@ -1,169 +1,850 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
 use sha2::Digest;
 
+use crate::accumulator::RootAccumulator;
+use crate::bloom::BloomFilter;
+use crate::hasher::{DigestHasher, Hasher, Sha256Hasher};
+use crate::metrics::{self, MetricsEvent};
+use crate::multihash::{HashAlgorithm, Multihash};
+use crate::node_hash::{InternalHash, LeafHash};
+use crate::root::Root;
+
 pub type Data = Vec<u8>;
 pub type Hash = Vec<u8>;
 
-/// Each element in Merkle Tree is a node
+/// Every hash of a tree in a single buffer, level by level from the leaves up to the root
+///
+/// A level holds a hash per node, the odd node out of a level appearing again as the last node of
+/// the next. The children of the node at `(level, index)` are therefore the nodes at `2 * index`
+/// and `2 * index + 1` of the level below, and the siblings of a leaf are read straight from its index.
+/// Owned trees keep the buffer in a `Vec`, `BufferTree` in a slice the caller lends it.
 #[derive(Debug, Clone)]
-pub struct Node {
-    /// each Node has a hash value:
-    /// - which is either hash of leaf(when left and right are `None`)
-    /// - or hash of its concatenated children from left and right
-    value: Hash,
-    /// a Node in Merkle Tree might have a children node to the left
-    left: Option<Box<Node>>,
-    /// a Node in Merkle Tree might have a children node to the right
-    right: Option<Box<Node>>,
+pub(crate) struct Levels<B: Storage = Vec<u8>> {
+    hashes: B,
+    /// position of the first hash of each level, counted in hashes, followed by the number of hashes
+    starts: Vec<usize>,
+    digest_len: usize,
 }
 
-/// The Merkle Tree is really just the top level root that will grow to the left or right
-pub struct MerkleTree {
-    /// Merkle Tree starts from a top level root Node
-    root: Node,
+/// The Merkle Tree keeps the hashes of all its levels in one buffer, the leaves first and the root last
+pub struct MerkleTree<H: Hasher = Sha256Hasher> {
+    /// hash function the tree was built with, also used to hash data someone asks a proof for
+    pub(crate) hasher: H,
+    /// every Node of the tree, addressed by level and index
+    /// shared with the snapshots frozen from the tree, and copied on the first write while they are
+    pub(crate) levels: Arc<Levels>,
+    /// number of leaves the tree was constructed from
+    pub(crate) leaf_count: usize,
+    /// filter over the leaf hashes to rule out non-members quickly, when one was built
+    pub(crate) bloom_filter: Option<Arc<BloomFilter>>,
 }
 
 /// Which side to put Hash on when concatenating proof hashes
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum HashDirection {
     Left,
     Right,
 }
 
-#[derive(Debug, Default)]
-pub struct Proof {
+pub struct Proof<H: Hasher = Sha256Hasher> {
     /// The hashes to use when verifying the proof
     /// The first element of the tuple is which side the hash should be on when concatenating
-    hashes: Vec<(HashDirection, Hash)>,
+    pub(crate) hashes: Vec<(HashDirection, Hash)>,
+    /// ties the proof to the hash function of the tree it belongs to
+    pub(crate) hasher: PhantomData<fn() -> H>,
 }
 
 impl MerkleTree {
-    /// Gets root hash for this tree
-    pub fn root(&self) -> Hash {
-        self.root.value.clone()
+    /// Estimates the peak number of bytes constructing a tree over `leaf_count` leaves with `hash_size` byte
+    /// hashes takes, so that builds that would not fit can be rejected before any hashing starts
+    /// on top of the finished tree this counts the leaf hashes and levels that are alive while pairing them up,
+    /// but not the input data itself
+    pub fn estimate_memory_bytes(leaf_count: usize, hash_size: usize) -> usize {
+        let leaf_hashes = leaf_count.saturating_mul(size_of::<Hash>().saturating_add(hash_size));
+        tree_memory_bytes(leaf_count, hash_size).saturating_add(leaf_hashes)
     }
 
     /// Constructs a Merkle tree from given input data
     pub fn construct(input: &[Data]) -> MerkleTree {
-        let mut leaves = input
-            .iter()
-            .map(hash_data)
-            .map(|hash| {
-                Node {
-                    value: hash,
-                    left: None,
-                    right: None,
-                }
-            })
-            .collect::<Vec<Node>>();
+        MerkleTree::construct_with_hasher(input, Sha256Hasher::new())
+    }
 
+    /// Constructs a Merkle tree over the set of the given input data, see `construct_canonical_with_hasher`
+    pub fn construct_canonical(input: &[Data]) -> MerkleTree {
+        MerkleTree::construct_canonical_with_hasher(input, Sha256Hasher::new())
+    }
 
-        while leaves.len() > 1{
-            let mut new_nodes = Vec::with_capacity(leaves.len() / 2);
+    /// Constructs a Merkle tree from given input data hashed with any RustCrypto digest,
+    /// e.g. `MerkleTree::construct_with::<sha3::Sha3_256>(&input)`
+    pub fn construct_with<D: Digest + 'static>(input: &[Data]) -> MerkleTree<DigestHasher<D>> {
+        MerkleTree::construct_with_hasher(input, DigestHasher::new())
+    }
 
-            for chunk in leaves.chunks_exact(2) {
-                let left = chunk[0].clone();
-                let right = chunk[1].clone();
-                new_nodes.push(Node{
-                    value: hash_concat(&left.value, &right.value),
-                    left: Some(Box::new(left)),
-                    right: Some(Box::new(right))
-                });
-            }
+    /// Constructs a Merkle tree from leaves that were already hashed, e.g. by a previous construction
+    pub fn from_leaf_hashes(leaf_hashes: Vec<LeafHash>) -> MerkleTree {
+        MerkleTree::from_leaf_hashes_with_hasher(leaf_hashes, Sha256Hasher::new())
+    }
 
-            if leaves.len() % 2 == 1 {
-                new_nodes.push( Node{
-                    value: leaves.last().unwrap().value.clone(),
-                    left: None,
-                    right: None
-                });
-            }
-            leaves = new_nodes
+    /// Verifies that the given input data produces the given root hash
+    pub fn verify(input: &[Data], root_hash: &Root) -> bool {
+        MerkleTree::verify_with_hasher(input, root_hash, Sha256Hasher::new())
+    }
+
+    /// Verifies that leaves read one at a time, e.g. from a file too large to hold, produce the given root hash
+    pub fn verify_streaming<I>(leaves: I, root_hash: &Root) -> bool
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        MerkleTree::verify_streaming_with_hasher(leaves, root_hash, Sha256Hasher::new())
+    }
+
+    /// Verifies that the given input data produces the given multihash-encoded root
+    /// roots produced by a different digest algorithm never verify, even if the digest bytes match
+    pub fn verify_multihash(input: &[Data], root: &Multihash) -> bool {
+        root.algorithm() == HashAlgorithm::Sha2_256 && MerkleTree::verify(input, &Root::new(root.digest().to_vec()))
+    }
+
+    /// Verifies that the given data and proof_path correctly produce the given root_hash
+    pub fn verify_proof(data: &Data, proof: &Proof, root_hash: &Root) -> bool {
+        MerkleTree::verify_proof_with_hasher(data, proof, root_hash, &Sha256Hasher::new())
+    }
+
+    /// Verifies a SHA-256 proof of the leaf at `leaf_index` in a tree of `tree_size` leaves,
+    /// see `verify_proof_at_with_hasher`
+    pub fn verify_proof_at(data: &Data, leaf_index: u64, tree_size: u64, proof: &Proof, root_hash: &Root) -> bool {
+        MerkleTree::verify_proof_at_with_hasher(data, leaf_index, tree_size, proof, root_hash, &Sha256Hasher::new())
+    }
+
+    /// Verifies a SHA-256 proof of a leaf that was already hashed, see `verify_proof_hashed_with_hasher`
+    pub fn verify_proof_hashed(leaf_hash: &LeafHash, proof: &Proof, root_hash: &Root) -> bool {
+        MerkleTree::verify_proof_hashed_with_hasher(leaf_hash, proof, root_hash, &Sha256Hasher::new())
+    }
+
+    /// Verifies a SHA-256 proof without allocating, see `verify_proof_in_place_with_hasher`
+    pub fn verify_proof_in_place(data: &[u8], proof: &Proof, root_hash: &Root, scratch: &mut [u8; 64]) -> bool {
+        MerkleTree::verify_proof_in_place_with_hasher(data, proof, root_hash, &Sha256Hasher::new(), scratch)
+    }
+
+    /// Verifies that the given input data produces the given root hash with a RustCrypto digest
+    pub fn verify_with<D: Digest + 'static>(input: &[Data], root_hash: &Root) -> bool {
+        MerkleTree::verify_with_hasher(input, root_hash, DigestHasher::<D>::new())
+    }
+
+    /// Verifies a proof of a tree constructed with `construct_with` with the same digest
+    pub fn verify_proof_with<D: Digest + 'static>(data: &Data, proof: &Proof<DigestHasher<D>>, root_hash: &Root) -> bool {
+        MerkleTree::verify_proof_with_hasher(data, proof, root_hash, &DigestHasher::new())
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Gets root hash for this tree
+    pub fn root(&self) -> Root {
+        Root::new(self.root_hash().to_vec())
+    }
+
+    /// the root hash, borrowed from the last level
+    fn root_hash(&self) -> &[u8] {
+        self.levels.hash(self.levels.count() - 1, 0)
+    }
+
+    /// Gets root hash for this tree tagged with the algorithm that produced it
+    /// `None` when the hash function has no multicodec code
+    pub fn root_multihash(&self) -> Option<Multihash> {
+        Some(Multihash::new(self.hasher.algorithm()?, self.root().into_hash()))
+    }
+
+    /// Gets number of leaves in this tree
+    pub fn leaf_count(&self) -> u64 {
+        self.leaf_count as u64
+    }
+
+    /// Gets the hash function this tree was built with
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Gets number of bytes this tree occupies, counting every hash but not allocator overhead
+    pub fn estimated_memory_bytes(&self) -> usize {
+        tree_memory_bytes(self.leaf_count, self.hasher.digest_len())
+    }
+
+    /// Constructs a Merkle tree from given input data, hashing with the given hash function
+    pub fn construct_with_hasher(input: &[Data], hasher: H) -> MerkleTree<H> {
+        let mut levels = Levels::with_capacity(input.len(), hasher.digest_len());
+        for data in input {
+            levels.push_leaf(hasher.hash(data));
         }
+        MerkleTree::from_levels(levels, hasher)
+    }
+
+    /// Constructs a Merkle tree over the set of the given input data, hashing with the given hash function
+    /// the leaf hashes are sorted and repeated ones dropped before the tree is built, so any two lists of
+    /// the same items, in whatever order and with whatever repeats, give the same root;
+    /// proofs are by position in the sorted leaves, see `leaf_hash` and `prove`
+    pub fn construct_canonical_with_hasher(input: &[Data], hasher: H) -> MerkleTree<H> {
+        let mut leaf_hashes: Vec<LeafHash> = input.iter().map(|data| LeafHash::of(data, &hasher)).collect();
+        leaf_hashes.sort_unstable();
+        leaf_hashes.dedup();
+        MerkleTree::from_leaf_hashes_with_hasher(leaf_hashes, hasher)
+    }
 
-        MerkleTree{
-            root: leaves.pop().unwrap()
+    /// Constructs a Merkle tree from leaves that were already hashed with the given hash function
+    pub fn from_leaf_hashes_with_hasher(leaf_hashes: Vec<LeafHash>, hasher: H) -> MerkleTree<H> {
+        let mut levels = Levels::with_capacity(leaf_hashes.len(), hasher.digest_len());
+        for leaf_hash in leaf_hashes {
+            levels.push_leaf(leaf_hash.into_hash());
         }
+        MerkleTree::from_levels(levels, hasher)
     }
 
-    /// Verifies that the given input data produces the given root hash
-    pub fn verify(input: &[Data], root_hash: &Hash) -> bool {
-        let mt = MerkleTree::construct(input);
-        let hash: Vec<u8> = mt.root();
-        hash.eq(root_hash)
+    /// pairs up the leaves already pushed into `levels` until only the root is left
+    fn from_levels(mut levels: Levels, hasher: H) -> MerkleTree<H> {
+        let leaf_count = levels.hashes.len() / levels.digest_len;
+        assert!(leaf_count > 0, "trees have at least one leaf");
+        // the room was reserved up front, so this doesn't reallocate
+        levels.hashes.resize(node_count(leaf_count) * levels.digest_len, 0);
+        levels.build(leaf_count, &hasher);
+
+        MerkleTree {
+            hasher,
+            levels: Arc::new(levels),
+            leaf_count,
+            bloom_filter: None,
+        }
+    }
+    /// Gets the leaf hashes of this tree in input order
+    pub(crate) fn leaf_hashes(&self) -> impl ExactSizeIterator<Item = &[u8]> {
+        self.levels.level(0).chunks_exact(self.levels.digest_len)
     }
 
-    /// Verifies that the given data and proof_path correctly produce the given root_hash
-    pub fn verify_proof(data: &Data, proof: &Proof, root_hash: &Hash) -> bool {
-        let mut hashed_data = hash_data(data);
+    /// Verifies that the given input data produces the given root hash with the given hash function
+    pub fn verify_with_hasher(input: &[Data], root_hash: &Root, hasher: H) -> bool {
+        MerkleTree::verify_streaming_with_hasher(input, root_hash, hasher)
+    }
+
+    /// Verifies that leaves read one at a time produce the given root hash with the given hash function
+    /// only the peaks of the complete subtrees are held, never the tree
+    pub fn verify_streaming_with_hasher<I>(leaves: I, root_hash: &Root, hasher: H) -> bool
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut accumulator = RootAccumulator::with_hasher(hasher);
+        accumulator.extend(leaves);
+        accumulator.finish().is_some_and(|root| root == *root_hash)
+    }
+
+    /// Verifies that the given data and proof_path correctly produce the given root_hash with the given hash function
+    /// proofs holding a hash of any other length than the hash function produces never verify
+    pub fn verify_proof_with_hasher(data: &Data, proof: &Proof<H>, root_hash: &Root, hasher: &H) -> bool {
+        MerkleTree::verify_proof_hashed_with_hasher(&LeafHash::of(data, hasher), proof, root_hash, hasher)
+    }
+
+    /// Verifies that the proof leads from a leaf that was already hashed to the given root_hash with the given hash function
+    pub fn verify_proof_hashed_with_hasher(leaf_hash: &LeafHash, proof: &Proof<H>, root_hash: &Root, hasher: &H) -> bool {
+        let digest_len = hasher.digest_len();
+        let lengths = [leaf_hash.as_bytes(), root_hash.as_bytes()].into_iter().chain(proof.hashes.iter().map(|(_, hash)| hash.as_slice()));
+        if lengths.into_iter().any(|hash| hash.len() != digest_len) {
+            return metrics::verified(hasher, false);
+        }
+        let mut hashed_data = leaf_hash.as_bytes().to_vec();
         for (hash_direction, hash) in &proof.hashes {
             match hash_direction {
-                HashDirection::Left => { hashed_data = hash_concat(&hash, &hashed_data) },
-                HashDirection::Right => { hashed_data = hash_concat(&hashed_data, &hash) }
+                HashDirection::Left => { hashed_data = hasher.hash_concat(hash, &hashed_data) },
+                HashDirection::Right => { hashed_data = hasher.hash_concat(&hashed_data, hash) }
             }
         };
-        hashed_data.eq(root_hash)
+        metrics::verified(hasher, hashed_data == root_hash.as_bytes())
+    }
+
+    /// Verifies that the proof is the one of the leaf at `leaf_index` in a tree of `tree_size` leaves,
+    /// and that it proves the given data is there
+    /// the proof has to take exactly the sibling sides the position gives, one per level on which the leaf
+    /// has a sibling, so a proof of one position can't be passed off as the proof of another
+    pub fn verify_proof_at_with_hasher(data: &Data, leaf_index: u64, tree_size: u64, proof: &Proof<H>, root_hash: &Root, hasher: &H) -> bool {
+        let Some(directions) = sibling_directions(leaf_index, tree_size) else {
+            return metrics::verified(hasher, false);
+        };
+        let positioned = directions.len() == proof.hashes.len()
+            && directions.iter().zip(&proof.hashes).all(|(expected, (hash_direction, _))| expected == hash_direction);
+        metrics::verified(hasher, positioned) && MerkleTree::verify_proof_with_hasher(data, proof, root_hash, hasher)
+    }
+
+    /// Verifies a proof like `verify_proof_with_hasher` without allocating, hashing into `scratch` instead
+    /// the hash function has to hash in place, as the ones of this crate do, see `Hasher::hash_into`
+    ///
+    /// # Panics
+    ///
+    /// When `scratch` is shorter than two hashes.
+    pub fn verify_proof_in_place_with_hasher(data: &[u8], proof: &Proof<H>, root_hash: &Root, hasher: &H, scratch: &mut [u8]) -> bool {
+        let digest_len = hasher.digest_len();
+        assert!(scratch.len() >= 2 * digest_len, "scratch holds two hashes");
+        if root_hash.as_bytes().len() != digest_len || proof.hashes.iter().any(|(_, hash)| hash.len() != digest_len) {
+            return metrics::verified(hasher, false);
+        }
+        // the running hash and the next one take turns in the two halves of the scratch buffer
+        let (mut current, mut next) = scratch[..2 * digest_len].split_at_mut(digest_len);
+        hasher.hash_into(data, current);
+        for (hash_direction, hash) in &proof.hashes {
+            match hash_direction {
+                HashDirection::Left => hasher.hash_concat_into(hash, current, next),
+                HashDirection::Right => hasher.hash_concat_into(current, hash, next),
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        let verified = current == root_hash.as_bytes();
+        scrub_slice(&mut scratch[..2 * digest_len]);
+        metrics::verified(hasher, verified)
     }
 
     /// Returns a list of hashes that can be used to prove that the given data is in this tree
-    pub fn prove(&self, data: &Data) -> Option<Proof> {
-        let leaf = hash_data(data);
-        traverse_and_collect_proofs(&self.root, &leaf)
+    pub fn prove(&self, data: &Data) -> Option<Proof<H>> {
+        let leaf = self.hasher.hash(data);
+        let index = self.leaf_hashes().position(|leaf_hash| leaf_hash == leaf.as_slice())?;
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Some(Proof::new(self.levels.path(index)))
+    }
+
+    /// Gets the hash of the leaf at `index`
+    pub fn leaf_hash(&self, index: u64) -> Option<LeafHash> {
+        let index = self.leaf_position(index)?;
+        Some(LeafHash::new(self.levels.hash(0, index).to_vec()))
+    }
+
+    /// Gets the hash of the node at `index` on `level` above the leaves, level 1 pairing up the leaves
+    /// `None` for leaves, also when promoted to a higher level, and for positions the tree doesn't have
+    pub fn internal_hash(&self, level: usize, index: u64) -> Option<InternalHash> {
+        let index = usize::try_from(index).ok()?;
+        if level == 0 || level >= self.levels.count() || index >= self.levels.len(level) {
+            return None;
+        }
+        // a leaf promoted up to this level has no children to hash
+        self.levels.children(level, index)?;
+        Some(InternalHash::new(self.levels.hash(level, index).to_vec()))
+    }
+
+    /// Returns the proof for the leaf at `index`, reading its siblings straight from their positions
+    pub fn prove_by_index(&self, index: u64) -> Option<Proof<H>> {
+        let path = self.levels.path(self.leaf_position(index)?);
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Some(Proof::new(path))
+    }
+
+    /// Returns the siblings of the leaf at `index` one at a time, without collecting them into a `Proof`
+    pub fn proof_iter(&self, index: u64) -> Option<ProofIter<'_>> {
+        let path = self.levels.path_iter(self.leaf_position(index)?);
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Some(path)
+    }
+
+    /// position of the leaf at `index` in memory, `None` when the tree has no such leaf
+    pub(crate) fn leaf_position(&self, index: u64) -> Option<usize> {
+        usize::try_from(index).ok().filter(|index| *index < self.leaf_count)
+    }
+
+    /// Returns the proofs for the leaves at the given indices, in the order they are given
+    /// each proof reads one sibling per level, without walking the tree
+    ///
+    /// # Panics
+    ///
+    /// When an index is not below the leaf count.
+    pub fn prove_many(&self, indices: &[u64]) -> Vec<Proof<H>> {
+        self.prove_many_parallel(indices, 1)
+    }
+
+    /// Returns the proofs for the leaves at the given indices like `prove_many`,
+    /// splitting the indices between `workers` threads
+    ///
+    /// # Panics
+    ///
+    /// When an index is not below the leaf count.
+    pub fn prove_many_parallel(&self, indices: &[u64], workers: usize) -> Vec<Proof<H>> {
+        assert!(workers > 0, "at least one worker has to prove");
+        let indices: Vec<usize> = indices
+            .iter()
+            .map(|index| self.leaf_position(*index).unwrap_or_else(|| panic!("leaf index {index} out of range for a tree of {} leaves", self.leaf_count)))
+            .collect();
+        let per_worker = indices.len().div_ceil(workers).max(1);
+        // only the levels are shared with the workers, the hasher need not be `Sync`
+        let levels = &self.levels;
+        let paths: Vec<Vec<(HashDirection, Hash)>> = if workers == 1 {
+            indices.iter().map(|index| levels.path(*index)).collect()
+        } else {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = indices
+                    .chunks(per_worker)
+                    .map(|part| scope.spawn(move || part.iter().map(|index| levels.path(*index)).collect::<Vec<_>>()))
+                    .collect();
+                handles.into_iter().flat_map(|handle| handle.join().expect("proving doesn't panic")).collect()
+            })
+        };
+        for _ in &paths {
+            metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        }
+        paths.into_iter().map(Proof::new).collect()
+    }
+}
+
+impl Levels {
+    /// Starts the levels of a tree of `leaf_count` leaves of `digest_len` byte hashes,
+    /// reserving room for every Node at once
+    fn with_capacity(leaf_count: usize, digest_len: usize) -> Levels {
+        Levels {
+            hashes: Vec::with_capacity(node_count(leaf_count) * digest_len),
+            starts: vec![0],
+            digest_len,
+        }
+    }
+
+    /// Makes room for leaves appended to built levels and writes them after the old ones
+    /// every level above the leaves is moved up to where it starts in the larger tree, without hashing
+    /// anything, so `rebuild_paths` over the new leaves finishes the tree
+    pub(crate) fn grow(&mut self, leaf_hashes: &[LeafHash]) {
+        let len = self.digest_len;
+        let (old_count, leaf_count) = (self.len(0), self.len(0) + leaf_hashes.len());
+        let mut starts = vec![0, leaf_count];
+        let mut size = leaf_count;
+        while size > 1 {
+            size = size.div_ceil(2);
+            starts.push(starts[starts.len() - 1] + size);
+        }
+        let total = starts[starts.len() - 1] * len;
+        if total > self.hashes.capacity() {
+            // grown by hand, so that the old buffer is scrubbed before it is freed
+            let mut grown = Vec::with_capacity(total.max(2 * self.hashes.capacity()));
+            grown.extend_from_slice(&self.hashes);
+            scrub(&mut std::mem::replace(&mut self.hashes, grown));
+        }
+        self.hashes.resize(total, 0);
+        // every level starts at least as far up as before, and above where the level below ends,
+        // so moving the top level first never overwrites a level that is yet to move
+        for level in (1..self.count()).rev() {
+            let (old_start, old_len) = (self.starts[level], self.len(level));
+            self.hashes.copy_within(old_start * len..(old_start + old_len) * len, starts[level] * len);
+        }
+        for (index, leaf_hash) in (old_count..).zip(leaf_hashes) {
+            self.hashes[index * len..(index + 1) * len].copy_from_slice(leaf_hash.as_bytes());
+        }
+        self.starts = starts;
+    }
+
+    /// appends the next leaf hash, before any level above the leaves is built
+    fn push_leaf(&mut self, mut leaf_hash: Hash) {
+        assert_eq!(leaf_hash.len(), self.digest_len, "leaf hashes have the length of the hash function");
+        self.hashes.extend_from_slice(&leaf_hash);
+        scrub(&mut leaf_hash);
+    }
+}
+
+impl<'a> Levels<&'a mut [u8]> {
+    /// Starts the levels of a tree of `digest_len` byte hashes inside `buffer`, which the leaf hashes are
+    /// then written to back to back before `build` is called
+    pub(crate) fn in_buffer(buffer: &'a mut [u8], digest_len: usize) -> Levels<&'a mut [u8]> {
+        Levels {
+            hashes: buffer,
+            starts: vec![0],
+            digest_len,
+        }
+    }
+
+    /// Gets the room for the hashes of the first `leaf_count` leaves
+    pub(crate) fn leaves_mut(&mut self, leaf_count: usize) -> &mut [u8] {
+        &mut self.hashes[..leaf_count * self.digest_len]
+    }
+}
+
+impl<B: Storage> Levels<B> {
+    /// Pairs up the first `leaf_count` hashes of the buffer until only the root is left,
+    /// writing each level after the one below it; the buffer has room for `node_count(leaf_count)` hashes
+    pub(crate) fn build<H: Hasher>(&mut self, leaf_count: usize, hasher: &H) {
+        let len = self.digest_len;
+        self.starts.truncate(1);
+        self.starts.push(leaf_count);
+        let (mut start, mut size) = (0, leaf_count);
+        while size > 1 {
+            for (next, left) in (start + size..).zip((start..start + size).step_by(2)) {
+                self.hash_children(left, left + 1 < start + size, next, hasher);
+            }
+            start += size;
+            size = size.div_ceil(2);
+            self.starts.push(start + size);
+        }
+    }
+
+    /// Recomputes the nodes above the leaves at the given indices, sorted and without repeats,
+    /// each once, leaving every other node as it is
+    pub(crate) fn rebuild_paths<H: Hasher>(&mut self, leaves: &[usize], hasher: &H) {
+        let mut dirty = leaves.to_vec();
+        for level in 1..self.count() {
+            for index in &mut dirty {
+                *index /= 2;
+            }
+            dirty.dedup();
+            let (below, below_len) = (self.starts[level - 1], self.len(level - 1));
+            for index in &dirty {
+                self.hash_children(below + 2 * index, 2 * index + 1 < below_len, self.starts[level] + index, hasher);
+            }
+        }
+    }
+
+    /// writes the parent of the hash at position `left` to position `next`, both counted across all levels
+    fn hash_children<H: Hasher>(&mut self, left: usize, has_right: bool, next: usize, hasher: &H) {
+        let len = self.digest_len;
+        if has_right {
+            // the children come before the parent, so both halves can be borrowed at once
+            let (below, above) = self.hashes.as_mut().split_at_mut(next * len);
+            hasher.hash_concat_into(&below[left * len..(left + 1) * len], &below[(left + 1) * len..(left + 2) * len], &mut above[..len]);
+        } else {
+            // odd node out is promoted to the next level, so that leaves below it can still be proven
+            self.hashes.as_mut().copy_within(left * len..(left + 1) * len, next * len);
+        }
+    }
+
+    /// hash at a position counted across all levels
+    fn at(&self, position: usize) -> &[u8] {
+        &self.hashes.as_ref()[position * self.digest_len..(position + 1) * self.digest_len]
+    }
+
+    /// Gets number of levels, the leaves and the root included
+    pub(crate) fn count(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    /// Gets number of Nodes on a level
+    pub(crate) fn len(&self, level: usize) -> usize {
+        self.starts[level + 1] - self.starts[level]
+    }
+
+    /// Gets the hashes of a level back to back
+    pub(crate) fn level(&self, level: usize) -> &[u8] {
+        &self.hashes.as_ref()[self.starts[level] * self.digest_len..self.starts[level + 1] * self.digest_len]
+    }
+
+    /// Gets the hash of the Node at `index` on `level`
+    pub(crate) fn hash(&self, level: usize, index: usize) -> &[u8] {
+        debug_assert!(index < self.len(level));
+        self.at(self.starts[level] + index)
+    }
+
+    /// Gets the hash of the Node at `index` on `level` to overwrite
+    pub(crate) fn hash_mut(&mut self, level: usize, index: usize) -> &mut [u8] {
+        debug_assert!(index < self.len(level));
+        let position = self.starts[level] + index;
+        &mut self.hashes.as_mut()[position * self.digest_len..(position + 1) * self.digest_len]
+    }
+
+    /// Gets the positions of the two children of a Node, looking through the levels it was promoted across
+    /// `None` for leaves
+    pub(crate) fn children(&self, mut level: usize, mut index: usize) -> Option<[(usize, usize); 2]> {
+        while level > 0 {
+            let (left, right) = (2 * index, 2 * index + 1);
+            if right < self.len(level - 1) {
+                return Some([(level - 1, left), (level - 1, right)]);
+            }
+            level -= 1;
+            index = left;
+        }
+        None
+    }
+
+    /// siblings of the leaf at `index` from the leaf up, skipping the levels it is promoted across
+    pub(crate) fn path(&self, index: usize) -> Vec<(HashDirection, Hash)> {
+        self.path_iter(index).map(|(hash_direction, hash)| (hash_direction, hash.to_vec())).collect()
+    }
+
+    /// siblings of the leaf at `index`, as `path` but borrowed from the levels one at a time
+    pub(crate) fn path_iter(&self, index: usize) -> ProofIter<'_> {
+        ProofIter {
+            hashes: self.hashes.as_ref(),
+            starts: &self.starts,
+            digest_len: self.digest_len,
+            level: 0,
+            position: index,
+        }
     }
 }
 
-/// recursive function to traverse the Merkle Tree through its children Nodes
-/// if Leaf is found, it returns a `Proof` which contains a list of hash proofs
-/// if Leaf is not found in Merkle Tree, then it returns `None` proofs
-fn traverse_and_collect_proofs<'a>(node: &'a Node, searched_leaf: &'a Hash) -> Option<Proof> {
-    if node.left.is_some() {
-        // going deeper to the left Node and searching for Leaf
-        let proofs = traverse_and_collect_proofs(&node.left.as_ref().unwrap(), searched_leaf);
-        if proofs.is_some() {
-            // collecting proofs during bubbling up from left
-            let mut proof = proofs.unwrap();
-            let value: &Hash = node.right.as_ref().unwrap().value.as_ref();
-            proof.hashes.push((HashDirection::Right, value.clone()));
-            return Some(proof)
+/// Siblings of a leaf read one at a time from the tree, from the leaf up, see `MerkleTree::proof_iter`
+///
+/// Each item is the side the sibling goes on and its hash, borrowed from the tree, so a proof can be
+/// written out, e.g. into a network buffer, without a `Proof` or any hash being allocated first.
+/// The items are the hashes of the `Proof` that `prove_by_index` returns for the same leaf.
+#[derive(Debug, Clone)]
+pub struct ProofIter<'a> {
+    hashes: &'a [u8],
+    starts: &'a [usize],
+    digest_len: usize,
+    /// next level to look for a sibling on
+    level: usize,
+    /// index of the leaf's ancestor on that level
+    position: usize,
+}
+
+impl<'a> Iterator for ProofIter<'a> {
+    type Item = (HashDirection, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the root level has no siblings
+        while self.level + 2 < self.starts.len() {
+            let (level, position) = (self.level, self.position);
+            self.level += 1;
+            self.position /= 2;
+            let sibling = position ^ 1;
+            if sibling < self.starts[level + 1] - self.starts[level] {
+                let hash_direction = if position.is_multiple_of(2) { HashDirection::Right } else { HashDirection::Left };
+                let start = (self.starts[level] + sibling) * self.digest_len;
+                return Some((hash_direction, &self.hashes[start..start + self.digest_len]));
+            }
         }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.level..self.starts.len().saturating_sub(2))
+            .filter(|&level| (self.position >> (level - self.level)) ^ 1 < self.starts[level + 1] - self.starts[level])
+            .count();
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for ProofIter<'_> {}
+
+impl std::iter::FusedIterator for ProofIter<'_> {}
+
+impl<H: Hasher> Proof<H> {
+    /// Creates a proof from sibling hashes ordered from the leaf up
+    pub(crate) fn new(hashes: Vec<(HashDirection, Hash)>) -> Proof<H> {
+        Proof { hashes, hasher: PhantomData }
+    }
+
+    /// Serializes the proof without the parameters of its tree, as a little-endian `u32` count of hashes,
+    /// followed by each hash encoded as its direction byte (left 0 or right 1), its length byte and the hash itself
+    /// this is the body `to_bytes` writes behind the parameters, for formats that record them once for many proofs
+    ///
+    /// # Panics
+    ///
+    /// When a hash is longer than 255 bytes or there are more than `u32::MAX` hashes, which the
+    /// format has no room for.
+    pub fn to_untagged_bytes(&self) -> Vec<u8> {
+        self.as_proof_ref().to_untagged_bytes()
     }
-    if node.right.is_some() {
-        // going deeper to the right Node and searching for Leaf
-        let proofs = traverse_and_collect_proofs(&node.right.as_ref().unwrap(), searched_leaf);
-        if proofs.is_some() {
-            // collecting proofs during bubbling up from right
-            let mut proof = proofs.unwrap();
-            let value: &Hash = node.left.as_ref().unwrap().value.as_ref();
-            proof.hashes.push((HashDirection::Left, value.clone()));
-            return Some(proof)
+
+    /// Deserializes a proof produced by `to_untagged_bytes`
+    /// returns `None` when the bytes are truncated, contain an unknown direction or have trailing data
+    pub fn from_untagged_bytes(bytes: &[u8]) -> Option<Proof<H>> {
+        let (count, mut rest) = bytes.split_first_chunk::<4>()?;
+        let count = u32::from_le_bytes(*count) as usize;
+        // every hash takes at least two bytes, which bounds the allocation for hostile counts
+        let mut hashes = Vec::with_capacity(count.min(rest.len() / 2));
+        for _ in 0..count {
+            let ([direction, len], tail) = rest.split_first_chunk::<2>()?;
+            let hash_direction = match direction {
+                0 => HashDirection::Left,
+                1 => HashDirection::Right,
+                _ => return None,
+            };
+            if tail.len() < *len as usize {
+                return None;
+            }
+            let (hash, tail) = tail.split_at(*len as usize);
+            hashes.push((hash_direction, hash.to_vec()));
+            rest = tail;
         }
+        rest.is_empty().then_some(Proof::new(hashes))
     }
-    if node.left.is_none() && node.right.is_none() && node.value.eq(searched_leaf) {
-        // we just found the Leaf in Merkle tree, lets start to bubble up collecting proofs on the way up
-        return Some(Proof{
-            hashes: vec![]
-        })
+}
+
+/// bytes of a finished tree: every hash of every level and the start of each level
+fn tree_memory_bytes(leaf_count: usize, hash_size: usize) -> usize {
+    // levels above the leaves halve the Nodes, each start takes a `usize` and the number of hashes one more
+    let starts = 2 + (usize::BITS - leaf_count.saturating_sub(1).leading_zeros()) as usize;
+    node_count(leaf_count)
+        .saturating_mul(hash_size)
+        .saturating_add(starts * size_of::<usize>())
+        .saturating_add(size_of::<MerkleTree>())
+}
+
+/// number of hashes the levels of a tree over `leaf_count` leaves hold, promoted Nodes counted on every level
+pub(crate) fn node_count(leaf_count: usize) -> usize {
+    let mut count = leaf_count;
+    let mut size = leaf_count;
+    while size > 1 {
+        size = size.div_ceil(2);
+        count = count.saturating_add(size);
     }
-    // we just traversed entire Merkle tree without finding Leaf, therefore return None `Proof`
-    None
+    count
+}
+
+/// sides of the siblings of a leaf from the leaf up, skipping the levels where its node is promoted
+pub(crate) fn sibling_directions(leaf_index: u64, leaf_count: u64) -> Option<Vec<HashDirection>> {
+    if leaf_index >= leaf_count {
+        return None;
+    }
+    let mut directions = vec![];
+    let (mut position, mut level_size) = (leaf_index, leaf_count);
+    while level_size > 1 {
+        if position.is_multiple_of(2) {
+            if position + 1 < level_size {
+                directions.push(HashDirection::Right);
+            }
+        } else {
+            directions.push(HashDirection::Left);
+        }
+        position /= 2;
+        level_size = level_size.div_ceil(2);
+    }
+    Some(directions)
+}
+
+/// number of leaves in the left subtree of a tree with `leaf_count` leaves
+/// pairing levels and promoting the odd node out always leaves the largest power of two,
+/// strictly smaller than `leaf_count`, on the left
+pub(crate) fn split_point(leaf_count: u64) -> u64 {
+    debug_assert!(leaf_count > 1);
+    1 << (u64::BITS - 1 - (leaf_count - 1).leading_zeros())
 }
 
 /// hashing the input Leafs
-fn hash_data(data: &Data) -> Hash {
+pub(crate) fn hash_data(data: &Data) -> Hash {
     sha2::Sha256::digest(data).to_vec()
 }
 
 /// concatenating left and right hash values to create a new parent value
-fn hash_concat(h1: &Hash, h2: &Hash) -> Hash {
-    let h3 = h1.iter().chain(h2).copied().collect();
-    hash_data(&h3)
+pub(crate) fn hash_concat(h1: &Hash, h2: &Hash) -> Hash {
+    let mut h3 = h1.iter().chain(h2).copied().collect();
+    let hash = hash_data(&h3);
+    scrub(&mut h3);
+    hash
+}
+
+/// overwrites a buffer that held leaf material with zeros, including its spare capacity
+/// without the `zeroize` feature the buffer is left to the allocator as it is
+// a `Vec` rather than a slice, so that the spare capacity is scrubbed as well
+#[allow(clippy::ptr_arg)]
+pub(crate) fn scrub(buffer: &mut Vec<u8>) {
+    #[cfg(feature = "zeroize")]
+    zeroize::Zeroize::zeroize(buffer);
+}
+
+/// overwrites a hash held in a slice with zeros, see `scrub`
+pub(crate) fn scrub_slice(buffer: &mut [u8]) {
+    #[cfg(feature = "zeroize")]
+    zeroize::Zeroize::zeroize(buffer);
+}
+
+/// Buffers the hashes of `Levels` can live in
+pub(crate) trait Storage: AsRef<[u8]> + AsMut<[u8]> {
+    /// overwrites the hashes with zeros once the levels are dropped, see `scrub`
+    fn scrub(&mut self);
+}
+
+impl Storage for Vec<u8> {
+    fn scrub(&mut self) {
+        scrub(self);
+    }
+}
+
+impl Storage for &mut [u8] {
+    fn scrub(&mut self) {
+        scrub_slice(self);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<B: Storage> Drop for Levels<B> {
+    fn drop(&mut self) {
+        self.hashes.scrub();
+    }
+}
+
+impl<H: Hasher> Default for Proof<H> {
+    fn default() -> Self {
+        Proof::new(vec![])
+    }
+}
+
+// two trees are equal when they have the same root over the same number of leaves: a tree commits to
+// its leaves through the root, so the levels in between are never compared, and neither are blooms
+
+impl<H: Hasher> PartialEq for MerkleTree<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_count == other.leaf_count && self.root_hash() == other.root_hash()
+    }
+}
+
+impl<H: Hasher> Eq for MerkleTree<H> {}
+
+impl<H: Hasher> std::hash::Hash for MerkleTree<H> {
+    fn hash<S: std::hash::Hasher>(&self, state: &mut S) {
+        std::hash::Hash::hash(self.root_hash(), state);
+        state.write_usize(self.leaf_count);
+    }
+}
+
+/// Shows the root and the leaf count, never the levels
+impl<H: Hasher> fmt::Debug for MerkleTree<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MerkleTree")
+            .field("root", &self.root())
+            .field("leaf_count", &self.leaf_count)
+            .finish()
+    }
+}
+
+// two proofs are equal when they take the same hashes from the same sides, in the same order
+
+impl<H: Hasher> PartialEq for Proof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hashes == other.hashes
+    }
+}
+
+impl<H: Hasher> Eq for Proof<H> {}
+
+impl<H: Hasher> std::hash::Hash for Proof<H> {
+    fn hash<S: std::hash::Hasher>(&self, state: &mut S) {
+        std::hash::Hash::hash(&self.hashes, state);
+    }
+}
+
+/// Shows each sibling as its side and its hash in hex
+impl<H: Hasher> fmt::Debug for Proof<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let siblings: Vec<_> = self.hashes.iter().map(|(hash_direction, hash)| (hash_direction, HexHash(hash))).collect();
+        f.debug_struct("Proof").field("hashes", &siblings).finish()
+    }
+}
+
+/// hash written as hex digits rather than as a list of bytes
+pub(crate) struct HexHash<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for HexHash<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<H: Hasher> Drop for Proof<H> {
+    fn drop(&mut self) {
+        for (_, hash) in &mut self.hashes {
+            scrub(hash);
+        }
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::unnecessary_cast)]
 mod tests {
     use super::*;
+    use crate::hasher::{Blake2bHasher, Truncated};
 
     fn example_data(n: usize) -> Vec<Data> {
         let mut data = vec![];
@@ -195,14 +876,14 @@ mod tests {
     fn test_verify_function_with_single_element_should_return_true() {
         let data = example_data(1);
         let hash = hash_data(&data[0]);
-        assert_eq!(MerkleTree::verify(&data, &hash), true);
+        assert_eq!(MerkleTree::verify(&data, &Root::new(hash)), true);
     }
 
     #[test]
     fn test_verify_function_with_two_elements_and_non_concatenated_hash_should_return_false() {
         let data2 = example_data(2);
         let hash2 = hash_data(&data2[0]);
-        assert_eq!(MerkleTree::verify(&data2, &hash2), false);
+        assert_eq!(MerkleTree::verify(&data2, &Root::new(hash2)), false);
     }
 
     #[test]
@@ -211,7 +892,7 @@ mod tests {
         let hash1 = hash_data(&data[0]);
         let hash2 = hash_data(&data[1]);
         let root = hash_concat(&hash1, &hash2);
-        assert_eq!(MerkleTree::verify(&data, &root), true);
+        assert_eq!(MerkleTree::verify(&data, &Root::new(root)), true);
     }
 
     #[test]
@@ -220,7 +901,7 @@ mod tests {
         let hash1 = hash_data(&data[1]);
         let hash2 = hash_data(&data[0]);
         let root = hash_concat(&hash1, &hash2);
-        assert_eq!(MerkleTree::verify(&data, &root), false);
+        assert_eq!(MerkleTree::verify(&data, &Root::new(root)), false);
     }
 
     #[test]
@@ -228,9 +909,7 @@ mod tests {
         let data = example_data(2);
         let tree = MerkleTree::construct(&data);
         let hash2 = hash_data(&data[1]);
-        let proof = Proof {
-            hashes: vec![(HashDirection::Right, hash2)]
-        };
+        let proof = Proof::new(vec![(HashDirection::Right, hash2)]);
         let actual = MerkleTree::verify_proof(&data[0], &proof, &tree.root());
         assert_eq!(true, actual);
     }
@@ -240,9 +919,7 @@ mod tests {
         let data = example_data(2);
         let tree = MerkleTree::construct(&data);
         let hash2 = hash_data(&data[1]);
-        let proof = Proof {
-            hashes: vec![(HashDirection::Left, hash2)]
-        };
+        let proof = Proof::new(vec![(HashDirection::Left, hash2)]);
         let actual = MerkleTree::verify_proof(&data[0], &proof, &tree.root());
         assert_eq!(false, actual);
     }
@@ -255,12 +932,10 @@ mod tests {
         let hash2 = hash_data(&data[1]);
         let hash5 = hash_concat(&hash1, &hash2);
         let hash4 = hash_data(&data[3]);
-        let proof = Proof {
-            hashes: vec![
+        let proof = Proof::new(vec![
                 (HashDirection::Right, hash4),
                 (HashDirection::Left, hash5)
-            ]
-        };
+            ]);
         let actual = MerkleTree::verify_proof(&data[2], &proof, &tree.root());
         assert_eq!(true, actual);
     }
@@ -272,9 +947,7 @@ mod tests {
         let actual = tree.prove(&data[0]);
 
         let hash2 = hash_data(&data[1]);
-        let expected = Proof {
-            hashes: vec![(HashDirection::Right, hash2)]
-        };
+        let expected: Proof = Proof::new(vec![(HashDirection::Right, hash2)]);
         assert_eq!(expected.hashes, actual.expect("this should return Proof").hashes)
     }
 
@@ -304,24 +977,20 @@ mod tests {
         let root_hash = hash_concat(&hash13, &hash14);
 
         let actual = tree.prove(&data[1]);
-        let expected = Proof {
-            hashes: vec![
+        let expected: Proof = Proof::new(vec![
                 (HashDirection::Left, hash1),
                 (HashDirection::Right, hash10),
                 (HashDirection::Right, hash14)
-            ]
-        };
+            ]);
         assert_eq!(expected.hashes, actual.expect("this should return Proof").hashes);
 
 
         let actual = tree.prove(&data[4]);
-        let expected = Proof {
-            hashes: vec![
+        let expected: Proof = Proof::new(vec![
                 (HashDirection::Right, hash6),
                 (HashDirection::Right, hash12),
                 (HashDirection::Left, hash13)
-            ]
-        };
+            ]);
         assert_eq!(expected.hashes, actual.expect("this should return Proof").hashes);
 
         assert_eq!(tree.root(), root_hash);
@@ -337,9 +1006,7 @@ mod tests {
         let hash1 = hash_data(&data[0]);
 
         let actual = tree.prove(&data[0]);
-        let expected = Proof {
-            hashes: vec![]
-        };
+        let expected: Proof = Proof::new(vec![]);
         assert_eq!(expected.hashes, actual.expect("this should return Proof").hashes);
         assert_eq!(tree.root(), hash1);
     }
@@ -354,4 +1021,325 @@ mod tests {
 
         assert_eq!(actual.is_none(), true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_prove_that_every_leaf_of_uneven_tree_will_be_proven() {
+        // with 6 leaves the promoted node on the second level is a subtree, not a leaf
+        let data = example_data(6);
+        let tree = MerkleTree::construct(&data);
+
+        for leaf in &data {
+            let proof = tree.prove(leaf).expect("this should return Proof");
+            assert!(MerkleTree::verify_proof(leaf, &proof, &tree.root()));
+        }
+    }
+
+    #[test]
+    fn test_construct_promotes_odd_subtrees_not_leaves() {
+        // root of leaves split at the largest power of two below their count, which promoting the odd
+        // node out of each level amounts to
+        fn split_root(leaves: &[Hash]) -> Hash {
+            if leaves.len() == 1 {
+                return leaves[0].clone();
+            }
+            let (left, right) = leaves.split_at(split_point(leaves.len() as u64) as usize);
+            hash_concat(&split_root(left), &split_root(right))
+        }
+
+        for n in 1..=33 {
+            let data = example_data(n);
+            let tree = MerkleTree::construct(&data);
+            let leaves: Vec<Hash> = data.iter().map(hash_data).collect();
+            assert_eq!(tree.root(), split_root(&leaves), "{n} leaves");
+            for leaf in &data {
+                let proof = tree.prove(leaf).expect("this should return Proof");
+                assert!(MerkleTree::verify_proof(leaf, &proof, &tree.root()), "{n} leaves");
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_proof_in_place_matches_verify_proof() {
+        let data = example_data(11);
+        let tree = MerkleTree::construct(&data);
+        let mut scratch = [0; 64];
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = tree.prove_by_index(index as u64).expect("this should return Proof");
+            assert!(MerkleTree::verify_proof_in_place(leaf, &proof, &tree.root(), &mut scratch));
+            assert!(!MerkleTree::verify_proof_in_place(&data[(index + 1) % 11], &proof, &tree.root(), &mut scratch));
+        }
+
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        let tree = MerkleTree::construct_with_hasher(&data, hasher);
+        let proof = tree.prove_by_index(4).expect("this should return Proof");
+        let mut scratch = vec![0; 40];
+        assert!(MerkleTree::verify_proof_in_place_with_hasher(&data[4], &proof, &tree.root(), &hasher, &mut scratch));
+        assert!(!MerkleTree::verify_proof_in_place_with_hasher(&data[4], &proof, &Root::new(tree.root().as_bytes()[..19].to_vec()), &hasher, &mut scratch));
+    }
+
+    #[test]
+    fn test_verify_proof_at_binds_proofs_to_their_position() {
+        let data = example_data(7);
+        let tree = MerkleTree::construct(&data);
+        for (index, leaf) in (0..).zip(&data) {
+            let proof = tree.prove_by_index(index).expect("this should return Proof");
+            assert!(MerkleTree::verify_proof_at(leaf, index, 7, &proof, &tree.root()));
+            assert!(!MerkleTree::verify_proof_at(leaf, 7, 7, &proof, &tree.root()));
+        }
+
+        // positions past what a 32-bit `usize` holds are checked the same way
+        let directions = sibling_directions(1 << 40, (1 << 40) + 1).expect("index is in range");
+        assert_eq!(directions, vec![HashDirection::Left]);
+        assert_eq!(split_point((1 << 40) + 1), 1 << 40);
+        // the promoted last leaf has a sibling on every level of a tree of eight
+        let proof = tree.prove_by_index(6).expect("this should return Proof");
+        assert!(!MerkleTree::verify_proof_at(&data[6], 6, 8, &proof, &tree.root()));
+
+        // two equal leaves verify with each other's proofs, but only at their own position
+        let data: Vec<Data> = vec![vec![1], vec![2], vec![1], vec![2]];
+        let tree = MerkleTree::construct(&data);
+        let proof = tree.prove_by_index(0).expect("this should return Proof");
+        assert!(MerkleTree::verify_proof(&data[2], &proof, &tree.root()));
+        assert!(MerkleTree::verify_proof_at(&data[0], 0, 4, &proof, &tree.root()));
+        assert!(!MerkleTree::verify_proof_at(&data[2], 2, 4, &proof, &tree.root()));
+    }
+
+    #[test]
+    fn test_leaf_and_internal_hashes_are_told_apart() {
+        let data = example_data(3);
+        let tree = MerkleTree::construct(&data);
+        let leaf_hashes: Vec<LeafHash> = (0..3).map(|index| tree.leaf_hash(index).expect("index is in range")).collect();
+        assert_eq!(leaf_hashes[1], LeafHash::of(&data[1], &Sha256Hasher::new()));
+        assert!(tree.leaf_hash(3).is_none());
+        assert_eq!(MerkleTree::from_leaf_hashes(leaf_hashes.clone()).root(), tree.root());
+
+        let proof = tree.prove_by_index(1).expect("this should return Proof");
+        assert!(MerkleTree::verify_proof_hashed(&leaf_hashes[1], &proof, &tree.root()));
+        assert!(!MerkleTree::verify_proof_hashed(&leaf_hashes[0], &proof, &tree.root()));
+
+        let parent = tree.internal_hash(1, 0).expect("the first two leaves are paired");
+        assert_eq!(parent, InternalHash::of(leaf_hashes[0].as_bytes(), leaf_hashes[1].as_bytes(), &Sha256Hasher::new()));
+        assert_eq!(tree.internal_hash(2, 0).expect("the root has children").as_bytes(), tree.root().as_bytes());
+        // the third leaf is promoted to level 1, and stays a leaf
+        assert!(tree.internal_hash(1, 1).is_none());
+        assert!(tree.internal_hash(0, 0).is_none());
+        assert!(tree.internal_hash(3, 0).is_none());
+    }
+
+    #[test]
+    fn test_trees_and_proofs_compare_by_what_they_commit_to() {
+        use std::collections::HashSet;
+
+        let data = example_data(5);
+        let tree = MerkleTree::construct(&data);
+        let rebuilt = MerkleTree::from_leaf_hashes((0..5).map(|index| tree.leaf_hash(index).expect("index is in range")).collect()).with_bloom_filter(0.01);
+        assert_eq!(tree, rebuilt);
+        assert_ne!(tree, MerkleTree::construct(&data[..4]));
+        let trees: HashSet<MerkleTree> = [tree, rebuilt, MerkleTree::construct(&data[1..])].into_iter().collect();
+        assert_eq!(trees.len(), 2);
+
+        let tree = MerkleTree::construct(&data);
+        assert_eq!(tree.prove_by_index(2), tree.prove(&data[2]));
+        assert_ne!(tree.prove_by_index(2), tree.prove_by_index(3));
+        assert_eq!(format!("{tree:?}"), format!("MerkleTree {{ root: {:?}, leaf_count: 5 }}", tree.root()));
+        let proof = tree.prove_by_index(4).expect("this should return Proof");
+        assert_eq!(format!("{proof:?}"), format!("Proof {{ hashes: [(Left, {})] }}", hex::encode(&proof.hashes[0].1)));
+    }
+
+    #[test]
+    fn test_proof_iter_yields_the_proof_hashes() {
+        for n in [1, 2, 3, 7, 8, 13] {
+            let data = example_data(n);
+            let tree = MerkleTree::construct(&data);
+            for index in 0..n as u64 {
+                let proof = tree.prove_by_index(index).expect("this should return Proof");
+                let siblings = tree.proof_iter(index).expect("index is in range");
+                assert_eq!(siblings.len(), proof.hashes.len());
+                let streamed: Vec<(HashDirection, Hash)> = siblings.map(|(hash_direction, hash)| (hash_direction, hash.to_vec())).collect();
+                assert_eq!(streamed, proof.hashes);
+            }
+            assert!(tree.proof_iter(n as u64).is_none());
+        }
+
+        // written straight into a reused buffer, as a server would
+        let data = example_data(13);
+        let tree = MerkleTree::construct(&data);
+        let mut buffer = Vec::with_capacity(256);
+        let mut siblings = tree.proof_iter(12).expect("index is in range");
+        // the last leaf is promoted over the first two levels
+        assert_eq!(siblings.len(), 2);
+        buffer.extend((siblings.len() as u32).to_le_bytes());
+        for (hash_direction, hash) in siblings.by_ref() {
+            buffer.extend([hash_direction as u8, hash.len() as u8]);
+            buffer.extend_from_slice(hash);
+        }
+        assert_eq!(siblings.len(), 0);
+        assert_eq!(buffer, tree.prove_by_index(12).expect("this should return Proof").to_untagged_bytes());
+    }
+
+    #[test]
+    fn test_canonical_trees_depend_only_on_the_set_of_leaves() {
+        let data = example_data(9);
+        let tree = MerkleTree::construct_canonical(&data);
+        let mut shuffled: Vec<Data> = data.iter().rev().cloned().collect();
+        shuffled.extend_from_slice(&data[2..5]);
+        assert_eq!(MerkleTree::construct_canonical(&shuffled), tree);
+        assert!(tree.leaf_hashes().is_sorted());
+        assert_eq!(tree.leaf_hashes().len(), 9);
+
+        let proof = tree.prove(&data[3]).expect("this should return Proof");
+        assert!(MerkleTree::verify_proof(&data[3], &proof, &tree.root()));
+        assert_ne!(MerkleTree::construct_canonical(&data[1..]), tree);
+
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        let tree = MerkleTree::construct_canonical_with_hasher(&shuffled, hasher);
+        assert_eq!(tree.root(), MerkleTree::construct_canonical_with_hasher(&data, hasher).root());
+    }
+
+    #[test]
+    fn test_proof_bytes_round_trip() {
+        let data = example_data(5);
+        let tree = MerkleTree::construct(&data);
+        let proof = tree.prove(&data[2]).expect("this should return Proof");
+
+        let decoded: Proof = Proof::from_bytes(&proof.to_bytes()).expect("this should decode Proof");
+        assert_eq!(proof.hashes, decoded.hashes);
+    }
+
+    #[test]
+    fn test_proof_from_malformed_bytes_will_return_none() {
+        let proof: Proof = Proof::new(vec![(HashDirection::Left, hash_data(&vec![1]))]);
+        let bytes = proof.to_untagged_bytes();
+
+        assert!(Proof::<Sha256Hasher>::from_untagged_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(Proof::<Sha256Hasher>::from_untagged_bytes(&[bytes.as_slice(), &[0]].concat()).is_none());
+        let mut invalid_direction = bytes.clone();
+        invalid_direction[4] = 2;
+        assert!(Proof::<Sha256Hasher>::from_untagged_bytes(&invalid_direction).is_none());
+        assert!(Proof::<Sha256Hasher>::from_untagged_bytes(&[]).is_none());
+    }
+
+    #[test]
+    fn test_memory_estimates() {
+        let data = example_data(4);
+        let tree = MerkleTree::construct(&data);
+        // 7 Nodes of 32 bytes on 3 levels, whose starts are followed by the number of hashes
+        let expected = size_of::<MerkleTree>() + 4 * size_of::<usize>() + 7 * 32;
+        assert_eq!(tree.estimated_memory_bytes(), expected);
+
+        assert!(MerkleTree::estimate_memory_bytes(4, 32) > expected);
+        assert!(MerkleTree::estimate_memory_bytes(1 << 20, 32) > (1 << 20) * 2 * 32);
+        assert_eq!(MerkleTree::estimate_memory_bytes(usize::MAX, 32), usize::MAX);
+    }
+
+    #[test]
+    fn test_trees_carry_digest_length_of_their_hasher() {
+        use crate::hasher::Shake256Hasher;
+
+        let data = example_data(5);
+        let hasher = Shake256Hasher::new(48).expect("valid length");
+        let tree = MerkleTree::construct_with_hasher(&data, hasher);
+        assert_eq!(tree.root().as_bytes().len(), 48);
+        assert!(MerkleTree::verify_with_hasher(&data, &tree.root(), hasher));
+
+        let proof = tree.prove(&data[3]).expect("this should return Proof");
+        assert!(proof.hashes.iter().all(|(_, hash)| hash.len() == 48));
+        assert!(MerkleTree::verify_proof_with_hasher(&data[3], &proof, &tree.root(), &hasher));
+
+        // a truncated root or a different output length must not verify
+        assert!(!MerkleTree::verify_proof_with_hasher(&data[3], &proof, &Root::new(tree.root().as_bytes()[..32].to_vec()), &hasher));
+        let shorter = Shake256Hasher::new(32).expect("valid length");
+        assert!(!MerkleTree::verify_proof_with_hasher(&data[3], &proof, &tree.root(), &shorter));
+
+        let blake2b = MerkleTree::construct_with_hasher(&data, Blake2bHasher::new(20).expect("valid length"));
+        assert_eq!(blake2b.root().as_bytes().len(), 20);
+        assert_eq!(blake2b.root_multihash().map(|root| root.algorithm()), Some(HashAlgorithm::Blake2b(20)));
+    }
+
+    #[test]
+    fn test_trees_constructed_with_rustcrypto_digests() {
+        let data = example_data(5);
+        let tree = MerkleTree::construct_with::<sha3::Sha3_256>(&data);
+        assert_eq!(tree.root().as_bytes().len(), 32);
+        assert_ne!(tree.root(), MerkleTree::construct(&data).root());
+        assert!(MerkleTree::verify_with::<sha3::Sha3_256>(&data, &tree.root()));
+        assert!(!MerkleTree::verify_with::<sha2::Sha256>(&data, &tree.root()));
+
+        let proof = tree.prove(&data[4]).expect("this should return Proof");
+        assert!(MerkleTree::verify_proof_with::<sha3::Sha3_256>(&data[4], &proof, &tree.root()));
+        let decoded = Proof::from_bytes_with_hasher(&proof.to_bytes_with_hasher(tree.hasher()).expect("SHA3-256 has a code"), tree.hasher()).expect("this should decode Proof");
+        assert!(MerkleTree::verify_proof_with::<sha3::Sha3_256>(&data[4], &decoded, &tree.root()));
+
+        let sha512 = MerkleTree::construct_with::<sha2::Sha512>(&data);
+        assert_eq!(sha512.root().as_bytes().len(), 64);
+        assert_eq!(sha512.root_multihash().map(|root| root.algorithm()), Some(HashAlgorithm::Sha2_512));
+        assert!(MerkleTree::construct_with::<sha2::Sha224>(&data).root_multihash().is_none());
+
+        // the default tree is the SHA-256 digest tree
+        let sha256: MerkleTree = MerkleTree::construct_with::<sha2::Sha256>(&data);
+        assert_eq!(sha256.root(), MerkleTree::construct(&data).root());
+    }
+
+    #[test]
+    fn test_prove_by_index_and_prove_many_match_prove() {
+        let data = example_data(13);
+        let tree = MerkleTree::construct(&data);
+        for (index, leaf) in (0..).zip(&data) {
+            let proof = tree.prove_by_index(index).expect("this should return Proof");
+            assert_eq!(proof.hashes, tree.prove(leaf).expect("this should return Proof").hashes);
+        }
+        assert!(tree.prove_by_index(13).is_none());
+        assert!(tree.prove_by_index(u64::MAX).is_none());
+        assert_eq!(tree.leaf_count(), 13);
+
+        let indices = [12, 0, 5, 5, 7, 1];
+        for proofs in [tree.prove_many(&indices), tree.prove_many_parallel(&indices, 4)] {
+            assert_eq!(proofs.len(), indices.len());
+            for (index, proof) in indices.iter().zip(&proofs) {
+                assert!(MerkleTree::verify_proof(&data[*index as usize], proof, &tree.root()));
+            }
+        }
+        assert!(tree.prove_many_parallel(&[], 3).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 255 bytes")]
+    fn test_to_bytes_panics_for_hashes_too_long_for_their_length_byte() {
+        let proof: Proof = Proof::new(vec![(HashDirection::Left, vec![0; 256])]);
+        proof.to_untagged_bytes();
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn test_prove_many_panics_for_index_out_of_range() {
+        MerkleTree::construct(&example_data(4)).prove_many(&[1, 4]);
+    }
+
+    #[test]
+    fn test_trees_with_truncated_hashes() {
+        let hasher = Truncated::new(Sha256Hasher::new(), 16).expect("valid length");
+        let data = example_data(9);
+        let tree = MerkleTree::construct_with_hasher(&data, hasher);
+        assert_eq!(tree.root().as_bytes().len(), 16);
+        for leaf in &data {
+            let proof = tree.prove(leaf).expect("this should return Proof");
+            assert!(proof.hashes.iter().all(|(_, hash)| hash.len() == 16));
+            assert!(MerkleTree::verify_proof_with_hasher(leaf, &proof, &tree.root(), &hasher));
+            assert_eq!(proof.to_untagged_bytes().len(), 4 + proof.hashes.len() * 18);
+        }
+        // the full hashes of an untruncated tree are refused
+        let full = MerkleTree::construct(&data);
+        let proof = Proof::new(full.prove(&data[0]).expect("this should return Proof").hashes.clone());
+        assert!(!MerkleTree::verify_proof_with_hasher(&data[0], &proof, &full.root(), &hasher));
+    }
+
+    #[test]
+    fn test_verify_streaming_matches_constructed_root() {
+        let data = example_data(37);
+        let root = MerkleTree::construct(&data).root();
+        assert!(MerkleTree::verify_streaming((0..37_u8).map(|i| [i]), &root));
+        assert!(!MerkleTree::verify_streaming((0..36_u8).map(|i| [i]), &root));
+        assert!(!MerkleTree::verify_streaming(Vec::<Data>::new(), &root));
+        assert!(!MerkleTree::verify(&[], &root));
+    }
+}
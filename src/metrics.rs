@@ -0,0 +1,260 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::Hash;
+use crate::multihash::HashAlgorithm;
+
+/// Something that happened while building, proving or verifying, as reported to a `MetricsSink`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsEvent {
+    /// a hash was computed over `bytes` bytes, leaf data or two child hashes
+    Hashed { bytes: u64 },
+    /// a proof was generated for one leaf
+    ProofGenerated,
+    /// a proof didn't lead to the root it was checked against
+    VerificationFailed,
+}
+
+/// Receiver of the events of every tree built with a `MeteredHasher`, e.g. to count them for Prometheus
+///
+/// Events are reported as they happen, from the thread they happen on, so recording one has to be cheap:
+/// incrementing a counter, not taking a lock for the duration of a request. `Counters` is a sink that
+/// does just that.
+pub trait MetricsSink: Send + Sync {
+    fn record(&self, event: MetricsEvent);
+}
+
+/// Sink counting every event, to be read out by a metrics exporter
+#[derive(Debug, Default)]
+pub struct Counters {
+    hashes: AtomicU64,
+    bytes_hashed: AtomicU64,
+    proofs: AtomicU64,
+    verification_failures: AtomicU64,
+}
+
+impl Counters {
+    /// Creates counters all at zero
+    pub fn new() -> Counters {
+        Counters::default()
+    }
+
+    /// Gets number of hashes computed
+    pub fn hashes(&self) -> u64 {
+        self.hashes.load(Ordering::Relaxed)
+    }
+
+    /// Gets number of bytes fed into hashes
+    pub fn bytes_hashed(&self) -> u64 {
+        self.bytes_hashed.load(Ordering::Relaxed)
+    }
+
+    /// Gets number of proofs generated
+    pub fn proofs(&self) -> u64 {
+        self.proofs.load(Ordering::Relaxed)
+    }
+
+    /// Gets number of proofs that failed to verify
+    pub fn verification_failures(&self) -> u64 {
+        self.verification_failures.load(Ordering::Relaxed)
+    }
+}
+
+impl MetricsSink for Counters {
+    fn record(&self, event: MetricsEvent) {
+        match event {
+            MetricsEvent::Hashed { bytes } => {
+                self.hashes.fetch_add(1, Ordering::Relaxed);
+                self.bytes_hashed.fetch_add(bytes, Ordering::Relaxed);
+            }
+            MetricsEvent::ProofGenerated => {
+                self.proofs.fetch_add(1, Ordering::Relaxed);
+            }
+            MetricsEvent::VerificationFailed => {
+                self.verification_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Any hasher reporting to a sink every hash it computes, and every proof generated or failing to verify
+/// with it
+///
+/// Trees, proofs and verifications take their hasher everywhere anyway, so metering happens by building
+/// with this one instead of wrapping each call. The hashes are those of the inner hasher, and so are
+/// the roots, proofs and serialized formats. Clones report to the same sink.
+#[derive(Clone)]
+pub struct MeteredHasher<H: Hasher = Sha256Hasher> {
+    inner: H,
+    sink: Arc<dyn MetricsSink>,
+}
+
+impl<H: Hasher> MeteredHasher<H> {
+    /// Creates a hasher hashing with `inner` and reporting to `sink`
+    pub fn new(inner: H, sink: Arc<dyn MetricsSink>) -> MeteredHasher<H> {
+        MeteredHasher { inner, sink }
+    }
+
+    /// Gets the hasher that is metered
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    fn hashed(&self, bytes: usize) {
+        self.sink.record(MetricsEvent::Hashed { bytes: bytes as u64 });
+    }
+}
+
+impl<H: Hasher + fmt::Debug> fmt::Debug for MeteredHasher<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MeteredHasher").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl<H: Hasher> Hasher for MeteredHasher<H> {
+    fn algorithm(&self) -> Option<HashAlgorithm> {
+        self.inner.algorithm()
+    }
+
+    fn digest_len(&self) -> usize {
+        self.inner.digest_len()
+    }
+
+    fn hash(&self, data: &[u8]) -> Hash {
+        self.hashed(data.len());
+        self.inner.hash(data)
+    }
+
+    fn hash_concat(&self, left: &[u8], right: &[u8]) -> Hash {
+        self.hashed(left.len() + right.len());
+        self.inner.hash_concat(left, right)
+    }
+
+    fn hash_into(&self, data: &[u8], out: &mut [u8]) {
+        self.hashed(data.len());
+        self.inner.hash_into(data, out);
+    }
+
+    fn hash_concat_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        self.hashed(left.len() + right.len());
+        self.inner.hash_concat_into(left, right, out);
+    }
+
+    fn metrics(&self) -> Option<&dyn MetricsSink> {
+        Some(self.sink.as_ref())
+    }
+}
+
+/// reports an event to the sink of a hasher, if it has one
+pub(crate) fn record(hasher: &impl Hasher, event: MetricsEvent) {
+    if let Some(sink) = hasher.metrics() {
+        sink.record(event);
+    }
+}
+
+/// passes through the outcome of a verification, reporting it to the sink of the hasher when it failed
+pub(crate) fn verified(hasher: &impl Hasher, verified: bool) -> bool {
+    if !verified {
+        record(hasher, MetricsEvent::VerificationFailed);
+    }
+    verified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Truncated;
+    use crate::merkletree::{Data, MerkleTree};
+    use crate::root::Root;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8; 10]).collect()
+    }
+
+    #[test]
+    fn test_metered_trees_count_hashes_and_proofs() {
+        let data = example_data(5);
+        let counters = Arc::new(Counters::new());
+        let hasher = MeteredHasher::new(Sha256Hasher::new(), counters.clone());
+        let tree = MerkleTree::construct_with_hasher(&data, hasher.clone());
+        assert_eq!(tree.root(), MerkleTree::construct(&data).root());
+        // five leaves of ten bytes, and four nodes over two hashes each
+        assert_eq!((counters.hashes(), counters.bytes_hashed()), (9, 5 * 10 + 4 * 64));
+
+        let proofs = tree.prove_many(&[0, 4]);
+        let proof = tree.prove_by_index(2).expect("index is in range");
+        assert_eq!(counters.proofs(), 3);
+        assert!(MerkleTree::verify_proof_with_hasher(&data[2], &proof, &tree.root(), &hasher));
+        assert!(!MerkleTree::verify_proof_with_hasher(&data[2], &proofs[0], &tree.root(), &hasher));
+        assert!(!MerkleTree::verify_proof_at_with_hasher(&data[2], 3, 5, &proof, &tree.root(), &hasher));
+        assert!(!MerkleTree::verify_proof_in_place_with_hasher(&data[0], &proof, &tree.root(), &hasher, &mut [0; 64]));
+        assert_eq!(counters.verification_failures(), 3);
+    }
+
+    #[test]
+    fn test_every_kind_of_tree_counts_proofs_and_rejections() {
+        use crate::disk_tree::DiskTree;
+        use crate::indexed::IndexedTree;
+        use crate::merkle_log::MerkleLog;
+        use crate::nary::NaryMerkleTree;
+        use crate::node_store::{MemoryNodeStore, TreeStore};
+
+        let data = example_data(5);
+        let counters = Arc::new(Counters::new());
+        let hasher = MeteredHasher::new(Sha256Hasher::new(), counters.clone());
+        let counted = || (counters.proofs(), counters.verification_failures());
+
+        let mut log = MerkleLog::with_hasher(hasher.clone());
+        for leaf in &data {
+            log.append(leaf);
+        }
+        let (head, old) = (log.head().expect("log has entries"), log.head_at(3).expect("log had three entries"));
+        let mut proof = log.prove_inclusion(2, &head).expect("index is in range");
+        proof.leaf_index = 3;
+        assert!(!MerkleLog::verify_inclusion_with_hasher(&data[2], &proof, &head, &hasher));
+        let consistency = log.prove_consistency(&old, &head).expect("heads are the log's");
+        assert!(!MerkleLog::verify_consistency_with_hasher(&head, &old, &consistency, &hasher));
+        assert_eq!(counted(), (1, 2));
+
+        let nary = NaryMerkleTree::construct_with_hasher(&data, 3, hasher.clone()).expect("valid input");
+        let proof = nary.prove(&data[0]).expect("leaf is in the tree");
+        assert!(!NaryMerkleTree::verify_proof_with_hasher(&data[1], &proof, &nary.root(), &hasher));
+        assert_eq!(counted(), (2, 3));
+
+        let mut indexed = IndexedTree::with_hasher(3, hasher.clone());
+        indexed.insert(2, &data[2]);
+        let proof = indexed.prove(2).expect("slot is occupied");
+        assert!(!IndexedTree::verify_slot_with_hasher(&data[2], 3, 3, &proof, &indexed.root(), &hasher));
+        assert_eq!(counted(), (3, 4));
+
+        let tree = MerkleTree::construct_with_hasher(&data, hasher.clone());
+        let found = tree.search(&data[1]);
+        assert!(!MerkleTree::verify_search_with_hasher(&data[1], &found, 6, &tree.root(), &hasher));
+        assert!(tree.prove_ref(4).is_some());
+        let mut store = TreeStore::with_store(MemoryNodeStore::new(), hasher.clone());
+        let root = store.insert_tree(&tree).expect("stores in memory");
+        assert!(store.prove(&root, 5, 4).expect("reads from memory").is_some());
+        assert_eq!(counted(), (6, 5));
+
+        let dir = std::env::temp_dir().join(format!("merkle-metrics-{}", std::process::id()));
+        let disk_tree = DiskTree::build_with_hasher(&data, &dir, 0, hasher.clone()).expect("writes levels").expect("has leaves");
+        assert!(disk_tree.prove_by_index(1).expect("reads levels").is_some());
+        assert_eq!(counted(), (7, 5));
+        drop(disk_tree);
+        std::fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_wrapped_metered_hashers_keep_reporting() {
+        let data = example_data(4);
+        let counters = Arc::new(Counters::new());
+        let hasher = Truncated::new(MeteredHasher::new(Sha256Hasher::new(), counters.clone()), 16).expect("valid length");
+        let tree = MerkleTree::construct_with_hasher(&data, hasher.clone());
+        assert_eq!(counters.hashes(), 7);
+        let proof = tree.prove_by_index(1).expect("index is in range");
+        assert!(!MerkleTree::verify_proof_with_hasher(&data[1], &proof, &Root::new(vec![0; 16]), &hasher));
+        assert_eq!((counters.proofs(), counters.verification_failures()), (1, 1));
+    }
+}
@@ -0,0 +1,134 @@
+use sha2::{Digest, Sha256};
+
+use crate::merkletree::{split_point, MerkleTree};
+
+/// Proof of a leaf of a SHA-256 tree in the smallest wire format: the leaf's index and its siblings
+///
+/// The siblings carry no direction tags, the verifier derives them from the index and the size of
+/// the tree the root commits to. On the wire the index is a little-endian `u64`, followed by the
+/// 32 byte siblings from the leaf up, with nothing else in between or after.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinimalProof {
+    pub leaf_index: u64,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MinimalProof {
+    /// Serializes the proof in the wire format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.leaf_index.to_le_bytes().to_vec();
+        for sibling in &self.siblings {
+            bytes.extend_from_slice(sibling);
+        }
+        bytes
+    }
+
+    /// Deserializes a proof in the wire format
+    /// returns `None` when the bytes after the index do not split into whole siblings
+    pub fn from_bytes(bytes: &[u8]) -> Option<MinimalProof> {
+        let (leaf_index, siblings) = bytes.split_first_chunk::<8>()?;
+        if !siblings.len().is_multiple_of(32) {
+            return None;
+        }
+        Some(MinimalProof {
+            leaf_index: u64::from_le_bytes(*leaf_index),
+            siblings: siblings.chunks_exact(32).map(|sibling| sibling.try_into().expect("chunks are 32 bytes")).collect(),
+        })
+    }
+}
+
+impl MerkleTree {
+    /// Returns the minimal proof for the leaf at `index`
+    pub fn prove_minimal(&self, index: u64) -> Option<MinimalProof> {
+        let proof = self.prove_by_index(index)?;
+        Some(MinimalProof {
+            leaf_index: index,
+            siblings: proof.hashes.iter().map(|(_, hash)| hash.as_slice().try_into().expect("SHA-256 hashes are 32 bytes")).collect(),
+        })
+    }
+}
+
+/// Verifies that `leaf` is at the proof's index in the tree of `tree_size` leaves with the given root
+///
+/// Self-contained so it can be ported line by line: descend from the root, where a subtree of `n > 1`
+/// leaves has the largest power of two below `n` on its left; a leaf on the left takes the next
+/// sibling from the right, a leaf on the right from the left. Leaves hash as `SHA-256(leaf)` and nodes
+/// as `SHA-256(left || right)`. The proof has to hold exactly one sibling per level of the descent.
+pub fn verify(leaf: &[u8], proof: &MinimalProof, tree_size: u64, root: &[u8; 32]) -> bool {
+    if proof.leaf_index >= tree_size {
+        return false;
+    }
+    // sides of the siblings from the root down
+    let mut sibling_on_left = vec![];
+    let (mut index, mut size) = (proof.leaf_index, tree_size);
+    while size > 1 {
+        let left = split_point(size);
+        sibling_on_left.push(index >= left);
+        if index >= left {
+            index -= left;
+            size -= left;
+        } else {
+            size = left;
+        }
+    }
+    if sibling_on_left.len() != proof.siblings.len() {
+        return false;
+    }
+
+    let mut hash: [u8; 32] = Sha256::digest(leaf).into();
+    for (sibling, on_left) in proof.siblings.iter().zip(sibling_on_left.iter().rev()) {
+        let (left, right) = if *on_left { (sibling, &hash) } else { (&hash, sibling) };
+        hash = Sha256::new().chain_update(left).chain_update(right).finalize().into();
+    }
+    hash == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    fn root_of(tree: &MerkleTree) -> [u8; 32] {
+        tree.root().as_bytes().try_into().expect("SHA-256 root")
+    }
+
+    #[test]
+    fn test_minimal_proofs_verify_for_every_leaf() {
+        for n in [1, 2, 5, 8, 13] {
+            let data = example_data(n);
+            let tree = MerkleTree::construct(&data);
+            for (index, leaf) in (0..).zip(&data) {
+                let proof = tree.prove_minimal(index).expect("index is in range");
+                let decoded = MinimalProof::from_bytes(&proof.to_bytes()).expect("round trips");
+                assert_eq!(decoded, proof);
+                assert!(verify(leaf, &decoded, n as u64, &root_of(&tree)));
+            }
+            assert!(tree.prove_minimal(n as u64).is_none());
+        }
+    }
+
+    #[test]
+    fn test_minimal_proofs_bind_index_and_tree_size() {
+        let data = example_data(6);
+        let tree = MerkleTree::construct(&data);
+        let proof = tree.prove_minimal(2).expect("index is in range");
+        assert_eq!(proof.to_bytes().len(), 8 + 3 * 32);
+
+        let moved = MinimalProof { leaf_index: 3, ..proof.clone() };
+        assert!(!verify(&data[2], &moved, 6, &root_of(&tree)));
+        assert!(!verify(&data[2], &proof, 3, &root_of(&tree)));
+        assert!(!verify(&data[2], &proof, 2, &root_of(&tree)));
+        assert!(!verify(&data[3], &proof, 6, &root_of(&tree)));
+    }
+
+    #[test]
+    fn test_from_malformed_bytes_will_return_none() {
+        assert!(MinimalProof::from_bytes(&[0; 7]).is_none());
+        assert!(MinimalProof::from_bytes(&[0; 8 + 31]).is_none());
+        assert_eq!(MinimalProof::from_bytes(&[0; 8]).map(|proof| proof.siblings.len()), Some(0));
+    }
+}
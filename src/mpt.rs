@@ -0,0 +1,307 @@
+use std::fmt;
+
+use sha3::{Digest, Keccak256};
+
+/// root of the trie without any keys, the Keccak-256 hash of the RLP encoding of an empty string
+pub const EMPTY_TRIE_ROOT: [u8; 32] = [
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e, 0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c,
+    0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
+/// A decoded RLP item, borrowing from the encoding it was decoded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rlp<'a> {
+    Bytes(&'a [u8]),
+    List(Vec<Rlp<'a>>),
+}
+
+/// Reasons a Merkle-Patricia proof is refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MptError {
+    /// the proof node at this position is not valid RLP, or not a branch, extension or leaf node
+    MalformedNode(usize),
+    /// the proof node at this position does not hash to the reference its parent holds
+    HashMismatch(usize),
+    /// the path needs more nodes than the proof holds
+    MissingNode,
+    /// the path ends before the proof does
+    UnusedNodes,
+}
+
+impl fmt::Display for MptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MptError::MalformedNode(index) => write!(f, "proof node {index} is malformed"),
+            MptError::HashMismatch(index) => write!(f, "proof node {index} does not match its reference"),
+            MptError::MissingNode => write!(f, "proof ends before the path does"),
+            MptError::UnusedNodes => write!(f, "proof holds nodes past the end of the path"),
+        }
+    }
+}
+
+impl std::error::Error for MptError {}
+
+impl<'a> Rlp<'a> {
+    /// Decodes exactly one item, `None` when the bytes are malformed or have trailing data
+    pub fn decode(bytes: &'a [u8]) -> Option<Rlp<'a>> {
+        let (item, rest) = decode_item(bytes)?;
+        rest.is_empty().then_some(item)
+    }
+
+    /// Gets the content of a string item
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            Rlp::Bytes(bytes) => Some(bytes),
+            Rlp::List(_) => None,
+        }
+    }
+
+    /// Gets the items of a list item
+    pub fn as_list(&self) -> Option<&[Rlp<'a>]> {
+        match self {
+            Rlp::List(items) => Some(items),
+            Rlp::Bytes(_) => None,
+        }
+    }
+}
+
+/// one item from the front of `bytes` and whatever follows it
+fn decode_item(bytes: &[u8]) -> Option<(Rlp<'_>, &[u8])> {
+    let (prefix, rest) = bytes.split_first()?;
+    let (is_list, header, len) = match prefix {
+        0x00..=0x7f => return Some((Rlp::Bytes(&bytes[..1]), rest)),
+        0x80..=0xb7 => (false, 0, usize::from(prefix - 0x80)),
+        0xb8..=0xbf => (false, usize::from(prefix - 0xb7), long_length(rest, usize::from(prefix - 0xb7))?),
+        0xc0..=0xf7 => (true, 0, usize::from(prefix - 0xc0)),
+        0xf8..=0xff => (true, usize::from(prefix - 0xf7), long_length(rest, usize::from(prefix - 0xf7))?),
+    };
+    let payload = rest.get(header..header.checked_add(len)?)?;
+    let rest = &rest[header + len..];
+    if !is_list {
+        // a single byte below 0x80 encodes as itself, never as a one byte string
+        if len == 1 && payload[0] < 0x80 {
+            return None;
+        }
+        return Some((Rlp::Bytes(payload), rest));
+    }
+    let mut items = vec![];
+    let mut remaining = payload;
+    while !remaining.is_empty() {
+        let (item, tail) = decode_item(remaining)?;
+        items.push(item);
+        remaining = tail;
+    }
+    Some((Rlp::List(items), rest))
+}
+
+/// big-endian length of `len_len` bytes at the front of `bytes`, which has to need the long form
+fn long_length(bytes: &[u8], len_len: usize) -> Option<usize> {
+    let len_bytes = bytes.get(..len_len)?;
+    if len_len > size_of::<usize>() || len_bytes[0] == 0 {
+        return None;
+    }
+    let len = len_bytes.iter().fold(0, |len, byte| (len << 8) | usize::from(*byte));
+    (len > 55).then_some(len)
+}
+
+/// Keccak-256 hash, which Ethereum tries reference their nodes and key their paths by
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// Verifies a Merkle-Patricia proof, as `eth_getProof` returns, for `key` in the trie with the given root
+///
+/// The proof holds the RLP encoded nodes on the path from the root down. Returns the value stored
+/// at the key, or `None` when the proof shows that the trie does not hold the key at all.
+/// Keys are used as they are given, the secure tries of Ethereum state hash them with `keccak256` first.
+pub fn verify_proof(root: &[u8; 32], key: &[u8], proof: &[impl AsRef<[u8]>]) -> Result<Option<Vec<u8>>, MptError> {
+    if proof.is_empty() {
+        return if *root == EMPTY_TRIE_ROOT { Ok(None) } else { Err(MptError::MissingNode) };
+    }
+    let path: Vec<u8> = key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect();
+    let mut path = path.as_slice();
+    let mut nodes = proof.iter().map(AsRef::as_ref).enumerate();
+
+    let (mut index, encoded) = nodes.next().expect("proof is not empty");
+    if keccak256(encoded) != *root {
+        return Err(MptError::HashMismatch(index));
+    }
+    let mut node = Rlp::decode(encoded).ok_or(MptError::MalformedNode(index))?;
+    loop {
+        let items = node.as_list().ok_or(MptError::MalformedNode(index))?;
+        let child = match items {
+            [children @ .., value] if children.len() == 16 => match path.split_first() {
+                None => {
+                    let value = value.as_bytes().ok_or(MptError::MalformedNode(index))?;
+                    return finish(nodes.next(), (!value.is_empty()).then(|| value.to_vec()));
+                }
+                Some((nibble, rest)) => {
+                    path = rest;
+                    &children[usize::from(*nibble)]
+                }
+            },
+            [encoded_path, next] => {
+                let (is_leaf, node_path) = decode_path(encoded_path.as_bytes().ok_or(MptError::MalformedNode(index))?)
+                    .ok_or(MptError::MalformedNode(index))?;
+                if is_leaf {
+                    let value = next.as_bytes().ok_or(MptError::MalformedNode(index))?;
+                    return finish(nodes.next(), (node_path == path).then(|| value.to_vec()));
+                }
+                match path.strip_prefix(node_path.as_slice()) {
+                    Some(rest) => {
+                        path = rest;
+                        next
+                    }
+                    // the key's path leaves the extension, so no node holds it
+                    None => return finish(nodes.next(), None),
+                }
+            }
+            _ => return Err(MptError::MalformedNode(index)),
+        };
+
+        node = match child {
+            // an empty slot, no key continues down this way
+            Rlp::Bytes([]) => return finish(nodes.next(), None),
+            Rlp::Bytes(reference) if reference.len() == 32 => {
+                let (next_index, encoded) = nodes.next().ok_or(MptError::MissingNode)?;
+                index = next_index;
+                if keccak256(encoded) != *reference {
+                    return Err(MptError::HashMismatch(index));
+                }
+                Rlp::decode(encoded).ok_or(MptError::MalformedNode(index))?
+            }
+            // nodes encoding to less than 32 bytes are embedded in their parent
+            Rlp::List(_) => child.clone(),
+            Rlp::Bytes(_) => return Err(MptError::MalformedNode(index)),
+        };
+    }
+}
+
+/// the result of a proof whose path ended, as long as no nodes are left over
+fn finish<T>(next: Option<T>, value: Option<Vec<u8>>) -> Result<Option<Vec<u8>>, MptError> {
+    match next {
+        Some(_) => Err(MptError::UnusedNodes),
+        None => Ok(value),
+    }
+}
+
+/// nibbles of a hex-prefix encoded path and whether it belongs to a leaf rather than an extension
+fn decode_path(encoded: &[u8]) -> Option<(bool, Vec<u8>)> {
+    let (first, rest) = encoded.split_first()?;
+    let flag = first >> 4;
+    if flag > 3 || (flag & 1 == 0 && first & 0x0f != 0) {
+        return None;
+    }
+    let mut nibbles = vec![];
+    if flag & 1 == 1 {
+        nibbles.push(first & 0x0f);
+    }
+    nibbles.extend(rest.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]));
+    Some((flag >= 2, nibbles))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// RLP encoding of a string
+    pub(crate) fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+        if bytes.len() == 1 && bytes[0] < 0x80 {
+            return bytes.to_vec();
+        }
+        [length_prefix(0x80, bytes.len()), bytes.to_vec()].concat()
+    }
+
+    /// RLP encoding of a list of encoded items
+    pub(crate) fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+        let payload = items.concat();
+        [length_prefix(0xc0, payload.len()), payload].concat()
+    }
+
+    fn length_prefix(offset: u8, len: usize) -> Vec<u8> {
+        if len <= 55 {
+            return vec![offset + len as u8];
+        }
+        let len_bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|byte| *byte == 0).collect();
+        [vec![offset + 55 + len_bytes.len() as u8], len_bytes].concat()
+    }
+
+    /// hex-prefix encoding of a path of nibbles
+    pub(crate) fn encode_path(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+        let flag = if is_leaf { 2 } else { 0 } + (nibbles.len() % 2) as u8;
+        let mut padded = vec![flag];
+        if nibbles.len().is_multiple_of(2) {
+            padded.push(0);
+        }
+        padded.extend_from_slice(nibbles);
+        padded.chunks(2).map(|pair| (pair[0] << 4) | pair[1]).collect()
+    }
+
+    pub(crate) fn nibbles(key: &[u8]) -> Vec<u8> {
+        key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()
+    }
+
+    /// a trie of two keys that differ in their first nibble: a branch over two hashed leaves
+    fn two_key_trie(first: &[u8], second: &[u8], value: &[u8]) -> ([u8; 32], Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let leaf = |key: &[u8]| encode_list(&[encode_bytes(&encode_path(&nibbles(key)[1..], true)), encode_bytes(value)]);
+        let (first_leaf, second_leaf) = (leaf(first), leaf(second));
+        let mut children = vec![encode_bytes(&[]); 17];
+        children[usize::from(first[0] >> 4)] = encode_bytes(&keccak256(&first_leaf));
+        children[usize::from(second[0] >> 4)] = encode_bytes(&keccak256(&second_leaf));
+        let branch = encode_list(&children);
+        (keccak256(&branch), vec![branch.clone(), first_leaf], vec![branch, second_leaf])
+    }
+
+    #[test]
+    fn test_rlp_decoding() {
+        assert_eq!(Rlp::decode(&[0x83, b'd', b'o', b'g']), Some(Rlp::Bytes(b"dog")));
+        assert_eq!(Rlp::decode(&[0x80]), Some(Rlp::Bytes(&[])));
+        assert_eq!(Rlp::decode(&[0xc0]), Some(Rlp::List(vec![])));
+        assert_eq!(Rlp::decode(&[0xc2, 0x01, 0x80]), Some(Rlp::List(vec![Rlp::Bytes(&[1]), Rlp::Bytes(&[])])));
+        let long = vec![7; 60];
+        assert_eq!(Rlp::decode(&encode_bytes(&long)), Some(Rlp::Bytes(&long)));
+        // non-canonical and truncated encodings
+        assert!(Rlp::decode(&[0x81, 0x05]).is_none());
+        assert!(Rlp::decode(&[0xb8, 0x05, 1, 2, 3, 4, 5]).is_none());
+        assert!(Rlp::decode(&[0x83, b'd']).is_none());
+        assert!(Rlp::decode(&[0x01, 0x02]).is_none());
+    }
+
+    #[test]
+    fn test_proofs_of_present_and_absent_keys() {
+        let (first, second) = (keccak256(b"first"), keccak256(b"second"));
+        assert_ne!(first[0] >> 4, second[0] >> 4);
+        let (root, first_proof, second_proof) = two_key_trie(&first, &second, &[0x2a; 40]);
+        assert_eq!(verify_proof(&root, &first, &first_proof), Ok(Some(vec![0x2a; 40])));
+        assert_eq!(verify_proof(&root, &second, &second_proof), Ok(Some(vec![0x2a; 40])));
+
+        // a key below the first leaf's nibble but with another path is proven absent by the same nodes
+        let mut other = first;
+        other[31] ^= 1;
+        assert_eq!(verify_proof(&root, &other, &first_proof), Ok(None));
+        // a key whose slot in the branch is empty needs the branch alone
+        let empty_slot = (0..=255).map(|i| keccak256(&[i])).find(|key| key[0] >> 4 != first[0] >> 4 && key[0] >> 4 != second[0] >> 4);
+        let empty_slot = empty_slot.expect("some key falls into another slot");
+        assert_eq!(verify_proof(&root, &empty_slot, &first_proof[..1]), Ok(None));
+        assert_eq!(verify_proof(&EMPTY_TRIE_ROOT, &first, &Vec::<Vec<u8>>::new()), Ok(None));
+    }
+
+    #[test]
+    fn test_tampered_proofs_are_refused() {
+        let (first, second) = (keccak256(b"first"), keccak256(b"second"));
+        let (root, first_proof, second_proof) = two_key_trie(&first, &second, &[0x2a; 40]);
+        assert_eq!(verify_proof(&root, &first, &second_proof), Err(MptError::HashMismatch(1)));
+        assert_eq!(verify_proof(&root, &first, &first_proof[..1]), Err(MptError::MissingNode));
+        let mut tampered = first_proof.clone();
+        tampered[1][5] ^= 1;
+        assert_eq!(verify_proof(&root, &first, &tampered), Err(MptError::HashMismatch(1)));
+        let extra = [first_proof.clone(), vec![vec![0x80]]].concat();
+        assert_eq!(verify_proof(&root, &first, &extra), Err(MptError::UnusedNodes));
+        assert_eq!(verify_proof(&[0; 32], &first, &first_proof), Err(MptError::HashMismatch(0)));
+    }
+
+    #[test]
+    fn test_empty_trie_root() {
+        assert_eq!(keccak256(&[0x80]), EMPTY_TRIE_ROOT);
+    }
+}
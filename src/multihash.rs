@@ -0,0 +1,185 @@
+use crate::merkletree::Hash;
+
+/// Digest algorithms a root hash can be produced with, identified by their multihash code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Sha2_256,
+    Sha2_512,
+    Sha3_256,
+    Sha3_512,
+    Keccak256,
+    Blake3,
+    Shake128,
+    Shake256,
+    /// BLAKE2b with the given output length in bytes, `1..=64`
+    Blake2b(u8),
+}
+
+impl HashAlgorithm {
+    /// code of the algorithm in the multicodec table
+    pub fn code(&self) -> u64 {
+        match self {
+            HashAlgorithm::Sha2_256 => 0x12,
+            HashAlgorithm::Sha2_512 => 0x13,
+            HashAlgorithm::Sha3_256 => 0x16,
+            HashAlgorithm::Sha3_512 => 0x14,
+            HashAlgorithm::Keccak256 => 0x1b,
+            HashAlgorithm::Blake3 => 0x1e,
+            HashAlgorithm::Shake128 => 0x18,
+            HashAlgorithm::Shake256 => 0x19,
+            // the table reserves a code per output length, from blake2b-8 at 0xb201 to blake2b-512 at 0xb240
+            HashAlgorithm::Blake2b(len) => 0xb200 + u64::from(*len),
+        }
+    }
+
+    /// looks up the algorithm for a multicodec code, `None` when this crate does not know it
+    pub fn from_code(code: u64) -> Option<HashAlgorithm> {
+        match code {
+            0x12 => Some(HashAlgorithm::Sha2_256),
+            0x13 => Some(HashAlgorithm::Sha2_512),
+            0x16 => Some(HashAlgorithm::Sha3_256),
+            0x14 => Some(HashAlgorithm::Sha3_512),
+            0x1b => Some(HashAlgorithm::Keccak256),
+            0x1e => Some(HashAlgorithm::Blake3),
+            0x18 => Some(HashAlgorithm::Shake128),
+            0x19 => Some(HashAlgorithm::Shake256),
+            0xb201..=0xb240 => Some(HashAlgorithm::Blake2b((code - 0xb200) as u8)),
+            _ => None,
+        }
+    }
+}
+
+/// A self-describing digest: the algorithm that produced it travels together with the digest bytes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Multihash {
+    algorithm: HashAlgorithm,
+    digest: Hash,
+}
+
+impl Multihash {
+    /// Wraps a digest produced by the given algorithm
+    pub fn new(algorithm: HashAlgorithm, digest: Hash) -> Multihash {
+        Multihash { algorithm, digest }
+    }
+
+    /// Algorithm that produced the digest
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// The raw digest bytes
+    pub fn digest(&self) -> &Hash {
+        &self.digest
+    }
+
+    /// Encodes as `<varint code><varint digest length><digest>`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_varint(&mut bytes, self.algorithm.code());
+        write_varint(&mut bytes, self.digest.len() as u64);
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    /// Decodes bytes produced by `to_bytes`
+    /// returns `None` for unknown algorithms, malformed varints, truncated digests or trailing data
+    pub fn from_bytes(bytes: &[u8]) -> Option<Multihash> {
+        let (code, rest) = read_varint(bytes)?;
+        let algorithm = HashAlgorithm::from_code(code)?;
+        let (len, rest) = read_varint(rest)?;
+        if rest.len() as u64 != len {
+            return None;
+        }
+        Some(Multihash::new(algorithm, rest.to_vec()))
+    }
+}
+
+/// appends `value` as unsigned LEB128, the varint flavour used by multiformats
+pub(crate) fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// reads an unsigned LEB128 varint, returning the value and the remaining bytes
+/// multiformats caps varints at 9 bytes and forbids non-minimal encodings
+pub(crate) fn read_varint(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(9) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            if i > 0 && *byte == 0 {
+                return None;
+            }
+            return Some((value, &bytes[i + 1..]));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::{Data, MerkleTree};
+
+    #[test]
+    fn test_root_multihash_is_prefixed_with_sha2_256_code_and_length() {
+        let data: Vec<Data> = vec![vec![0], vec![1], vec![2], vec![3]];
+        let tree = MerkleTree::construct(&data);
+        let bytes = tree.root_multihash().expect("SHA-256 has a multicodec code").to_bytes();
+
+        assert_eq!(bytes[..2], [0x12, 0x20]);
+        assert_eq!(bytes[2..], tree.root());
+    }
+
+    #[test]
+    fn test_multihash_bytes_round_trip() {
+        let multihash = Multihash::new(HashAlgorithm::Blake3, vec![7; 32]);
+        let decoded = Multihash::from_bytes(&multihash.to_bytes()).expect("this should decode Multihash");
+        assert_eq!(multihash, decoded);
+    }
+
+    #[test]
+    fn test_multihash_from_malformed_bytes_will_return_none() {
+        let bytes = Multihash::new(HashAlgorithm::Sha2_256, vec![7; 32]).to_bytes();
+
+        assert!(Multihash::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(Multihash::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_none());
+        // identity hash is not a digest algorithm we build trees with
+        assert!(Multihash::from_bytes(&[0x00, 0x01, 0x07]).is_none());
+        // non-minimal varint for the code
+        assert!(Multihash::from_bytes(&[0x92, 0x00, 0x01, 0x07]).is_none());
+    }
+
+    #[test]
+    fn test_hash_algorithm_code_round_trip() {
+        for algorithm in [HashAlgorithm::Sha2_256, HashAlgorithm::Sha3_512, HashAlgorithm::Keccak256, HashAlgorithm::Shake256, HashAlgorithm::Blake2b(1), HashAlgorithm::Blake2b(64)] {
+            assert_eq!(HashAlgorithm::from_code(algorithm.code()), Some(algorithm));
+        }
+        assert_eq!(HashAlgorithm::Blake2b(32).code(), 0xb220);
+        assert_eq!(HashAlgorithm::from_code(0xb200), None);
+        assert_eq!(HashAlgorithm::from_code(0xb241), None);
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX as u64, (1 << 63) - 1] {
+            let mut bytes = vec![];
+            write_varint(&mut bytes, value);
+            assert_eq!(read_varint(&bytes), Some((value, &[][..])));
+        }
+    }
+
+    #[test]
+    fn test_verify_multihash_rejects_root_of_other_algorithm() {
+        let data: Vec<Data> = vec![vec![0], vec![1]];
+        let tree = MerkleTree::construct(&data);
+        let root = tree.root_multihash().expect("SHA-256 has a multicodec code");
+        assert!(MerkleTree::verify_multihash(&data, &root));
+
+        let blake3_root = Multihash::new(HashAlgorithm::Blake3, tree.root().into_hash());
+        assert!(!MerkleTree::verify_multihash(&data, &blake3_root));
+    }
+}
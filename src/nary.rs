@@ -0,0 +1,204 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{Data, Hash};
+use crate::metrics::{self, MetricsEvent};
+use crate::params::TreeParams;
+use crate::root::Root;
+
+/// Merkle tree whose nodes have up to `arity` children instead of two
+///
+/// Every node hashes the concatenation of its children's hashes, left to right. A level is grouped
+/// into runs of `arity` nodes, the last run holding whatever is left over; a lone node left over is
+/// promoted to the next level as it is, so an arity of two gives the root of `MerkleTree`.
+/// A wider tree has fewer levels, and its proofs fewer but larger steps.
+pub struct NaryMerkleTree<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    arity: usize,
+    /// hashes of every level, the leaves first and the root last
+    levels: Vec<Vec<Hash>>,
+}
+
+/// Proof that a leaf is in an n-ary tree, one step per level from the leaf up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NaryProof {
+    pub steps: Vec<NaryProofStep>,
+}
+
+/// The siblings of a node and where the node goes in between them to hash their parent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NaryProofStep {
+    /// how many of the siblings come before the node
+    pub position: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl NaryMerkleTree {
+    /// Constructs a SHA-256 tree with the given arity, see `construct_with_hasher`
+    pub fn construct(input: &[Data], arity: usize) -> Option<NaryMerkleTree> {
+        NaryMerkleTree::construct_with_hasher(input, arity, Sha256Hasher::new())
+    }
+
+    /// Verifies a proof of a SHA-256 tree, see `verify_proof_with_hasher`
+    pub fn verify_proof(data: &Data, proof: &NaryProof, root_hash: &Root) -> bool {
+        NaryMerkleTree::verify_proof_with_hasher(data, proof, root_hash, &Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> NaryMerkleTree<H> {
+    /// Constructs a tree with the given arity from given input data
+    /// returns `None` when there is no data or the arity is below two
+    pub fn construct_with_hasher(input: &[Data], arity: usize, hasher: H) -> Option<NaryMerkleTree<H>> {
+        if input.is_empty() || arity < 2 {
+            return None;
+        }
+        let mut levels = vec![input.iter().map(|data| hasher.hash(data)).collect::<Vec<Hash>>()];
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let parents = level
+                .chunks(arity)
+                .map(|children| match children {
+                    [promoted] => promoted.clone(),
+                    children => hasher.hash(&children.concat()),
+                })
+                .collect();
+            levels.push(parents);
+        }
+        Some(NaryMerkleTree { hasher, arity, levels })
+    }
+
+    /// Gets root hash for this tree
+    pub fn root(&self) -> Root {
+        Root::new(self.levels.last().expect("trees have a root")[0].clone())
+    }
+
+    /// Gets the most children a node has
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Gets the parameters the tree was built with
+    /// `None` when the hash function has no multicodec code, or the arity doesn't fit a `u32`
+    pub fn params(&self) -> Option<TreeParams> {
+        Some(TreeParams { arity: u32::try_from(self.arity).ok()?, ..TreeParams::binary(&self.hasher)? })
+    }
+
+    /// Gets number of leaves the tree was constructed from
+    pub fn leaf_count(&self) -> u64 {
+        self.levels[0].len() as u64
+    }
+
+    /// Gets number of levels above the leaves, which is the most steps a proof takes
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    /// Returns the proof for the first leaf holding the given data
+    pub fn prove(&self, data: &Data) -> Option<NaryProof> {
+        let leaf = self.hasher.hash(data);
+        let index = self.levels[0].iter().position(|hash| *hash == leaf)?;
+        self.prove_by_index(index as u64)
+    }
+
+    /// Returns the proof for the leaf at `index`
+    pub fn prove_by_index(&self, index: u64) -> Option<NaryProof> {
+        let index = usize::try_from(index).ok().filter(|index| *index < self.levels[0].len())?;
+        let mut steps = vec![];
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let start = position - position % self.arity;
+            let group = &level[start..level.len().min(start + self.arity)];
+            // a promoted node has no siblings, so its level takes no step
+            if group.len() > 1 {
+                let offset = position - start;
+                let siblings = group.iter().enumerate().filter(|(i, _)| *i != offset).map(|(_, hash)| hash.clone()).collect();
+                steps.push(NaryProofStep { position: offset, siblings });
+            }
+            position /= self.arity;
+        }
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Some(NaryProof { steps })
+    }
+
+    /// Verifies that the given data and proof produce the given root hash with the given hash function
+    /// proofs holding a hash of any other length than the hash function produces never verify
+    pub fn verify_proof_with_hasher(data: &Data, proof: &NaryProof, root_hash: &Root, hasher: &H) -> bool {
+        let digest_len = hasher.digest_len();
+        let malformed = proof.steps.iter().any(|step| {
+            step.siblings.is_empty() || step.position > step.siblings.len() || step.siblings.iter().any(|hash| hash.len() != digest_len)
+        });
+        if malformed || root_hash.as_bytes().len() != digest_len {
+            return metrics::verified(hasher, false);
+        }
+        let mut hash = hasher.hash(data);
+        for step in &proof.steps {
+            let (before, after) = step.siblings.split_at(step.position);
+            let children: Vec<u8> = before.iter().chain([&hash]).chain(after).flatten().copied().collect();
+            hash = hasher.hash(&children);
+        }
+        metrics::verified(hasher, hash == *root_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::MerkleTree;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_binary_arity_matches_merkle_tree() {
+        for n in [1, 2, 3, 7, 8, 13] {
+            let data = example_data(n);
+            let tree = NaryMerkleTree::construct(&data, 2).expect("valid input");
+            assert_eq!(tree.root(), MerkleTree::construct(&data).root());
+        }
+    }
+
+    #[test]
+    fn test_every_leaf_is_proven_for_wider_trees() {
+        for arity in [3, 4, 16] {
+            for n in [1, 2, 5, 17, 64, 100] {
+                let data = example_data(n);
+                let tree = NaryMerkleTree::construct(&data, arity).expect("valid input");
+                for leaf in &data {
+                    let proof = tree.prove(leaf).expect("leaf is in the tree");
+                    assert!(proof.steps.len() <= tree.depth());
+                    assert!(NaryMerkleTree::verify_proof(leaf, &proof, &tree.root()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_wider_trees_take_fewer_steps() {
+        let data = example_data(256);
+        let binary = NaryMerkleTree::construct(&data, 2).expect("valid input");
+        let wide = NaryMerkleTree::construct(&data, 16).expect("valid input");
+        assert_eq!(binary.depth(), 8);
+        assert_eq!(wide.depth(), 2);
+        let proof = wide.prove_by_index(200).expect("index is in range");
+        assert_eq!(proof.steps.len(), 2);
+        let siblings = (192..208).filter(|i| *i != 200).map(|i| Sha256Hasher::new().hash(&[i as u8])).collect();
+        assert_eq!(proof.steps[0], NaryProofStep { position: 8, siblings });
+    }
+
+    #[test]
+    fn test_tampered_proofs_will_not_verify() {
+        let data = example_data(20);
+        let tree = NaryMerkleTree::construct(&data, 4).expect("valid input");
+        let proof = tree.prove_by_index(6).expect("index is in range");
+
+        let mut moved = proof.clone();
+        moved.steps[0].position = 1;
+        assert!(!NaryMerkleTree::verify_proof(&data[6], &moved, &tree.root()));
+        let mut out_of_range = proof.clone();
+        out_of_range.steps[0].position = 4;
+        assert!(!NaryMerkleTree::verify_proof(&data[6], &out_of_range, &tree.root()));
+        assert!(!NaryMerkleTree::verify_proof(&data[7], &proof, &tree.root()));
+
+        assert!(NaryMerkleTree::construct(&data, 1).is_none());
+        assert!(NaryMerkleTree::construct(&[], 4).is_none());
+        assert!(tree.prove_by_index(20).is_none());
+    }
+}
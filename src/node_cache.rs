@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::merkletree::Hash;
+use crate::node_store::{NodeStore, StoredNode};
+
+/// Store keeping the nodes read last in memory, in front of a slower backend
+///
+/// The nodes near the root are on the path of every proof, so a `TreeStore` over a database reads the same
+/// few of them again for every `prove`. This store answers from memory for the `capacity` nodes used last,
+/// and only goes to the backend for the others, evicting the node that went unused longest to make room.
+/// Nodes are stored under their own hash and never change, and every put and remove goes through to the
+/// backend as well, so what is cached is never stale. The cache is behind a lock that is only held to look
+/// a node up or to insert one, never while the backend is read, so threads proving at once share it.
+#[derive(Debug)]
+pub struct CachedNodeStore<S: NodeStore> {
+    store: S,
+    capacity: usize,
+    cache: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// cached nodes with the tick of their last use, and the nodes by tick, least recently used first
+#[derive(Debug, Default)]
+struct Lru {
+    nodes: HashMap<Hash, (StoredNode, u64)>,
+    by_use: BTreeMap<u64, Hash>,
+    tick: u64,
+}
+
+impl<S: NodeStore> CachedNodeStore<S> {
+    /// Puts a cache of at most `capacity` nodes in front of the backend, no cache at all for 0
+    pub fn new(store: S, capacity: usize) -> CachedNodeStore<S> {
+        CachedNodeStore {
+            store,
+            capacity,
+            cache: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Gets the backend
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Takes the backend out of the cache
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    /// Gets most nodes the cache holds
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Gets number of nodes cached
+    pub fn len(&self) -> usize {
+        self.lock().nodes.len()
+    }
+
+    /// Whether no node is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets number of gets answered from memory
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Gets number of gets that went to the backend
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Drops every cached node, e.g. after the backend was changed around the cache
+    pub fn clear(&mut self) {
+        *self.cache_mut() = Lru::default();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Lru> {
+        // a panic while the cache is locked leaves every cached node the one stored under its hash
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cache_mut(&mut self) -> &mut Lru {
+        self.cache.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Lru {
+    /// gets a cached node, marking it as used last
+    fn get(&mut self, hash: &[u8]) -> Option<StoredNode> {
+        self.tick += 1;
+        let (node, used) = self.nodes.get_mut(hash)?;
+        let hash = self.by_use.remove(used).expect("every cached node has a tick");
+        *used = self.tick;
+        self.by_use.insert(self.tick, hash);
+        Some(node.clone())
+    }
+
+    /// caches a node as used last, evicting the least recently used one when the cache is full
+    fn insert(&mut self, hash: &[u8], node: StoredNode, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        self.remove(hash);
+        if self.nodes.len() == capacity {
+            if let Some((_, evicted)) = self.by_use.pop_first() {
+                self.nodes.remove(&evicted);
+            }
+        }
+        self.tick += 1;
+        self.nodes.insert(hash.to_vec(), (node, self.tick));
+        self.by_use.insert(self.tick, hash.to_vec());
+    }
+
+    fn remove(&mut self, hash: &[u8]) {
+        if let Some((_, used)) = self.nodes.remove(hash) {
+            self.by_use.remove(&used);
+        }
+    }
+}
+
+impl<S: NodeStore> NodeStore for CachedNodeStore<S> {
+    type Error = S::Error;
+
+    fn get(&self, hash: &[u8]) -> Result<Option<StoredNode>, S::Error> {
+        if let Some(node) = self.lock().get(hash) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(node));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let node = self.store.get(hash)?;
+        if let Some(node) = &node {
+            self.lock().insert(hash, node.clone(), self.capacity);
+        }
+        Ok(node)
+    }
+
+    fn put(&mut self, hash: &[u8], node: &StoredNode) -> Result<(), S::Error> {
+        self.store.put(hash, node)?;
+        let capacity = self.capacity;
+        self.cache_mut().insert(hash, node.clone(), capacity);
+        Ok(())
+    }
+
+    fn remove(&mut self, hash: &[u8]) -> Result<(), S::Error> {
+        self.cache_mut().remove(hash);
+        self.store.remove(hash)
+    }
+
+    fn hashes(&self) -> Result<Vec<Hash>, S::Error> {
+        self.store.hashes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+    use crate::merkletree::{Data, MerkleTree};
+    use crate::node_store::{MemoryNodeStore, TreeStore};
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_proofs_read_the_upper_levels_from_memory() {
+        let data = example_data(64);
+        let tree = MerkleTree::construct(&data);
+        let mut store = TreeStore::new();
+        let root = store.insert_tree(&tree).expect("stores in memory");
+
+        let mut cached = TreeStore::with_store(CachedNodeStore::new(store.store().clone(), 8), Sha256Hasher::new());
+        cached.pin(&root);
+        for index in 0..8 {
+            assert_eq!(cached.prove(&root, 64, index), Ok(tree.prove_by_index(index)));
+        }
+        // each proof reads 6 nodes and a leaf, but only the first one reads the four nodes at the top
+        let backend = cached.store();
+        assert_eq!(backend.hits() + backend.misses(), 8 * 7);
+        assert_eq!(backend.misses(), 7 + 1 + 2 + 1 + 3 + 1 + 2 + 1);
+        assert_eq!(backend.len(), 8);
+    }
+
+    #[test]
+    fn test_threads_share_the_cache() {
+        let data = example_data(64);
+        let tree = MerkleTree::construct(&data);
+        let mut store = TreeStore::new();
+        let root = store.insert_tree(&tree).expect("stores in memory");
+
+        let cached = TreeStore::with_store(CachedNodeStore::new(store.store().clone(), 127), Sha256Hasher::new());
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let (cached, tree, root) = (&cached, &tree, &root);
+                scope.spawn(move || {
+                    for index in (0..64).map(|i| (i + thread * 16) % 64) {
+                        assert_eq!(cached.prove(root, 64, index), Ok(tree.prove_by_index(index)));
+                    }
+                });
+            }
+        });
+        // the cache holds the whole tree, so every node was read from the backend at least once,
+        // and at most once by each thread that missed it before another inserted it
+        let backend = cached.store();
+        assert_eq!(backend.hits() + backend.misses(), 4 * 64 * 7);
+        assert!((127..=4 * 127).contains(&backend.misses()));
+        assert_eq!(backend.len(), 127);
+    }
+
+    #[test]
+    fn test_least_recently_used_nodes_are_evicted() {
+        let hashes: Vec<Hash> = (0..3).map(|i| vec![i; 32]).collect();
+        let mut cached = CachedNodeStore::new(MemoryNodeStore::new(), 2);
+        for hash in &hashes {
+            cached.put(hash, &StoredNode::Leaf).expect("stores in memory");
+        }
+        // the first node was evicted by the third
+        assert_eq!(cached.len(), 2);
+        assert_eq!(cached.get(&hashes[1]), Ok(Some(StoredNode::Leaf)));
+        assert_eq!((cached.hits(), cached.misses()), (1, 0));
+        assert_eq!(cached.get(&hashes[0]), Ok(Some(StoredNode::Leaf)));
+        assert_eq!(cached.misses(), 1);
+        // reading the first node evicted the third, used longer ago than the second
+        assert_eq!(cached.get(&hashes[1]), Ok(Some(StoredNode::Leaf)));
+        assert_eq!(cached.get(&hashes[2]), Ok(Some(StoredNode::Leaf)));
+        assert_eq!((cached.hits(), cached.misses()), (2, 2));
+
+        cached.remove(&hashes[2]).expect("removes from memory");
+        assert_eq!(cached.get(&hashes[2]), Ok(None));
+        assert_eq!(cached.store().len(), 2);
+
+        let mut uncached = CachedNodeStore::new(cached.into_inner(), 0);
+        assert_eq!(uncached.get(&hashes[0]), Ok(Some(StoredNode::Leaf)));
+        uncached.put(&hashes[2], &StoredNode::Leaf).expect("stores in memory");
+        assert!(uncached.is_empty());
+    }
+}
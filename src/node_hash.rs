@@ -0,0 +1,102 @@
+use std::fmt;
+
+use crate::hasher::Hasher;
+use crate::merkletree::Hash;
+
+/// Hash of the data of a leaf, what trees are built from and proofs start at
+///
+/// Leaf and inner node hashes are byte strings of the same length, so nothing but their type tells
+/// them apart. Functions that take already hashed leaves take a `LeafHash`, and an inner node, a root
+/// or some other digest can't be handed to them without saying so with `LeafHash::new`.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LeafHash(Hash);
+
+/// Hash of the concatenation of the hashes of two children, what every node above the leaves holds
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InternalHash(Hash);
+
+impl LeafHash {
+    /// Takes a hash as the hash of a leaf
+    pub fn new(hash: Hash) -> LeafHash {
+        LeafHash(hash)
+    }
+
+    /// Hashes the data of a leaf with the given hash function
+    pub fn of<H: Hasher>(data: &[u8], hasher: &H) -> LeafHash {
+        LeafHash(hasher.hash(data))
+    }
+
+    /// Gets the bytes of the hash
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Gives the hash back as a plain hash
+    pub fn into_hash(self) -> Hash {
+        self.0
+    }
+}
+
+impl InternalHash {
+    /// Takes a hash as the hash of an inner node
+    pub fn new(hash: Hash) -> InternalHash {
+        InternalHash(hash)
+    }
+
+    /// Hashes the hashes of two children into their parent with the given hash function
+    pub fn of<H: Hasher>(left: &[u8], right: &[u8], hasher: &H) -> InternalHash {
+        InternalHash(hasher.hash_concat(left, right))
+    }
+
+    /// Gets the bytes of the hash
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Gives the hash back as a plain hash
+    pub fn into_hash(self) -> Hash {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for LeafHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for InternalHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for LeafHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LeafHash({})", hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for InternalHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InternalHash({})", hex::encode(&self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    #[test]
+    fn test_node_hashes_hash_like_the_tree() {
+        let hasher = Sha256Hasher::new();
+        let left = LeafHash::of(b"left", &hasher);
+        let right = LeafHash::of(b"right", &hasher);
+        assert_eq!(left.as_bytes(), hasher.hash(b"left").as_slice());
+        let parent = InternalHash::of(left.as_ref(), right.as_ref(), &hasher);
+        assert_eq!(parent.clone().into_hash(), hasher.hash_concat(left.as_bytes(), right.as_bytes()));
+        assert_eq!(format!("{left:?}"), format!("LeafHash({})", hex::encode(left.as_bytes())));
+        assert_eq!(LeafHash::new(left.clone().into_hash()), left);
+    }
+}
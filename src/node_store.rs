@@ -0,0 +1,431 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{split_point, Hash, HashDirection, MerkleTree, Proof};
+use crate::metrics::{self, MetricsEvent};
+use crate::node_hash::{InternalHash, LeafHash};
+use crate::root::Root;
+
+/// What is stored under the hash of a node: nothing for a leaf, the hashes of the two children otherwise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoredNode {
+    Leaf,
+    Internal { left: Hash, right: Hash },
+}
+
+/// Backend holding nodes by their hash, in memory, in files or in a key-value database
+///
+/// A store only gets, puts and removes objects; content addressing, pinning and garbage collection
+/// are done on top of it by `TreeStore`, the same way for every backend.
+pub trait NodeStore {
+    type Error: std::error::Error;
+
+    /// Gets the node stored under a hash, `None` when there is none
+    fn get(&self, hash: &[u8]) -> Result<Option<StoredNode>, Self::Error>;
+
+    /// Stores a node under its hash, replacing what was stored there
+    fn put(&mut self, hash: &[u8], node: &StoredNode) -> Result<(), Self::Error>;
+
+    /// Removes the node stored under a hash, if there is one
+    fn remove(&mut self, hash: &[u8]) -> Result<(), Self::Error>;
+
+    /// Gets the hashes of every stored node, in any order
+    fn hashes(&self) -> Result<Vec<Hash>, Self::Error>;
+}
+
+/// Store keeping its nodes in a map
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryNodeStore {
+    nodes: HashMap<Hash, StoredNode>,
+}
+
+impl MemoryNodeStore {
+    /// Creates an empty store
+    pub fn new() -> MemoryNodeStore {
+        MemoryNodeStore::default()
+    }
+
+    /// Gets number of stored nodes
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether no node is stored
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl NodeStore for MemoryNodeStore {
+    type Error = Infallible;
+
+    fn get(&self, hash: &[u8]) -> Result<Option<StoredNode>, Infallible> {
+        Ok(self.nodes.get(hash).cloned())
+    }
+
+    fn put(&mut self, hash: &[u8], node: &StoredNode) -> Result<(), Infallible> {
+        self.nodes.insert(hash.to_vec(), node.clone());
+        Ok(())
+    }
+
+    fn remove(&mut self, hash: &[u8]) -> Result<(), Infallible> {
+        self.nodes.remove(hash);
+        Ok(())
+    }
+
+    fn hashes(&self) -> Result<Vec<Hash>, Infallible> {
+        Ok(self.nodes.keys().cloned().collect())
+    }
+}
+
+/// Content-addressed store of many trees, each node kept once however many trees share it
+///
+/// Nodes are stored under their own hash, so a subtree two trees have in common, e.g. every complete
+/// subtree of an older version of an append-only tree, is stored once, as objects are in git. A tree
+/// is kept for as long as its root is pinned; every pin is counted, and `collect_garbage` removes the
+/// nodes no pinned root reaches any more. A node is only ever stored after its children, so every
+/// stored node has its whole subtree in the store.
+#[derive(Debug, Clone, Default)]
+pub struct TreeStore<S: NodeStore = MemoryNodeStore, H: Hasher = Sha256Hasher> {
+    store: S,
+    hasher: H,
+    /// number of pins of each pinned root
+    pins: HashMap<Hash, u64>,
+}
+
+impl TreeStore {
+    /// Creates an empty store of SHA-256 trees kept in memory
+    pub fn new() -> TreeStore {
+        TreeStore::default()
+    }
+}
+
+impl<S: NodeStore, H: Hasher> TreeStore<S, H> {
+    /// Stores trees built with the given hash function in the given backend
+    /// nodes already in the backend are only reachable once a root above them is pinned
+    pub fn with_store(store: S, hasher: H) -> TreeStore<S, H> {
+        TreeStore {
+            store,
+            hasher,
+            pins: HashMap::new(),
+        }
+    }
+
+    /// Gets the backend
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Stores every node of the tree that isn't stored yet and pins its root
+    pub fn insert_tree(&mut self, tree: &MerkleTree<H>) -> Result<Root, S::Error> {
+        let root_level = tree.levels.count() - 1;
+        self.insert_node(tree, root_level, 0)?;
+        let root = tree.root();
+        self.pin(&root);
+        Ok(root)
+    }
+
+    /// Builds the tree over already hashed leaves straight into the store and pins its root
+    /// `Ok(None)` when there are no leaves; see `insert_runs`
+    pub fn insert_leaves(&mut self, leaf_hashes: impl IntoIterator<Item = LeafHash>) -> Result<Option<Root>, S::Error> {
+        let mut runs: Vec<(Hash, u64)> = vec![];
+        for leaf_hash in leaf_hashes {
+            push_run(&mut runs, leaf_hash.into_hash(), 1);
+        }
+        self.insert_runs(runs.into_iter().map(|(hash, count)| (LeafHash::new(hash), count)))
+    }
+
+    /// Builds the tree over runs of repeated leaves, each given once with its length, and pins its root
+    ///
+    /// The levels are kept as runs too: a run of equal nodes pairs up into a run of equal parents, so
+    /// each distinct node is hashed and stored once, and a million copies of a record cost as much as
+    /// twenty. The root is the one `MerkleTree::construct` computes over the leaves written out in full.
+    /// `Ok(None)` when there are no leaves.
+    pub fn insert_runs(&mut self, runs: impl IntoIterator<Item = (LeafHash, u64)>) -> Result<Option<Root>, S::Error> {
+        let mut level: Vec<(Hash, u64)> = vec![];
+        for (leaf_hash, count) in runs {
+            if count > 0 {
+                self.put_leaf(&leaf_hash)?;
+                push_run(&mut level, leaf_hash.into_hash(), count);
+            }
+        }
+        if level.is_empty() {
+            return Ok(None);
+        }
+        while level.len() > 1 || level[0].1 > 1 {
+            let mut parents = vec![];
+            // the node left over from the previous run, waiting for its right sibling
+            let mut carry: Option<Hash> = None;
+            for (hash, mut count) in level {
+                if let Some(left) = carry.take() {
+                    let parent = self.store_parent(left, hash.clone())?;
+                    push_run(&mut parents, parent, 1);
+                    count -= 1;
+                }
+                if count >= 2 {
+                    let parent = self.store_parent(hash.clone(), hash.clone())?;
+                    push_run(&mut parents, parent, count / 2);
+                }
+                if count % 2 == 1 {
+                    carry = Some(hash);
+                }
+            }
+            // odd node out is promoted to the next level, as `MerkleTree` does
+            if let Some(promoted) = carry {
+                push_run(&mut parents, promoted, 1);
+            }
+            level = parents;
+        }
+        let root = Root::new(level.pop().expect("one node is left").0);
+        self.pin(&root);
+        Ok(Some(root))
+    }
+
+    /// Pins a root once more, keeping its tree from garbage collection
+    pub fn pin(&mut self, root: &Root) {
+        *self.pins.entry(root.as_bytes().to_vec()).or_default() += 1;
+    }
+
+    /// Takes back one pin of a root, returning whether it is still pinned
+    /// the tree's nodes stay stored until the next `collect_garbage`
+    pub fn unpin(&mut self, root: &Root) -> bool {
+        match self.pins.get_mut(root.as_bytes()) {
+            Some(pins) if *pins > 1 => {
+                *pins -= 1;
+                true
+            }
+            _ => {
+                self.pins.remove(root.as_bytes());
+                false
+            }
+        }
+    }
+
+    /// Gets number of pins of a root
+    pub fn pin_count(&self, root: &Root) -> u64 {
+        self.pins.get(root.as_bytes()).copied().unwrap_or(0)
+    }
+
+    /// Gets the node stored under a hash
+    pub fn get(&self, hash: &[u8]) -> Result<Option<StoredNode>, S::Error> {
+        self.store.get(hash)
+    }
+
+    /// Stores a leaf that was already hashed
+    pub fn put_leaf(&mut self, leaf_hash: &LeafHash) -> Result<(), S::Error> {
+        if self.store.get(leaf_hash.as_bytes())?.is_none() {
+            self.store.put(leaf_hash.as_bytes(), &StoredNode::Leaf)?;
+        }
+        Ok(())
+    }
+
+    /// Stores the parent of two stored nodes under its hash, which is returned
+    /// `Ok(None)` when either child is not stored
+    pub fn put_node(&mut self, left: &[u8], right: &[u8]) -> Result<Option<InternalHash>, S::Error> {
+        if self.store.get(left)?.is_none() || self.store.get(right)?.is_none() {
+            return Ok(None);
+        }
+        let hash = InternalHash::of(left, right, &self.hasher);
+        let node = StoredNode::Internal {
+            left: left.to_vec(),
+            right: right.to_vec(),
+        };
+        self.store.put(hash.as_bytes(), &node)?;
+        Ok(Some(hash))
+    }
+
+    /// Returns the proof for the leaf at `index` of the stored tree of `leaf_count` leaves under `root`
+    /// `Ok(None)` when the index is out of range or the tree is not stored
+    pub fn prove(&self, root: &Root, leaf_count: u64, index: u64) -> Result<Option<Proof<H>>, S::Error> {
+        if index >= leaf_count {
+            return Ok(None);
+        }
+        let mut hashes = vec![];
+        let (mut node, mut offset, mut size) = (root.as_bytes().to_vec(), 0, leaf_count);
+        while size > 1 {
+            let Some(StoredNode::Internal { left, right }) = self.store.get(&node)? else {
+                return Ok(None);
+            };
+            // the left subtree holds the largest power of two of the leaves, as in `MerkleTree`
+            let left_size = split_point(size);
+            if index - offset < left_size {
+                hashes.push((HashDirection::Right, right));
+                (node, size) = (left, left_size);
+            } else {
+                hashes.push((HashDirection::Left, left));
+                (node, offset, size) = (right, offset + left_size, size - left_size);
+            }
+        }
+        if self.store.get(&node)?.is_none() {
+            return Ok(None);
+        }
+        hashes.reverse();
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Ok(Some(Proof::new(hashes)))
+    }
+
+    /// Removes every node no pinned root reaches, returning how many were removed
+    pub fn collect_garbage(&mut self) -> Result<usize, S::Error> {
+        let mut reachable: HashSet<Hash> = HashSet::new();
+        let mut pending: Vec<Hash> = self.pins.keys().cloned().collect();
+        while let Some(hash) = pending.pop() {
+            if reachable.contains(&hash) {
+                continue;
+            }
+            if let Some(StoredNode::Internal { left, right }) = self.store.get(&hash)? {
+                pending.push(left);
+                pending.push(right);
+            }
+            reachable.insert(hash);
+        }
+        let mut removed = 0;
+        for hash in self.store.hashes()? {
+            if !reachable.contains(&hash) {
+                self.store.remove(&hash)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// hashes and stores the parent of two stored nodes
+    fn store_parent(&mut self, left: Hash, right: Hash) -> Result<Hash, S::Error> {
+        let hash = self.hasher.hash_concat(&left, &right);
+        self.store.put(&hash, &StoredNode::Internal { left, right })?;
+        Ok(hash)
+    }
+
+    /// stores the subtree under the node at `index` on `level`, children first, skipping subtrees already stored
+    fn insert_node(&mut self, tree: &MerkleTree<H>, level: usize, index: usize) -> Result<(), S::Error> {
+        let hash = tree.levels.hash(level, index);
+        let existing = self.store.get(hash)?;
+        // the bytes of a 64 byte leaf can be the two hashes of a node, which then is the better thing to keep
+        if matches!(existing, Some(StoredNode::Internal { .. })) {
+            return Ok(());
+        }
+        let node = match tree.levels.children(level, index) {
+            Some([(left_level, left), (right_level, right)]) => {
+                self.insert_node(tree, left_level, left)?;
+                self.insert_node(tree, right_level, right)?;
+                StoredNode::Internal {
+                    left: tree.levels.hash(left_level, left).to_vec(),
+                    right: tree.levels.hash(right_level, right).to_vec(),
+                }
+            }
+            None if existing.is_some() => return Ok(()),
+            None => StoredNode::Leaf,
+        };
+        self.store.put(hash, &node)
+    }
+}
+
+/// appends `count` copies of a node to a level kept as runs, extending the last run when it is of the same node
+fn push_run(runs: &mut Vec<(Hash, u64)>, hash: Hash, count: u64) {
+    match runs.last_mut() {
+        Some((last, last_count)) if *last == hash => *last_count += count,
+        _ => runs.push((hash, count)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_stored_trees_serve_their_proofs() {
+        let mut store = TreeStore::new();
+        for n in [1, 2, 3, 7, 13] {
+            let data = example_data(n);
+            let tree = MerkleTree::construct(&data);
+            let root = store.insert_tree(&tree).expect("stores in memory");
+            for index in 0..n as u64 {
+                let proof = store.prove(&root, n as u64, index).expect("reads from memory").expect("tree is stored");
+                assert_eq!(proof, tree.prove_by_index(index).expect("index is in range"));
+            }
+            assert!(store.prove(&root, n as u64, n as u64).expect("reads from memory").is_none());
+        }
+        let unknown = Root::new(vec![0; 32]);
+        assert!(store.prove(&unknown, 2, 0).expect("reads from memory").is_none());
+    }
+
+    #[test]
+    fn test_versions_share_nodes_until_collected() {
+        let data = example_data(16);
+        let (old, new) = (MerkleTree::construct(&data[..8]), MerkleTree::construct(&data));
+        let mut store = TreeStore::new();
+        let old_root = store.insert_tree(&old).expect("stores in memory");
+        assert_eq!(store.store().len(), 15);
+        // the old tree is the left half of the new one, which adds 15 nodes and a root
+        let new_root = store.insert_tree(&new).expect("stores in memory");
+        assert_eq!(store.store().len(), 31);
+
+        store.pin(&old_root);
+        assert!(store.unpin(&old_root));
+        assert_eq!(store.collect_garbage(), Ok(0));
+        assert!(!store.unpin(&new_root));
+        assert_eq!(store.pin_count(&new_root), 0);
+        assert_eq!(store.collect_garbage(), Ok(16));
+        assert!(store.prove(&new_root, 16, 0).expect("reads from memory").is_none());
+        assert!(store.prove(&old_root, 8, 7).expect("reads from memory").is_some());
+
+        assert!(!store.unpin(&old_root));
+        assert_eq!(store.collect_garbage(), Ok(15));
+        assert!(store.store().is_empty());
+    }
+
+    #[test]
+    fn test_nodes_are_put_above_stored_children() {
+        let data = example_data(3);
+        let hasher = Sha256Hasher::new();
+        let leaves: Vec<LeafHash> = data.iter().map(|leaf| LeafHash::of(leaf, &hasher)).collect();
+        let mut store = TreeStore::new();
+        for leaf in &leaves[..2] {
+            store.put_leaf(leaf).expect("stores in memory");
+        }
+        let parent = store.put_node(leaves[0].as_bytes(), leaves[1].as_bytes()).expect("stores in memory").expect("children are stored");
+        assert!(store.put_node(parent.as_bytes(), leaves[2].as_bytes()).expect("stores in memory").is_none());
+        store.put_leaf(&leaves[2]).expect("stores in memory");
+        let root = Root::new(store.put_node(parent.as_bytes(), leaves[2].as_bytes()).expect("stores in memory").expect("children are stored").into_hash());
+        assert_eq!(root, MerkleTree::construct(&data).root());
+        store.pin(&root);
+        assert_eq!(store.prove(&root, 3, 2), Ok(MerkleTree::construct(&data).prove_by_index(2)));
+    }
+
+    #[test]
+    fn test_repeated_subtrees_are_stored_once() {
+        let data: Vec<Data> = vec![b"same".to_vec(); 64];
+        let mut store = TreeStore::new();
+        store.insert_tree(&MerkleTree::construct(&data)).expect("stores in memory");
+        // one node per level
+        assert_eq!(store.store().len(), 7);
+    }
+
+    #[test]
+    fn test_runs_of_leaves_build_the_tree_they_stand_for() {
+        let hasher = Sha256Hasher::new();
+        let runs: Vec<(Data, u64)> = vec![(b"padding".to_vec(), 37), (b"record".to_vec(), 1), (b"padding".to_vec(), 26), (b"other".to_vec(), 3)];
+        let data: Vec<Data> = runs.iter().flat_map(|(leaf, count)| std::iter::repeat_n(leaf.clone(), *count as usize)).collect();
+        let tree = MerkleTree::construct(&data);
+
+        let mut store = TreeStore::new();
+        let root = store.insert_runs(runs.iter().map(|(leaf, count)| (LeafHash::of(leaf, &hasher), *count))).expect("stores in memory").expect("has leaves");
+        assert_eq!(root, tree.root());
+        // far fewer nodes than the 133 of the tree written out
+        assert!(store.store().len() < 40);
+        for index in [0, 36, 37, 38, 66] {
+            assert_eq!(store.prove(&root, data.len() as u64, index), Ok(tree.prove_by_index(index)));
+        }
+
+        let mut other = TreeStore::new();
+        let leaves = data.iter().map(|leaf| LeafHash::of(leaf, &hasher));
+        assert_eq!(other.insert_leaves(leaves), Ok(Some(root)));
+        assert_eq!(other.store().len(), store.store().len());
+        assert_eq!(other.insert_runs([(LeafHash::of(b"empty", &hasher), 0)]), Ok(None));
+    }
+}
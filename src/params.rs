@@ -0,0 +1,265 @@
+use std::fmt;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{MerkleTree, Proof};
+use crate::proof_ref::ProofRef;
+use crate::snapshot::PaddingStrategy;
+
+/// number of bytes the parameters take in serialized formats
+pub const PARAMS_LEN: usize = 15;
+
+/// How the hashes of leaves are told apart from the hashes of nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainSeparation {
+    /// leaves hash as `H(data)` and nodes as `H(left || right)`, nothing tells them apart
+    None,
+}
+
+impl DomainSeparation {
+    /// identifier of the separation in serialized formats
+    pub fn id(&self) -> u8 {
+        match self {
+            DomainSeparation::None => 0,
+        }
+    }
+
+    /// looks up the separation for an identifier, `None` when this crate does not know it
+    pub fn from_id(id: u8) -> Option<DomainSeparation> {
+        match id {
+            0 => Some(DomainSeparation::None),
+            _ => None,
+        }
+    }
+}
+
+/// Everything besides the root that a verifier has to share with the tree a proof or snapshot comes from
+///
+/// Serialized formats write the parameters of their tree up front, and readers compare them with the ones
+/// they are configured for before looking at any hash, so a proof of a tree built another way is refused
+/// with the parameter that differs instead of merely failing to verify. The bytes are the multicodec code
+/// of the hash algorithm (`u64` little-endian), the padding strategy, the hash length, the domain separation
+/// and the arity (`u32` little-endian).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeParams {
+    /// multicodec code of the hash algorithm, see `HashAlgorithm::code`
+    pub algorithm: u64,
+    pub padding: PaddingStrategy,
+    /// length in bytes of every hash
+    pub digest_len: u8,
+    pub domain_separation: DomainSeparation,
+    /// most children a node has
+    pub arity: u32,
+}
+
+/// Parameter in which serialized bytes differ from the parameters a reader expects, with both values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamsMismatch {
+    HashAlgorithm { expected: u64, found: u64 },
+    Padding { expected: u8, found: u8 },
+    HashLength { expected: u8, found: u8 },
+    DomainSeparation { expected: u8, found: u8 },
+    Arity { expected: u32, found: u32 },
+}
+
+impl fmt::Display for ParamsMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamsMismatch::HashAlgorithm { expected, found } => write!(f, "hash algorithm {found:#x} where {expected:#x} was expected"),
+            ParamsMismatch::Padding { expected, found } => write!(f, "padding strategy {found} where {expected} was expected"),
+            ParamsMismatch::HashLength { expected, found } => write!(f, "hash length {found} where {expected} was expected"),
+            ParamsMismatch::DomainSeparation { expected, found } => write!(f, "domain separation {found} where {expected} was expected"),
+            ParamsMismatch::Arity { expected, found } => write!(f, "arity {found} where {expected} was expected"),
+        }
+    }
+}
+
+impl std::error::Error for ParamsMismatch {}
+
+/// Reasons a proof written by `Proof::to_bytes` can not be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofFormatError {
+    /// the hash function of the reader has no multicodec code to compare with
+    Unidentified,
+    /// the proof belongs to a tree with other parameters
+    Mismatch(ParamsMismatch),
+    /// the bytes are truncated, have trailing data or hold hashes of the wrong length
+    Malformed,
+}
+
+impl fmt::Display for ProofFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofFormatError::Unidentified => write!(f, "hash algorithm has no multicodec code"),
+            ProofFormatError::Mismatch(mismatch) => write!(f, "proof of another tree: {mismatch}"),
+            ProofFormatError::Malformed => write!(f, "malformed proof"),
+        }
+    }
+}
+
+impl std::error::Error for ProofFormatError {}
+
+impl From<ParamsMismatch> for ProofFormatError {
+    fn from(mismatch: ParamsMismatch) -> ProofFormatError {
+        ProofFormatError::Mismatch(mismatch)
+    }
+}
+
+impl TreeParams {
+    /// Parameters of a `MerkleTree` built with the given hash function
+    /// `None` when the hash function has no multicodec code
+    pub fn binary(hasher: &impl Hasher) -> Option<TreeParams> {
+        Some(TreeParams {
+            algorithm: hasher.algorithm()?.code(),
+            padding: PaddingStrategy::PromoteOdd,
+            digest_len: hasher.digest_len() as u8,
+            domain_separation: DomainSeparation::None,
+            arity: 2,
+        })
+    }
+
+    /// Serializes the parameters into the `PARAMS_LEN` bytes formats write them as
+    pub fn to_bytes(&self) -> [u8; PARAMS_LEN] {
+        let mut bytes = [0; PARAMS_LEN];
+        bytes[..8].copy_from_slice(&self.algorithm.to_le_bytes());
+        bytes[8] = self.padding.id();
+        bytes[9] = self.digest_len;
+        bytes[10] = self.domain_separation.id();
+        bytes[11..].copy_from_slice(&self.arity.to_le_bytes());
+        bytes
+    }
+
+    /// Checks that serialized parameters are these ones, reporting the first that differs
+    /// identifiers this crate doesn't know are reported as they are found, as a mismatch
+    pub fn check(&self, bytes: &[u8; PARAMS_LEN]) -> Result<(), ParamsMismatch> {
+        let algorithm = u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"));
+        let arity = u32::from_le_bytes(bytes[11..].try_into().expect("4 bytes"));
+        if algorithm != self.algorithm {
+            return Err(ParamsMismatch::HashAlgorithm { expected: self.algorithm, found: algorithm });
+        }
+        if bytes[8] != self.padding.id() {
+            return Err(ParamsMismatch::Padding { expected: self.padding.id(), found: bytes[8] });
+        }
+        if bytes[9] != self.digest_len {
+            return Err(ParamsMismatch::HashLength { expected: self.digest_len, found: bytes[9] });
+        }
+        if bytes[10] != self.domain_separation.id() {
+            return Err(ParamsMismatch::DomainSeparation { expected: self.domain_separation.id(), found: bytes[10] });
+        }
+        if arity != self.arity {
+            return Err(ParamsMismatch::Arity { expected: self.arity, found: arity });
+        }
+        Ok(())
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Gets the parameters the tree was built with, `None` when the hash function has no multicodec code
+    pub fn params(&self) -> Option<TreeParams> {
+        TreeParams::binary(&self.hasher)
+    }
+}
+
+impl Proof {
+    /// Serializes a proof of a SHA-256 tree behind its parameters, see `to_bytes_with_hasher`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_hasher(&Sha256Hasher::new()).expect("SHA-256 has a multicodec code")
+    }
+
+    /// Reads a proof of a SHA-256 tree written by `to_bytes`, see `from_bytes_with_hasher`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Proof, ProofFormatError> {
+        Proof::from_bytes_with_hasher(bytes, &Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> Proof<H> {
+    /// Serializes the proof as the parameters of a tree built with the given hash function,
+    /// followed by the proof as written by `to_untagged_bytes`
+    /// returns `None` when the hash function has no multicodec code to record
+    pub fn to_bytes_with_hasher(&self, hasher: &H) -> Option<Vec<u8>> {
+        self.as_proof_ref().to_bytes_with_hasher(hasher)
+    }
+
+    /// Reads a proof written by `to_bytes_with_hasher` for a tree built with the given hash function
+    /// refuses proofs of trees with any other parameters, naming the one that differs
+    pub fn from_bytes_with_hasher(bytes: &[u8], hasher: &H) -> Result<Proof<H>, ProofFormatError> {
+        let expected = TreeParams::binary(hasher).ok_or(ProofFormatError::Unidentified)?;
+        let (params, proof) = bytes.split_first_chunk::<PARAMS_LEN>().ok_or(ProofFormatError::Malformed)?;
+        expected.check(params)?;
+        Proof::from_untagged_bytes(proof)
+            .filter(|proof| proof.hashes.iter().all(|(_, hash)| hash.len() == hasher.digest_len()))
+            .ok_or(ProofFormatError::Malformed)
+    }
+}
+
+impl ProofRef<'_> {
+    /// Serializes a borrowed proof of a SHA-256 tree into the bytes `Proof::to_bytes` gives
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_hasher(&Sha256Hasher::new()).expect("SHA-256 has a multicodec code")
+    }
+}
+
+impl<H: Hasher> ProofRef<'_, H> {
+    /// Serializes a borrowed proof into the bytes `Proof::to_bytes_with_hasher` gives
+    pub fn to_bytes_with_hasher(&self, hasher: &H) -> Option<Vec<u8>> {
+        let mut bytes = TreeParams::binary(hasher)?.to_bytes().to_vec();
+        bytes.extend(self.to_untagged_bytes());
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Blake2bHasher, Shake128Hasher};
+    use crate::merkletree::Data;
+    use crate::nary::NaryMerkleTree;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_proofs_round_trip_and_refuse_other_trees() {
+        let data = example_data(7);
+        let tree = MerkleTree::construct(&data);
+        let proof = tree.prove_by_index(3).expect("index is in range");
+        let bytes = proof.to_bytes();
+        assert_eq!(bytes.len(), PARAMS_LEN + proof.to_untagged_bytes().len());
+        assert_eq!(tree.prove_ref(3).expect("index is in range").to_bytes(), bytes);
+        assert_eq!(Proof::from_bytes(&bytes), Ok(proof));
+
+        let blake2b = Blake2bHasher::new(32).expect("valid length");
+        let error = Proof::from_bytes_with_hasher(&bytes, &blake2b).expect_err("proof of a SHA-256 tree");
+        assert_eq!(error, ProofFormatError::Mismatch(ParamsMismatch::HashAlgorithm { expected: 0xb220, found: 0x12 }));
+
+        let mut wider = bytes.clone();
+        wider[11] = 4;
+        assert_eq!(Proof::from_bytes(&wider), Err(ProofFormatError::Mismatch(ParamsMismatch::Arity { expected: 2, found: 4 })));
+        assert_eq!(Proof::from_bytes(&bytes[..bytes.len() - 1]), Err(ProofFormatError::Malformed));
+        assert_eq!(Proof::from_bytes(&bytes[..PARAMS_LEN - 1]), Err(ProofFormatError::Malformed));
+    }
+
+    #[test]
+    fn test_proofs_record_the_hash_length() {
+        let data = example_data(5);
+        let (short, long) = (Shake128Hasher::new(20).expect("valid length"), Shake128Hasher::new(32).expect("valid length"));
+        let tree = MerkleTree::construct_with_hasher(&data, short);
+        let bytes = tree.prove_by_index(1).expect("index is in range").to_bytes_with_hasher(&short).expect("SHAKE128 has a code");
+        assert!(Proof::from_bytes_with_hasher(&bytes, &short).is_ok());
+        let error = Proof::from_bytes_with_hasher(&bytes, &long).expect_err("proof of shorter hashes");
+        assert_eq!(error, ProofFormatError::Mismatch(ParamsMismatch::HashLength { expected: 32, found: 20 }));
+        assert_eq!(error.to_string(), "proof of another tree: hash length 20 where 32 was expected");
+    }
+
+    #[test]
+    fn test_trees_report_their_params() {
+        let data = example_data(9);
+        let params = MerkleTree::construct(&data).params().expect("SHA-256 has a code");
+        assert_eq!(params, TreeParams::binary(&Sha256Hasher::new()).expect("SHA-256 has a code"));
+        assert_eq!((params.algorithm, params.digest_len, params.arity), (0x12, 32, 2));
+        assert_eq!(params.check(&params.to_bytes()), Ok(()));
+
+        let nary = NaryMerkleTree::construct(&data, 4).expect("has leaves").params().expect("SHA-256 has a code");
+        assert_eq!(params.check(&nary.to_bytes()), Err(ParamsMismatch::Arity { expected: 2, found: 4 }));
+    }
+}
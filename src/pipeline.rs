@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{scrub, Data, Hash, MerkleTree};
+use crate::node_hash::LeafHash;
+
+impl MerkleTree {
+    /// Constructs a Merkle tree over `chunk_size` byte chunks of everything read from `reader`,
+    /// see `construct_pipelined_with_hasher`
+    pub fn construct_pipelined(reader: impl Read + Send, chunk_size: usize, workers: usize) -> io::Result<Option<MerkleTree>> {
+        MerkleTree::construct_pipelined_with_hasher(reader, chunk_size, workers, Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher + Sync> MerkleTree<H> {
+    /// Constructs a Merkle tree over `chunk_size` byte chunks of everything read from `reader`,
+    /// the last chunk holding whatever is left over; `Ok(None)` when the reader is empty
+    ///
+    /// A reader thread keeps reading while `workers` threads hash the chunks it has read so far,
+    /// and the calling thread puts the leaf hashes back in order and builds the levels above them.
+    /// The channels in between are bounded, so a slow stage holds back the ones before it
+    /// instead of piling up chunks in memory.
+    pub fn construct_pipelined_with_hasher(
+        reader: impl Read + Send,
+        chunk_size: usize,
+        workers: usize,
+        hasher: H,
+    ) -> io::Result<Option<MerkleTree<H>>> {
+        assert!(chunk_size > 0, "chunks must not be empty");
+        assert!(workers > 0, "at least one worker has to hash");
+        let (chunk_sender, chunk_receiver) = sync_channel::<(usize, Data)>(workers * 2);
+        let chunk_receiver = Mutex::new(chunk_receiver);
+        let (hash_sender, hash_receiver) = sync_channel::<(usize, Hash)>(workers * 2);
+
+        let (read, leaf_hashes) = thread::scope(|scope| {
+            let reader_thread = scope.spawn(move || read_chunks(reader, chunk_size, chunk_sender));
+            for _ in 0..workers {
+                let (chunk_receiver, hash_sender, hasher) = (&chunk_receiver, hash_sender.clone(), &hasher);
+                scope.spawn(move || loop {
+                    let next = chunk_receiver.lock().expect("workers don't panic while receiving").recv();
+                    let Ok((index, mut chunk)) = next else {
+                        break;
+                    };
+                    let hash = hasher.hash(&chunk);
+                    scrub(&mut chunk);
+                    if hash_sender.send((index, hash)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(hash_sender);
+
+            // workers finish out of order, so hashes wait here until all hashes before them arrived
+            let mut leaf_hashes = vec![];
+            let mut pending = HashMap::new();
+            for (index, hash) in hash_receiver {
+                pending.insert(index, hash);
+                while let Some(hash) = pending.remove(&leaf_hashes.len()) {
+                    leaf_hashes.push(LeafHash::new(hash));
+                }
+            }
+            (reader_thread.join().expect("reader thread panicked"), leaf_hashes)
+        });
+
+        read?;
+        if leaf_hashes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(MerkleTree::from_leaf_hashes_with_hasher(leaf_hashes, hasher)))
+    }
+}
+
+/// reads chunks in order and hands them to the workers, until the reader is exhausted
+fn read_chunks(mut reader: impl Read, chunk_size: usize, chunks: SyncSender<(usize, Data)>) -> io::Result<()> {
+    for index in 0.. {
+        let mut chunk = Vec::with_capacity(chunk_size);
+        (&mut reader).take(chunk_size as u64).read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            break;
+        }
+        let last = chunk.len() < chunk_size;
+        if chunks.send((index, chunk)).is_err() || last {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+
+    #[test]
+    fn test_pipelined_construction_matches_construct() {
+        let content: Vec<u8> = (0..10_007_u32).map(|i| (i * 7) as u8).collect();
+        for (chunk_size, workers) in [(100, 4), (1, 3), (10_007, 2), (20_000, 1)] {
+            let chunks: Vec<Data> = content.chunks(chunk_size).map(<[u8]>::to_vec).collect();
+            let tree = MerkleTree::construct_pipelined(content.as_slice(), chunk_size, workers)
+                .expect("reading from a slice")
+                .expect("reader is not empty");
+            assert_eq!(tree.root(), MerkleTree::construct(&chunks).root());
+            assert_eq!(tree.leaf_count(), chunks.len() as u64);
+        }
+        assert!(MerkleTree::construct_pipelined(&[][..], 64, 2).expect("reading from a slice").is_none());
+    }
+
+    #[test]
+    fn test_pipelined_construction_with_hasher() {
+        let content = vec![42; 1000];
+        let chunks: Vec<Data> = content.chunks(64).map(<[u8]>::to_vec).collect();
+        let hasher = Blake2bHasher::new(16).expect("valid length");
+        let tree = MerkleTree::construct_pipelined_with_hasher(content.as_slice(), 64, 3, hasher)
+            .expect("reading from a slice")
+            .expect("reader is not empty");
+        assert_eq!(tree.root(), MerkleTree::construct_with_hasher(&chunks, hasher).root());
+    }
+
+    #[test]
+    fn test_pipelined_construction_reports_read_errors() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk on fire"))
+            }
+        }
+        let error = MerkleTree::construct_pipelined(Failing, 64, 2).expect_err("read fails");
+        assert_eq!(error.to_string(), "disk on fire");
+    }
+}
@@ -0,0 +1,158 @@
+use sha2::{Digest, Sha256};
+
+use crate::hasher::Sha256Hasher;
+use crate::merkletree::{HashDirection, Proof};
+use crate::params::{TreeParams, PARAMS_LEN};
+
+/// A proof of at most `MAX_DEPTH` SHA-256 hashes stored inline, for verifiers without an allocator
+///
+/// Decoding and verification only ever use the stack, so an embedded verifier can check proofs
+/// produced by `MerkleTree::prove` and serialized with `Proof::to_bytes` without any heap at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofArray<const MAX_DEPTH: usize> {
+    /// the first `len` entries are the proof, in the same order as `Proof`, the rest is unused
+    hashes: [(HashDirection, [u8; 32]); MAX_DEPTH],
+    len: usize,
+}
+
+impl<const MAX_DEPTH: usize> Default for ProofArray<MAX_DEPTH> {
+    fn default() -> ProofArray<MAX_DEPTH> {
+        ProofArray::new()
+    }
+}
+
+impl<const MAX_DEPTH: usize> ProofArray<MAX_DEPTH> {
+    /// Creates an empty proof, which proves the single leaf of a one leaf tree
+    pub const fn new() -> ProofArray<MAX_DEPTH> {
+        ProofArray {
+            hashes: [(HashDirection::Left, [0; 32]); MAX_DEPTH],
+            len: 0,
+        }
+    }
+
+    /// Appends the next hash on the way up to the root
+    /// returns `false` and leaves the proof unchanged when it already holds `MAX_DEPTH` hashes
+    pub fn push(&mut self, hash_direction: HashDirection, hash: [u8; 32]) -> bool {
+        if self.len == MAX_DEPTH {
+            return false;
+        }
+        self.hashes[self.len] = (hash_direction, hash);
+        self.len += 1;
+        true
+    }
+
+    /// Gets number of hashes in the proof
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the proof has no hashes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The hashes of the proof, leaf first
+    pub fn hashes(&self) -> &[(HashDirection, [u8; 32])] {
+        &self.hashes[..self.len]
+    }
+
+    /// Copies a proof, `None` when it is deeper than `MAX_DEPTH` or its hashes are not SHA-256 sized
+    pub fn from_proof(proof: &Proof) -> Option<ProofArray<MAX_DEPTH>> {
+        let mut array = ProofArray::new();
+        for (hash_direction, hash) in &proof.hashes {
+            if !array.push(*hash_direction, hash.as_slice().try_into().ok()?) {
+                return None;
+            }
+        }
+        Some(array)
+    }
+
+    /// Decodes the format written by `Proof::to_bytes` without allocating
+    /// returns `None` for malformed bytes, proofs of trees with other parameters than a SHA-256 `MerkleTree`,
+    /// and proofs deeper than `MAX_DEPTH`
+    pub fn from_bytes(bytes: &[u8]) -> Option<ProofArray<MAX_DEPTH>> {
+        let (params, bytes) = bytes.split_first_chunk::<PARAMS_LEN>()?;
+        TreeParams::binary(&Sha256Hasher::new())?.check(params).ok()?;
+        let (count, mut rest) = bytes.split_first_chunk::<4>()?;
+        let mut array = ProofArray::new();
+        for _ in 0..u32::from_le_bytes(*count) {
+            let ([direction, 32], tail) = rest.split_first_chunk::<2>()? else {
+                return None;
+            };
+            let hash_direction = match direction {
+                0 => HashDirection::Left,
+                1 => HashDirection::Right,
+                _ => return None,
+            };
+            let (hash, tail) = tail.split_first_chunk::<32>()?;
+            if !array.push(hash_direction, *hash) {
+                return None;
+            }
+            rest = tail;
+        }
+        rest.is_empty().then_some(array)
+    }
+
+    /// Verifies that the given data and this proof correctly produce the given root hash
+    /// every digest lives on the stack, nothing is allocated
+    pub fn verify(&self, data: &[u8], root_hash: &[u8; 32]) -> bool {
+        let mut hashed_data = Sha256::digest(data);
+        for (hash_direction, hash) in self.hashes() {
+            let (left, right) = match hash_direction {
+                HashDirection::Left => (hash.as_slice(), hashed_data.as_slice()),
+                HashDirection::Right => (hashed_data.as_slice(), hash.as_slice()),
+            };
+            hashed_data = Sha256::new().chain_update(left).chain_update(right).finalize();
+        }
+        hashed_data.as_slice() == root_hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::{Data, MerkleTree};
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_proof_array_verifies_proofs_of_tree() {
+        let data = example_data(11);
+        let tree = MerkleTree::construct(&data);
+        let root: [u8; 32] = tree.root().as_bytes().try_into().expect("SHA-256 root");
+
+        for leaf in &data {
+            let proof = tree.prove(leaf).expect("this should return Proof");
+            let from_proof = ProofArray::<4>::from_proof(&proof).expect("this should fit");
+            let from_bytes = ProofArray::<4>::from_bytes(&proof.to_bytes()).expect("this should decode");
+            assert_eq!(from_proof, from_bytes);
+            assert!(from_bytes.verify(leaf, &root));
+            assert!(!from_bytes.verify(&[42], &root));
+        }
+    }
+
+    #[test]
+    fn test_proof_array_rejects_proofs_deeper_than_max_depth() {
+        let data = example_data(8);
+        let tree = MerkleTree::construct(&data);
+        let proof = tree.prove(&data[0]).expect("this should return Proof");
+
+        assert!(ProofArray::<2>::from_proof(&proof).is_none());
+        assert!(ProofArray::<2>::from_bytes(&proof.to_bytes()).is_none());
+        assert_eq!(ProofArray::<3>::from_proof(&proof).map(|array| array.len()), Some(3));
+    }
+
+    #[test]
+    fn test_proof_array_from_malformed_bytes_will_return_none() {
+        let proof: Proof = Proof::new(vec![(HashDirection::Right, vec![1; 20])]);
+        assert!(ProofArray::<4>::from_bytes(&proof.to_bytes()).is_none());
+
+        let proof: Proof = Proof::new(vec![(HashDirection::Right, vec![1; 32])]);
+        let bytes = proof.to_bytes();
+        assert!(ProofArray::<4>::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(ProofArray::<4>::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_none());
+        assert!(ProofArray::<4>::from_bytes(&proof.to_untagged_bytes()).is_none());
+    }
+}
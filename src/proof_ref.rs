@@ -0,0 +1,134 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{HashDirection, HexHash, MerkleTree, Proof};
+use crate::metrics::{self, MetricsEvent};
+
+/// Proof borrowing its siblings from the tree instead of owning copies of them
+///
+/// `prove_by_index` copies every sibling into a hash of its own, only for a server to serialize the proof
+/// and drop it again. This view holds the side and a slice into the levels of the tree for each sibling,
+/// so nothing is copied until the bytes are written, and `to_proof` makes an owned `Proof` where one has
+/// to outlive the tree. The siblings are those of the `Proof` of the same leaf.
+pub struct ProofRef<'a, H: Hasher = Sha256Hasher> {
+    hashes: Vec<(HashDirection, &'a [u8])>,
+    /// ties the proof to the hash function of the tree it belongs to
+    hasher: PhantomData<fn() -> H>,
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Returns the proof for the leaf at `index` borrowing its siblings from the tree, see `ProofRef`
+    pub fn prove_ref(&self, index: u64) -> Option<ProofRef<'_, H>> {
+        let path = self.levels.path_iter(self.leaf_position(index)?);
+        metrics::record(&self.hasher, MetricsEvent::ProofGenerated);
+        Some(ProofRef { hashes: path.collect(), hasher: PhantomData })
+    }
+}
+
+impl<H: Hasher> Proof<H> {
+    /// Views the proof as one borrowing its siblings, e.g. to hand owned and borrowed proofs to the same code
+    pub fn as_proof_ref(&self) -> ProofRef<'_, H> {
+        ProofRef { hashes: self.hashes.iter().map(|(hash_direction, hash)| (*hash_direction, hash.as_slice())).collect(), hasher: PhantomData }
+    }
+}
+
+impl<'a, H: Hasher> ProofRef<'a, H> {
+    /// Gets the siblings from the leaf up, each with the side it goes on
+    pub fn hashes(&self) -> &[(HashDirection, &'a [u8])] {
+        &self.hashes
+    }
+
+    /// Gets number of siblings
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Whether the proof has no siblings, as the proof of a tree's only leaf
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Copies the siblings into an owned proof
+    pub fn to_proof(&self) -> Proof<H> {
+        Proof::new(self.hashes.iter().map(|(hash_direction, hash)| (*hash_direction, hash.to_vec())).collect())
+    }
+
+    /// Serializes the proof into the bytes `Proof::to_untagged_bytes` gives, copying each sibling only into the output
+    /// every encoding of proofs goes through this one
+    ///
+    /// # Panics
+    ///
+    /// When a hash is longer than 255 bytes or there are more than `u32::MAX` hashes.
+    pub fn to_untagged_bytes(&self) -> Vec<u8> {
+        let count = u32::try_from(self.hashes.len()).expect("proofs have at most u32::MAX hashes");
+        let mut bytes = Vec::with_capacity(4 + self.hashes.iter().map(|(_, hash)| 2 + hash.len()).sum::<usize>());
+        bytes.extend_from_slice(&count.to_le_bytes());
+        for (hash_direction, hash) in &self.hashes {
+            bytes.push(match hash_direction {
+                HashDirection::Left => 0,
+                HashDirection::Right => 1,
+            });
+            bytes.push(u8::try_from(hash.len()).expect("hashes are at most 255 bytes long"));
+            bytes.extend_from_slice(hash);
+        }
+        bytes
+    }
+}
+
+impl<H: Hasher> From<ProofRef<'_, H>> for Proof<H> {
+    fn from(proof: ProofRef<'_, H>) -> Proof<H> {
+        proof.to_proof()
+    }
+}
+
+impl<H: Hasher> Clone for ProofRef<'_, H> {
+    fn clone(&self) -> Self {
+        ProofRef { hashes: self.hashes.clone(), hasher: PhantomData }
+    }
+}
+
+impl<H: Hasher> PartialEq for ProofRef<'_, H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hashes == other.hashes
+    }
+}
+
+impl<H: Hasher> Eq for ProofRef<'_, H> {}
+
+/// Shows each sibling as its side and its hash in hex, as `Proof` does
+impl<H: Hasher> fmt::Debug for ProofRef<'_, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let siblings: Vec<_> = self.hashes.iter().map(|(hash_direction, hash)| (hash_direction, HexHash(hash))).collect();
+        f.debug_struct("ProofRef").field("hashes", &siblings).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8; 3]).collect()
+    }
+
+    #[test]
+    fn test_borrowed_proofs_match_owned_ones() {
+        let data = example_data(11);
+        let tree = MerkleTree::construct(&data);
+        for index in 0..11 {
+            let proof = tree.prove_by_index(index).expect("index is in range");
+            let borrowed = tree.prove_ref(index).expect("index is in range");
+            assert_eq!(borrowed.len(), proof.hashes.len());
+            assert_eq!(borrowed.to_bytes(), proof.to_bytes());
+            assert_eq!(borrowed.to_untagged_bytes(), proof.to_untagged_bytes());
+            assert_eq!(borrowed, proof.as_proof_ref());
+            assert_eq!(format!("{proof:?}"), format!("{borrowed:?}").replacen("ProofRef", "Proof", 1));
+            let owned: Proof = borrowed.into();
+            assert!(MerkleTree::verify_proof(&data[index as usize], &owned, &tree.root()));
+        }
+        assert!(tree.prove_ref(11).is_none());
+        assert!(MerkleTree::construct(&data[..1]).prove_ref(0).expect("index is in range").is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{MerkleTree, Proof};
+use crate::root::Root;
+use crate::tree_head::TreeHead;
+
+/// header holding the proof of the request body, as the hex of `Proof::to_bytes`
+pub const PROOF_HEADER: &str = "merkle-proof";
+/// header holding the index of the body's leaf, required when verifying against a `TreeHead`
+pub const LEAF_INDEX_HEADER: &str = "merkle-leaf-index";
+
+/// Checks that the body of an HTTP request is a leaf of a configured tree, before the request is served
+///
+/// The proof of the body comes in the `merkle-proof` header, and when the tree is given by its head,
+/// the body's position in the `merkle-leaf-index` header, so the proof is bound to that leaf of a tree
+/// of exactly that size. Header names are matched without regard to case. This holds the parsing and
+/// the verdict every server needs, and an extractor or middleware of any HTTP framework only has to
+/// hand the headers and the body over and turn a `RequestProofError` into a response with its `status`.
+///
+/// The extractors for axum and actix-web live in the `merkle-tree-middleware` crate next to this one,
+/// behind its `axum` and `actix-web` features, so that this crate is not pinned to either framework.
+#[derive(Debug, Clone)]
+pub struct RequestVerifier<H: Hasher = Sha256Hasher> {
+    expected: Expected,
+    hasher: H,
+}
+
+/// What a request is verified against
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expected {
+    Root(Root),
+    Head(TreeHead),
+}
+
+/// Reasons a request is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestProofError {
+    /// the request carries no proof, or no leaf index where one is needed
+    MissingHeader(&'static str),
+    /// the header is not valid hex, not a proof or not a leaf index
+    MalformedHeader(&'static str),
+    /// the proof doesn't prove the body is in the tree
+    Rejected,
+}
+
+impl RequestProofError {
+    /// Gets the HTTP status a server should answer with, 400 for requests it can't read and 403 for refused ones
+    pub fn status(&self) -> u16 {
+        match self {
+            RequestProofError::MissingHeader(_) | RequestProofError::MalformedHeader(_) => 400,
+            RequestProofError::Rejected => 403,
+        }
+    }
+}
+
+impl fmt::Display for RequestProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestProofError::MissingHeader(name) => write!(f, "missing {name} header"),
+            RequestProofError::MalformedHeader(name) => write!(f, "malformed {name} header"),
+            RequestProofError::Rejected => write!(f, "proof doesn't prove the request body"),
+        }
+    }
+}
+
+impl std::error::Error for RequestProofError {}
+
+impl RequestVerifier {
+    /// Verifies request bodies against the root of a SHA-256 tree
+    pub fn new(root: Root) -> RequestVerifier {
+        RequestVerifier::with_hasher(root, Sha256Hasher::new())
+    }
+
+    /// Verifies request bodies against the head of a SHA-256 tree, at the leaf index the request gives
+    pub fn for_head(head: TreeHead) -> RequestVerifier {
+        RequestVerifier::for_head_with_hasher(head, Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> RequestVerifier<H> {
+    /// Verifies request bodies against the root of a tree built with the given hash function
+    pub fn with_hasher(root: Root, hasher: H) -> RequestVerifier<H> {
+        RequestVerifier { expected: Expected::Root(root), hasher }
+    }
+
+    /// Verifies request bodies against the head of a tree built with the given hash function
+    pub fn for_head_with_hasher(head: TreeHead, hasher: H) -> RequestVerifier<H> {
+        RequestVerifier { expected: Expected::Head(head), hasher }
+    }
+
+    /// Verifies the body of a request with the given headers, returning the proof it came with
+    pub fn verify<'a>(&self, headers: impl IntoIterator<Item = (&'a str, &'a [u8])>, body: &[u8]) -> Result<Proof<H>, RequestProofError> {
+        let (mut proof, mut leaf_index) = (None, None);
+        for (name, value) in headers {
+            if name.eq_ignore_ascii_case(PROOF_HEADER) {
+                proof = Some(value);
+            } else if name.eq_ignore_ascii_case(LEAF_INDEX_HEADER) {
+                leaf_index = Some(value);
+            }
+        }
+        let proof = proof.ok_or(RequestProofError::MissingHeader(PROOF_HEADER))?;
+        let proof = hex::decode(proof.trim_ascii())
+            .ok()
+            .and_then(|bytes| Proof::from_bytes_with_hasher(&bytes, &self.hasher).ok())
+            .ok_or(RequestProofError::MalformedHeader(PROOF_HEADER))?;
+
+        let body = body.to_vec();
+        let verified = match &self.expected {
+            Expected::Root(root) => MerkleTree::verify_proof_with_hasher(&body, &proof, root, &self.hasher),
+            Expected::Head(head) => {
+                let leaf_index = leaf_index.ok_or(RequestProofError::MissingHeader(LEAF_INDEX_HEADER))?;
+                let leaf_index = std::str::from_utf8(leaf_index)
+                    .ok()
+                    .and_then(|index| index.trim().parse().ok())
+                    .ok_or(RequestProofError::MalformedHeader(LEAF_INDEX_HEADER))?;
+                MerkleTree::verify_proof_at_with_hasher(&body, leaf_index, head.tree_size, &proof, &head.root, &self.hasher)
+            }
+        };
+        if verified {
+            Ok(proof)
+        } else {
+            Err(RequestProofError::Rejected)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| format!("request {i}").into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_request_bodies_are_verified_against_the_root() {
+        let data = example_data(6);
+        let tree = MerkleTree::construct(&data);
+        let verifier = RequestVerifier::new(tree.root());
+        let proof = hex::encode(tree.prove_by_index(2).expect("index is in range").to_bytes());
+
+        let headers = [("Content-Type", b"text/plain".as_slice()), ("Merkle-Proof", proof.as_bytes())];
+        assert_eq!(verifier.verify(headers, &data[2]), tree.prove_by_index(2).ok_or(RequestProofError::Rejected));
+        let error = verifier.verify(headers, &data[3]).expect_err("body is another leaf");
+        assert_eq!((error, error.status()), (RequestProofError::Rejected, 403));
+
+        let error = verifier.verify([], &data[2]).expect_err("no proof");
+        assert_eq!((error, error.status()), (RequestProofError::MissingHeader(PROOF_HEADER), 400));
+        assert_eq!(verifier.verify([(PROOF_HEADER, b"zz".as_slice())], &data[2]), Err(RequestProofError::MalformedHeader(PROOF_HEADER)));
+    }
+
+    #[test]
+    fn test_request_bodies_are_verified_at_their_index_against_a_head() {
+        let data = example_data(6);
+        let tree = MerkleTree::construct(&data);
+        let verifier = RequestVerifier::for_head(tree.head());
+        let proof = hex::encode(tree.prove_by_index(4).expect("index is in range").to_bytes());
+
+        let headers = |index: &'static str| [(PROOF_HEADER, proof.as_bytes()), (LEAF_INDEX_HEADER, index.as_bytes())];
+        assert!(verifier.verify(headers("4"), &data[4]).is_ok());
+        assert_eq!(verifier.verify(headers("5"), &data[4]), Err(RequestProofError::Rejected));
+        assert_eq!(verifier.verify(headers("four"), &data[4]), Err(RequestProofError::MalformedHeader(LEAF_INDEX_HEADER)));
+        assert_eq!(verifier.verify([(PROOF_HEADER, proof.as_bytes())], &data[4]), Err(RequestProofError::MissingHeader(LEAF_INDEX_HEADER)));
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::merkletree::Hash;
+
+/// Root hash of a tree, the commitment proofs and inputs are verified against
+///
+/// A root is a hash like any other, but one that stands for a whole tree. Keeping it apart from
+/// `Hash` in signatures means a leaf hash or a proof sibling can't be handed over where a root
+/// is expected without saying so with `Root::new`.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Root(Hash);
+
+/// Reason a string is not a root: it is empty or not an even number of hex digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseRootError;
+
+impl Root {
+    /// Takes a hash as the root of a tree
+    pub fn new(hash: Hash) -> Root {
+        Root(hash)
+    }
+
+    /// Gets the bytes of the root
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Gives the root back as a plain hash
+    pub fn into_hash(self) -> Hash {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for Root {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// a root compares equal to the plain hash of the same bytes, so checks against computed hashes stay short
+
+impl PartialEq<[u8]> for Root {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<Hash> for Root {
+    fn eq(&self, other: &Hash) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<Root> for [u8] {
+    fn eq(&self, other: &Root) -> bool {
+        *self == other.0
+    }
+}
+
+impl PartialEq<Root> for Hash {
+    fn eq(&self, other: &Root) -> bool {
+        *self == other.0
+    }
+}
+
+impl fmt::Display for Root {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for Root {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Root({self})")
+    }
+}
+
+/// Parses the lowercase or uppercase hex digits of a root, as `Display` writes them
+impl FromStr for Root {
+    type Err = ParseRootError;
+
+    fn from_str(hex: &str) -> Result<Root, ParseRootError> {
+        match hex::decode(hex) {
+            Ok(hash) if !hash.is_empty() => Ok(Root(hash)),
+            _ => Err(ParseRootError),
+        }
+    }
+}
+
+impl fmt::Display for ParseRootError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a root is a non-empty string of hex digit pairs")
+    }
+}
+
+impl std::error::Error for ParseRootError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roots_round_trip_through_hex() {
+        let root: Root = "1f7379539707bcaea00564168d1d4d626b09b73f8a2a365234c62d763f854da2".parse().expect("valid hex");
+        assert_eq!(root.as_bytes().len(), 32);
+        assert_eq!(root.to_string().parse(), Ok(root.clone()));
+        assert_eq!("1F7379539707BCAEA00564168D1D4D626B09B73F8A2A365234C62D763F854DA2".parse(), Ok(root.clone()));
+        assert_eq!(format!("{root:?}"), format!("Root({root})"));
+        assert_eq!(Root::new(root.clone().into_hash()), root);
+        assert_eq!(root, root.as_bytes().to_vec());
+        assert_ne!(root.as_bytes()[1..], root);
+
+        assert_eq!("".parse::<Root>(), Err(ParseRootError));
+        assert_eq!("abc".parse::<Root>(), Err(ParseRootError));
+        assert_eq!("zz".parse::<Root>(), Err(ParseRootError));
+    }
+}
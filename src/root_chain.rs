@@ -0,0 +1,204 @@
+use std::fmt;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::Hash;
+use crate::tree_head::TreeHead;
+
+/// A published head together with the link committing to it and to every head published before it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainEntry {
+    pub head: TreeHead,
+    /// hash of the link of the entry before, the tree size (`u64` little-endian) and the root
+    /// the entry before the first one has a link of all zeros
+    pub link: Hash,
+}
+
+/// History of the heads a log published, each entry chained to the one before it
+///
+/// A bare root says nothing about the roots before it, so an operator can hand an auditor an old root
+/// as if it were current. Here every entry commits to the whole history through its link: an auditor who
+/// kept any link can check that a later sequence of entries extends it, and that the log neither shrank
+/// nor published two roots for one size along the way. Only the tree size and root of a head are
+/// committed to, timestamps are not.
+#[derive(Debug, Clone)]
+pub struct RootChain<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    entries: Vec<ChainEntry>,
+}
+
+/// Reasons a sequence of entries is not an unbroken chain, with the position of the offending entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainError {
+    /// the link doesn't commit to the entry before and the entry's own head
+    BrokenLink { position: usize },
+    /// the tree is smaller than the one of the entry before
+    Regressed { position: usize },
+    /// the tree has the size of the one before but another root
+    Forked { position: usize },
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::BrokenLink { position } => write!(f, "entry {position} doesn't link to the entry before"),
+            ChainError::Regressed { position } => write!(f, "entry {position} covers fewer leaves than the entry before"),
+            ChainError::Forked { position } => write!(f, "entry {position} has another root for the size of the entry before"),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+impl RootChain {
+    /// Starts an empty chain linked with SHA-256
+    pub fn new() -> RootChain {
+        RootChain::with_hasher(Sha256Hasher::new())
+    }
+
+    /// Verifies a SHA-256 chain from its first entry, see `verify_with_hasher`
+    pub fn verify(entries: &[ChainEntry]) -> Result<(), ChainError> {
+        RootChain::verify_with_hasher(entries, &Sha256Hasher::new())
+    }
+
+    /// Verifies that SHA-256 entries extend a trusted one, see `verify_extension_with_hasher`
+    pub fn verify_extension(trusted: &ChainEntry, entries: &[ChainEntry]) -> Result<(), ChainError> {
+        RootChain::verify_extension_with_hasher(trusted, entries, &Sha256Hasher::new())
+    }
+}
+
+impl Default for RootChain {
+    fn default() -> Self {
+        RootChain::new()
+    }
+}
+
+impl<H: Hasher> RootChain<H> {
+    /// Starts an empty chain linked with the given hash function
+    pub fn with_hasher(hasher: H) -> RootChain<H> {
+        RootChain { hasher, entries: vec![] }
+    }
+
+    /// Gets the entries in the order they were published
+    pub fn entries(&self) -> &[ChainEntry] {
+        &self.entries
+    }
+
+    /// Gets the entry published last, the one whose link commits to the whole chain
+    pub fn latest(&self) -> Option<&ChainEntry> {
+        self.entries.last()
+    }
+
+    /// Gets number of entries in the chain
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no head was published yet
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Publishes a head, linking it to the entry before
+    /// refuses heads of smaller trees than the latest one, and other roots for the latest size
+    pub fn push(&mut self, head: TreeHead) -> Result<&ChainEntry, ChainError> {
+        let position = self.entries.len();
+        if let Some(latest) = self.entries.last() {
+            check_growth(&latest.head, &head, position)?;
+        }
+        let previous = self.entries.last().map(|latest| latest.link.clone());
+        let link = link(&self.hasher, previous.as_deref(), &head);
+        self.entries.push(ChainEntry { head, link });
+        Ok(&self.entries[position])
+    }
+
+    /// Verifies that the entries are a whole chain, the first one linking to nothing before it
+    /// positions in errors count from the first entry
+    pub fn verify_with_hasher(entries: &[ChainEntry], hasher: &H) -> Result<(), ChainError> {
+        verify_from(None, entries, hasher)
+    }
+
+    /// Verifies that the entries continue the chain right after a trusted entry, e.g. the latest one
+    /// an auditor saw before, so no head published in between was replaced or left out
+    /// positions in errors count from the first of the entries
+    pub fn verify_extension_with_hasher(trusted: &ChainEntry, entries: &[ChainEntry], hasher: &H) -> Result<(), ChainError> {
+        verify_from(Some(trusted), entries, hasher)
+    }
+}
+
+/// link of a head following the entry with the given link, or the first head when there is none
+fn link<H: Hasher>(hasher: &H, previous: Option<&[u8]>, head: &TreeHead) -> Hash {
+    let zeros = vec![0; hasher.digest_len()];
+    let mut committed = previous.unwrap_or(&zeros).to_vec();
+    committed.extend_from_slice(&head.tree_size.to_le_bytes());
+    committed.extend_from_slice(head.root.as_bytes());
+    hasher.hash(&committed)
+}
+
+/// checks that a head only ever grows the tree of the head before it
+fn check_growth(previous: &TreeHead, head: &TreeHead, position: usize) -> Result<(), ChainError> {
+    if head.tree_size < previous.tree_size {
+        return Err(ChainError::Regressed { position });
+    }
+    if head.tree_size == previous.tree_size && head.root != previous.root {
+        return Err(ChainError::Forked { position });
+    }
+    Ok(())
+}
+
+fn verify_from<'a, H: Hasher>(mut previous: Option<&'a ChainEntry>, entries: &'a [ChainEntry], hasher: &H) -> Result<(), ChainError> {
+    for (position, entry) in entries.iter().enumerate() {
+        if let Some(previous) = previous {
+            check_growth(&previous.head, &entry.head, position)?;
+        }
+        if entry.link != link(hasher, previous.map(|previous| previous.link.as_slice()), &entry.head) {
+            return Err(ChainError::BrokenLink { position });
+        }
+        previous = Some(entry);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::{Data, MerkleTree};
+
+    fn heads(sizes: &[usize]) -> Vec<TreeHead> {
+        let data: Vec<Data> = (0..*sizes.iter().max().expect("has sizes")).map(|i| vec![i as u8]).collect();
+        sizes.iter().map(|size| MerkleTree::construct(&data[..*size]).head()).collect()
+    }
+
+    #[test]
+    fn test_chains_verify_whole_and_from_a_trusted_entry() {
+        let mut chain = RootChain::new();
+        for head in heads(&[1, 3, 3, 8, 20]) {
+            chain.push(head).expect("the log only grows");
+        }
+        assert_eq!(chain.len(), 5);
+        assert_eq!(RootChain::verify(chain.entries()), Ok(()));
+        let (seen, later) = chain.entries().split_at(2);
+        assert_eq!(RootChain::verify_extension(&seen[1], later), Ok(()));
+        // an auditor holding the second entry is shown a chain leaving out the third
+        assert_eq!(RootChain::verify_extension(&seen[1], &later[1..]), Err(ChainError::BrokenLink { position: 0 }));
+    }
+
+    #[test]
+    fn test_substituted_roots_break_the_chain() {
+        let old = heads(&[2, 5, 9]);
+        let mut chain = RootChain::new();
+        for head in &old {
+            chain.push(head.clone()).expect("the log only grows");
+        }
+        assert_eq!(chain.push(old[1].clone()).map(|entry| entry.link.clone()), Err(ChainError::Regressed { position: 3 }));
+        let other = MerkleTree::construct(&vec![vec![7]; 9]).head();
+        assert_eq!(chain.push(other.clone()).map(|entry| entry.link.clone()), Err(ChainError::Forked { position: 3 }));
+
+        // an old root passed off at a later position, keeping the link that was published there
+        let mut entries = chain.entries().to_vec();
+        entries[2].head = old[0].clone();
+        assert_eq!(RootChain::verify(&entries), Err(ChainError::Regressed { position: 2 }));
+        // or another root of the published size
+        entries[2].head = other;
+        assert_eq!(RootChain::verify(&entries), Err(ChainError::BrokenLink { position: 2 }));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{sibling_directions, Proof};
+
+// rs_merkle builds trees the way `MerkleTree` does: it takes leaves that are already hashed, hashes
+// each pair as `hash(left || right)` and promotes the odd node out of a level unchanged. The tree of
+// `MerkleTree::from_leaf_hashes` over the leaves given to `rs_merkle::MerkleTree::<Sha256>::from_leaves`,
+// or `MerkleTree::construct` over the unhashed leaves, therefore has the same root and the same proofs.
+
+impl Proof {
+    /// Reads a single-leaf `rs_merkle::MerkleProof` of a SHA-256 tree,
+    /// see `from_rs_merkle_bytes_with_hasher`
+    pub fn from_rs_merkle_bytes(bytes: &[u8], leaf_index: u64, leaf_count: u64) -> Option<Proof> {
+        Proof::from_rs_merkle_bytes_with_hasher(bytes, leaf_index, leaf_count, &Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> Proof<H> {
+    /// Serializes the proof as `rs_merkle::MerkleProof::to_bytes` does: the sibling hashes from the leaf up,
+    /// concatenated without any directions, which rs_merkle derives from the leaf index and leaf count
+    pub fn to_rs_merkle_bytes(&self) -> Vec<u8> {
+        self.hashes.iter().flat_map(|(_, hash)| hash.iter().copied()).collect()
+    }
+
+    /// Reads the bytes of a `rs_merkle::MerkleProof` for the single leaf at `leaf_index` in a tree of
+    /// `leaf_count` leaves, restoring the directions from the leaf's position
+    /// returns `None` when the index is out of range or the bytes don't hold one hash per level that has a sibling
+    pub fn from_rs_merkle_bytes_with_hasher(bytes: &[u8], leaf_index: u64, leaf_count: u64, hasher: &H) -> Option<Proof<H>> {
+        let directions = sibling_directions(leaf_index, leaf_count)?;
+        if bytes.len() != directions.len() * hasher.digest_len() {
+            return None;
+        }
+        let hashes = bytes.chunks_exact(hasher.digest_len()).map(<[u8]>::to_vec);
+        Some(Proof::new(directions.into_iter().zip(hashes).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::{Data, MerkleTree};
+
+    #[test]
+    fn test_tree_matches_rs_merkle_example() {
+        // the example of the rs_merkle documentation, over the SHA-256 hashes of six leaves
+        let data: Vec<Data> = ["a", "b", "c", "d", "e", "f"].iter().map(|leaf| leaf.as_bytes().to_vec()).collect();
+        let tree = MerkleTree::construct(&data);
+        assert_eq!(hex::encode(tree.root()), "1f7379539707bcaea00564168d1d4d626b09b73f8a2a365234c62d763f854da2");
+
+        // its proof of the leaf at index 3 holds the sibling of every level, concatenated
+        let proof = tree.prove_by_index(3).expect("this should return Proof");
+        assert_eq!(proof.to_rs_merkle_bytes().len(), 3 * 32);
+        let read = Proof::from_rs_merkle_bytes(&proof.to_rs_merkle_bytes(), 3, 6).expect("valid rs_merkle proof");
+        assert!(MerkleTree::verify_proof(&data[3], &read, &tree.root()));
+    }
+
+    #[test]
+    fn test_rs_merkle_bytes_round_trip_for_every_leaf() {
+        for n in [1, 2, 3, 5, 8, 11] {
+            let data: Vec<Data> = (0..n).map(|i| vec![i as u8]).collect();
+            let tree = MerkleTree::construct(&data);
+            for index in 0..n {
+                let proof = tree.prove_by_index(index).expect("this should return Proof");
+                let read = Proof::from_rs_merkle_bytes(&proof.to_rs_merkle_bytes(), index, n).expect("valid rs_merkle proof");
+                assert_eq!(read.hashes, proof.hashes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_rs_merkle_bytes_will_return_none() {
+        let data: Vec<Data> = (0..5).map(|i| vec![i as u8]).collect();
+        let bytes = MerkleTree::construct(&data).prove_by_index(4).expect("this should return Proof").to_rs_merkle_bytes();
+        // the promoted fifth leaf has a single sibling
+        assert_eq!(bytes.len(), 32);
+        assert!(Proof::from_rs_merkle_bytes(&bytes, 4, 5).is_some());
+        assert!(Proof::from_rs_merkle_bytes(&bytes, 3, 5).is_none());
+        assert!(Proof::from_rs_merkle_bytes(&bytes, 5, 5).is_none());
+        assert!(Proof::from_rs_merkle_bytes(&bytes[1..], 4, 5).is_none());
+    }
+}
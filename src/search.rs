@@ -0,0 +1,204 @@
+use std::cmp::Ordering;
+use std::io;
+
+use crate::disk_tree::DiskTree;
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{sibling_directions, Hash, MerkleTree, Proof};
+use crate::metrics;
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+
+/// Outcome of searching a tree whose leaf hashes are sorted, e.g. one built by `construct_canonical`
+///
+/// A key that is a leaf comes with its inclusion proof. A key that isn't comes with the leaves right
+/// below and right above its hash, each proven at its position: as the leaves are sorted, two adjacent
+/// leaves bracketing the hash leave no position the key could be at, and neither does the first leaf
+/// being above it or the last one below it. The absence only holds for trees that really are sorted,
+/// which the root alone doesn't show.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SearchResult<H: Hasher = Sha256Hasher> {
+    Found { index: u64, proof: Proof<H> },
+    /// `below` is `None` when the key is below the first leaf, `above` when it is above the last one
+    Absent { below: Option<Bracket<H>>, above: Option<Bracket<H>> },
+}
+
+/// Leaf next to an absent key, with the proof of it being at `index`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Bracket<H: Hasher = Sha256Hasher> {
+    pub index: u64,
+    pub leaf_hash: LeafHash,
+    pub proof: Proof<H>,
+}
+
+/// where the binary search ended: at the leaf of the key, or between two leaves that bracket it
+enum Position {
+    Found(u64),
+    /// index of the first leaf above the key, the leaf count when there is none
+    Before(u64),
+}
+
+impl MerkleTree {
+    /// Verifies the result of searching a SHA-256 tree, see `verify_search_with_hasher`
+    pub fn verify_search(key: &[u8], result: &SearchResult, leaf_count: u64, root_hash: &Root) -> bool {
+        MerkleTree::verify_search_with_hasher(key, result, leaf_count, root_hash, &Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Searches the sorted leaves for the hash of `key`, reading one leaf per halving of the range
+    pub fn search(&self, key: &[u8]) -> SearchResult<H> {
+        self.search_hash(&LeafHash::of(key, &self.hasher))
+    }
+
+    /// Searches the sorted leaves for a key that was already hashed, see `search`
+    pub fn search_hash(&self, key_hash: &LeafHash) -> SearchResult<H> {
+        let found: io::Result<_> = search(self.leaf_count(), key_hash, |index| Ok(self.levels.hash(0, index as usize).to_vec()), |index| {
+            Ok(self.prove_by_index(index).expect("index is in range"))
+        });
+        found.expect("reading from memory can't fail")
+    }
+
+    /// Verifies that a search result proves `key` to be in the tree of `leaf_count` sorted leaves under the
+    /// given root_hash, or to be absent from it, hashing with the given hash function
+    pub fn verify_search_with_hasher(key: &[u8], result: &SearchResult<H>, leaf_count: u64, root_hash: &Root, hasher: &H) -> bool {
+        let key_hash = LeafHash::of(key, hasher);
+        let proves = |index: u64, leaf_hash: &LeafHash, proof: &Proof<H>| {
+            let positioned = sibling_directions(index, leaf_count).is_some_and(|directions| directions.iter().eq(proof.hashes.iter().map(|(hash_direction, _)| hash_direction)));
+            metrics::verified(hasher, positioned) && MerkleTree::verify_proof_hashed_with_hasher(leaf_hash, proof, root_hash, hasher)
+        };
+        match result {
+            SearchResult::Found { index, proof } => proves(*index, &key_hash, proof),
+            SearchResult::Absent { below, above } => {
+                let ordered = below.as_ref().is_none_or(|below| below.leaf_hash < key_hash) && above.as_ref().is_none_or(|above| above.leaf_hash > key_hash);
+                let adjacent = match (below, above) {
+                    (Some(below), Some(above)) => below.index.checked_add(1) == Some(above.index),
+                    (None, Some(above)) => above.index == 0,
+                    (Some(below), None) => below.index.checked_add(1) == Some(leaf_count),
+                    (None, None) => false,
+                };
+                // the neighbours are checked before any proof, so a rejected result is counted once
+                metrics::verified(hasher, ordered && adjacent)
+                    && below.as_ref().is_none_or(|below| proves(below.index, &below.leaf_hash, &below.proof))
+                    && above.as_ref().is_none_or(|above| proves(above.index, &above.leaf_hash, &above.proof))
+            }
+        }
+    }
+}
+
+impl<H: Hasher> DiskTree<H> {
+    /// Searches the leaves of a tree built over data whose hashes are sorted for the hash of `key`
+    /// reads one leaf hash per halving of the range, and the siblings of the proofs, see `MerkleTree::search`
+    pub fn search(&self, key: &[u8]) -> io::Result<SearchResult<H>> {
+        let key_hash = LeafHash::of(key, &self.hasher);
+        search(self.leaf_count(), &key_hash, |index| self.read_hash(0, index), |index| {
+            Ok(self.prove_by_index(index)?.expect("index is in range"))
+        })
+    }
+}
+
+/// binary search over `leaf_count` sorted leaf hashes read with `read`, proving the result with `prove`
+fn search<H: Hasher>(
+    leaf_count: u64,
+    key_hash: &LeafHash,
+    mut read: impl FnMut(u64) -> io::Result<Hash>,
+    mut prove: impl FnMut(u64) -> io::Result<Proof<H>>,
+) -> io::Result<SearchResult<H>> {
+    let (mut low, mut high) = (0, leaf_count);
+    let mut read_hashes = vec![];
+    let position = loop {
+        if low == high {
+            break Position::Before(low);
+        }
+        let middle = low + (high - low) / 2;
+        let leaf_hash = read(middle)?;
+        let ordering = leaf_hash.as_slice().cmp(key_hash.as_bytes());
+        read_hashes.push((middle, leaf_hash));
+        match ordering {
+            Ordering::Less => low = middle + 1,
+            Ordering::Greater => high = middle,
+            Ordering::Equal => break Position::Found(middle),
+        }
+    };
+    // the leaves bracketing the key were both read on the way
+    let mut bracket = |index: u64| -> io::Result<Bracket<H>> {
+        let (_, leaf_hash) = read_hashes.iter().find(|(read_index, _)| *read_index == index).expect("bracketing leaves were read");
+        Ok(Bracket { index, leaf_hash: LeafHash::new(leaf_hash.clone()), proof: prove(index)? })
+    };
+    Ok(match position {
+        Position::Found(index) => SearchResult::Found { index, proof: prove(index)? },
+        Position::Before(index) => SearchResult::Absent {
+            below: index.checked_sub(1).map(&mut bracket).transpose()?,
+            above: (index < leaf_count).then(|| bracket(index)).transpose()?,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| format!("key {i}").into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_search_finds_keys_and_proves_absent_ones() {
+        let data = example_data(50);
+        let tree = MerkleTree::construct_canonical(&data);
+        let (root, leaf_count) = (tree.root(), tree.leaf_count());
+        for key in &data {
+            let result = tree.search(key);
+            assert!(matches!(result, SearchResult::Found { .. }));
+            assert!(MerkleTree::verify_search(key, &result, leaf_count, &root));
+            assert!(!MerkleTree::verify_search(b"key 50", &result, leaf_count, &root));
+        }
+        let mut edges = (false, false);
+        for absent in (50..300).map(|i| format!("key {i}").into_bytes()) {
+            let result = tree.search(&absent);
+            let SearchResult::Absent { below, above } = &result else {
+                panic!("key is absent");
+            };
+            edges = (edges.0 || below.is_none(), edges.1 || above.is_none());
+            assert!(MerkleTree::verify_search(&absent, &result, leaf_count, &root));
+            assert!(!MerkleTree::verify_search(&data[0], &result, leaf_count, &root));
+        }
+        // some of the absent keys hash below the first leaf and some above the last one
+        assert_eq!(edges, (true, true));
+    }
+
+    #[test]
+    fn test_bracketing_leaves_have_to_be_adjacent() {
+        let data = example_data(9);
+        let tree = MerkleTree::construct_canonical(&data);
+        let absent = (9..).map(|i| format!("key {i}").into_bytes()).find(|key| {
+            matches!(tree.search(key), SearchResult::Absent { below: Some(below), above: Some(_) } if below.index > 0)
+        });
+        let absent = absent.expect("some key falls between two leaves");
+        let SearchResult::Absent { below: Some(below), above } = tree.search(&absent) else {
+            panic!("key is absent");
+        };
+        // the leaf before the real lower bracket is below the key as well, but leaves a gap
+        let index = below.index - 1;
+        let earlier = Bracket { index, leaf_hash: tree.leaf_hash(index).expect("index is in range"), proof: tree.prove_by_index(index).expect("index is in range") };
+        let gapped = SearchResult::Absent { below: Some(earlier), above };
+        assert!(!MerkleTree::verify_search(&absent, &gapped, tree.leaf_count(), &tree.root()));
+    }
+
+    #[test]
+    fn test_disk_trees_search_their_sorted_leaves() {
+        let dir = std::env::temp_dir().join(format!("merkle-search-{}", std::process::id()));
+        let mut data = example_data(20);
+        let hasher = Sha256Hasher::new();
+        data.sort_by_key(|leaf| hasher.hash(leaf));
+        let disk_tree = DiskTree::build(&data, &dir, 0).expect("writes levels").expect("has leaves");
+        let tree = MerkleTree::construct_canonical(&data);
+        for key in [data[7].clone(), b"key 20".to_vec(), b"key 21".to_vec()] {
+            let result = disk_tree.search(&key).expect("reads levels");
+            assert_eq!(result, tree.search(&key));
+            assert!(MerkleTree::verify_search(&key, &result, 20, disk_tree.root()));
+        }
+        drop(disk_tree);
+        std::fs::remove_dir_all(dir).expect("removes directory");
+    }
+}
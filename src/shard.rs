@@ -0,0 +1,194 @@
+use std::fmt;
+
+use crate::accumulator::RootAccumulator;
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::Hash;
+use crate::root::Root;
+
+/// What a worker sends back after hashing the leaves `start..start + leaf_count` of a larger tree
+///
+/// A shard's own root is of no use to the coordinator unless the shard happens to cover a complete
+/// subtree of the whole tree. Instead the summary holds the roots of the largest complete subtrees that
+/// fit inside the range, from left to right, which for any split into shards are the ones the whole
+/// tree is made of. `ShardSummary::combine` then merges them into the root `MerkleTree::construct`
+/// computes over all leaves, without the coordinator hashing a single leaf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardSummary {
+    pub start: u64,
+    pub leaf_count: u64,
+    /// roots of the complete subtrees covering the range, leftmost first, see `subtree_heights`
+    pub hashes: Vec<Hash>,
+}
+
+/// Reasons summaries can't be combined into a root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CombineError {
+    /// the summaries cover no leaves at all
+    NoLeaves,
+    /// the summaries, ordered by start, leave out or cover twice the leaves from `expected`
+    NotContiguous { expected: u64, found: u64 },
+    /// the summary starting at the given leaf has the wrong number of hashes or hashes of the wrong length
+    MalformedSummary(u64),
+}
+
+impl fmt::Display for CombineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CombineError::NoLeaves => write!(f, "summaries cover no leaves"),
+            CombineError::NotContiguous { expected, found } => write!(f, "expected a shard starting at leaf {expected}, found one at {found}"),
+            CombineError::MalformedSummary(start) => write!(f, "malformed summary of the shard starting at leaf {start}"),
+        }
+    }
+}
+
+impl std::error::Error for CombineError {}
+
+impl ShardSummary {
+    /// Hashes the leaves of a SHA-256 tree starting at leaf `start`, see `build_with_hasher`
+    pub fn build<T: AsRef<[u8]>>(start: u64, leaves: &[T]) -> ShardSummary {
+        ShardSummary::build_with_hasher(start, leaves, &Sha256Hasher::new())
+    }
+
+    /// Combines the summaries of the shards of a SHA-256 tree, see `combine_with_hasher`
+    pub fn combine(summaries: &[ShardSummary]) -> Result<Root, CombineError> {
+        ShardSummary::combine_with_hasher(summaries, &Sha256Hasher::new())
+    }
+
+    /// Hashes the leaves of the shard whose first leaf is leaf `start` of the whole tree, with the given hash function
+    pub fn build_with_hasher<T: AsRef<[u8]>, H: Hasher>(start: u64, leaves: &[T], hasher: &H) -> ShardSummary {
+        let mut rest = leaves;
+        let mut hashes = vec![];
+        for height in subtree_heights(start, leaves.len() as u64) {
+            let (subtree, tail) = rest.split_at(1 << height);
+            let mut accumulator = RootAccumulator::with_hasher(hasher.clone());
+            accumulator.extend(subtree);
+            hashes.push(accumulator.finish().expect("subtrees have at least one leaf").into_hash());
+            rest = tail;
+        }
+        ShardSummary {
+            start,
+            leaf_count: leaves.len() as u64,
+            hashes,
+        }
+    }
+
+    /// Combines the summaries of shards that together cover the leaves of a tree from the first one on
+    /// into the root of the whole tree; the summaries may come in any order, empty shards are ignored
+    pub fn combine_with_hasher<H: Hasher>(summaries: &[ShardSummary], hasher: &H) -> Result<Root, CombineError> {
+        let mut ordered: Vec<&ShardSummary> = summaries.iter().filter(|summary| summary.leaf_count > 0).collect();
+        ordered.sort_by_key(|summary| summary.start);
+
+        // complete subtrees as (height, root), merged with their left sibling as soon as it is next to them
+        let mut stack: Vec<(u32, Hash)> = vec![];
+        let mut expected = 0u64;
+        for summary in ordered {
+            if summary.start != expected {
+                return Err(CombineError::NotContiguous { expected, found: summary.start });
+            }
+            let heights = subtree_heights(summary.start, summary.leaf_count);
+            let malformed = summary.start.checked_add(summary.leaf_count).is_none()
+                || heights.len() != summary.hashes.len()
+                || summary.hashes.iter().any(|hash| hash.len() != hasher.digest_len());
+            if malformed {
+                return Err(CombineError::MalformedSummary(summary.start));
+            }
+            for (height, hash) in heights.into_iter().zip(&summary.hashes) {
+                // the subtree is a right child exactly when its index on its level is odd
+                let mut position = expected >> height;
+                expected += 1 << height;
+                let (mut height, mut node) = (height, hash.clone());
+                while !position.is_multiple_of(2) && stack.last().is_some_and(|(left, _)| *left == height) {
+                    let (_, left) = stack.pop().expect("a left sibling is on the stack");
+                    node = hasher.hash_concat(&left, &node);
+                    height += 1;
+                    position >>= 1;
+                }
+                stack.push((height, node));
+            }
+        }
+        let (last, rest) = stack.split_last().ok_or(CombineError::NoLeaves)?;
+        // the odd subtrees on the right are promoted until they meet one of their size, as in `Frontier`
+        Ok(Root::new(rest.iter().rev().fold(last.1.clone(), |right, (_, left)| hasher.hash_concat(left, &right))))
+    }
+}
+
+/// heights of the largest complete subtrees of the whole tree that cover the leaves `start..start + leaf_count`, leftmost first
+/// each starts at a multiple of its own size, so it is a subtree whatever the size of the whole tree
+fn subtree_heights(start: u64, leaf_count: u64) -> Vec<u32> {
+    let mut heights = vec![];
+    let (mut position, end) = (start, start.saturating_add(leaf_count));
+    while position < end {
+        let mut height = position.trailing_zeros().min(63);
+        while 1u64 << height > end - position {
+            height -= 1;
+        }
+        heights.push(height);
+        position += 1 << height;
+    }
+    heights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::{Data, MerkleTree};
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| (i as u32).to_le_bytes().to_vec()).collect()
+    }
+
+    /// summaries of the shards cut at the given leaves
+    fn summaries(data: &[Data], cuts: &[usize]) -> Vec<ShardSummary> {
+        let mut bounds = vec![0];
+        bounds.extend_from_slice(cuts);
+        bounds.push(data.len());
+        // cuts at the same leaf leave empty shards in between
+        bounds.sort();
+        bounds.windows(2).map(|range| ShardSummary::build(range[0] as u64, &data[range[0]..range[1]])).collect()
+    }
+
+    #[test]
+    fn test_uneven_shards_combine_into_the_monolithic_root() {
+        for n in [1, 2, 3, 7, 13, 100] {
+            let data = example_data(n);
+            let root = MerkleTree::construct(&data).root();
+            for cuts in [vec![], vec![n / 2], vec![1, n / 3, n - 1], (1..n).collect()] {
+                let mut shards = summaries(&data, &cuts);
+                assert_eq!(ShardSummary::combine(&shards), Ok(root.clone()));
+                // the coordinator may receive them in any order
+                shards.reverse();
+                assert_eq!(ShardSummary::combine(&shards), Ok(root.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_summaries_hold_the_complete_subtrees_of_their_range() {
+        let data = example_data(13);
+        let summary = ShardSummary::build(3, &data[3..12]);
+        // leaves 3, 4..8, 8..12
+        assert_eq!(summary.hashes.len(), 3);
+        assert_eq!(summary.hashes[1], MerkleTree::construct(&data[4..8]).root());
+        assert_eq!(subtree_heights(0, 13), vec![3, 2, 0]);
+
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        let shards = [ShardSummary::build_with_hasher(0, &data[..5], &hasher), ShardSummary::build_with_hasher(5, &data[5..], &hasher)];
+        assert_eq!(ShardSummary::combine_with_hasher(&shards, &hasher), Ok(MerkleTree::construct_with_hasher(&data, hasher).root()));
+    }
+
+    #[test]
+    fn test_gaps_overlaps_and_malformed_summaries_are_refused() {
+        let data = example_data(10);
+        let shards = summaries(&data, &[4, 7]);
+        assert_eq!(ShardSummary::combine(&[shards[0].clone(), shards[2].clone()]), Err(CombineError::NotContiguous { expected: 4, found: 7 }));
+        assert_eq!(ShardSummary::combine(&[shards[1].clone()]), Err(CombineError::NotContiguous { expected: 0, found: 4 }));
+        let overlapping = ShardSummary::build(3, &data[3..4]);
+        assert_eq!(ShardSummary::combine(&[shards[0].clone(), overlapping]), Err(CombineError::NotContiguous { expected: 4, found: 3 }));
+
+        let mut truncated = shards.clone();
+        truncated[1].hashes.pop();
+        assert_eq!(ShardSummary::combine(&truncated), Err(CombineError::MalformedSummary(4)));
+        assert_eq!(ShardSummary::combine(&[]), Err(CombineError::NoLeaves));
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::MerkleTree;
+use crate::node_hash::LeafHash;
+use crate::params::{DomainSeparation, ParamsMismatch, TreeParams, PARAMS_LEN};
+
+/// bytes every snapshot starts with
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"MRKL";
+/// version of the snapshot format written by this crate
+pub const SNAPSHOT_VERSION: u8 = 2;
+/// version of the snapshot and checkpoint formats before they recorded every parameter of the tree
+/// still read, as the parameters they leave out have only ever had their default
+pub(crate) const LEGACY_VERSION: u8 = 1;
+
+/// How a tree pairs up a level with an odd number of nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddingStrategy {
+    /// the odd node out is promoted to the next level unchanged
+    PromoteOdd,
+}
+
+impl PaddingStrategy {
+    /// identifier of the strategy in serialized formats
+    pub fn id(&self) -> u8 {
+        match self {
+            PaddingStrategy::PromoteOdd => 0,
+        }
+    }
+
+    /// looks up the strategy for an identifier, `None` when this crate does not know it
+    pub fn from_id(id: u8) -> Option<PaddingStrategy> {
+        match id {
+            0 => Some(PaddingStrategy::PromoteOdd),
+            _ => None,
+        }
+    }
+}
+
+/// Reasons a snapshot can not be imported
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// the input is not a snapshot at all
+    BadMagic,
+    /// the snapshot was written by an incompatible version of the format
+    UnsupportedVersion(u8),
+    /// the tree was hashed with an algorithm this build can not reproduce
+    UnsupportedHashAlgorithm(u64),
+    /// the tree was padded with a strategy this build can not reproduce
+    UnsupportedPadding(u8),
+    /// the hash length does not match the hash algorithm
+    InvalidHashLength(u8),
+    /// the leaves and nodes were told apart in a way this build can not reproduce
+    UnsupportedDomainSeparation(u8),
+    /// the nodes have another number of children than the tree being read into
+    UnsupportedArity(u32),
+    /// the snapshot declares no leaves, which is no tree
+    Empty,
+    /// the checkpoint holds another number of peaks than its leaf count has set bits
+    PeakCountMismatch { leaf_count: u64, peaks: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "{error}"),
+            SnapshotError::BadMagic => write!(f, "not a merkle tree snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {version}"),
+            SnapshotError::UnsupportedHashAlgorithm(code) => write!(f, "unsupported hash algorithm {code:#x}"),
+            SnapshotError::UnsupportedPadding(id) => write!(f, "unsupported padding strategy {id}"),
+            SnapshotError::InvalidHashLength(len) => write!(f, "invalid hash length {len}"),
+            SnapshotError::UnsupportedDomainSeparation(id) => write!(f, "unsupported domain separation {id}"),
+            SnapshotError::UnsupportedArity(arity) => write!(f, "unsupported arity {arity}"),
+            SnapshotError::Empty => write!(f, "snapshot has no leaves"),
+            SnapshotError::PeakCountMismatch { leaf_count, peaks } => write!(f, "{peaks} peaks for {leaf_count} leaves"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> SnapshotError {
+        SnapshotError::Io(error)
+    }
+}
+
+impl From<ParamsMismatch> for SnapshotError {
+    fn from(mismatch: ParamsMismatch) -> SnapshotError {
+        match mismatch {
+            ParamsMismatch::HashAlgorithm { found, .. } => SnapshotError::UnsupportedHashAlgorithm(found),
+            ParamsMismatch::Padding { found, .. } => SnapshotError::UnsupportedPadding(found),
+            ParamsMismatch::HashLength { found, .. } => SnapshotError::InvalidHashLength(found),
+            ParamsMismatch::DomainSeparation { found, .. } => SnapshotError::UnsupportedDomainSeparation(found),
+            ParamsMismatch::Arity { found, .. } => SnapshotError::UnsupportedArity(found),
+        }
+    }
+}
+
+/// parameters of a binary tree of the hash function written into headers
+/// hash functions without a multicodec code can't be serialized, as their output could not be told apart on import
+pub(crate) fn header_params(hasher: &impl Hasher) -> io::Result<TreeParams> {
+    TreeParams::binary(hasher).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "hash algorithm has no multicodec code"))
+}
+
+/// reads the parameters of a header and checks them against those of a binary tree of the hash function
+pub(crate) fn read_params(mut reader: impl Read, hasher: &impl Hasher) -> Result<(), SnapshotError> {
+    let mut params = [0; PARAMS_LEN];
+    reader.read_exact(&mut params)?;
+    check_params(&params, hasher)
+}
+
+/// reads the parameters of a version 1 header, the algorithm code, the padding strategy when `with_padding`
+/// and the hash length, and checks them as those of a binary tree promoting odd nodes without domain separation
+pub(crate) fn read_legacy_params(mut reader: impl Read, hasher: &impl Hasher, with_padding: bool) -> Result<(), SnapshotError> {
+    let mut legacy = [0; 10];
+    let legacy = &mut legacy[..if with_padding { 10 } else { 9 }];
+    reader.read_exact(legacy)?;
+    let mut params = [0; PARAMS_LEN];
+    params[..8].copy_from_slice(&legacy[..8]);
+    params[8] = if with_padding { legacy[8] } else { PaddingStrategy::PromoteOdd.id() };
+    params[9] = legacy[legacy.len() - 1];
+    params[10] = DomainSeparation::None.id();
+    params[11..].copy_from_slice(&2u32.to_le_bytes());
+    check_params(&params, hasher)
+}
+
+fn check_params(params: &[u8; PARAMS_LEN], hasher: &impl Hasher) -> Result<(), SnapshotError> {
+    match TreeParams::binary(hasher) {
+        Some(expected) => Ok(expected.check(params)?),
+        None => Err(SnapshotError::UnsupportedHashAlgorithm(u64::from_le_bytes(params[..8].try_into().expect("8 bytes")))),
+    }
+}
+
+impl MerkleTree {
+    /// Reads a snapshot written by `export_snapshot` of a SHA-256 tree
+    /// refuses snapshots whose version, hash algorithm or padding strategy this build can not reproduce
+    pub fn import_snapshot(reader: impl Read) -> Result<MerkleTree, SnapshotError> {
+        MerkleTree::import_snapshot_with_hasher(reader, Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Writes the tree as a self-describing snapshot
+    ///
+    /// The header holds the magic bytes, the format version, the parameters of the tree as `TreeParams`
+    /// writes them and the leaf count (`u64` little-endian).
+    /// The leaf hashes follow in order, everything above them is recomputed on import.
+    pub fn export_snapshot(&self, mut writer: impl Write) -> io::Result<()> {
+        let leaf_hashes = self.leaf_hashes();
+        writer.write_all(&SNAPSHOT_MAGIC)?;
+        writer.write_all(&[SNAPSHOT_VERSION])?;
+        writer.write_all(&header_params(&self.hasher)?.to_bytes())?;
+        writer.write_all(&(leaf_hashes.len() as u64).to_le_bytes())?;
+        for leaf_hash in leaf_hashes {
+            writer.write_all(leaf_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a snapshot written by `export_snapshot` of a tree built with the given hash function
+    /// refuses snapshots of a version or of tree parameters other than those of a tree of the hash function
+    /// version 1 snapshots, which don't record the domain separation and arity, are read as binary trees without one
+    pub fn import_snapshot_with_hasher(mut reader: impl Read, hasher: H) -> Result<MerkleTree<H>, SnapshotError> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if header[..4] != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        match header[4] {
+            SNAPSHOT_VERSION => read_params(&mut reader, &hasher)?,
+            LEGACY_VERSION => read_legacy_params(&mut reader, &hasher, true)?,
+            version => return Err(SnapshotError::UnsupportedVersion(version)),
+        }
+        let mut leaf_count = [0; 8];
+        reader.read_exact(&mut leaf_count)?;
+        let leaf_count = u64::from_le_bytes(leaf_count);
+        if leaf_count == 0 {
+            return Err(SnapshotError::Empty);
+        }
+
+        // the declared count is untrusted, so the leaves are allocated as they actually arrive
+        let mut leaf_hashes: Vec<LeafHash> = Vec::with_capacity(leaf_count.min(1 << 16) as usize);
+        for _ in 0..leaf_count {
+            let mut leaf_hash = vec![0; hasher.digest_len()];
+            reader.read_exact(&mut leaf_hash)?;
+            leaf_hashes.push(LeafHash::new(leaf_hash));
+        }
+        Ok(MerkleTree::from_leaf_hashes_with_hasher(leaf_hashes, hasher))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_snapshot(n: usize) -> (MerkleTree, Vec<u8>) {
+        let data = (0..n).map(|i| vec![i as u8]).collect::<Vec<Data>>();
+        let tree = MerkleTree::construct(&data);
+        let mut snapshot = vec![];
+        tree.export_snapshot(&mut snapshot).expect("this should export");
+        (tree, snapshot)
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let (tree, snapshot) = example_snapshot(5);
+        assert_eq!(snapshot.len(), 28 + 5 * 32);
+
+        let imported = MerkleTree::import_snapshot(snapshot.as_slice()).expect("this should import");
+        assert_eq!(imported.root(), tree.root());
+        assert_eq!(imported.leaf_count(), 5);
+    }
+
+    #[test]
+    fn test_import_snapshot_refuses_incompatible_headers() {
+        let (_, snapshot) = example_snapshot(2);
+        let with = |position: usize, value: u8| {
+            let mut changed = snapshot.clone();
+            changed[position] = value;
+            MerkleTree::import_snapshot(changed.as_slice())
+        };
+
+        assert!(matches!(with(0, b'X'), Err(SnapshotError::BadMagic)));
+        assert!(matches!(with(4, 3), Err(SnapshotError::UnsupportedVersion(3))));
+        assert!(matches!(with(5, 0x1e), Err(SnapshotError::UnsupportedHashAlgorithm(0x1e))));
+        assert!(matches!(with(13, 1), Err(SnapshotError::UnsupportedPadding(1))));
+        assert!(matches!(with(14, 20), Err(SnapshotError::InvalidHashLength(20))));
+        assert!(matches!(with(15, 1), Err(SnapshotError::UnsupportedDomainSeparation(1))));
+        assert!(matches!(with(16, 16), Err(SnapshotError::UnsupportedArity(16))));
+        assert!(matches!(with(20, 0), Err(SnapshotError::Empty)));
+        assert!(matches!(with(20, 3), Err(SnapshotError::Io(_))));
+    }
+
+    #[test]
+    fn test_import_snapshot_reads_version_1() {
+        let (tree, snapshot) = example_snapshot(5);
+        // version 1 wrote the algorithm code, the padding strategy and the hash length only
+        let mut legacy = [&SNAPSHOT_MAGIC[..], &[1], &snapshot[5..15]].concat();
+        legacy.extend_from_slice(&snapshot[20..]);
+
+        let imported = MerkleTree::import_snapshot(legacy.as_slice()).expect("this should import");
+        assert_eq!(imported.root(), tree.root());
+        legacy[13] = 1;
+        assert!(matches!(MerkleTree::import_snapshot(legacy.as_slice()), Err(SnapshotError::UnsupportedPadding(1))));
+    }
+
+    #[test]
+    fn test_snapshot_records_hasher_and_digest_length() {
+        use crate::hasher::Shake128Hasher;
+        use crate::multihash::HashAlgorithm;
+
+        let data = (0..3).map(|i| vec![i as u8]).collect::<Vec<Data>>();
+        let hasher = Shake128Hasher::new(20).expect("valid length");
+        let tree = MerkleTree::construct_with_hasher(&data, hasher);
+        let mut snapshot = vec![];
+        tree.export_snapshot(&mut snapshot).expect("this should export");
+        assert_eq!(snapshot[5], HashAlgorithm::Shake128.code() as u8);
+        assert_eq!(snapshot[14], 20);
+
+        let imported = MerkleTree::import_snapshot_with_hasher(snapshot.as_slice(), hasher).expect("this should import");
+        assert_eq!(imported.root(), tree.root());
+
+        let longer = Shake128Hasher::new(32).expect("valid length");
+        let error = MerkleTree::import_snapshot_with_hasher(snapshot.as_slice(), longer);
+        assert!(matches!(error, Err(SnapshotError::InvalidHashLength(20))));
+        assert!(matches!(MerkleTree::import_snapshot(snapshot.as_slice()), Err(SnapshotError::UnsupportedHashAlgorithm(0x18))));
+    }
+}
@@ -0,0 +1,330 @@
+use std::array;
+use std::io::{self, Read};
+
+use crate::merkletree::{scrub, scrub_slice, split_point, Data, Hash};
+use crate::root::Root;
+
+/// bytes of content in every leaf of the tree, the chunk length of BLAKE3
+pub const CHUNK_LEN: usize = 1024;
+/// bytes of the little-endian content length every encoding starts with
+pub const HEADER_LEN: usize = 8;
+/// bytes of a chaining value, which is also the length of the root
+const CV_LEN: usize = 32;
+/// bytes compressed at once, a chunk is compressed one block after another
+const BLOCK_LEN: usize = 64;
+
+// domain flags, which keep chunks, parent nodes and the root from passing for one another
+const CHUNK_START: u32 = 1;
+const CHUNK_END: u32 = 2;
+const PARENT: u32 = 4;
+const ROOT: u32 = 8;
+
+const IV: [u32; 8] = [0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19];
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+/// BLAKE3 hash of a subtree, the BLAKE3 hash of the whole content when it is compressed as the root
+type ChainingValue = [u8; CV_LEN];
+
+/// quarter round of the compression function, mixing two message words into a column or diagonal
+fn g(state: &mut [u32; 16], [a, b, c, d]: [usize; 4], mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+/// BLAKE3 compression of one block into the chaining value that comes before it
+fn compress(cv: &[u32; 8], block: &[u8; BLOCK_LEN], counter: u64, block_len: u32, flags: u32) -> [u32; 8] {
+    let mut m: [u32; 16] = array::from_fn(|i| u32::from_le_bytes(block[4 * i..4 * i + 4].try_into().expect("four bytes")));
+    let mut state = [
+        cv[0], cv[1], cv[2], cv[3], cv[4], cv[5], cv[6], cv[7],
+        IV[0], IV[1], IV[2], IV[3], counter as u32, (counter >> 32) as u32, block_len, flags,
+    ];
+    for _ in 0..7 {
+        g(&mut state, [0, 4, 8, 12], m[0], m[1]);
+        g(&mut state, [1, 5, 9, 13], m[2], m[3]);
+        g(&mut state, [2, 6, 10, 14], m[4], m[5]);
+        g(&mut state, [3, 7, 11, 15], m[6], m[7]);
+        g(&mut state, [0, 5, 10, 15], m[8], m[9]);
+        g(&mut state, [1, 6, 11, 12], m[10], m[11]);
+        g(&mut state, [2, 7, 8, 13], m[12], m[13]);
+        g(&mut state, [3, 4, 9, 14], m[14], m[15]);
+        m = MSG_PERMUTATION.map(|i| m[i]);
+    }
+    array::from_fn(|i| state[i] ^ state[i + 8])
+}
+
+fn to_bytes(words: [u32; 8]) -> ChainingValue {
+    let mut bytes = [0; CV_LEN];
+    for (bytes, word) in bytes.chunks_exact_mut(4).zip(words) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
+
+/// chaining value of the chunk at position `counter` of the content
+/// an empty chunk, which only empty content has, is compressed as a single empty block
+fn chunk_cv(chunk: &[u8], counter: u64, flags: u32) -> ChainingValue {
+    debug_assert!(chunk.len() <= CHUNK_LEN);
+    let blocks = chunk.len().div_ceil(BLOCK_LEN).max(1);
+    let mut cv = IV;
+    let mut block = [0; BLOCK_LEN];
+    for i in 0..blocks {
+        let bytes = &chunk[i * BLOCK_LEN..chunk.len().min((i + 1) * BLOCK_LEN)];
+        block[..bytes.len()].copy_from_slice(bytes);
+        block[bytes.len()..].fill(0);
+        let start = if i == 0 { CHUNK_START } else { 0 };
+        let end = if i == blocks - 1 { CHUNK_END | flags } else { 0 };
+        cv = compress(&cv, &block, counter, bytes.len() as u32, start | end);
+    }
+    scrub_slice(&mut block);
+    to_bytes(cv)
+}
+
+/// chaining value of a parent node over the chaining values of its subtrees
+fn parent_cv(left: &[u8], right: &[u8], flags: u32) -> ChainingValue {
+    let mut block = [0; BLOCK_LEN];
+    block[..CV_LEN].copy_from_slice(left);
+    block[CV_LEN..].copy_from_slice(right);
+    to_bytes(compress(&IV, &block, 0, BLOCK_LEN as u32, PARENT | flags))
+}
+
+/// number of chunks `content_len` bytes are split into
+/// empty content is a single empty chunk, so that every content has a root
+fn chunk_count(content_len: u64) -> u64 {
+    content_len.div_ceil(CHUNK_LEN as u64).max(1)
+}
+
+/// Encodes `content` for verified streaming, returning the root hash and the encoding
+///
+/// The root is the BLAKE3 hash of the content: the content is split into 1024-byte chunks, and chunks and
+/// parent nodes are compressed with their own domain flags, the root with one more. The encoding is the
+/// combined encoding of Bao: the content length in 8 little-endian bytes, then the tree in pre-order, with
+/// the two child hashes of every parent node before the subtrees they authenticate and the chunk bytes in
+/// place of every leaf. A `Decoder` can therefore check every byte against the root before handing it out.
+pub fn encode(content: &[u8]) -> (Root, Vec<u8>) {
+    let parents = chunk_count(content.len() as u64) as usize - 1;
+    let mut encoded = Vec::with_capacity(HEADER_LEN + content.len() + 2 * CV_LEN * parents);
+    encoded.extend_from_slice(&(content.len() as u64).to_le_bytes());
+    let root = encode_subtree(content, 0, ROOT, &mut encoded);
+    (Root::new(root.to_vec()), encoded)
+}
+
+/// recursive pre-order walk interleaving child hashes and chunks, `counter` being the first chunk's position
+fn encode_subtree(content: &[u8], counter: u64, flags: u32, encoded: &mut Vec<u8>) -> ChainingValue {
+    if content.len() <= CHUNK_LEN {
+        encoded.extend_from_slice(content);
+        return chunk_cv(content, counter, flags);
+    }
+    let left_count = split_point(chunk_count(content.len() as u64));
+    let (left, right) = content.split_at(left_count as usize * CHUNK_LEN);
+    // the child hashes come first, but are only known once the subtrees below have been walked
+    let node = encoded.len();
+    encoded.resize(node + 2 * CV_LEN, 0);
+    let left = encode_subtree(left, counter, 0, encoded);
+    let right = encode_subtree(right, counter + left_count, 0, encoded);
+    encoded[node..node + CV_LEN].copy_from_slice(&left);
+    encoded[node + CV_LEN..node + 2 * CV_LEN].copy_from_slice(&right);
+    parent_cv(&left, &right, flags)
+}
+
+/// Reads an encoding produced by `encode`, verifying it against a trusted root as bytes arrive
+///
+/// Each chunk is only returned from `read` once its hash has been authenticated up to the root,
+/// and the first corrupt chunk or child hash fails the read with `io::ErrorKind::InvalidData`.
+///
+/// The content length in the header is not trusted on its own: it decides the shape of the tree the
+/// decoder expects, and a wrong one makes a chunk or child hash fail at the latest with the last chunk,
+/// since chunks and parent nodes can't pass for one another.
+pub struct Decoder<R: Read> {
+    reader: R,
+    /// trusted root, until the header has been read and it becomes the first pending subtree
+    root: Hash,
+    /// content bytes that still have to be read from the encoding, `None` before the header
+    remaining: Option<u64>,
+    /// subtrees still to be read, as their expected hash, chunk count, position of their first chunk
+    /// and domain flags, next one on top
+    pending: Vec<(Hash, u64, u64, u32)>,
+    /// verified chunk not yet fully handed out, with the position of the next byte to hand out
+    chunk: Data,
+    position: usize,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Starts decoding content that was encoded under `root`
+    pub fn new(reader: R, root: &Root) -> Decoder<R> {
+        Decoder { reader, root: root.as_bytes().to_vec(), remaining: None, pending: vec![], chunk: vec![], position: 0 }
+    }
+
+    /// descends the pending subtrees until the next chunk has been read and verified
+    /// returns `false` once the whole tree has been read
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                let mut header = [0; HEADER_LEN];
+                self.reader.read_exact(&mut header)?;
+                let content_len = u64::from_le_bytes(header);
+                self.pending.push((std::mem::take(&mut self.root), chunk_count(content_len), 0, ROOT));
+                *self.remaining.insert(content_len)
+            }
+        };
+        while let Some((expected, chunk_count, counter, flags)) = self.pending.pop() {
+            if chunk_count == 1 {
+                let len = remaining.min(CHUNK_LEN as u64) as usize;
+                let mut chunk = vec![0; len];
+                self.reader.read_exact(&mut chunk)?;
+                if chunk_cv(&chunk, counter, flags)[..] != expected[..] {
+                    scrub(&mut chunk);
+                    return Err(corrupt("chunk does not match its hash"));
+                }
+                self.remaining = Some(remaining - len as u64);
+                scrub(&mut self.chunk);
+                self.chunk = chunk;
+                self.position = 0;
+                return Ok(true);
+            }
+            let mut children = [0; 2 * CV_LEN];
+            self.reader.read_exact(&mut children)?;
+            let (left, right) = children.split_at(CV_LEN);
+            if parent_cv(left, right, flags)[..] != expected[..] {
+                return Err(corrupt("child hashes do not match their parent"));
+            }
+            let left_count = split_point(chunk_count);
+            self.pending.push((right.to_vec(), chunk_count - left_count, counter + left_count, 0));
+            self.pending.push((left.to_vec(), left_count, counter, 0));
+        }
+        Ok(false)
+    }
+}
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(feature = "zeroize")]
+impl<R: Read> Drop for Decoder<R> {
+    fn drop(&mut self) {
+        scrub(&mut self.chunk);
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // an empty chunk at the end of empty content still has to be verified before reporting EOF
+        while self.position == self.chunk.len() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_encode_root_is_blake3_of_content() {
+        // the test vectors of BLAKE3, whose inputs are this repeating pattern
+        let vectors = [
+            (0, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+            (1, "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+            (1023, "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11"),
+            (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+            (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+            (2048, "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+            (3072, "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2"),
+        ];
+        for (len, expected) in vectors {
+            assert_eq!(hex::encode(encode(&example_content(len)).0), expected, "content of {len} bytes");
+        }
+        assert_eq!(hex::encode(encode(b"abc").0), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+
+        // 5 chunks have 4 parent nodes with two child hashes each
+        let content = example_content(5000);
+        assert_eq!(encode(&content).1.len(), HEADER_LEN + content.len() + 4 * 64);
+    }
+
+    #[test]
+    fn test_decoder_round_trip() {
+        for len in [0, 1, 1023, 1024, 1025, 5000] {
+            let content = example_content(len);
+            let (root, encoded) = encode(&content);
+
+            let mut decoded = vec![];
+            Decoder::new(encoded.as_slice(), &root).read_to_end(&mut decoded).expect("this should decode content");
+            assert_eq!(decoded, content);
+        }
+    }
+
+    #[test]
+    fn test_decoder_aborts_on_first_corrupt_chunk() {
+        let content = example_content(5000);
+        let (root, mut encoded) = encode(&content);
+        // last byte belongs to the last chunk, everything before it is intact
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+
+        let mut decoder = Decoder::new(encoded.as_slice(), &root);
+        let mut decoded = vec![];
+        let mut buf = [0; 64];
+        let error = loop {
+            match decoder.read(&mut buf) {
+                Ok(len) => decoded.extend_from_slice(&buf[..len]),
+                Err(error) => break error,
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decoded, content[..4 * CHUNK_LEN]);
+    }
+
+    #[test]
+    fn test_decoder_rejects_corrupt_child_hashes_and_wrong_length() {
+        let content = example_content(2000);
+        let (root, mut encoded) = encode(&content);
+
+        let mut decoded = vec![];
+        let mut shorter = encoded.clone();
+        shorter[..HEADER_LEN].copy_from_slice(&1999u64.to_le_bytes());
+        let error = Decoder::new(shorter.as_slice(), &root).read_to_end(&mut decoded);
+        assert_eq!(error.expect_err("length mismatch should fail").kind(), io::ErrorKind::InvalidData);
+
+        encoded[HEADER_LEN] ^= 1;
+        let error = Decoder::new(encoded.as_slice(), &root).read_to_end(&mut decoded);
+        assert_eq!(error.expect_err("corrupt hash should fail").kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_chunks_do_not_pass_for_parent_nodes() {
+        // three chunks split two and one, four chunks two and two, so both lengths agree on the root's children
+        let content = example_content(3 * CHUNK_LEN);
+        let (root, mut encoded) = encode(&content);
+        encoded[..HEADER_LEN].copy_from_slice(&(4 * CHUNK_LEN as u64).to_le_bytes());
+
+        let mut decoder = Decoder::new(encoded.as_slice(), &root);
+        let mut decoded = vec![];
+        let mut buf = [0; CHUNK_LEN];
+        let error = loop {
+            match decoder.read(&mut buf) {
+                Ok(len) => decoded.extend_from_slice(&buf[..len]),
+                Err(error) => break error,
+            }
+        };
+        // the last chunk is read as a parent node and fails the chaining value of a chunk
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decoded, content[..2 * CHUNK_LEN]);
+    }
+}
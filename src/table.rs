@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::merkletree::{Data, MerkleTree, Proof};
+use crate::root::Root;
+
+/// A tree over the rows of a table with proofs looked up by primary key
+///
+/// Rows are leaves in the order they were read, so exports meant to be compared have to read
+/// them in a fixed order, e.g. `ORDER BY` the primary key.
+pub struct TableSnapshot {
+    tree: MerkleTree,
+    rows: Vec<Data>,
+    /// position of every row by its primary key
+    keys: HashMap<Vec<u8>, usize>,
+}
+
+/// Reasons a table snapshot can not be built
+#[derive(Debug)]
+pub enum TableError {
+    #[cfg(feature = "sqlx")]
+    Database(sqlx::Error),
+    /// the query result has no column of the primary key's name
+    MissingPrimaryKey,
+    /// the row at this position has no primary key value
+    NullPrimaryKey(usize),
+    /// two rows have the same primary key
+    DuplicateKey(Vec<u8>),
+    /// there are no rows, which is no tree
+    Empty,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "sqlx")]
+            TableError::Database(error) => write!(f, "{error}"),
+            TableError::MissingPrimaryKey => write!(f, "primary key column is missing"),
+            TableError::NullPrimaryKey(row) => write!(f, "row {row}: primary key is null"),
+            TableError::DuplicateKey(key) => write!(f, "duplicate primary key 0x{}", hex::encode(key)),
+            TableError::Empty => write!(f, "no rows"),
+        }
+    }
+}
+
+impl std::error::Error for TableError {}
+
+#[cfg(feature = "sqlx")]
+impl From<sqlx::Error> for TableError {
+    fn from(error: sqlx::Error) -> TableError {
+        TableError::Database(error)
+    }
+}
+
+impl TableSnapshot {
+    /// Builds the tree over canonically encoded rows, each given together with its primary key
+    pub fn build(rows: impl IntoIterator<Item = (Vec<u8>, Data)>) -> Result<TableSnapshot, TableError> {
+        let mut keys = HashMap::new();
+        let mut encoded = vec![];
+        for (position, (key, row)) in rows.into_iter().enumerate() {
+            if keys.contains_key(&key) {
+                return Err(TableError::DuplicateKey(key));
+            }
+            keys.insert(key, position);
+            encoded.push(row);
+        }
+        if encoded.is_empty() {
+            return Err(TableError::Empty);
+        }
+        Ok(TableSnapshot {
+            tree: MerkleTree::construct(&encoded),
+            rows: encoded,
+            keys,
+        })
+    }
+
+    /// Gets the root to publish for this export
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Gets number of rows in the snapshot
+    pub fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Gets the encoded row with the given primary key together with the proof that it is in the snapshot
+    pub fn proof(&self, key: &[u8]) -> Option<(&Data, Proof)> {
+        // rows of equal contents are different leaves, so the proof is looked up by position
+        let position = *self.keys.get(key)?;
+        Some((&self.rows[position], self.tree.prove_by_index(position as u64)?))
+    }
+}
+
+/// Encodes a row as its column count (`u32` little-endian) followed by every column in order:
+/// its name and its type name, each prefixed by its length (`u32` little-endian), then a null byte
+/// (1 for SQL `NULL`, 0 otherwise) and unless null the value's bytes, also prefixed by their length
+///
+/// Values are taken in the database's binary format, so the same value always encodes the same.
+pub fn encode_row<'a>(columns: impl ExactSizeIterator<Item = (&'a str, &'a str, Option<&'a [u8]>)>) -> Data {
+    let mut row = (columns.len() as u32).to_le_bytes().to_vec();
+    for (name, type_name, value) in columns {
+        write_length_prefixed(&mut row, name.as_bytes());
+        write_length_prefixed(&mut row, type_name.as_bytes());
+        match value {
+            None => row.push(1),
+            Some(value) => {
+                row.push(0);
+                write_length_prefixed(&mut row, value);
+            }
+        }
+    }
+    row
+}
+
+fn write_length_prefixed(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(field);
+}
+
+/// Canonically encodes a Postgres row with `encode_row`
+#[cfg(feature = "sqlx")]
+pub fn encode_pg_row(row: &sqlx::postgres::PgRow) -> Result<Data, TableError> {
+    use sqlx::{Column, Row, TypeInfo, ValueRef};
+
+    let mut values = Vec::with_capacity(row.len());
+    for column in row.columns() {
+        let value = row.try_get_raw(column.ordinal())?;
+        let bytes = if value.is_null() {
+            None
+        } else {
+            Some(value.as_bytes().map_err(sqlx::Error::Decode)?)
+        };
+        values.push((column.name(), value.type_info().name().to_string(), bytes));
+    }
+    Ok(encode_row(values.iter().map(|(name, type_name, value)| (*name, type_name.as_str(), *value))))
+}
+
+/// Streams the rows of a Postgres query into a snapshot keyed by the `primary_key` column
+///
+/// Keys are the column's value in the binary format of Postgres, e.g. the 8 big-endian bytes of an
+/// `int8` or the UTF-8 bytes of a `text`. Only the encoding of every row is kept, and the tree is built
+/// once the last row has arrived, so the whole result is held in memory.
+#[cfg(feature = "sqlx")]
+pub async fn snapshot_query<'e, E>(executor: E, query: &'e str, primary_key: &str) -> Result<TableSnapshot, TableError>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    use futures_util::TryStreamExt;
+    use sqlx::{Row, ValueRef};
+
+    let mut rows = sqlx::query(query).fetch(executor);
+    let mut encoded = vec![];
+    while let Some(row) = rows.try_next().await? {
+        let key = row.try_get_raw(primary_key).map_err(|error| match error {
+            sqlx::Error::ColumnNotFound(_) => TableError::MissingPrimaryKey,
+            error => TableError::Database(error),
+        })?;
+        if key.is_null() {
+            return Err(TableError::NullPrimaryKey(encoded.len()));
+        }
+        let key = key.as_bytes().map_err(sqlx::Error::Decode)?.to_vec();
+        encoded.push((key, encode_pg_row(&row)?));
+    }
+    TableSnapshot::build(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_rows(n: u64) -> Vec<(Vec<u8>, Data)> {
+        (0..n)
+            .map(|id| {
+                let key = id.to_be_bytes();
+                let name = format!("row {id}");
+                let columns = [("id", "INT8", Some(key.as_slice())), ("name", "TEXT", Some(name.as_bytes())), ("deleted_at", "TIMESTAMPTZ", None)];
+                (key.to_vec(), encode_row(columns.into_iter()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rows_are_proven_by_primary_key() {
+        let rows = example_rows(7);
+        let snapshot = TableSnapshot::build(rows.clone()).expect("valid rows");
+        assert_eq!(snapshot.row_count(), 7);
+
+        for (key, encoded) in &rows {
+            let (row, proof) = snapshot.proof(key).expect("row is in the snapshot");
+            assert_eq!(row, encoded);
+            assert!(MerkleTree::verify_proof(row, &proof, &snapshot.root()));
+        }
+        assert!(snapshot.proof(&7_u64.to_be_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_rows_of_equal_contents_are_proven_at_their_own_position() {
+        let row = encode_row([("name", "TEXT", Some(&b"same"[..]))].into_iter());
+        let snapshot = TableSnapshot::build((0..3_u8).map(|key| (vec![key], row.clone()))).expect("valid rows");
+
+        for key in 0..3_u8 {
+            let (_, proof) = snapshot.proof(&[key]).expect("row is in the snapshot");
+            let expected = snapshot.tree.prove_by_index(u64::from(key)).expect("this should return Proof");
+            assert_eq!(proof.to_bytes(), expected.to_bytes());
+            assert!(MerkleTree::verify_proof(&row, &proof, &snapshot.root()));
+        }
+        let first = snapshot.proof(&[0]).expect("row is in the snapshot").1;
+        assert_ne!(snapshot.proof(&[2]).expect("row is in the snapshot").1.to_bytes(), first.to_bytes());
+    }
+
+    #[test]
+    fn test_encode_row_tells_null_from_empty_and_names_columns() {
+        let null = encode_row([("a", "TEXT", None)].into_iter());
+        let empty = encode_row([("a", "TEXT", Some(&[][..]))].into_iter());
+        let renamed = encode_row([("b", "TEXT", None)].into_iter());
+        assert_ne!(null, empty);
+        assert_ne!(null, renamed);
+        assert_eq!(empty, [&1_u32.to_le_bytes()[..], &[1, 0, 0, 0, b'a', 4, 0, 0, 0], b"TEXT", &[0, 0, 0, 0, 0]].concat());
+    }
+
+    #[test]
+    fn test_build_rejects_duplicate_keys_and_empty_tables() {
+        let mut rows = example_rows(3);
+        rows.push(rows[1].clone());
+        assert!(matches!(TableSnapshot::build(rows), Err(TableError::DuplicateKey(key)) if key == 1_u64.to_be_bytes()));
+        assert!(matches!(TableSnapshot::build(vec![]), Err(TableError::Empty)));
+    }
+}
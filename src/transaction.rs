@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::MerkleTree;
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+
+/// Batch of appends and updates to a tree that takes effect all at once, or not at all
+///
+/// Nothing touches the tree before `commit`, which writes the staged leaves and recomputes only the nodes
+/// above the updated and appended ones, each once however many of its leaves changed. Appending moves the
+/// levels above the leaves up to make room, but hashes none of the nodes it moves. Dropping the transaction,
+/// or calling `rollback`, leaves the tree exactly as it was, so a batch derived from fallible processing can
+/// bail out with `?` at any point. A bloom filter of the tree learns the new leaves, and keeps answering
+/// "maybe" for replaced ones.
+pub struct Transaction<'a, H: Hasher = Sha256Hasher> {
+    tree: &'a mut MerkleTree<H>,
+    /// new hashes of leaves the tree already has, by index
+    updates: BTreeMap<usize, LeafHash>,
+    /// leaves to append after the ones the tree already has
+    appended: Vec<LeafHash>,
+}
+
+/// Reasons a change can not be staged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionError {
+    /// the index is neither a leaf of the tree nor one appended in the transaction
+    IndexOutOfRange { index: u64, leaf_count: u64 },
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::IndexOutOfRange { index, leaf_count } => write!(f, "leaf index {index} out of range for {leaf_count} leaves"),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Starts a batch of appends and updates that only take effect on `commit`
+    pub fn begin(&mut self) -> Transaction<'_, H> {
+        Transaction { tree: self, updates: BTreeMap::new(), appended: vec![] }
+    }
+}
+
+impl<H: Hasher> Transaction<'_, H> {
+    /// Gets number of leaves the tree will have once the transaction is committed
+    pub fn leaf_count(&self) -> u64 {
+        (self.tree.leaf_count + self.appended.len()) as u64
+    }
+
+    /// Stages data to be hashed and appended as the next leaf, returning the index it will have
+    pub fn append(&mut self, data: &[u8]) -> u64 {
+        let leaf_hash = LeafHash::of(data, &self.tree.hasher);
+        self.append_hash(leaf_hash)
+    }
+
+    /// Stages a leaf that was already hashed to be appended, returning the index it will have
+    ///
+    /// # Panics
+    ///
+    /// When the hash is not as long as the hashes of the tree.
+    pub fn append_hash(&mut self, leaf_hash: LeafHash) -> u64 {
+        self.check_len(&leaf_hash);
+        self.appended.push(leaf_hash);
+        self.leaf_count() - 1
+    }
+
+    /// Stages the leaf at `index` to be replaced by the hash of `data`
+    /// the index may be one of a leaf appended earlier in the same transaction
+    pub fn update(&mut self, index: u64, data: &[u8]) -> Result<(), TransactionError> {
+        let leaf_hash = LeafHash::of(data, &self.tree.hasher);
+        self.update_hash(index, leaf_hash)
+    }
+
+    /// Stages the leaf at `index` to be replaced by a hash that was already computed
+    ///
+    /// # Panics
+    ///
+    /// When the hash is not as long as the hashes of the tree.
+    pub fn update_hash(&mut self, index: u64, leaf_hash: LeafHash) -> Result<(), TransactionError> {
+        self.check_len(&leaf_hash);
+        let out_of_range = TransactionError::IndexOutOfRange { index, leaf_count: self.leaf_count() };
+        let position = usize::try_from(index).map_err(|_| out_of_range)?;
+        if position < self.tree.leaf_count {
+            self.updates.insert(position, leaf_hash);
+        } else {
+            *self.appended.get_mut(position - self.tree.leaf_count).ok_or(out_of_range)? = leaf_hash;
+        }
+        Ok(())
+    }
+
+    /// Applies every staged change to the tree, returning its new root
+    pub fn commit(self) -> Root {
+        let Transaction { tree, updates, appended } = self;
+        // the levels and the filter may be shared with snapshots of the tree, which keep the old ones
+        if let Some(bloom_filter) = &mut tree.bloom_filter {
+            let bloom_filter = Arc::make_mut(bloom_filter);
+            for leaf_hash in updates.values().chain(&appended) {
+                bloom_filter.insert(leaf_hash.as_bytes());
+            }
+        }
+        if !updates.is_empty() || !appended.is_empty() {
+            // only the paths of the updated and appended leaves are rehashed, in place
+            let levels = Arc::make_mut(&mut tree.levels);
+            for (index, leaf_hash) in &updates {
+                levels.hash_mut(0, *index).copy_from_slice(leaf_hash.as_bytes());
+            }
+            levels.grow(&appended);
+            let leaf_count = tree.leaf_count + appended.len();
+            let dirty: Vec<usize> = updates.keys().copied().chain(tree.leaf_count..leaf_count).collect();
+            levels.rebuild_paths(&dirty, &tree.hasher);
+            tree.leaf_count = leaf_count;
+        }
+        tree.root()
+    }
+
+    /// Discards every staged change, leaving the tree as it was before `begin`
+    /// dropping the transaction does the same
+    pub fn rollback(self) {}
+
+    fn check_len(&self, leaf_hash: &LeafHash) {
+        assert_eq!(leaf_hash.as_bytes().len(), self.tree.hasher.digest_len(), "leaf hashes have the length of the hash function");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| format!("leaf {i}").into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_committed_batches_match_a_tree_built_from_scratch() {
+        let mut data = example_data(6);
+        let mut tree = MerkleTree::construct(&data);
+
+        let mut transaction = tree.begin();
+        transaction.update(1, b"one").expect("index is in range");
+        assert_eq!(transaction.append(b"six"), 6);
+        assert_eq!(transaction.append(b"seven"), 7);
+        transaction.update(7, b"SEVEN").expect("index was appended");
+        assert_eq!(transaction.update(8, b"eight"), Err(TransactionError::IndexOutOfRange { index: 8, leaf_count: 8 }));
+        let root = transaction.commit();
+
+        data[1] = b"one".to_vec();
+        data.extend([b"six".to_vec(), b"SEVEN".to_vec()]);
+        assert_eq!(root, MerkleTree::construct(&data).root());
+        assert_eq!(tree, MerkleTree::construct(&data));
+
+        // updates alone rebuild the levels in place
+        let mut transaction = tree.begin();
+        transaction.update(0, b"zero").expect("index is in range");
+        transaction.update(7, b"seven").expect("index is in range");
+        transaction.commit();
+        (data[0], data[7]) = (b"zero".to_vec(), b"seven".to_vec());
+        assert_eq!(tree, MerkleTree::construct(&data));
+        let proof = tree.prove_by_index(7).expect("index is in range");
+        assert!(MerkleTree::verify_proof(&data[7], &proof, &tree.root()));
+    }
+
+    #[test]
+    fn test_updates_rehash_only_the_paths_of_changed_leaves() {
+        use crate::metrics::{Counters, MeteredHasher};
+
+        let mut data = example_data(13);
+        let counters = Arc::new(Counters::new());
+        let hasher = MeteredHasher::new(Sha256Hasher::new(), counters.clone());
+        let mut tree = MerkleTree::construct_with_hasher(&data, hasher.clone());
+
+        let mut transaction = tree.begin();
+        for (index, leaf) in [(0, b"zero"), (1, b"one!"), (12, b"last")] {
+            transaction.update_hash(index, LeafHash::of(leaf, &Sha256Hasher::new())).expect("index is in range");
+            data[index as usize] = leaf.to_vec();
+        }
+        let hashes = counters.hashes();
+        let root = transaction.commit();
+        // leaves 0 and 1 share their parent and grandparent, leaf 12 is promoted up to level 3
+        assert_eq!(counters.hashes() - hashes, 5);
+        assert_eq!(root, MerkleTree::construct(&data).root());
+        assert_eq!(tree.to_heap_bytes(), MerkleTree::construct(&data).to_heap_bytes());
+    }
+
+    #[test]
+    fn test_appends_hash_only_the_paths_of_new_leaves() {
+        use crate::metrics::{Counters, MeteredHasher};
+
+        let mut data = example_data(13);
+        let counters = Arc::new(Counters::new());
+        let hasher = MeteredHasher::new(Sha256Hasher::new(), counters.clone());
+        let mut tree = MerkleTree::construct_with_hasher(&data, hasher.clone());
+        let frozen = tree.freeze();
+        let frozen_root = tree.root();
+
+        let mut transaction = tree.begin();
+        transaction.update(12, b"twelve").expect("index is in range");
+        for leaf in [b"13", b"14", b"15"] {
+            transaction.append(leaf);
+        }
+        let hashes = counters.hashes();
+        let root = transaction.commit();
+        // the 16 leaves form a complete tree, and the new ones are all under its right half
+        assert_eq!(counters.hashes() - hashes, 5);
+        data[12] = b"twelve".to_vec();
+        data.extend([b"13".to_vec(), b"14".to_vec(), b"15".to_vec()]);
+        assert_eq!(root, MerkleTree::construct(&data).root());
+        assert_eq!(tree.to_heap_bytes(), MerkleTree::construct(&data).to_heap_bytes());
+        assert_eq!(frozen.root(), frozen_root);
+
+        // growing a tree a leaf at a time keeps every level where a tree built at once has it
+        let mut tree = MerkleTree::construct(&data[..1]);
+        for n in 2..=data.len() {
+            let mut transaction = tree.begin();
+            transaction.append(&data[n - 1]);
+            transaction.commit();
+            assert_eq!(tree.to_heap_bytes(), MerkleTree::construct(&data[..n]).to_heap_bytes());
+        }
+    }
+
+    #[test]
+    fn test_rolled_back_batches_leave_the_tree_untouched() {
+        let data = example_data(5);
+        let mut tree = MerkleTree::construct(&data).with_bloom_filter(0.01);
+
+        let stage = |tree: &mut MerkleTree| -> Result<Root, TransactionError> {
+            let mut transaction = tree.begin();
+            transaction.append(b"five");
+            transaction.update(2, b"two")?;
+            transaction.update(9, b"nine")?;
+            Ok(transaction.commit())
+        };
+        assert!(stage(&mut tree).is_err());
+        assert_eq!(tree, MerkleTree::construct(&data));
+        assert!(!tree.maybe_contains(&b"five".to_vec()));
+
+        let mut transaction = tree.begin();
+        transaction.append(b"five");
+        transaction.rollback();
+        assert_eq!(tree.leaf_count(), 5);
+
+        let mut transaction = tree.begin();
+        transaction.append(b"five");
+        transaction.commit();
+        assert!(tree.maybe_contains(&b"five".to_vec()));
+        assert!(tree.maybe_contains(&data[0]));
+    }
+}
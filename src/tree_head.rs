@@ -0,0 +1,119 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::{sibling_directions, split_point, Data, Hash, HashDirection, MerkleTree, Proof};
+use crate::root::Root;
+
+/// Commitment to a tree: its root together with the number of leaves it covers
+///
+/// A bare root does not tell how many leaves it covers, which for an append-only log
+/// lets a proof against an older, smaller tree pass for one against the current tree.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TreeHead {
+    pub root: Root,
+    pub tree_size: u64,
+    /// when the head was issued, in milliseconds since the Unix epoch
+    pub timestamp: Option<u64>,
+}
+
+impl TreeHead {
+    /// Creates a head without a timestamp
+    pub fn new(root: Root, tree_size: u64) -> TreeHead {
+        TreeHead {
+            root,
+            tree_size,
+            timestamp: None,
+        }
+    }
+
+    /// Stamps the head with the time it is issued at, in milliseconds since the Unix epoch
+    pub fn with_timestamp(mut self, timestamp: u64) -> TreeHead {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Whether both heads commit to the same tree, regardless of when they were issued
+    pub fn describes_same_tree(&self, other: &TreeHead) -> bool {
+        self.tree_size == other.tree_size && self.root == other.root
+    }
+}
+
+impl MerkleTree {
+    /// Verifies a proof against a head of a SHA-256 tree, see `verify_proof_against_head_with_hasher`
+    pub fn verify_proof_against_head(data: &Data, proof: &Proof, head: &TreeHead) -> bool {
+        MerkleTree::verify_proof_against_head_with_hasher(data, proof, head, &Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Gets the head committing to this tree, without a timestamp
+    pub fn head(&self) -> TreeHead {
+        TreeHead::new(self.root(), self.leaf_count as u64)
+    }
+
+    /// Verifies that the data and proof produce the head's root in a tree of the head's size
+    /// the proof's path has to lead to some leaf of a tree of that size, so paths that only exist
+    /// in trees of other sizes never verify
+    pub fn verify_proof_against_head_with_hasher(data: &Data, proof: &Proof<H>, head: &TreeHead, hasher: &H) -> bool {
+        path_fits_tree_size(&proof.hashes, head.tree_size) && MerkleTree::verify_proof_with_hasher(data, proof, &head.root, hasher)
+    }
+}
+
+/// whether a path of sibling hashes from the leaf up is the path of some leaf in a tree of `tree_size` leaves
+pub(crate) fn path_fits_tree_size(hashes: &[(HashDirection, Hash)], tree_size: u64) -> bool {
+    // descending from the root, each sibling on the right puts the leaf into the left subtree,
+    // which finds the only leaf the path can belong to
+    let (mut leaf_index, mut size) = (0, tree_size);
+    for (hash_direction, _) in hashes.iter().rev() {
+        if size < 2 {
+            return false;
+        }
+        let left = split_point(size);
+        match hash_direction {
+            HashDirection::Right => size = left,
+            HashDirection::Left => (leaf_index, size) = (leaf_index + left, size - left),
+        }
+    }
+    sibling_directions(leaf_index, tree_size).is_some_and(|directions| directions.iter().eq(hashes.iter().map(|(hash_direction, _)| hash_direction)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| vec![i as u8]).collect()
+    }
+
+    #[test]
+    fn test_proofs_verify_against_head_of_their_tree() {
+        let data = example_data(6);
+        let tree = MerkleTree::construct(&data);
+        let head = tree.head();
+        assert_eq!(head.tree_size, 6);
+        for leaf in &data {
+            let proof = tree.prove(leaf).expect("this should return Proof");
+            assert!(MerkleTree::verify_proof_against_head(leaf, &proof, &head));
+            // the right root claimed for a size whose tree has no leaf with this path is refused
+            assert!(!MerkleTree::verify_proof_against_head(leaf, &proof, &TreeHead::new(tree.root(), 16)));
+        }
+    }
+
+    #[test]
+    fn test_path_fits_tree_size() {
+        // the promoted fifth leaf of a five leaf tree has a single sibling on the left
+        let sibling = vec![0; 32];
+        assert!(path_fits_tree_size(&[(HashDirection::Left, sibling.clone())], 5));
+        assert!(!path_fits_tree_size(&[(HashDirection::Left, sibling.clone())], 4));
+        assert!(path_fits_tree_size(&[(HashDirection::Left, sibling.clone())], 2));
+        assert!(path_fits_tree_size(&[], 1));
+        assert!(!path_fits_tree_size(&[], 2));
+        assert!(!path_fits_tree_size(&[(HashDirection::Right, sibling)], 1));
+    }
+
+    #[test]
+    fn test_heads_with_timestamps_describe_same_tree() {
+        let head = MerkleTree::construct(&example_data(3)).head();
+        let stamped = head.clone().with_timestamp(1_700_000_000_000);
+        assert_ne!(head, stamped);
+        assert!(head.describes_same_tree(&stamped));
+    }
+}
@@ -0,0 +1,436 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::MerkleTree;
+use crate::node_hash::LeafHash;
+use crate::root::Root;
+use crate::snapshot::SnapshotError;
+use crate::transaction::TransactionError;
+
+/// bytes every write-ahead log starts with
+pub const WAL_MAGIC: [u8; 4] = *b"MRKW";
+/// version of the log format written by this crate
+pub const WAL_VERSION: u8 = 1;
+
+/// One change to a `DurableTree`, as it is recorded in the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalOp {
+    /// a leaf appended after the last one
+    Append(LeafHash),
+    /// the leaf at `index` replaced
+    Update { index: u64, leaf_hash: LeafHash },
+}
+
+/// Reasons a `DurableTree` can not be opened or changed
+#[derive(Debug)]
+pub enum WalError {
+    Io(io::Error),
+    /// the snapshot next to the log can not be read
+    Snapshot(SnapshotError),
+    /// a change refers to a leaf the tree doesn't have, and nothing of its batch was written
+    Rejected(TransactionError),
+    /// the log is not a log, or one of its complete records can not be applied to the snapshot
+    Corrupt,
+}
+
+impl fmt::Display for WalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalError::Io(error) => write!(f, "{error}"),
+            WalError::Snapshot(error) => write!(f, "{error}"),
+            WalError::Rejected(error) => write!(f, "{error}"),
+            WalError::Corrupt => write!(f, "write-ahead log doesn't match its snapshot"),
+        }
+    }
+}
+
+impl std::error::Error for WalError {}
+
+impl From<io::Error> for WalError {
+    fn from(error: io::Error) -> WalError {
+        WalError::Io(error)
+    }
+}
+
+impl From<SnapshotError> for WalError {
+    fn from(error: SnapshotError) -> WalError {
+        WalError::Snapshot(error)
+    }
+}
+
+/// Tree kept in a directory, every batch of changes made durable in a write-ahead log before it is applied
+///
+/// The directory holds a `snapshot` of the tree as `export_snapshot` writes it, and a `wal` of the
+/// batches applied since. A batch is checked against the tree, written to the log as one record ending
+/// in the hash of its contents and synced to disk, and only then applied in memory as one transaction,
+/// which hashes again only the nodes above the leaves it appends or updates. Opening the directory replays
+/// the complete records over the snapshot: a record torn by a crash fails its hash and is cut off, so the
+/// tree comes back at the root of the last batch that was reported durable, never halfway through one.
+///
+/// The log covers a tree kept in memory, which it makes durable through the snapshot. `DiskTree` and a
+/// `TreeStore` write their nodes without a log, so a crash in the middle of a change leaves them as far
+/// as it got.
+///
+/// `checkpoint` folds the log into a new snapshot, which bounds the time opening takes. The log starts with
+/// the root of the snapshot it goes with, so a log a crash left behind after its snapshot was replaced is
+/// recognized as already folded in and discarded.
+pub struct DurableTree<H: Hasher = Sha256Hasher> {
+    hasher: H,
+    dir: PathBuf,
+    /// `None` until the first leaf is appended
+    tree: Option<MerkleTree<H>>,
+    wal: File,
+}
+
+impl DurableTree {
+    /// Opens or creates a SHA-256 tree in `dir`, see `open_with_hasher`
+    pub fn open(dir: impl AsRef<Path>) -> Result<DurableTree, WalError> {
+        DurableTree::open_with_hasher(dir, Sha256Hasher::new())
+    }
+}
+
+impl<H: Hasher> DurableTree<H> {
+    /// Opens the tree in `dir` built with the given hash function, recovering it from its snapshot and log
+    /// the directory is created if missing, holding an empty tree
+    pub fn open_with_hasher(dir: impl AsRef<Path>, hasher: H) -> Result<DurableTree<H>, WalError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let tree = match File::open(dir.join("snapshot")) {
+            Ok(file) => Some(MerkleTree::import_snapshot_with_hasher(io::BufReader::new(file), hasher.clone())?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error.into()),
+        };
+        let mut wal = OpenOptions::new().read(true).append(true).create(true).open(dir.join("wal"))?;
+        // the entry of a log that was just created has to be durable before anything is logged to it
+        sync_dir(&dir)?;
+        let mut durable = DurableTree { hasher, dir, tree, wal: wal.try_clone()? };
+
+        let mut log = vec![];
+        wal.read_to_end(&mut log)?;
+        let header = durable.header();
+        if log.len() < header.len() || log[..header.len()] != header {
+            if log.len() >= WAL_MAGIC.len() && log[..WAL_MAGIC.len()] != WAL_MAGIC {
+                return Err(WalError::Corrupt);
+            }
+            // a log cut short before its header, or one left behind by a checkpoint, holds nothing to replay
+            durable.start_log()?;
+            return Ok(durable);
+        }
+        let mut rest = &log[header.len()..];
+        while let Some((ops, tail)) = durable.read_record(rest) {
+            validate(durable.leaf_count(), &ops).map_err(|_| WalError::Corrupt)?;
+            apply(&mut durable.tree, &ops, &durable.hasher);
+            rest = tail;
+        }
+        // whatever is left is a record torn by a crash, which was never reported durable
+        wal.set_len((log.len() - rest.len()) as u64)?;
+        wal.sync_data()?;
+        Ok(durable)
+    }
+
+    /// Gets the tree as of the last batch applied, `None` while no leaf was appended
+    pub fn tree(&self) -> Option<&MerkleTree<H>> {
+        self.tree.as_ref()
+    }
+
+    /// Gets root hash of the tree, `None` while no leaf was appended
+    pub fn root(&self) -> Option<Root> {
+        self.tree.as_ref().map(MerkleTree::root)
+    }
+
+    /// Gets number of leaves of the tree
+    pub fn leaf_count(&self) -> u64 {
+        self.tree.as_ref().map_or(0, MerkleTree::leaf_count)
+    }
+
+    /// Durably hashes and appends a leaf, returning its index
+    pub fn append(&mut self, data: &[u8]) -> Result<u64, WalError> {
+        let index = self.leaf_count();
+        self.apply(&[WalOp::Append(LeafHash::of(data, &self.hasher))])?;
+        Ok(index)
+    }
+
+    /// Durably replaces the leaf at `index` by the hash of `data`
+    pub fn update(&mut self, index: u64, data: &[u8]) -> Result<(), WalError> {
+        self.apply(&[WalOp::Update { index, leaf_hash: LeafHash::of(data, &self.hasher) }])?;
+        Ok(())
+    }
+
+    /// Durably applies a batch of changes as one, returning the new root
+    /// the batch is refused as a whole, with nothing written, when a change refers to a leaf the tree
+    /// doesn't have by then
+    ///
+    /// # Panics
+    ///
+    /// When a leaf hash is not as long as the hashes of the tree.
+    pub fn apply(&mut self, ops: &[WalOp]) -> Result<Option<Root>, WalError> {
+        for WalOp::Append(leaf_hash) | WalOp::Update { leaf_hash, .. } in ops {
+            assert_eq!(leaf_hash.as_bytes().len(), self.hasher.digest_len(), "leaf hashes have the length of the hash function");
+        }
+        validate(self.leaf_count(), ops).map_err(WalError::Rejected)?;
+        self.wal.write_all(&self.record(ops))?;
+        self.wal.sync_data()?;
+        apply(&mut self.tree, ops, &self.hasher);
+        Ok(self.root())
+    }
+
+    /// Folds the log into a new snapshot and starts an empty log
+    /// both files are written next to the ones they replace and renamed over them, syncing the directory
+    /// after each rename so that a crash can't undo it
+    pub fn checkpoint(&mut self) -> Result<(), WalError> {
+        let Some(tree) = &self.tree else {
+            return Ok(());
+        };
+        let partial = self.dir.join("snapshot.partial");
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        tree.export_snapshot(&mut writer)?;
+        writer.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        fs::rename(&partial, self.dir.join("snapshot"))?;
+        sync_dir(&self.dir)?;
+        self.start_log()
+    }
+
+    /// replaces the log by one holding only the header of the current tree
+    fn start_log(&mut self) -> Result<(), WalError> {
+        let partial = self.dir.join("wal.partial");
+        let mut file = File::create(&partial)?;
+        file.write_all(&self.header())?;
+        file.sync_all()?;
+        fs::rename(&partial, self.dir.join("wal"))?;
+        sync_dir(&self.dir)?;
+        self.wal = OpenOptions::new().append(true).open(self.dir.join("wal"))?;
+        Ok(())
+    }
+
+    /// magic bytes, version and the root the records of the log apply to, all zeros for an empty tree
+    fn header(&self) -> Vec<u8> {
+        let mut header = WAL_MAGIC.to_vec();
+        header.push(WAL_VERSION);
+        match &self.tree {
+            Some(tree) => header.extend_from_slice(tree.root().as_bytes()),
+            None => header.resize(header.len() + self.hasher.digest_len(), 0),
+        }
+        header
+    }
+
+    /// a record is the length of its body (`u32` little-endian), the body and the hash of the body
+    /// the body is the number of changes (`u32` little-endian), each an append (0) or an update (1)
+    /// followed by the index of the leaf (`u64` little-endian) for updates and the leaf hash
+    fn record(&self, ops: &[WalOp]) -> Vec<u8> {
+        let mut body = (ops.len() as u32).to_le_bytes().to_vec();
+        for op in ops {
+            match op {
+                WalOp::Append(leaf_hash) => {
+                    body.push(0);
+                    body.extend_from_slice(leaf_hash.as_bytes());
+                }
+                WalOp::Update { index, leaf_hash } => {
+                    body.push(1);
+                    body.extend_from_slice(&index.to_le_bytes());
+                    body.extend_from_slice(leaf_hash.as_bytes());
+                }
+            }
+        }
+        let mut record = (body.len() as u32).to_le_bytes().to_vec();
+        record.extend_from_slice(&body);
+        record.extend_from_slice(&self.hasher.hash(&body));
+        record
+    }
+
+    /// reads the record at the start of `bytes`, `None` when it is cut short or doesn't match its hash
+    fn read_record<'a>(&self, bytes: &'a [u8]) -> Option<(Vec<WalOp>, &'a [u8])> {
+        let digest_len = self.hasher.digest_len();
+        let (len, rest) = bytes.split_first_chunk::<4>()?;
+        let len = u32::from_le_bytes(*len) as usize;
+        if rest.len() < len.checked_add(digest_len)? {
+            return None;
+        }
+        let (body, rest) = rest.split_at(len);
+        let (hash, rest) = rest.split_at(digest_len);
+        if self.hasher.hash(body) != hash {
+            return None;
+        }
+        let (count, mut body) = body.split_first_chunk::<4>()?;
+        let mut ops = vec![];
+        for _ in 0..u32::from_le_bytes(*count) {
+            let (tag, tail) = body.split_first()?;
+            let (index, tail) = match tag {
+                0 => (None, tail),
+                1 => {
+                    let (index, tail) = tail.split_first_chunk::<8>()?;
+                    (Some(u64::from_le_bytes(*index)), tail)
+                }
+                _ => return None,
+            };
+            if tail.len() < digest_len {
+                return None;
+            }
+            let (leaf_hash, tail) = tail.split_at(digest_len);
+            let leaf_hash = LeafHash::new(leaf_hash.to_vec());
+            ops.push(match index {
+                None => WalOp::Append(leaf_hash),
+                Some(index) => WalOp::Update { index, leaf_hash },
+            });
+            body = tail;
+        }
+        body.is_empty().then_some((ops, rest))
+    }
+}
+
+/// checks that every change of a batch refers to a leaf the tree has by then
+fn validate(mut leaf_count: u64, ops: &[WalOp]) -> Result<(), TransactionError> {
+    for op in ops {
+        match op {
+            WalOp::Append(_) => leaf_count += 1,
+            WalOp::Update { index, .. } if *index >= leaf_count => return Err(TransactionError::IndexOutOfRange { index: *index, leaf_count }),
+            WalOp::Update { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+/// applies a batch that passed `validate` in a single transaction
+fn apply<H: Hasher>(tree: &mut Option<MerkleTree<H>>, ops: &[WalOp], hasher: &H) {
+    let mut ops = ops.iter();
+    if tree.is_none() {
+        // a valid batch on an empty tree starts with an append
+        let Some(WalOp::Append(first)) = ops.next() else {
+            return;
+        };
+        *tree = Some(MerkleTree::from_leaf_hashes_with_hasher(vec![first.clone()], hasher.clone()));
+    }
+    let mut transaction = tree.as_mut().expect("tree has a leaf").begin();
+    for op in ops {
+        match op {
+            WalOp::Append(leaf_hash) => {
+                transaction.append_hash(leaf_hash.clone());
+            }
+            WalOp::Update { index, leaf_hash } => transaction.update_hash(*index, leaf_hash.clone()).expect("batch was validated"),
+        }
+    }
+    transaction.commit();
+}
+
+/// flushes the entries of the directory, so that files created or renamed in it survive a crash
+/// only unix lets a directory be opened to sync it
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkletree::Data;
+
+    fn example_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("merkle-wal-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn example_data(n: usize) -> Vec<Data> {
+        (0..n).map(|i| format!("entry {i}").into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_changes_survive_reopening_and_checkpoints() {
+        let dir = example_dir("reopen");
+        let mut data = example_data(7);
+        let mut tree = DurableTree::open(&dir).expect("opens");
+        assert_eq!(tree.root(), None);
+        for leaf in &data[..5] {
+            tree.append(leaf).expect("appends");
+        }
+        tree.update(2, b"two").expect("updates");
+        data[2] = b"two".to_vec();
+        drop(tree);
+
+        let mut tree = DurableTree::open(&dir).expect("recovers");
+        assert_eq!(tree.root(), Some(MerkleTree::construct(&data[..5]).root()));
+        tree.checkpoint().expect("checkpoints");
+        let ops: Vec<WalOp> = data[5..].iter().map(|leaf| WalOp::Append(LeafHash::of(leaf, &Sha256Hasher::new()))).collect();
+        assert_eq!(tree.apply(&ops).expect("applies"), Some(MerkleTree::construct(&data).root()));
+        drop(tree);
+
+        let tree = DurableTree::open(&dir).expect("recovers");
+        assert_eq!(tree.tree(), Some(&MerkleTree::construct(&data)));
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_appends_hash_only_the_path_of_the_new_leaf() {
+        use crate::metrics::{Counters, MeteredHasher};
+        use std::sync::Arc;
+
+        let dir = example_dir("incremental");
+        let counters = Arc::new(Counters::new());
+        let mut tree = DurableTree::open_with_hasher(&dir, MeteredHasher::new(Sha256Hasher::new(), counters.clone())).expect("opens");
+        let data = example_data(65);
+        for leaf in &data[..64] {
+            tree.append(leaf).expect("appends");
+        }
+        let before = counters.hashes();
+        tree.append(&data[64]).expect("appends");
+        // the leaf, the record and the root above the leaf promoted to the top, not the 64 nodes of a rebuild
+        assert_eq!(counters.hashes() - before, 3);
+        assert_eq!(tree.root(), Some(MerkleTree::construct(&data).root()));
+        fs::remove_dir_all(&dir).expect("removes the directory");
+    }
+
+    #[test]
+    fn test_torn_records_and_refused_batches_are_not_applied() {
+        let dir = example_dir("torn");
+        let data = example_data(4);
+        let mut tree = DurableTree::open(&dir).expect("opens");
+        for leaf in &data[..3] {
+            tree.append(leaf).expect("appends");
+        }
+        let refused = [WalOp::Append(LeafHash::of(&data[3], &Sha256Hasher::new())), WalOp::Update { index: 9, leaf_hash: LeafHash::of(b"nine", &Sha256Hasher::new()) }];
+        assert!(matches!(tree.apply(&refused), Err(WalError::Rejected(TransactionError::IndexOutOfRange { index: 9, leaf_count: 4 }))));
+        let durable = tree.root();
+        drop(tree);
+
+        // a crash in the middle of writing the next record
+        let wal = dir.join("wal");
+        let before = fs::metadata(&wal).expect("log exists").len();
+        let mut file = OpenOptions::new().append(true).open(&wal).expect("opens log");
+        file.write_all(&[40, 0, 0, 0, 1, 0, 0, 0, 0, 7, 7]).expect("writes");
+        drop(file);
+
+        let mut tree = DurableTree::open(&dir).expect("recovers");
+        assert_eq!(tree.root(), durable);
+        assert_eq!(fs::metadata(&wal).expect("log exists").len(), before);
+        tree.append(&data[3]).expect("appends after the cut");
+        drop(tree);
+        assert_eq!(DurableTree::open(&dir).expect("recovers").root(), Some(MerkleTree::construct(&data).root()));
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+
+    #[test]
+    fn test_logs_left_behind_by_a_checkpoint_are_discarded() {
+        let dir = example_dir("stale");
+        let data = example_data(3);
+        let mut tree = DurableTree::open(&dir).expect("opens");
+        for leaf in &data {
+            tree.append(leaf).expect("appends");
+        }
+        let stale = fs::read(dir.join("wal")).expect("reads log");
+        tree.checkpoint().expect("checkpoints");
+        drop(tree);
+
+        // the snapshot was replaced, but the crash came before the log was
+        fs::write(dir.join("wal"), stale).expect("writes log");
+        let tree = DurableTree::open(&dir).expect("recovers");
+        assert_eq!(tree.tree(), Some(&MerkleTree::construct(&data)));
+
+        fs::write(dir.join("wal"), b"not a log").expect("writes log");
+        assert!(matches!(DurableTree::open(&dir), Err(WalError::Corrupt)));
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+}
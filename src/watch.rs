@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::integrity::{changed_chunks, list_files, Change, FileRecord, IntegrityManifest};
+use crate::manifest::file_entry;
+use crate::merkletree::{Data, MerkleTree};
+use crate::root::Root;
+
+/// Keeps the integrity manifest of a directory up to date by polling it
+///
+/// Each poll only rehashes the files whose size or modification time changed since the last one,
+/// every other file keeps its chunk hashes and root, so the top root is rebuilt from file roots
+/// without reading their content again. A write that keeps both size and modification time
+/// goes unnoticed, as it does for `make` and `rsync`.
+pub struct DirectoryWatcher {
+    dir: PathBuf,
+    chunk_size: usize,
+    files: BTreeMap<String, WatchedFile>,
+}
+
+struct WatchedFile {
+    record: FileRecord,
+    modified: SystemTime,
+}
+
+impl DirectoryWatcher {
+    /// Hashes every regular file below `dir` in `chunk_size` byte chunks
+    pub fn new(dir: impl AsRef<Path>, chunk_size: usize) -> io::Result<DirectoryWatcher> {
+        assert!(chunk_size > 0, "chunks must not be empty");
+        let mut watcher = DirectoryWatcher {
+            dir: dir.as_ref().to_path_buf(),
+            chunk_size,
+            files: BTreeMap::new(),
+        };
+        watcher.poll()?;
+        Ok(watcher)
+    }
+
+    /// Gets the manifest of the directory as of the last poll
+    pub fn manifest(&self) -> IntegrityManifest {
+        let files = self.files.values().map(|file| file.record.clone()).collect();
+        IntegrityManifest::from_records(self.chunk_size, files)
+    }
+
+    /// Gets the root of the directory as of the last poll, `None` while it holds no files
+    pub fn root(&self) -> Option<Root> {
+        let entries: Vec<Data> = self.files.iter().map(|(path, file)| file_entry(path, &file.record.root)).collect();
+        (!entries.is_empty()).then(|| MerkleTree::construct(&entries).root())
+    }
+
+    /// Looks for files that were added, removed or modified since the last poll and rehashes them
+    /// returns every change in path order, a failed poll leaves the watcher as it was
+    pub fn poll(&mut self) -> io::Result<Vec<Change>> {
+        let mut paths = vec![];
+        list_files(&self.dir, "", &mut paths)?;
+        paths.sort();
+
+        let mut changes = vec![];
+        let mut files = BTreeMap::new();
+        let mut unchanged = vec![];
+        for path in paths {
+            let metadata = fs::metadata(self.dir.join(&path))?;
+            let modified = metadata.modified()?;
+            let known = self.files.get(&path);
+            if known.is_some_and(|known| known.modified == modified && known.record.size == metadata.len()) {
+                // picked up from the last poll once this one can't fail anymore
+                unchanged.push(path);
+                continue;
+            }
+
+            let record = FileRecord::hash(path.clone(), File::open(self.dir.join(&path))?, self.chunk_size)?;
+            match known {
+                None => changes.push(Change::Added(path.clone())),
+                Some(known) if known.record.leaf_hashes != record.leaf_hashes => changes.push(Change::Modified {
+                    path: path.clone(),
+                    chunks: changed_chunks(&known.record, &record),
+                }),
+                // touched without changing the content
+                Some(_) => {}
+            }
+            files.insert(path, WatchedFile { record, modified });
+        }
+        for path in unchanged {
+            let known = self.files.remove(&path).expect("unchanged files were known");
+            files.insert(path, known);
+        }
+        changes.extend(self.files.keys().filter(|path| !files.contains_key(*path)).map(|path| Change::Removed(path.clone())));
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        self.files = files;
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polls_report_changes_and_keep_root_current() {
+        let dir = std::env::temp_dir().join(format!("merkle-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).expect("creates directory");
+        fs::write(dir.join("a"), vec![1; 100]).expect("writes file");
+        fs::write(dir.join("sub").join("b"), vec![2; 10]).expect("writes file");
+
+        let mut watcher = DirectoryWatcher::new(&dir, 32).expect("scans directory");
+        assert_eq!(watcher.poll().expect("polls directory"), vec![]);
+        assert_eq!(watcher.root(), IntegrityManifest::scan(&dir, 32).expect("scans directory").root());
+
+        // same size, so only the newer modification time gives the write away
+        let mut content = vec![1; 100];
+        content[70] = 0;
+        fs::write(dir.join("a"), content).expect("writes file");
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        File::options().write(true).open(dir.join("a")).and_then(|file| file.set_modified(later)).expect("sets time");
+        fs::remove_file(dir.join("sub").join("b")).expect("removes file");
+        fs::write(dir.join("c"), b"c").expect("writes file");
+
+        let changes = watcher.poll().expect("polls directory");
+        assert_eq!(
+            changes,
+            vec![
+                Change::Modified { path: "a".into(), chunks: vec![2] },
+                Change::Added("c".into()),
+                Change::Removed("sub/b".into()),
+            ]
+        );
+        assert_eq!(watcher.manifest(), IntegrityManifest::scan(&dir, 32).expect("scans directory"));
+        assert_eq!(watcher.root(), watcher.manifest().root());
+
+        // touching a file without changing it is not a change
+        File::options().write(true).open(dir.join("c")).and_then(|file| file.set_modified(later)).expect("sets time");
+        assert_eq!(watcher.poll().expect("polls directory"), vec![]);
+        fs::remove_dir_all(dir).expect("removes directory");
+    }
+}
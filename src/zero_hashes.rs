@@ -0,0 +1,72 @@
+use crate::hasher::{Hasher, Sha256Hasher};
+use crate::merkletree::Hash;
+use crate::node_hash::LeafHash;
+
+/// Hashes of the subtrees whose every leaf is the default leaf, one per height up to a maximum
+///
+/// Padding a tree to a fixed size or leaving most of a tree empty both come down to the same
+/// hashes: the default leaf, the hash of two of them, the hash of two of those and so on. They are
+/// computed once, `max_height` hashes in all, and read back by height, e.g. for the siblings an empty
+/// part of the tree contributes to a proof, see `IndexedTree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZeroHashes {
+    /// hash of an empty subtree by height, the default leaf first
+    hashes: Vec<Hash>,
+}
+
+impl ZeroHashes {
+    /// Computes the SHA-256 hashes of empty subtrees up to `max_height` over an all zero default leaf,
+    /// see `with_hasher`
+    pub fn new(max_height: u32) -> ZeroHashes {
+        let hasher = Sha256Hasher::new();
+        ZeroHashes::with_hasher(max_height, LeafHash::new(vec![0; hasher.digest_len()]), &hasher)
+    }
+
+    /// Computes the hashes of the empty subtrees of every height up to `max_height` over the given default leaf,
+    /// hashing with the given hash function
+    pub fn with_hasher<H: Hasher>(max_height: u32, default_leaf: LeafHash, hasher: &H) -> ZeroHashes {
+        let mut hashes = Vec::with_capacity(max_height as usize + 1);
+        hashes.push(default_leaf.into_hash());
+        for height in 0..max_height as usize {
+            hashes.push(hasher.hash_concat(&hashes[height], &hashes[height]));
+        }
+        ZeroHashes { hashes }
+    }
+
+    /// Gets the tallest height there is a hash for
+    pub fn max_height(&self) -> u32 {
+        self.hashes.len() as u32 - 1
+    }
+
+    /// Gets the default leaf
+    pub fn default_leaf(&self) -> &[u8] {
+        &self.hashes[0]
+    }
+
+    /// Gets the root of an empty subtree of `2^height` leaves, `None` above the maximum height
+    pub fn get(&self, height: u32) -> Option<&[u8]> {
+        self.hashes.get(height as usize).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2bHasher;
+    use crate::merkletree::{Data, MerkleTree};
+
+    #[test]
+    fn test_zero_hashes_are_roots_of_default_leaves() {
+        let hasher = Blake2bHasher::new(20).expect("valid length");
+        let default_leaf = LeafHash::of(b"empty", &hasher);
+        let zero_hashes = ZeroHashes::with_hasher(4, default_leaf.clone(), &hasher);
+        assert_eq!(zero_hashes.max_height(), 4);
+        assert_eq!(zero_hashes.default_leaf(), default_leaf.as_bytes());
+        let padding: Vec<Data> = vec![b"empty".to_vec(); 16];
+        assert_eq!(zero_hashes.get(4), Some(MerkleTree::construct_with_hasher(&padding, hasher).root().as_bytes()));
+        assert_eq!(zero_hashes.get(2), Some(MerkleTree::construct_with_hasher(&padding[..4], hasher).root().as_bytes()));
+        assert!(zero_hashes.get(5).is_none());
+
+        assert_eq!(ZeroHashes::new(0).get(0), Some([0; 32].as_slice()));
+    }
+}